@@ -0,0 +1,75 @@
+//! Compares `search_parallel` with the default, single-threaded search
+//! on an index fragmented into many segments.
+//!
+//! Run with `cargo bench --bench parallel_search`.
+
+use std::time::{Duration, Instant};
+
+use tantivy::collector::TopDocs;
+use tantivy::query::QueryParser;
+use tantivy::Index;
+use tantivy_basics::ingest::segmenting_writer;
+use tantivy_basics::schema::{register_sample_tokenizers, sample_schema};
+use tantivy_basics::search::search_parallel;
+use tantivy_basics::test_support::generate_corpus;
+
+const NUM_SEGMENTS: usize = 32;
+const DOCS_PER_SEGMENT: usize = 20_000;
+const SEED: u64 = 42;
+const RUNS: u32 = 20;
+
+fn main() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let title = schema.get_field("title")?;
+    let index = Index::create_in_ram(schema);
+    register_sample_tokenizers(&index);
+
+    // One commit per segment, and no merges, to keep the index fragmented.
+    let mut index_writer = segmenting_writer(&index)?;
+    let corpus = generate_corpus(NUM_SEGMENTS * DOCS_PER_SEGMENT, SEED);
+    for segment in corpus.chunks(DOCS_PER_SEGMENT) {
+        for doc in segment {
+            index_writer.add_document(doc.clone())?;
+        }
+        index_writer.commit()?;
+    }
+
+    let searcher = index.reader()?.searcher();
+    let query = QueryParser::for_index(&index, vec![title]).parse_query("sea OR men OR north")?;
+
+    assert_eq!(
+        search_parallel(&searcher, &query, 10)?,
+        searcher.search(&query, &TopDocs::with_limit(10))?
+    );
+
+    let sequential = time(|| {
+        searcher.search(&query, &TopDocs::with_limit(10))?;
+        Ok(())
+    })?;
+    let parallel = time(|| {
+        search_parallel(&searcher, &query, 10)?;
+        Ok(())
+    })?;
+
+    println!(
+        "{} segments of {DOCS_PER_SEGMENT} documents, mean of {RUNS} runs",
+        searcher.segment_readers().len()
+    );
+    println!("sequential: {:>8.3} ms", millis(sequential));
+    println!("parallel:   {:>8.3} ms", millis(parallel));
+    Ok(())
+}
+
+/// Mean duration of `RUNS` calls of `run`, after a warm-up call.
+fn time(mut run: impl FnMut() -> tantivy::Result<()>) -> tantivy::Result<Duration> {
+    run()?;
+    let started_at = Instant::now();
+    for _ in 0..RUNS {
+        run()?;
+    }
+    Ok(started_at.elapsed() / RUNS)
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
@@ -0,0 +1,74 @@
+//! Compares `search_summary` with running its three collectors one by one.
+//!
+//! Run with `cargo bench --bench single_pass`.
+
+use std::time::{Duration, Instant};
+
+use tantivy::collector::{Count, FacetCollector, TopDocs};
+use tantivy::query::QueryParser;
+use tantivy::schema::Facet;
+use tantivy::Index;
+use tantivy_basics::schema::{register_sample_tokenizers, sample_schema};
+use tantivy_basics::search::search_summary;
+use tantivy_basics::test_support::generate_corpus;
+use tantivy_basics::DEFAULT_HEAP_SIZE;
+
+const NUM_DOCS: usize = 200_000;
+const SEED: u64 = 42;
+const RUNS: u32 = 20;
+const CATEGORIES: [&str; 4] = [
+    "/fiction/classics",
+    "/fiction/horror",
+    "/non-fiction/travel",
+    "/non-fiction/history",
+];
+
+fn main() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let title = schema.get_field("title")?;
+    let category = schema.get_field("category")?;
+    let index = Index::create_in_ram(schema);
+    register_sample_tokenizers(&index);
+
+    let mut index_writer = index.writer(DEFAULT_HEAP_SIZE)?;
+    for (i, mut doc) in generate_corpus(NUM_DOCS, SEED).into_iter().enumerate() {
+        doc.add_facet(category, Facet::from(CATEGORIES[i % CATEGORIES.len()]));
+        index_writer.add_document(doc)?;
+    }
+    index_writer.commit()?;
+
+    let searcher = index.reader()?.searcher();
+    let query = QueryParser::for_index(&index, vec![title]).parse_query("sea OR men")?;
+
+    let single_pass = time(|| {
+        search_summary(&searcher, &query, 10, category, &["/"])?;
+        Ok(())
+    })?;
+    let multi_pass = time(|| {
+        searcher.search(&query, &TopDocs::with_limit(10))?;
+        searcher.search(&query, &Count)?;
+        let mut facet_collector = FacetCollector::for_field("category");
+        facet_collector.add_facet("/");
+        searcher.search(&query, &facet_collector)?;
+        Ok(())
+    })?;
+
+    println!("{NUM_DOCS} documents, mean of {RUNS} runs");
+    println!("single pass: {:>8.3} ms", millis(single_pass));
+    println!("multi pass:  {:>8.3} ms", millis(multi_pass));
+    Ok(())
+}
+
+/// Mean duration of `RUNS` calls of `run`, after a warm-up call.
+fn time(mut run: impl FnMut() -> tantivy::Result<()>) -> tantivy::Result<Duration> {
+    run()?;
+    let started_at = Instant::now();
+    for _ in 0..RUNS {
+        run()?;
+    }
+    Ok(started_at.elapsed() / RUNS)
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
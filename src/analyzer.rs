@@ -0,0 +1,340 @@
+//! Named tokenizer chains, described in configuration.
+//!
+//! A chain is a base tokenizer followed by token filters, e.g.
+//!
+//! ```toml
+//! [tokenizers.stemmed]
+//! tokenizer = "simple"
+//! filters = ["lowercase", "stem"]
+//! max_token_length = 64
+//! ```
+//!
+//! `hyphens = "keep"` makes the simple tokenizer keep words joined by
+//! hyphens, like `state-of-the-art`, as one token, and `hyphens = "both"`
+//! adds the parts too, so `state` and `art` find the compound as well.
+//!
+//! The `soundex` filter indexes each word by how it sounds, for name
+//! search where `Smyth` should find `Smith`. Like every filter, it only
+//! applies to the fields whose `tokenizer` is a chain that has it.
+//!
+//! [`RAW_LOWERCASE`] is always available, without being configured.
+
+use serde::Deserialize;
+use tantivy::tokenizer::{
+    AsciiFoldingFilter, LowerCaser, RawTokenizer, RemoveLongFilter, SimpleTokenizer, Stemmer,
+    TextAnalyzer, TextAnalyzerBuilder, Token, TokenFilter, TokenStream, Tokenizer,
+    WhitespaceTokenizer,
+};
+
+/// The tokenizer that splits text before any filter runs.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BaseTokenizer {
+    /// Splits on anything that is not alphanumeric.
+    #[default]
+    Simple,
+    /// Splits on whitespace only.
+    Whitespace,
+    /// Keeps the whole value as a single token.
+    Raw,
+}
+
+/// What the simple tokenizer does with words joined by hyphens.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Hyphens {
+    /// `state-of-the-art` is the four tokens `state`, `of`, `the` and `art`.
+    #[default]
+    Split,
+    /// `state-of-the-art` is the single token `state-of-the-art`.
+    Keep,
+    /// `state-of-the-art` is the compound and, at the same positions,
+    /// its four parts.
+    Both,
+}
+
+/// A filter applied to every token, in the order given.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenFilterConfig {
+    Lowercase,
+    AsciiFolding,
+    /// English stemming.
+    Stem,
+    /// Replaces each word by its [Soundex](https://en.wikipedia.org/wiki/Soundex)
+    /// code, `smith` and `smyth` both becoming `s530`.
+    Soundex,
+}
+
+/// A tokenizer chain as written in the configuration.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+pub struct TokenizerConfig {
+    #[serde(default)]
+    pub tokenizer: BaseTokenizer,
+    #[serde(default)]
+    pub filters: Vec<TokenFilterConfig>,
+    /// Only changes the `simple` tokenizer: the others split on whitespace
+    /// or not at all, so they already keep hyphenated words.
+    #[serde(default)]
+    pub hyphens: Hyphens,
+    /// Tokens longer than this many bytes, like base64 blobs, are dropped
+    /// instead of bloating the term dictionary.
+    pub max_token_length: Option<usize>,
+}
+
+impl TokenizerConfig {
+    /// Builds the `TextAnalyzer` to register in the index's tokenizer manager.
+    pub fn build(&self) -> TextAnalyzer {
+        let mut builder: TextAnalyzerBuilder = match self.tokenizer {
+            BaseTokenizer::Simple if self.hyphens != Hyphens::Split => {
+                TextAnalyzer::builder(HyphenatedTokenizer::new(self.hyphens)).dynamic()
+            }
+            BaseTokenizer::Simple => TextAnalyzer::builder(SimpleTokenizer::default()).dynamic(),
+            BaseTokenizer::Whitespace => {
+                TextAnalyzer::builder(WhitespaceTokenizer::default()).dynamic()
+            }
+            BaseTokenizer::Raw => TextAnalyzer::builder(RawTokenizer::default()).dynamic(),
+        };
+        for filter in &self.filters {
+            builder = match filter {
+                TokenFilterConfig::Lowercase => builder.filter_dynamic(LowerCaser),
+                TokenFilterConfig::AsciiFolding => builder.filter_dynamic(AsciiFoldingFilter),
+                TokenFilterConfig::Stem => builder.filter_dynamic(Stemmer::default()),
+                TokenFilterConfig::Soundex => builder.filter_dynamic(Soundex),
+            };
+        }
+        if let Some(max_token_length) = self.max_token_length {
+            builder = builder.filter_dynamic(RemoveLongFilter::limit(max_token_length + 1));
+        }
+        builder.build()
+    }
+}
+
+/// Name of a chain that keeps the whole value as one token, lowercased.
+///
+/// It makes untokenized fields like tags match regardless of case:
+/// `NewYork` and `newyork` are the same term, `New York` stays one term.
+pub const RAW_LOWERCASE: &str = "raw_lowercase";
+
+/// Builds the [`RAW_LOWERCASE`] chain.
+pub fn raw_lowercase() -> TextAnalyzer {
+    TokenizerConfig {
+        tokenizer: BaseTokenizer::Raw,
+        filters: vec![TokenFilterConfig::Lowercase],
+        hyphens: Hyphens::Split,
+        max_token_length: None,
+    }
+    .build()
+}
+
+/// Builds a chain like tantivy's `default` tokenizer, keeping tokens up to
+/// `max_token_length` bytes instead of the built-in 39.
+pub fn default_with_max_token_length(max_token_length: usize) -> TextAnalyzer {
+    TokenizerConfig {
+        tokenizer: BaseTokenizer::Simple,
+        filters: vec![TokenFilterConfig::Lowercase],
+        hyphens: Hyphens::Split,
+        max_token_length: Some(max_token_length),
+    }
+    .build()
+}
+
+/// Splits like `SimpleTokenizer`, but handles hyphenated words as `hyphens` says.
+#[derive(Clone)]
+struct HyphenatedTokenizer {
+    hyphens: Hyphens,
+    tokens: Vec<Token>,
+}
+
+impl HyphenatedTokenizer {
+    fn new(hyphens: Hyphens) -> HyphenatedTokenizer {
+        HyphenatedTokenizer {
+            hyphens,
+            tokens: Vec::new(),
+        }
+    }
+}
+
+impl Tokenizer for HyphenatedTokenizer {
+    type TokenStream<'a> = HyphenatedTokenStream<'a>;
+
+    fn token_stream<'a>(&'a mut self, text: &'a str) -> HyphenatedTokenStream<'a> {
+        self.tokens.clear();
+        let mut position = 0;
+        let mut words = words(text).peekable();
+        while let Some(first) = words.next() {
+            let mut compound = vec![first];
+            while let Some(&next) = words.peek() {
+                let last = compound[compound.len() - 1];
+                if &text[last.1..next.0] != "-" {
+                    break;
+                }
+                compound.push(next);
+                words.next();
+            }
+            let (from, to) = (compound[0].0, compound[compound.len() - 1].1);
+            if compound.len() == 1 || self.hyphens == Hyphens::Keep {
+                self.tokens.push(token(text, from, to, position, 1));
+                position += 1;
+                continue;
+            }
+            self.tokens
+                .push(token(text, from, to, position, compound.len()));
+            for (from, to) in compound {
+                self.tokens.push(token(text, from, to, position, 1));
+                position += 1;
+            }
+        }
+        HyphenatedTokenStream {
+            tokens: &mut self.tokens,
+            current: None,
+        }
+    }
+}
+
+/// The byte ranges of the runs of alphanumeric characters of `text`.
+fn words(text: &str) -> impl Iterator<Item = (usize, usize)> + '_ {
+    let mut chars = text.char_indices().peekable();
+    std::iter::from_fn(move || {
+        let (from, _) = chars.find(|(_, c)| c.is_alphanumeric())?;
+        let mut to = text.len();
+        while let Some(&(offset, c)) = chars.peek() {
+            if !c.is_alphanumeric() {
+                to = offset;
+                break;
+            }
+            chars.next();
+        }
+        Some((from, to))
+    })
+}
+
+fn token(text: &str, from: usize, to: usize, position: usize, position_length: usize) -> Token {
+    Token {
+        offset_from: from,
+        offset_to: to,
+        position,
+        text: text[from..to].to_string(),
+        position_length,
+    }
+}
+
+struct HyphenatedTokenStream<'a> {
+    tokens: &'a mut Vec<Token>,
+    current: Option<usize>,
+}
+
+impl TokenStream for HyphenatedTokenStream<'_> {
+    fn advance(&mut self) -> bool {
+        let next = self.current.map_or(0, |current| current + 1);
+        self.current = Some(next);
+        next < self.tokens.len()
+    }
+
+    fn token(&self) -> &Token {
+        &self.tokens[self.current.unwrap_or(0)]
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        &mut self.tokens[self.current.unwrap_or(0)]
+    }
+}
+
+/// The token filter behind [`TokenFilterConfig::Soundex`].
+///
+/// Tokens that don't start with an ASCII letter, like numbers, are kept as
+/// they are, and the letters that are not ASCII ignored.
+#[derive(Clone)]
+struct Soundex;
+
+impl TokenFilter for Soundex {
+    type Tokenizer<T: Tokenizer> = SoundexFilter<T>;
+
+    fn transform<T: Tokenizer>(self, tokenizer: T) -> SoundexFilter<T> {
+        SoundexFilter { tokenizer }
+    }
+}
+
+#[derive(Clone)]
+struct SoundexFilter<T> {
+    tokenizer: T,
+}
+
+impl<T: Tokenizer> Tokenizer for SoundexFilter<T> {
+    type TokenStream<'a> = SoundexTokenStream<T::TokenStream<'a>>;
+
+    fn token_stream<'a>(&'a mut self, text: &'a str) -> Self::TokenStream<'a> {
+        SoundexTokenStream {
+            tail: self.tokenizer.token_stream(text),
+        }
+    }
+}
+
+struct SoundexTokenStream<T> {
+    tail: T,
+}
+
+impl<T: TokenStream> TokenStream for SoundexTokenStream<T> {
+    fn advance(&mut self) -> bool {
+        if !self.tail.advance() {
+            return false;
+        }
+        if let Some(code) = soundex(&self.tail.token().text) {
+            self.tail.token_mut().text = code;
+        }
+        true
+    }
+
+    fn token(&self) -> &Token {
+        self.tail.token()
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        self.tail.token_mut()
+    }
+}
+
+/// The lowercase Soundex code of `word`: its first letter and the digits
+/// of the next three consonant sounds, `None` if it doesn't start with a
+/// letter.
+fn soundex(word: &str) -> Option<String> {
+    fn digit(letter: u8) -> Option<u8> {
+        Some(match letter {
+            b'b' | b'f' | b'p' | b'v' => b'1',
+            b'c' | b'g' | b'j' | b'k' | b'q' | b's' | b'x' | b'z' => b'2',
+            b'd' | b't' => b'3',
+            b'l' => b'4',
+            b'm' | b'n' => b'5',
+            b'r' => b'6',
+            _ => return None,
+        })
+    }
+
+    if !word.bytes().next()?.is_ascii_alphabetic() {
+        return None;
+    }
+    let mut letters = word
+        .bytes()
+        .filter(u8::is_ascii_alphabetic)
+        .map(|letter| letter.to_ascii_lowercase());
+    let first = letters.next()?;
+    let mut code = vec![first];
+    let mut previous = digit(first);
+    for letter in letters {
+        if code.len() == 4 {
+            break;
+        }
+        match digit(letter) {
+            // `h` and `w` don't separate two consonants with the same digit.
+            None if matches!(letter, b'h' | b'w') => {}
+            None => previous = None,
+            Some(digit) if previous == Some(digit) => {}
+            Some(digit) => {
+                code.push(digit);
+                previous = Some(digit);
+            }
+        }
+    }
+    code.resize(4, b'0');
+    String::from_utf8(code).ok()
+}
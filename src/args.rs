@@ -0,0 +1,145 @@
+//! Command line arguments.
+//!
+//! Arguments are few and simple, so they are parsed by hand:
+//! an optional subcommand followed by `--flag` or `--flag value` pairs.
+//! The common ones can be set once in a `--config` file, see [`CliConfig`].
+
+use std::collections::HashMap;
+use std::fs;
+use std::str::FromStr;
+
+use serde::Deserialize;
+use tantivy::TantivyError;
+
+/// The parsed command line.
+pub struct Args {
+    command: Option<String>,
+    rest: Vec<String>,
+    /// Values of the flags not given on the command line, from `--config`.
+    defaults: HashMap<String, String>,
+}
+
+/// Options read from a `--config` TOML file, each one the default of a flag:
+///
+/// ```toml
+/// path = "/var/lib/books"          # --path
+/// schema = "schema.toml"           # --schema
+/// heap_size = 100_000_000          # --heap-size
+/// threads = 4                      # --threads
+/// search_fields = ["title", "body"] # --fields
+/// output = "json"                  # --output
+/// ```
+///
+/// Flags given on the command line win. Relative paths are relative to the
+/// working directory, not to the config file.
+#[derive(Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct CliConfig {
+    pub path: Option<String>,
+    pub schema: Option<String>,
+    pub heap_size: Option<usize>,
+    pub threads: Option<usize>,
+    pub search_fields: Option<Vec<String>>,
+    pub output: Option<String>,
+}
+
+impl CliConfig {
+    pub fn from_toml(content: &str) -> tantivy::Result<CliConfig> {
+        toml::from_str(content)
+            .map_err(|err| TantivyError::InvalidArgument(format!("invalid config: {err}")))
+    }
+
+    /// The flags this config gives a value to, with their value.
+    fn flags(&self) -> HashMap<String, String> {
+        let flags = [
+            ("--path", self.path.clone()),
+            ("--schema", self.schema.clone()),
+            ("--heap-size", self.heap_size.map(|size| size.to_string())),
+            ("--threads", self.threads.map(|threads| threads.to_string())),
+            (
+                "--fields",
+                self.search_fields.as_ref().map(|fields| fields.join(",")),
+            ),
+            ("--output", self.output.clone()),
+        ];
+        flags
+            .into_iter()
+            .filter_map(|(flag, value)| Some((flag.to_string(), value?)))
+            .collect()
+    }
+}
+
+impl Args {
+    /// Parses the arguments of the current process.
+    pub fn from_env() -> Args {
+        Args::new(std::env::args().skip(1))
+    }
+
+    /// Parses `args`, which don't include the program name.
+    ///
+    /// ```
+    /// use tantivy_basics::args::Args;
+    ///
+    /// let args = Args::new(["search", "--query", "sea"].map(String::from));
+    /// assert_eq!(args.command(), Some("search"));
+    /// assert_eq!(args.value("--query"), Some("sea"));
+    /// ```
+    pub fn new(args: impl IntoIterator<Item = String>) -> Args {
+        let mut rest: Vec<String> = args.into_iter().collect();
+        let command = match rest.first() {
+            Some(first) if !first.starts_with("--") => Some(rest.remove(0)),
+            _ => None,
+        };
+        Args {
+            command,
+            rest,
+            defaults: HashMap::new(),
+        }
+    }
+
+    /// Takes the value of the flags that are not given from the `--config`
+    /// file, if there is one.
+    pub fn load_config(&mut self) -> tantivy::Result<()> {
+        if let Some(path) = self.value("--config") {
+            let config = CliConfig::from_toml(&fs::read_to_string(path)?)?;
+            self.defaults = config.flags();
+        }
+        Ok(())
+    }
+
+    /// The subcommand, if one was given.
+    pub fn command(&self) -> Option<&str> {
+        self.command.as_deref()
+    }
+
+    /// Returns the value following `name`, if any, or the one from `--config`.
+    pub fn value(&self, name: &str) -> Option<&str> {
+        let mut rest = self.rest.iter();
+        rest.find(|arg| *arg == name)
+            .and_then(|_| rest.next())
+            .or_else(|| self.defaults.get(name))
+            .map(String::as_str)
+    }
+
+    /// Like [`Args::value`], but the flag is mandatory.
+    pub fn required(&self, name: &str) -> tantivy::Result<&str> {
+        self.value(name)
+            .ok_or_else(|| TantivyError::InvalidArgument(format!("missing `{name} <value>`")))
+    }
+
+    /// Parses the value following `name`, if any.
+    pub fn parsed<T: FromStr>(&self, name: &str) -> tantivy::Result<Option<T>> {
+        self.value(name)
+            .map(|value| {
+                value.parse().map_err(|_| {
+                    TantivyError::InvalidArgument(format!("invalid value `{value}` for `{name}`"))
+                })
+            })
+            .transpose()
+    }
+
+    /// Whether the switch `name` was given.
+    pub fn flag(&self, name: &str) -> bool {
+        self.rest.iter().any(|arg| arg == name)
+    }
+}
@@ -0,0 +1,137 @@
+//! Caching the results of repeated queries.
+//!
+//! A read-heavy service tends to see the same queries over and over.
+//! [`QueryCache`] remembers the top documents of recent queries so they
+//! don't have to be searched again.
+//!
+//! Caching only makes sense for read-only searchers: a cached result is a
+//! snapshot of one searcher generation. The cache notices when it is handed
+//! a searcher from a newer generation and drops everything, and
+//! [`QueryCache::reload`] reloads the reader and clears the cache in one go.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use tantivy::{DocAddress, IndexReader, Score, Searcher};
+
+/// Hit and miss counters of a [`QueryCache`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+struct CacheEntry {
+    top_docs: Vec<(Score, DocAddress)>,
+    inserted_at: Instant,
+    last_used: u64,
+}
+
+/// An LRU cache of top documents keyed by the normalized query string.
+///
+/// Entries expire after `ttl`, and the least recently used one is evicted
+/// once `capacity` is reached.
+pub struct QueryCache {
+    capacity: usize,
+    ttl: Duration,
+    entries: HashMap<(String, usize), CacheEntry>,
+    generation_id: Option<u64>,
+    clock: u64,
+    stats: CacheStats,
+}
+
+impl QueryCache {
+    /// Creates an empty cache holding at most `capacity` queries for `ttl` each.
+    pub fn new(capacity: usize, ttl: Duration) -> QueryCache {
+        QueryCache {
+            capacity,
+            ttl,
+            entries: HashMap::new(),
+            generation_id: None,
+            clock: 0,
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// Returns the cached top `limit` documents for `query_str`,
+    /// or runs `search` and caches what it returns.
+    pub fn get_or_search<F>(
+        &mut self,
+        searcher: &Searcher,
+        query_str: &str,
+        limit: usize,
+        search: F,
+    ) -> tantivy::Result<Vec<(Score, DocAddress)>>
+    where
+        F: FnOnce() -> tantivy::Result<Vec<(Score, DocAddress)>>,
+    {
+        let generation_id = searcher.generation().generation_id();
+        if self.generation_id != Some(generation_id) {
+            self.invalidate();
+            self.generation_id = Some(generation_id);
+        }
+
+        self.clock += 1;
+        let key = (normalize(query_str), limit);
+        if let Some(entry) = self.entries.get_mut(&key) {
+            if entry.inserted_at.elapsed() < self.ttl {
+                entry.last_used = self.clock;
+                self.stats.hits += 1;
+                return Ok(entry.top_docs.clone());
+            }
+            self.entries.remove(&key);
+        }
+
+        self.stats.misses += 1;
+        let top_docs = search()?;
+        if self.capacity > 0 {
+            if self.entries.len() >= self.capacity {
+                self.evict_least_recently_used();
+            }
+            self.entries.insert(
+                key,
+                CacheEntry {
+                    top_docs: top_docs.clone(),
+                    inserted_at: Instant::now(),
+                    last_used: self.clock,
+                },
+            );
+        }
+        Ok(top_docs)
+    }
+
+    /// Reloads `reader` and forgets every cached result.
+    pub fn reload(&mut self, reader: &IndexReader) -> tantivy::Result<()> {
+        reader.reload()?;
+        self.invalidate();
+        Ok(())
+    }
+
+    /// Forgets every cached result. The stats are kept.
+    pub fn invalidate(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Hits and misses since the cache was created.
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    fn evict_least_recently_used(&mut self) {
+        let oldest = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(key, _)| key.clone());
+        if let Some(key) = oldest {
+            self.entries.remove(&key);
+        }
+    }
+}
+
+/// Collapses runs of whitespace so `"sea  whale "` and `"sea whale"` share an entry.
+///
+/// Case is left alone: `AND`/`OR` and field names are case sensitive to the parser.
+fn normalize(query_str: &str) -> String {
+    query_str.split_whitespace().collect::<Vec<_>>().join(" ")
+}
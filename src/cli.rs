@@ -0,0 +1,879 @@
+//! Command line handling.
+//!
+//! The arguments are parsed by [`Args`], which takes the defaults of the
+//! common flags from a `--config` file.
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+use tantivy::directory::MmapDirectory;
+use tantivy::query::{Query, QueryParser};
+use tantivy::schema::{Document, Field, Schema};
+use tantivy::{Index, IndexWriter, TantivyError};
+use tantivy_basics::args::Args;
+use tantivy_basics::collector::{
+    CappedCountCollector, ScoreHistogramCollector, SegmentHitsCollector,
+};
+use tantivy_basics::concurrent::ConcurrentIndex;
+use tantivy_basics::document::{truncate_stored, truncate_to_fit};
+use tantivy_basics::explain::{render_explanation, render_plan, search_explain_json};
+use tantivy_basics::export::{diff_indexes, export_ndjson, import_ndjson};
+use tantivy_basics::highlight::Highlighter;
+use tantivy_basics::ingest::{
+    ingest_in_segments, ingest_ndjson, ingest_stamped, open_input, segmenting_writer,
+    InterruptibleInput, Shutdown,
+};
+use tantivy_basics::maintenance;
+use tantivy_basics::metrics::Metrics;
+use tantivy_basics::query::{
+    fuzzy_query, parse_lenient, remove_stop_words, resolve_field_aliases, validate_query,
+    with_exact_match_boost, FieldBoosts, FuzzyDistance, QueryLimits, EXACT_MATCH_BOOST,
+};
+use tantivy_basics::rerank::{Reranker, VerbatimBoost};
+use tantivy_basics::schema::{
+    register_sample_tokenizers, sample_schema, sample_search_fields, SchemaConfig,
+};
+use tantivy_basics::scoring::{self, Scoring};
+use tantivy_basics::search::{
+    changes_since, count_by_field, estimate_hits, exact_term, search_with_fuzzy_fallback, SearchHit,
+};
+use tantivy_basics::server::SearchServer;
+use tantivy_basics::suggest::correct_query;
+use tantivy_basics::synonyms::SynonymMap;
+use tantivy_basics::warmup::warm_cache;
+use tantivy_basics::watch::FileWatcher;
+use tantivy_basics::DEFAULT_HEAP_SIZE;
+use tantivy_basics::{template, terms};
+use tracing::{info, info_span, warn, Level};
+
+/// Sends logs at `--log-level` (`warn` by default) and above to stderr.
+pub fn init_logging(args: &Args) -> tantivy::Result<()> {
+    let level: Level = args.parsed("--log-level")?.unwrap_or(Level::WARN);
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(io::stderr)
+        .init();
+    Ok(())
+}
+
+/// Loads the `--schema <schema.toml>` config, if one was given.
+fn schema_config(args: &Args) -> tantivy::Result<Option<SchemaConfig>> {
+    args.value("--schema")
+        .map(|path| SchemaConfig::load(Path::new(path)))
+        .transpose()
+}
+
+/// Opens the index at `--path`, creating it if needed.
+///
+/// The schema comes from the `--schema` config when given,
+/// and is the sample schema otherwise.
+fn open_index(args: &Args) -> tantivy::Result<Index> {
+    let path = args.required("--path")?;
+    let _span = info_span!("open_index", path).entered();
+    fs::create_dir_all(path)?;
+    if !Index::exists(&MmapDirectory::open(path)?)? {
+        info!("creating index");
+    }
+    match schema_config(args)? {
+        Some(config) => {
+            let index = maintenance::open_or_create(Path::new(path), config.build()?)?;
+            config.register_tokenizers(&index)?;
+            Ok(index)
+        }
+        None => {
+            let index = maintenance::open_or_create(Path::new(path), sample_schema())?;
+            register_sample_tokenizers(&index);
+            Ok(index)
+        }
+    }
+}
+
+/// Opens the existing index at `--path`.
+///
+/// The `--schema` config used to create it must be passed again
+/// so its tokenizers get registered.
+fn open_existing_index(args: &Args) -> tantivy::Result<Index> {
+    let index = Index::open_in_dir(args.required("--path")?)?;
+    match schema_config(args)? {
+        Some(config) => config.register_tokenizers(&index)?,
+        None => register_sample_tokenizers(&index),
+    }
+    Ok(index)
+}
+
+/// The fields a bareword query searches: the comma-separated `--fields`,
+/// or as the `--schema` config or the sample schema defines them.
+fn default_search_fields(args: &Args, schema: &Schema) -> tantivy::Result<Vec<Field>> {
+    if let Some(fields) = args.value("--fields") {
+        return fields
+            .split(',')
+            .map(|name| schema.get_field(name.trim()))
+            .collect();
+    }
+    Ok(match schema_config(args)? {
+        Some(config) => config.default_search_fields(schema),
+        None => sample_search_fields(schema),
+    })
+}
+
+/// A writer with `--heap-size` bytes of memory, `DEFAULT_HEAP_SIZE` by
+/// default, and `--threads` indexing threads, as many as tantivy picks by default.
+fn index_writer(args: &Args, index: &Index) -> tantivy::Result<IndexWriter> {
+    let heap_size = args.parsed("--heap-size")?.unwrap_or(DEFAULT_HEAP_SIZE);
+    match args.parsed("--threads")? {
+        Some(threads) => index.writer_with_num_threads(threads, heap_size),
+        None => index.writer(heap_size),
+    }
+}
+
+/// `index --path <dir> [--schema <schema.toml>] (--file <docs.ndjson> | --stdin)
+/// [--docs-per-segment <n>] [--heap-size <bytes>] [--threads <n>] [--fail-on-warning]
+/// [--finalize]`
+///
+/// Indexes NDJSON documents and commits once at the end,
+/// or every `n` documents so that each commit makes one segment.
+/// With `--file`, fails upfront if the disk is unlikely to fit the index.
+/// A `--file` ending in `.gz` is gunzipped while it is read.
+/// On SIGINT or SIGTERM, reading stops and what was read is committed.
+/// Unless `--docs-per-segment` is given, documents are stamped in the
+/// `opstamp` field, if the schema has one, for the `changes` command.
+/// With `--fail-on-warning`, any malformed line fails the load, after
+/// listing the lines, and nothing is committed, but for the segments
+/// `--docs-per-segment` already committed.
+/// With `--finalize`, the index is then merged into a single segment for
+/// the fastest searches, see `maintenance::finalize`.
+pub fn index(args: &Args) -> tantivy::Result<()> {
+    let _span = info_span!("index").entered();
+    let index = open_index(args)?;
+    let schema = index.schema();
+    let input: Box<dyn BufRead + Send> = if args.flag("--stdin") {
+        Box::new(BufReader::new(io::stdin()))
+    } else {
+        let path = Path::new(args.required("--file")?);
+        // The estimate only depends on the total size of the input,
+        // which is the compressed size for a `.gz` file.
+        let estimate = maintenance::estimate_index_size(1, fs::metadata(path)?.len());
+        maintenance::check_disk_space(Path::new(args.required("--path")?), estimate)?;
+        open_input(path)?
+    };
+    let shutdown = Shutdown::on_signal()?;
+    let input = InterruptibleInput::new(input, shutdown.clone());
+
+    let docs_per_segment: Option<u64> = args.parsed("--docs-per-segment")?;
+    let (mut index_writer, report) = match docs_per_segment {
+        Some(docs_per_segment) => {
+            let mut index_writer = segmenting_writer(&index)?;
+            let report = ingest_in_segments(&mut index_writer, &schema, input, docs_per_segment)?;
+            (index_writer, report)
+        }
+        None => {
+            let mut index_writer = index_writer(args, &index)?;
+            let report = match schema.get_field("opstamp") {
+                Ok(opstamp_field) => {
+                    ingest_stamped(&mut index_writer, &schema, input, opstamp_field)?
+                }
+                Err(_) => ingest_ndjson(&mut index_writer, &schema, input)?,
+            };
+            (index_writer, report)
+        }
+    };
+    if args.flag("--fail-on-warning") {
+        report.fail_on_skipped()?;
+    }
+    if shutdown.is_requested() {
+        warn!("stopped reading on shutdown, committing the documents read so far");
+    }
+    let opstamp = index_writer.commit()?;
+    // The full segments were counted as `ingest_in_segments` committed them.
+    let uncommitted = match docs_per_segment {
+        Some(docs_per_segment) => report.indexed % docs_per_segment,
+        None => report.indexed,
+    };
+    Metrics::global().observe_commit(uncommitted);
+    info!(
+        docs = report.indexed,
+        skipped = report.skipped,
+        opstamp,
+        "committed"
+    );
+
+    println!(
+        "indexed {} documents ({} skipped), opstamp {opstamp}",
+        report.indexed, report.skipped
+    );
+    if args.flag("--finalize") {
+        let reader = index.reader()?;
+        let compaction = maintenance::finalize(index_writer, &reader)?;
+        println!(
+            "finalized {} segments into {}, {} documents",
+            compaction.segments_before,
+            compaction.segments_after,
+            reader.searcher().num_docs()
+        );
+    }
+    Ok(())
+}
+
+/// `search --path <dir> --query <query> [options]`
+///
+/// Prints the top documents as `score<TAB>json`. Options:
+///
+/// - `--limit <n>`: number of results, 10 by default.
+/// - `--fields <field,...>`: the fields bareword queries search, instead of
+///   the default ones of the schema.
+/// - `--max-query-chars <n>`, `--max-query-terms <n>`: rejects longer queries,
+///   see `QueryLimits` for the defaults.
+/// - `--boost <field:weight,...>`: weights the matches in each field,
+///   e.g. `title:3,body:1`, instead of the `boost` of the `--schema` config.
+/// - `--schema <schema.toml>`: the config the index was created with, whose
+///   `[aliases]` are rewritten into the fields they stand for.
+/// - `--stop-words <word,...>`: ignores these words in the query, see
+///   `remove_stop_words`.
+/// - `--scoring <bm25|tfidf>`: the scoring model.
+/// - `--min-score <s>`: drops the results scoring under `s`.
+/// - `--plan`: prints how the query was parsed, its clauses, terms and
+///   boosts, instead of running it.
+/// - `--lenient`: never fails on the query syntax, see `parse_lenient`.
+/// - `--boost-verbatim <field>`: doubles the score of the hits whose stored
+///   field contains the query string as is, then reorders them.
+/// - `--boost-exact <field>`: adds the whole query string as a phrase in
+///   the field, heavily boosted, so a title equal to the query ranks first,
+///   see `with_exact_match_boost`.
+/// - `--fuzzy <auto|n>`: matches the query words with up to `n` typos, without query syntax.
+/// - `--relevance-floor <s>`: when the best hit scores under `s`, also
+///   matches the query words with typos and merges the hits, see
+///   `search_with_fuzzy_fallback`. `--fallback-distance <auto|n>` sets how
+///   many typos, `auto` by default.
+/// - `--autocorrect`: when nothing matches, retries once with the misspelled
+///   words corrected, and says so.
+/// - `--synonyms <file>`: expands the query terms, see `tantivy_basics::synonyms`.
+/// - `--template "{score:.2} — {title}"`: prints each hit through a template,
+///   see `tantivy_basics::template`.
+/// - `--max-result-bytes <n>`: cuts the longest stored strings of each hit,
+///   marked with `…`, until what is printed for the hit, its score,
+///   highlight and explanation included, takes at most `n` bytes, see
+///   `truncate_stored`. Highlights still come from the whole document.
+/// - `--highlight <field>`: adds the best fragment of the field, matches in `<b>`,
+///   and in JSON the number of matches in the whole field.
+///   Fields that are not stored are rebuilt from the index, which is slow.
+/// - `--explain`: adds how each score was computed.
+/// - `--approx-count <cap>`: counts the matches, stopping past `cap`, so a
+///   huge result set shows as `1000+`. In JSON, `count_exact` tells which.
+/// - `--output json`: prints a single JSON object instead, with the hits and
+///   the number of segments searched. Each hit tells its segment in `_segment`.
+/// - `--profile`: reports the search latency and the hits found in each segment,
+///   to correlate latency with segment fragmentation.
+/// - `--histogram`: draws the BM25 score distribution of every match,
+///   in `--buckets` ranges (10 by default) between 0 and the best score.
+///
+/// Warns when `estimate_hits` says the query may match nearly the whole index.
+pub fn search(args: &Args) -> tantivy::Result<()> {
+    let index = open_existing_index(args)?;
+    let schema = index.schema();
+    let searcher = index.reader()?.searcher();
+    let search_fields = default_search_fields(args, &schema)?;
+    // The boosts of the query come after those of the schema, to override them.
+    let mut boosts = schema_config(args)?
+        .map(|config| config.field_boosts())
+        .unwrap_or_default();
+    if let Some(FieldBoosts(query_boosts)) = args.parsed::<FieldBoosts>("--boost")? {
+        boosts.0.extend(query_boosts);
+    }
+    let query_parser = boosts.query_parser(&index, search_fields.clone())?;
+    let query_str = args.required("--query")?;
+    let _span = info_span!("search", query = query_str).entered();
+    let defaults = QueryLimits::default();
+    QueryLimits {
+        max_chars: args
+            .parsed("--max-query-chars")?
+            .unwrap_or(defaults.max_chars),
+        max_terms: args
+            .parsed("--max-query-terms")?
+            .unwrap_or(defaults.max_terms),
+    }
+    .check(query_str)?;
+    let resolved_query;
+    let query_str = match schema_config(args)? {
+        Some(config) if !config.aliases.is_empty() => {
+            resolved_query = resolve_field_aliases(query_str, &config.aliases);
+            resolved_query.as_str()
+        }
+        _ => query_str,
+    };
+    let stopped_query;
+    let query_str = match args.value("--stop-words") {
+        Some(stop_words) => {
+            let stop_words: Vec<&str> = stop_words.split(',').map(str::trim).collect();
+            stopped_query = remove_stop_words(query_str, &stop_words);
+            stopped_query.as_str()
+        }
+        None => query_str,
+    };
+    let fuzzy = args.parsed::<FuzzyDistance>("--fuzzy")?;
+    let lenient = args.flag("--lenient");
+    let synonyms = args
+        .value("--synonyms")
+        .map(|path| SynonymMap::load(Path::new(path)))
+        .transpose()?;
+    let build_query = |query_str: &str| -> tantivy::Result<Box<dyn Query>> {
+        let query = match fuzzy {
+            Some(distance) => fuzzy_query(&index, &search_fields, query_str, distance)?,
+            None if lenient => parse_lenient(&query_parser, query_str),
+            None => {
+                validate_query(&query_parser, query_str)?;
+                query_parser.parse_query(query_str)?
+            }
+        };
+        let query = match &synonyms {
+            Some(synonyms) => synonyms.expand(query),
+            None => query,
+        };
+        match args.value("--boost-exact") {
+            Some(name) => with_exact_match_boost(
+                &index,
+                query,
+                schema.get_field(name)?,
+                query_str,
+                EXACT_MATCH_BOOST,
+            ),
+            None => Ok(query),
+        }
+    };
+    let mut query = build_query(query_str)?;
+    if args.flag("--plan") {
+        print!("{}", render_plan(&schema, query.as_ref()));
+        return Ok(());
+    }
+    let num_docs = searcher.num_docs();
+    // Past 90% of the index, the query hardly narrows anything down.
+    // Queries without terms, like fuzzy or range ones, have no estimate.
+    if let Some(estimate) = estimate_hits(&searcher, query.as_ref())?
+        .filter(|&estimate| num_docs > 0 && estimate * 10 >= num_docs * 9)
+    {
+        warn!(
+            estimate,
+            num_docs, "the query may match nearly the whole index"
+        );
+    }
+
+    let limit = args.parsed("--limit")?.unwrap_or(10);
+    let max_result_bytes: Option<usize> = args.parsed("--max-result-bytes")?;
+    let scoring: Scoring = args.parsed("--scoring")?.unwrap_or_default();
+    let json_output = match args.value("--output").unwrap_or("text") {
+        "text" => false,
+        "json" => true,
+        other => {
+            return Err(TantivyError::InvalidArgument(format!(
+                "unknown output `{other}`, expected `text` or `json`"
+            )))
+        }
+    };
+    let relevance_floor = args.parsed("--relevance-floor")?;
+    if relevance_floor.is_some() && scoring != Scoring::Bm25 {
+        return Err(TantivyError::InvalidArgument(
+            "--relevance-floor is a BM25 score, it only works with --scoring bm25".to_string(),
+        ));
+    }
+    let fallback_distance = args
+        .parsed("--fallback-distance")?
+        .unwrap_or(FuzzyDistance::Auto);
+    let started_at = Instant::now();
+    let mut top_docs = match relevance_floor {
+        Some(floor) => search_with_fuzzy_fallback(
+            &searcher,
+            &query,
+            &search_fields,
+            query_str,
+            limit,
+            floor,
+            fallback_distance,
+        )?,
+        None => scoring::search(&searcher, &query, limit, scoring)?,
+    };
+    let mut corrected_query = None;
+    if top_docs.is_empty() && args.flag("--autocorrect") {
+        if let Some(corrected) = correct_query(&index, &searcher, &search_fields, query_str)? {
+            let retry = build_query(&corrected)?;
+            let retry_docs = scoring::search(&searcher, &retry, limit, scoring)?;
+            if !retry_docs.is_empty() {
+                query = retry;
+                top_docs = retry_docs;
+                corrected_query = Some(corrected);
+            }
+        }
+    }
+    let elapsed = started_at.elapsed();
+    Metrics::global().observe_query(elapsed);
+    info!(
+        hits = top_docs.len(),
+        latency_ms = elapsed.as_secs_f64() * 1000.0,
+        "searched"
+    );
+    if let Some(min_score) = args.parsed("--min-score")? {
+        let best_score = top_docs.first().map(|&(score, _)| score);
+        top_docs = scoring::filter_min_score(top_docs, min_score);
+        if let (Some(best_score), true) = (best_score, top_docs.is_empty()) {
+            warn!(
+                "--min-score {min_score} excluded every result, \
+                 the best one scored {best_score:.4}; try a lower threshold"
+            );
+        }
+    }
+    if let Some(field) = args.value("--boost-verbatim") {
+        let reranker = VerbatimBoost {
+            field: field.to_string(),
+            text: query_str.to_string(),
+            boost: 2.0,
+        };
+        let hits = top_docs
+            .iter()
+            .map(|&(score, doc_address)| SearchHit::fetch(&searcher, score, doc_address))
+            .collect::<tantivy::Result<Vec<_>>>()?;
+        top_docs = reranker
+            .rerank(hits)
+            .into_iter()
+            .map(|hit| (hit.score, hit.doc_address))
+            .collect();
+    }
+    let hit_count = args
+        .parsed("--approx-count")?
+        .map(|cap| searcher.search(&query, &CappedCountCollector::new(cap)))
+        .transpose()?;
+    let num_segments = searcher.segment_readers().len();
+    let segment_hits = if args.flag("--profile") {
+        Some(searcher.search(&query, &SegmentHitsCollector)?)
+    } else {
+        None
+    };
+    let highlighter = args
+        .value("--highlight")
+        .map(|field| {
+            let field = schema.get_field(field)?;
+            Highlighter::new(&searcher, &query, field, "<b>", "</b>")
+        })
+        .transpose()?;
+
+    if json_output {
+        let mut hits = Vec::with_capacity(top_docs.len());
+        for &(score, doc_address) in &top_docs {
+            let retrieved_doc = searcher.doc(doc_address)?;
+            let mut search_hit = SearchHit::new(&searcher, score, doc_address, &retrieved_doc)?;
+            let mut highlight = None;
+            if let (Some(highlighter), Some(field)) = (&highlighter, args.value("--highlight")) {
+                highlight = Some(highlighter.highlight(&searcher, doc_address, &retrieved_doc)?);
+                let match_count =
+                    highlighter.match_count(&searcher, doc_address, &retrieved_doc)?;
+                search_hit
+                    .match_counts
+                    .insert(field.to_string(), match_count);
+            }
+            let mut hit = json!({
+                "score": score,
+                "doc": {},
+                "_segment": search_hit.segment_json(),
+            });
+            if let Some(highlight) = highlight {
+                hit["highlight"] = json!(highlight);
+                hit["matches"] = json!(search_hit.match_counts);
+            }
+            if args.flag("--explain") {
+                hit["explanation"] = search_explain_json(&searcher, &query, doc_address)?;
+            }
+            if let Some(max_bytes) = max_result_bytes {
+                // The rest of the hit counts in the limit, only the document shrinks.
+                let rest = hit.to_string().len() - "{}".len();
+                let returned_doc =
+                    truncate_stored(&schema, &retrieved_doc, max_bytes.saturating_sub(rest));
+                search_hit = SearchHit::new(&searcher, score, doc_address, &returned_doc)?;
+            }
+            hit["doc"] = json!(search_hit.fields);
+            hits.push(hit);
+        }
+        let mut output = json!({ "hits": hits, "segments": num_segments });
+        if let Some(hit_count) = hit_count {
+            output["count"] = json!(hit_count.count);
+            output["count_exact"] = json!(hit_count.exact);
+        }
+        if let Some(corrected) = &corrected_query {
+            output["corrected_query"] = json!(corrected);
+        }
+        if let Some(segment_hits) = &segment_hits {
+            output["elapsed_ms"] = json!(elapsed.as_secs_f64() * 1000.0);
+            output["segment_hits"] = json!(segment_hits);
+        }
+        println!("{output}");
+    } else {
+        if let Some(corrected) = &corrected_query {
+            println!("Showing results for: {corrected}");
+        }
+        if let Some(hit_count) = hit_count {
+            println!("{hit_count} matches");
+        }
+        for &(score, doc_address) in &top_docs {
+            let retrieved_doc = searcher.doc(doc_address)?;
+            let highlight = highlighter
+                .as_ref()
+                .map(|highlighter| highlighter.highlight(&searcher, doc_address, &retrieved_doc))
+                .transpose()?;
+            let explanation = if args.flag("--explain") {
+                let explanation = search_explain_json(&searcher, &query, doc_address)?;
+                Some(render_explanation(&explanation))
+            } else {
+                None
+            };
+            let render = |doc: &Document| match args.value("--template") {
+                Some(format) => template::render(format, &schema, score, doc),
+                None => format!("{score:.4}\t{}", schema.to_json(doc)),
+            };
+            let returned_doc = match max_result_bytes {
+                Some(max_bytes) => {
+                    // Every line printed for the hit counts in the limit.
+                    let rest = highlight
+                        .as_ref()
+                        .map_or(0, |highlight| highlight.len() + 2)
+                        + explanation.as_ref().map_or(0, String::len);
+                    let max_bytes = max_bytes.saturating_sub(rest);
+                    truncate_to_fit(&retrieved_doc, max_bytes, |doc| render(doc).len() + 1)
+                }
+                None => retrieved_doc.clone(),
+            };
+            println!("{}", render(&returned_doc));
+            if let Some(highlight) = highlight {
+                println!("\t{highlight}");
+            }
+            if let Some(explanation) = explanation {
+                print!("{explanation}");
+            }
+        }
+        if let Some(segment_hits) = &segment_hits {
+            eprintln!(
+                "searched {num_segments} segments in {:.3} ms, hits per segment: {segment_hits:?}",
+                elapsed.as_secs_f64() * 1000.0
+            );
+        }
+    }
+
+    if args.flag("--histogram") {
+        let buckets = args.parsed("--buckets")?.unwrap_or(10);
+        if buckets == 0 {
+            return Err(TantivyError::InvalidArgument(
+                "--buckets must be at least 1".to_string(),
+            ));
+        }
+        let max_score = match scoring {
+            Scoring::Bm25 => top_docs.first().map(|&(score, _)| score),
+            Scoring::TfIdf => None,
+        };
+        let width = max_score.filter(|&score| score > 0.0).unwrap_or(1.0) / buckets as f32;
+        let histogram =
+            searcher.search(&query, &ScoreHistogramCollector::new(0.0, width, buckets)?)?;
+        print!("{}", histogram.render(40));
+    }
+    Ok(())
+}
+
+/// `clear --path <dir>`
+///
+/// Deletes every document but keeps the index itself.
+pub fn clear(args: &Args) -> tantivy::Result<()> {
+    let index = open_existing_index(args)?;
+    let previous_count = maintenance::clear(&index)?;
+    println!("deleted {previous_count} documents");
+    Ok(())
+}
+
+/// `snapshot --path <dir> --to <dir>`
+///
+/// Commits, then hard-links the index files into a new directory, copying
+/// those that can't be linked. The snapshot opens as an index of its own.
+pub fn snapshot(args: &Args) -> tantivy::Result<()> {
+    let index = open_existing_index(args)?;
+    let report = maintenance::snapshot(
+        &index,
+        Path::new(args.required("--path")?),
+        Path::new(args.required("--to")?),
+    )?;
+    println!(
+        "snapshot of {} files ({} linked, {} copied)",
+        report.linked + report.copied,
+        report.linked,
+        report.copied
+    );
+    Ok(())
+}
+
+/// `optimize --path <dir> [--term <field:value>]`
+///
+/// Merges the index into a single segment and reports the space reclaimed.
+/// With `--term`, also reports the document frequency of that term before
+/// and after, which the merge corrects after deletes.
+pub fn optimize(args: &Args) -> tantivy::Result<()> {
+    let index = open_existing_index(args)?;
+    let report = match args.value("--term") {
+        Some(sample) => {
+            let (field, value) = sample.split_once(':').ok_or_else(|| {
+                TantivyError::InvalidArgument(format!(
+                    "invalid term `{sample}`, expected `field:value`"
+                ))
+            })?;
+            let schema = index.schema();
+            let term = exact_term(&schema, schema.get_field(field)?, value)?.ok_or_else(|| {
+                TantivyError::InvalidArgument(format!("`{value}` is not a value of `{field}`"))
+            })?;
+            let report = maintenance::refresh_statistics(&index, &term)?;
+            println!(
+                "doc_freq of {sample}: {} -> {}",
+                report.doc_freq_before, report.doc_freq_after
+            );
+            report.compaction
+        }
+        None => maintenance::optimize(&index)?,
+    };
+    println!(
+        "segments: {} -> {}\nsize: {} -> {} bytes ({} reclaimed)",
+        report.segments_before,
+        report.segments_after,
+        report.bytes_before,
+        report.bytes_after,
+        report.bytes_reclaimed()
+    );
+    Ok(())
+}
+
+/// `export --path <dir> [--to <dump.ndjson>]`
+///
+/// Writes every stored document as NDJSON, to stdout unless `--to` is given.
+pub fn export(args: &Args) -> tantivy::Result<()> {
+    let index = open_existing_index(args)?;
+    let searcher = index.reader()?.searcher();
+    let count = match args.value("--to") {
+        Some(path) => export_ndjson(&searcher, BufWriter::new(File::create(path)?))?,
+        None => export_ndjson(&searcher, BufWriter::new(io::stdout().lock()))?,
+    };
+    eprintln!("exported {count} documents");
+    Ok(())
+}
+
+/// `diff --a <dir> --b <dir> [--id-field <field>] [--sample <n>]`
+///
+/// Compares the stored documents of two indexes, matched by their
+/// `--id-field` (`id` by default), and prints how many are only in one of
+/// them or differ, with up to `--sample` ids of each (10 by default).
+pub fn diff(args: &Args) -> tantivy::Result<()> {
+    let a = Index::open_in_dir(args.required("--a")?)?
+        .reader()?
+        .searcher();
+    let b = Index::open_in_dir(args.required("--b")?)?
+        .reader()?
+        .searcher();
+    let id_field = args.value("--id-field").unwrap_or("id");
+    let sample = args.parsed::<usize>("--sample")?.unwrap_or(10);
+    let diff = diff_indexes(&a, &b, id_field, sample)?;
+    for (label, count, ids) in [
+        ("only in a", diff.only_in_a, &diff.sample_only_in_a),
+        ("only in b", diff.only_in_b, &diff.sample_only_in_b),
+        ("differing", diff.differing, &diff.sample_differing),
+    ] {
+        if ids.is_empty() {
+            println!("{label}: {count}");
+        } else {
+            println!("{label}: {count} ({})", ids.join(", "));
+        }
+    }
+    println!("identical: {}", diff.identical);
+    if diff.missing_id > 0 {
+        println!("without an id: {}", diff.missing_id);
+    }
+    Ok(())
+}
+
+/// `import --path <dir> [--schema <schema.toml>] [--from <dump.ndjson>]`
+///
+/// Indexes a dump made by `export`, read from stdin unless `--from` is given.
+/// A `--from` file ending in `.gz` is gunzipped.
+pub fn import(args: &Args) -> tantivy::Result<()> {
+    let index = open_index(args)?;
+    let report = match args.value("--from") {
+        Some(path) => import_ndjson(&index, open_input(Path::new(path))?)?,
+        None => import_ndjson(&index, io::stdin().lock())?,
+    };
+    println!(
+        "imported {} documents ({} skipped)",
+        report.indexed, report.skipped
+    );
+    Ok(())
+}
+
+/// `top-terms --path <dir> --field <field> [--limit <n>]`
+///
+/// Prints the terms of a text field found in the most documents, 20 by default.
+pub fn top_terms(args: &Args) -> tantivy::Result<()> {
+    let index = open_existing_index(args)?;
+    let searcher = index.reader()?.searcher();
+    let field = index.schema().get_field(args.required("--field")?)?;
+    let limit = args.parsed("--limit")?.unwrap_or(20);
+
+    let terms = terms::top_terms(&searcher, field, limit)?;
+    let width = terms
+        .iter()
+        .map(|(term, _)| term.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max("term".len());
+    println!("{:<width$}  docs", "term");
+    for (term, doc_freq) in terms {
+        println!("{term:<width$}  {doc_freq}");
+    }
+    Ok(())
+}
+
+/// `warm --path <dir> --queries <queries.txt> [--limit <n>]`
+///
+/// Runs the hot queries listed in a file, one per line, to load the index
+/// into the OS page cache, and reports how long it took.
+pub fn warm(args: &Args) -> tantivy::Result<()> {
+    let index = open_existing_index(args)?;
+    let schema = index.schema();
+    let searcher = index.reader()?.searcher();
+    let query_parser = QueryParser::for_index(&index, default_search_fields(args, &schema)?);
+    let limit = args.parsed("--limit")?.unwrap_or(10);
+
+    let report = warm_cache(
+        &searcher,
+        &query_parser,
+        Path::new(args.required("--queries")?),
+        limit,
+    )?;
+    println!(
+        "ran {} queries ({} skipped) in {:.3} ms",
+        report.queries,
+        report.skipped,
+        report.elapsed.as_secs_f64() * 1000.0
+    );
+    Ok(())
+}
+
+/// `changes --path <dir> --since-opstamp <n> [--limit <n>]`
+///
+/// Prints the documents indexed after the commit `n`, as `opstamp<TAB>json`,
+/// oldest first and 100 at most.
+pub fn changes(args: &Args) -> tantivy::Result<()> {
+    let index = open_existing_index(args)?;
+    let schema = index.schema();
+    let searcher = index.reader()?.searcher();
+    let opstamp_field = schema.get_field("opstamp")?;
+    let since: u64 = args
+        .parsed("--since-opstamp")?
+        .ok_or_else(|| TantivyError::InvalidArgument("missing `--since-opstamp <n>`".into()))?;
+    let limit = args.parsed("--limit")?.unwrap_or(100);
+
+    for (opstamp, doc_address) in changes_since(&searcher, opstamp_field, since, None, limit)? {
+        println!("{opstamp}\t{}", schema.to_json(&searcher.doc(doc_address)?));
+    }
+    Ok(())
+}
+
+/// `migrate --path <dir> --schema <schema.toml> [--drop-unstored]`
+///
+/// Rebuilds the index with the fields added to its schema config,
+/// see `tantivy_basics::maintenance::migrate_add_field`.
+pub fn migrate(args: &Args) -> tantivy::Result<()> {
+    let config = SchemaConfig::load(Path::new(args.required("--schema")?))?;
+    let report = maintenance::migrate_add_field(
+        Path::new(args.required("--path")?),
+        &config.build()?,
+        |index| config.register_tokenizers(index),
+        args.flag("--drop-unstored"),
+    )?;
+    if report.added_fields.is_empty() {
+        println!("schema unchanged, nothing to migrate");
+    } else {
+        println!(
+            "copied {} documents, added fields {:?}",
+            report.documents, report.added_fields
+        );
+    }
+    Ok(())
+}
+
+/// `count-by --path <dir> --field <field>`
+///
+/// Prints how many documents have each value of a facet or fast string field.
+pub fn count_by(args: &Args) -> tantivy::Result<()> {
+    let index = open_existing_index(args)?;
+    let searcher = index.reader()?.searcher();
+    let field = index.schema().get_field(args.required("--field")?)?;
+
+    let counts = count_by_field(&searcher, field)?;
+    let width = counts
+        .iter()
+        .map(|(value, _)| value.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max("value".len());
+    println!("{:<width$}  docs", "value");
+    for (value, count) in counts {
+        println!("{value:<width$}  {count}");
+    }
+    Ok(())
+}
+
+/// `watch --path <dir> [--schema <schema.toml>] --file <docs.ndjson>
+/// [--id-field <field>] [--debounce-ms <ms>]`
+///
+/// Indexes the NDJSON file, then again whenever it changes, replacing the
+/// documents by `--id-field`, `id` by default, until SIGINT or SIGTERM.
+/// Changes closer together than `--debounce-ms`, 500 by default, are
+/// loaded at once.
+pub fn watch(args: &Args) -> tantivy::Result<()> {
+    let index = ConcurrentIndex::new(open_index(args)?)?;
+    let id_field = index
+        .index()
+        .schema()
+        .get_field(args.value("--id-field").unwrap_or("id"))?;
+    let debounce = Duration::from_millis(args.parsed("--debounce-ms")?.unwrap_or(500));
+    let shutdown = Shutdown::on_signal()?;
+    let _watcher = FileWatcher::start(
+        &index,
+        Path::new(args.required("--file")?),
+        id_field,
+        debounce,
+    )?;
+    while !shutdown.is_requested() {
+        thread::sleep(Duration::from_millis(100));
+    }
+    Ok(())
+}
+
+/// `serve --path <dir> [--addr <host:port>] [--warmup <queries.txt>]
+/// [--max-limit <n>] [--strict-limit]`
+///
+/// Serves searches and metrics over HTTP, on `127.0.0.1:8080` by default,
+/// see `tantivy_basics::server` for the routes. The hot queries of
+/// `--warmup` run in the background, `/readyz` answering 503 until they're done.
+/// A search asking for more than `--max-limit` hits, 1000 by default, gets
+/// that many, or a 400 with `--strict-limit`.
+pub fn serve(args: &Args) -> tantivy::Result<()> {
+    let index = open_existing_index(args)?;
+    let search_fields = default_search_fields(args, &index.schema())?;
+    let mut server = SearchServer::new(index, search_fields)?;
+    if let Some(max_limit) = args.parsed("--max-limit")? {
+        server = server.with_max_limit(max_limit);
+    }
+    if args.flag("--strict-limit") {
+        server = server.with_strict_limit();
+    }
+    let warmup = args.value("--warmup").map(Path::new);
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            if let Err(err) = server.warm_up(warmup) {
+                warn!(%err, "warm-up failed, the server stays unready");
+            }
+        });
+        server.serve(args.value("--addr").unwrap_or("127.0.0.1:8080"))
+    })
+}
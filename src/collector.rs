@@ -0,0 +1,945 @@
+//! Custom collectors.
+
+use std::collections::{BinaryHeap, HashMap};
+use std::fmt;
+
+use tantivy::collector::{Collector, SegmentCollector};
+use tantivy::columnar::{Column, StrColumn};
+use tantivy::query::Weight;
+use tantivy::{
+    DocAddress, DocId, DocSet, Order, Score, SegmentOrdinal, SegmentReader, TantivyError,
+    TERMINATED,
+};
+
+/// Counts matches per score range, to help pick a relevance cutoff.
+///
+/// Scores are split into `buckets` ranges of `width`, starting at `start`.
+/// Scores below `start` land in the first bucket and scores past the end in
+/// the last one, so every match is counted. Being a collector, it can run in
+/// the same pass as `TopDocs` by searching with the tuple `(top_docs, histogram)`.
+#[derive(Clone, Debug)]
+pub struct ScoreHistogramCollector {
+    start: Score,
+    width: Score,
+    buckets: usize,
+}
+
+impl ScoreHistogramCollector {
+    /// Fails if `width` is not a positive number or `buckets` is zero.
+    pub fn new(
+        start: Score,
+        width: Score,
+        buckets: usize,
+    ) -> tantivy::Result<ScoreHistogramCollector> {
+        if !(width > 0.0 && width.is_finite()) {
+            return Err(TantivyError::InvalidArgument(format!(
+                "bucket width must be positive, got {width}"
+            )));
+        }
+        if buckets == 0 {
+            return Err(TantivyError::InvalidArgument(
+                "at least one bucket is required".to_string(),
+            ));
+        }
+        Ok(ScoreHistogramCollector {
+            start,
+            width,
+            buckets,
+        })
+    }
+
+    fn bucket(&self, score: Score) -> usize {
+        let bucket = ((score - self.start) / self.width).floor();
+        if bucket < 0.0 {
+            0
+        } else {
+            (bucket as usize).min(self.buckets - 1)
+        }
+    }
+}
+
+/// The per-bucket counts produced by a [`ScoreHistogramCollector`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScoreHistogram {
+    pub start: Score,
+    pub width: Score,
+    pub counts: Vec<u64>,
+}
+
+impl ScoreHistogram {
+    /// The `[lower, upper)` score range of bucket `i`.
+    pub fn range(&self, i: usize) -> (Score, Score) {
+        let lower = self.start + self.width * i as Score;
+        (lower, lower + self.width)
+    }
+
+    /// Draws the histogram as text, one line per bucket,
+    /// with bars scaled to at most `max_bar` characters.
+    pub fn render(&self, max_bar: usize) -> String {
+        let max_count = self.counts.iter().copied().max().unwrap_or(0).max(1);
+        let mut out = String::new();
+        for (i, &count) in self.counts.iter().enumerate() {
+            let (lower, upper) = self.range(i);
+            let bar = (count as usize * max_bar).div_ceil(max_count as usize);
+            out.push_str(&format!(
+                "{lower:>8.3} - {upper:<8.3} | {:<max_bar$} {count}\n",
+                "#".repeat(bar)
+            ));
+        }
+        out
+    }
+}
+
+impl Collector for ScoreHistogramCollector {
+    type Fruit = ScoreHistogram;
+    type Child = ScoreHistogramSegmentCollector;
+
+    fn for_segment(
+        &self,
+        _segment_local_id: SegmentOrdinal,
+        _segment: &SegmentReader,
+    ) -> tantivy::Result<Self::Child> {
+        Ok(ScoreHistogramSegmentCollector {
+            histogram: self.clone(),
+            counts: vec![0; self.buckets],
+        })
+    }
+
+    fn requires_scoring(&self) -> bool {
+        true
+    }
+
+    fn merge_fruits(&self, segment_counts: Vec<Vec<u64>>) -> tantivy::Result<ScoreHistogram> {
+        let mut counts = vec![0; self.buckets];
+        for segment_counts in segment_counts {
+            for (total, count) in counts.iter_mut().zip(segment_counts) {
+                *total += count;
+            }
+        }
+        Ok(ScoreHistogram {
+            start: self.start,
+            width: self.width,
+            counts,
+        })
+    }
+}
+
+/// Per-segment half of [`ScoreHistogramCollector`].
+pub struct ScoreHistogramSegmentCollector {
+    histogram: ScoreHistogramCollector,
+    counts: Vec<u64>,
+}
+
+impl SegmentCollector for ScoreHistogramSegmentCollector {
+    type Fruit = Vec<u64>;
+
+    fn collect(&mut self, _doc: DocId, score: Score) {
+        self.counts[self.histogram.bucket(score)] += 1;
+    }
+
+    fn harvest(self) -> Vec<u64> {
+        self.counts
+    }
+}
+
+/// Counts matches in each segment, in segment ordinal order.
+///
+/// Every segment is reported, even when nothing matched in it, so the
+/// length of the result is the number of segments the query went through.
+pub struct SegmentHitsCollector;
+
+impl Collector for SegmentHitsCollector {
+    type Fruit = Vec<u64>;
+    type Child = SegmentHitsSegmentCollector;
+
+    fn for_segment(
+        &self,
+        segment_ord: SegmentOrdinal,
+        _segment: &SegmentReader,
+    ) -> tantivy::Result<Self::Child> {
+        Ok(SegmentHitsSegmentCollector {
+            segment_ord,
+            hits: 0,
+        })
+    }
+
+    fn requires_scoring(&self) -> bool {
+        false
+    }
+
+    fn merge_fruits(
+        &self,
+        mut segment_hits: Vec<(SegmentOrdinal, u64)>,
+    ) -> tantivy::Result<Vec<u64>> {
+        segment_hits.sort_by_key(|&(segment_ord, _)| segment_ord);
+        Ok(segment_hits.into_iter().map(|(_, hits)| hits).collect())
+    }
+}
+
+/// Per-segment half of [`SegmentHitsCollector`].
+pub struct SegmentHitsSegmentCollector {
+    segment_ord: SegmentOrdinal,
+    hits: u64,
+}
+
+impl SegmentCollector for SegmentHitsSegmentCollector {
+    type Fruit = (SegmentOrdinal, u64);
+
+    fn collect(&mut self, _doc: DocId, _score: Score) {
+        self.hits += 1;
+    }
+
+    fn harvest(self) -> (SegmentOrdinal, u64) {
+        (self.segment_ord, self.hits)
+    }
+}
+
+/// The best matches sharing one value of the group field.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Group {
+    /// `None` for the documents without a value.
+    pub value: Option<u64>,
+    /// How many documents of the group matched.
+    pub count: u64,
+    /// The best of them, best first.
+    pub top_docs: Vec<(Score, DocAddress)>,
+}
+
+/// Collapses matches by the value of a `u64` fast field, like Solr's field
+/// collapsing: the `groups` groups with the best matches, each with its
+/// `per_group` best documents.
+///
+/// Documents with several values are grouped by their first one.
+pub struct GroupCollector {
+    field: String,
+    groups: usize,
+    per_group: usize,
+}
+
+impl GroupCollector {
+    pub fn new(field: &str, groups: usize, per_group: usize) -> GroupCollector {
+        GroupCollector {
+            field: field.to_string(),
+            groups,
+            per_group,
+        }
+    }
+}
+
+/// Keeps the `limit` best of `docs`, best first.
+fn keep_best(docs: &mut Vec<(Score, DocAddress)>, limit: usize) {
+    docs.sort_by(|left, right| right.0.total_cmp(&left.0).then(left.1.cmp(&right.1)));
+    docs.truncate(limit);
+}
+
+impl Collector for GroupCollector {
+    type Fruit = Vec<Group>;
+    type Child = GroupSegmentCollector;
+
+    fn for_segment(
+        &self,
+        segment_ord: SegmentOrdinal,
+        segment: &SegmentReader,
+    ) -> tantivy::Result<Self::Child> {
+        Ok(GroupSegmentCollector {
+            values: segment.fast_fields().u64(&self.field)?,
+            segment_ord,
+            per_group: self.per_group,
+            groups: HashMap::new(),
+        })
+    }
+
+    fn requires_scoring(&self) -> bool {
+        true
+    }
+
+    fn merge_fruits(
+        &self,
+        segment_groups: Vec<HashMap<Option<u64>, Group>>,
+    ) -> tantivy::Result<Vec<Group>> {
+        let mut merged: HashMap<Option<u64>, Group> = HashMap::new();
+        for groups in segment_groups {
+            for (value, group) in groups {
+                let entry = merged.entry(value).or_insert_with(|| Group {
+                    value,
+                    count: 0,
+                    top_docs: Vec::new(),
+                });
+                entry.count += group.count;
+                entry.top_docs.extend(group.top_docs);
+            }
+        }
+        let mut groups: Vec<Group> = merged
+            .into_values()
+            .map(|mut group| {
+                keep_best(&mut group.top_docs, self.per_group);
+                group
+            })
+            .collect();
+        let best_score = |group: &Group| group.top_docs.first().map_or(Score::MIN, |hit| hit.0);
+        groups.sort_by(|left, right| {
+            best_score(right)
+                .total_cmp(&best_score(left))
+                .then(left.value.cmp(&right.value))
+        });
+        groups.truncate(self.groups);
+        Ok(groups)
+    }
+}
+
+/// Per-segment half of [`GroupCollector`].
+pub struct GroupSegmentCollector {
+    values: Column<u64>,
+    segment_ord: SegmentOrdinal,
+    per_group: usize,
+    groups: HashMap<Option<u64>, Group>,
+}
+
+impl SegmentCollector for GroupSegmentCollector {
+    type Fruit = HashMap<Option<u64>, Group>;
+
+    fn collect(&mut self, doc: DocId, score: Score) {
+        let value = self.values.first(doc);
+        let group = self.groups.entry(value).or_insert_with(|| Group {
+            value,
+            count: 0,
+            top_docs: Vec::new(),
+        });
+        group.count += 1;
+        group
+            .top_docs
+            .push((score, DocAddress::new(self.segment_ord, doc)));
+        // Trim now and then, not on every document.
+        if group.top_docs.len() >= self.per_group.max(1) * 2 {
+            keep_best(&mut group.top_docs, self.per_group);
+        }
+    }
+
+    fn harvest(mut self) -> HashMap<Option<u64>, Group> {
+        for group in self.groups.values_mut() {
+            keep_best(&mut group.top_docs, self.per_group);
+        }
+        self.groups
+    }
+}
+
+/// Counts matches per value of a fast string field, like `SELECT field,
+/// COUNT(*) ... GROUP BY field`. A document with several values counts once
+/// for each of them, one without a value is not counted.
+pub struct ValueCountCollector {
+    field: String,
+}
+
+impl ValueCountCollector {
+    pub fn new(field: &str) -> ValueCountCollector {
+        ValueCountCollector {
+            field: field.to_string(),
+        }
+    }
+}
+
+impl Collector for ValueCountCollector {
+    type Fruit = HashMap<String, u64>;
+    type Child = ValueCountSegmentCollector;
+
+    fn for_segment(
+        &self,
+        _segment_ord: SegmentOrdinal,
+        segment: &SegmentReader,
+    ) -> tantivy::Result<Self::Child> {
+        let values = segment.fast_fields().str(&self.field)?.ok_or_else(|| {
+            TantivyError::SchemaError(format!("field `{}` is not a fast string field", self.field))
+        })?;
+        Ok(ValueCountSegmentCollector {
+            counts: vec![0; values.num_terms()],
+            values,
+        })
+    }
+
+    fn requires_scoring(&self) -> bool {
+        false
+    }
+
+    fn merge_fruits(
+        &self,
+        segment_counts: Vec<tantivy::Result<HashMap<String, u64>>>,
+    ) -> tantivy::Result<HashMap<String, u64>> {
+        let mut counts = HashMap::new();
+        for segment_counts in segment_counts {
+            for (value, count) in segment_counts? {
+                *counts.entry(value).or_default() += count;
+            }
+        }
+        Ok(counts)
+    }
+}
+
+/// Per-segment half of [`ValueCountCollector`].
+///
+/// Values are counted by term ordinal, and only turned into strings once
+/// the segment is done.
+pub struct ValueCountSegmentCollector {
+    values: StrColumn,
+    counts: Vec<u64>,
+}
+
+impl SegmentCollector for ValueCountSegmentCollector {
+    type Fruit = tantivy::Result<HashMap<String, u64>>;
+
+    fn collect(&mut self, doc: DocId, _score: Score) {
+        for ord in self.values.term_ords(doc) {
+            self.counts[ord as usize] += 1;
+        }
+    }
+
+    fn harvest(self) -> tantivy::Result<HashMap<String, u64>> {
+        let mut counts = HashMap::new();
+        for (ord, &count) in self.counts.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            let mut value = String::new();
+            self.values.ord_to_str(ord as u64, &mut value)?;
+            counts.insert(value, count);
+        }
+        Ok(counts)
+    }
+}
+
+/// The documents a query matched, as one bitset per segment.
+///
+/// Doc ids are only meaningful for the searcher they come from, so a result
+/// set must be used with the same searcher it was collected with.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ResultSet {
+    segments: Vec<Vec<u64>>,
+}
+
+impl ResultSet {
+    pub fn contains(&self, doc_address: DocAddress) -> bool {
+        self.segments
+            .get(doc_address.segment_ord as usize)
+            .is_some_and(|bits| bit_is_set(bits, doc_address.doc_id))
+    }
+
+    /// The number of documents in the set.
+    pub fn len(&self) -> usize {
+        self.segments
+            .iter()
+            .flatten()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The documents of the set, in doc address order.
+    pub fn doc_addresses(&self) -> Vec<DocAddress> {
+        let mut doc_addresses = Vec::with_capacity(self.len());
+        for (segment_ord, bits) in self.segments.iter().enumerate() {
+            for (i, &word) in bits.iter().enumerate() {
+                let mut word = word;
+                while word != 0 {
+                    let doc_id = (i * 64) as DocId + word.trailing_zeros();
+                    doc_addresses.push(DocAddress::new(segment_ord as SegmentOrdinal, doc_id));
+                    word &= word - 1;
+                }
+            }
+        }
+        doc_addresses
+    }
+
+    /// The documents in both `self` and `other`.
+    pub fn intersection(&self, other: &ResultSet) -> ResultSet {
+        self.combine(other, |a, b| a & b)
+    }
+
+    /// The documents in `self`, `other` or both.
+    pub fn union(&self, other: &ResultSet) -> ResultSet {
+        self.combine(other, |a, b| a | b)
+    }
+
+    /// The documents in `self` but not in `other`.
+    pub fn difference(&self, other: &ResultSet) -> ResultSet {
+        self.combine(other, |a, b| a & !b)
+    }
+
+    /// Applies `op` to each pair of words, a missing word counting as no documents.
+    fn combine(&self, other: &ResultSet, op: impl Fn(u64, u64) -> u64) -> ResultSet {
+        let word = |bits: Option<&Vec<u64>>, i: usize| {
+            bits.and_then(|bits| bits.get(i)).copied().unwrap_or(0)
+        };
+        let segments = (0..self.segments.len().max(other.segments.len()))
+            .map(|segment_ord| {
+                let (a, b) = (
+                    self.segments.get(segment_ord),
+                    other.segments.get(segment_ord),
+                );
+                let words = a.map_or(0, Vec::len).max(b.map_or(0, Vec::len));
+                (0..words).map(|i| op(word(a, i), word(b, i))).collect()
+            })
+            .collect();
+        ResultSet { segments }
+    }
+}
+
+fn bit_is_set(bits: &[u64], doc: DocId) -> bool {
+    bits.get(doc as usize / 64)
+        .is_some_and(|word| word & (1 << (doc % 64)) != 0)
+}
+
+/// Collects every match into a [`ResultSet`].
+pub struct ResultSetCollector;
+
+impl Collector for ResultSetCollector {
+    type Fruit = ResultSet;
+    type Child = ResultSetSegmentCollector;
+
+    fn for_segment(
+        &self,
+        segment_ord: SegmentOrdinal,
+        segment: &SegmentReader,
+    ) -> tantivy::Result<Self::Child> {
+        Ok(ResultSetSegmentCollector {
+            segment_ord,
+            bits: vec![0; (segment.max_doc() as usize).div_ceil(64)],
+        })
+    }
+
+    fn requires_scoring(&self) -> bool {
+        false
+    }
+
+    fn merge_fruits(
+        &self,
+        segment_bits: Vec<(SegmentOrdinal, Vec<u64>)>,
+    ) -> tantivy::Result<ResultSet> {
+        let mut segments = vec![Vec::new(); segment_bits.len()];
+        for (segment_ord, bits) in segment_bits {
+            let segment_ord = segment_ord as usize;
+            if segment_ord >= segments.len() {
+                segments.resize(segment_ord + 1, Vec::new());
+            }
+            segments[segment_ord] = bits;
+        }
+        Ok(ResultSet { segments })
+    }
+}
+
+/// Per-segment half of [`ResultSetCollector`].
+pub struct ResultSetSegmentCollector {
+    segment_ord: SegmentOrdinal,
+    bits: Vec<u64>,
+}
+
+impl SegmentCollector for ResultSetSegmentCollector {
+    type Fruit = (SegmentOrdinal, Vec<u64>);
+
+    fn collect(&mut self, doc: DocId, _score: Score) {
+        self.bits[doc as usize / 64] |= 1 << (doc % 64);
+    }
+
+    fn harvest(self) -> (SegmentOrdinal, Vec<u64>) {
+        (self.segment_ord, self.bits)
+    }
+}
+
+/// Runs `collector` on the matches that are also in `within`, to refine
+/// the results of a previous query without running it again.
+pub struct WithinCollector<C> {
+    within: ResultSet,
+    collector: C,
+}
+
+impl<C: Collector> WithinCollector<C> {
+    pub fn new(within: ResultSet, collector: C) -> WithinCollector<C> {
+        WithinCollector { within, collector }
+    }
+}
+
+impl<C: Collector> Collector for WithinCollector<C> {
+    type Fruit = C::Fruit;
+    type Child = WithinSegmentCollector<C::Child>;
+
+    fn for_segment(
+        &self,
+        segment_ord: SegmentOrdinal,
+        segment: &SegmentReader,
+    ) -> tantivy::Result<Self::Child> {
+        let bits = self
+            .within
+            .segments
+            .get(segment_ord as usize)
+            .cloned()
+            .unwrap_or_default();
+        Ok(WithinSegmentCollector {
+            bits,
+            collector: self.collector.for_segment(segment_ord, segment)?,
+        })
+    }
+
+    fn requires_scoring(&self) -> bool {
+        self.collector.requires_scoring()
+    }
+
+    fn merge_fruits(
+        &self,
+        segment_fruits: Vec<<Self::Child as SegmentCollector>::Fruit>,
+    ) -> tantivy::Result<C::Fruit> {
+        self.collector.merge_fruits(segment_fruits)
+    }
+}
+
+/// Per-segment half of [`WithinCollector`].
+pub struct WithinSegmentCollector<C> {
+    bits: Vec<u64>,
+    collector: C,
+}
+
+impl<C: SegmentCollector> SegmentCollector for WithinSegmentCollector<C> {
+    type Fruit = C::Fruit;
+
+    fn collect(&mut self, doc: DocId, score: Score) {
+        if bit_is_set(&self.bits, doc) {
+            self.collector.collect(doc, score);
+        }
+    }
+
+    fn harvest(self) -> C::Fruit {
+        self.collector.harvest()
+    }
+}
+
+/// A number of matches, exact or a lower bound.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HitCount {
+    pub count: usize,
+    /// Whether `count` is the exact number of matches, rather than the cap
+    /// the counting stopped at.
+    pub exact: bool,
+}
+
+impl fmt::Display for HitCount {
+    /// `42`, or `1000+` when counting stopped at 1000.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.exact {
+            write!(f, "{}", self.count)
+        } else {
+            write!(f, "{}+", self.count)
+        }
+    }
+}
+
+/// Counts matches up to `cap`, like a results page showing "1000+ results".
+///
+/// Each segment stops going through its matches once it has found more
+/// than `cap`, so on a huge result set this costs at most `cap + 1` matches
+/// per segment instead of all of them. That only holds when it runs alone:
+/// combined with other collectors in a tuple, every match is visited anyway.
+#[derive(Clone, Copy, Debug)]
+pub struct CappedCountCollector {
+    cap: usize,
+}
+
+impl CappedCountCollector {
+    pub fn new(cap: usize) -> CappedCountCollector {
+        CappedCountCollector { cap }
+    }
+}
+
+impl Collector for CappedCountCollector {
+    type Fruit = HitCount;
+    type Child = CappedCountSegmentCollector;
+
+    fn for_segment(
+        &self,
+        _segment_ord: SegmentOrdinal,
+        _segment: &SegmentReader,
+    ) -> tantivy::Result<Self::Child> {
+        Ok(CappedCountSegmentCollector {
+            cap: self.cap,
+            count: 0,
+        })
+    }
+
+    fn requires_scoring(&self) -> bool {
+        false
+    }
+
+    fn merge_fruits(&self, segment_counts: Vec<usize>) -> tantivy::Result<HitCount> {
+        let count: usize = segment_counts.into_iter().sum();
+        Ok(if count > self.cap {
+            HitCount {
+                count: self.cap,
+                exact: false,
+            }
+        } else {
+            HitCount { count, exact: true }
+        })
+    }
+
+    fn collect_segment(
+        &self,
+        weight: &dyn Weight,
+        segment_ord: SegmentOrdinal,
+        reader: &SegmentReader,
+    ) -> tantivy::Result<usize> {
+        let mut segment_collector = self.for_segment(segment_ord, reader)?;
+        let mut scorer = weight.scorer(reader, 1.0)?;
+        let mut doc = scorer.doc();
+        while doc != TERMINATED && segment_collector.count <= self.cap {
+            if !reader.is_deleted(doc) {
+                segment_collector.collect(doc, 0.0);
+            }
+            doc = scorer.advance();
+        }
+        Ok(segment_collector.harvest())
+    }
+}
+
+/// Per-segment half of [`CappedCountCollector`].
+pub struct CappedCountSegmentCollector {
+    cap: usize,
+    count: usize,
+}
+
+impl SegmentCollector for CappedCountSegmentCollector {
+    type Fruit = usize;
+
+    fn collect(&mut self, _doc: DocId, _score: Score) {
+        // One past the cap is enough to know the count is not exact.
+        if self.count <= self.cap {
+            self.count += 1;
+        }
+    }
+
+    fn harvest(self) -> usize {
+        self.count
+    }
+}
+
+/// Where the documents without a value go when sorting by a field.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Missing {
+    First,
+    #[default]
+    Last,
+}
+
+/// Collects the top `limit` matches sorted by a `u64` fast field, in `order`,
+/// the documents lacking a value placed first or last as `missing` says.
+///
+/// `TopDocs::order_by_fast_field` leaves it to the column to decide what a
+/// missing value reads as. Here each document sorts on a key made of a rank,
+/// before, among or after the values, and the value itself, reversed for a
+/// descending order. Equal keys are ordered by doc address.
+#[derive(Clone, Debug)]
+pub struct SortByFieldCollector {
+    field: String,
+    order: Order,
+    missing: Missing,
+    limit: usize,
+}
+
+impl SortByFieldCollector {
+    pub fn new(field: &str, order: Order, missing: Missing, limit: usize) -> SortByFieldCollector {
+        SortByFieldCollector {
+            field: field.to_string(),
+            order,
+            missing,
+            limit,
+        }
+    }
+}
+
+/// The sort key of a document: rank 0 sorts first, 2 last, values rank 1.
+type SortKey = (u8, u64);
+
+impl Collector for SortByFieldCollector {
+    type Fruit = Vec<(Option<u64>, DocAddress)>;
+    type Child = SortByFieldSegmentCollector;
+
+    fn for_segment(
+        &self,
+        segment_ord: SegmentOrdinal,
+        segment: &SegmentReader,
+    ) -> tantivy::Result<Self::Child> {
+        Ok(SortByFieldSegmentCollector {
+            column: segment.fast_fields().column_opt::<u64>(&self.field)?,
+            segment_ord,
+            descending: matches!(self.order, Order::Desc),
+            missing: self.missing,
+            limit: self.limit,
+            top: BinaryHeap::new(),
+        })
+    }
+
+    fn requires_scoring(&self) -> bool {
+        false
+    }
+
+    fn merge_fruits(
+        &self,
+        segment_tops: Vec<Vec<(SortKey, Option<u64>, DocAddress)>>,
+    ) -> tantivy::Result<Self::Fruit> {
+        let mut top: Vec<_> = segment_tops.into_iter().flatten().collect();
+        top.sort_by_key(|&(key, _, doc_address)| (key, doc_address));
+        Ok(top
+            .into_iter()
+            .take(self.limit)
+            .map(|(_, value, doc_address)| (value, doc_address))
+            .collect())
+    }
+}
+
+/// Per-segment half of [`SortByFieldCollector`].
+pub struct SortByFieldSegmentCollector {
+    column: Option<Column<u64>>,
+    segment_ord: SegmentOrdinal,
+    descending: bool,
+    missing: Missing,
+    limit: usize,
+    /// The best `limit` documents so far, the worst on top.
+    top: BinaryHeap<(SortKey, DocId, Option<u64>)>,
+}
+
+impl SegmentCollector for SortByFieldSegmentCollector {
+    type Fruit = Vec<(SortKey, Option<u64>, DocAddress)>;
+
+    fn collect(&mut self, doc: DocId, _score: Score) {
+        let value = self.column.as_ref().and_then(|column| column.first(doc));
+        let key = match (value, self.missing) {
+            (None, Missing::First) => (0, 0),
+            (None, Missing::Last) => (2, 0),
+            (Some(value), _) if self.descending => (1, u64::MAX - value),
+            (Some(value), _) => (1, value),
+        };
+        if self.top.len() < self.limit {
+            self.top.push((key, doc, value));
+        } else if self
+            .top
+            .peek()
+            .is_some_and(|&(worst, worst_doc, _)| (key, doc) < (worst, worst_doc))
+        {
+            self.top.pop();
+            self.top.push((key, doc, value));
+        }
+    }
+
+    fn harvest(self) -> Self::Fruit {
+        let segment_ord = self.segment_ord;
+        self.top
+            .into_iter()
+            .map(|(key, doc, value)| (key, value, DocAddress::new(segment_ord, doc)))
+            .collect()
+    }
+}
+
+/// Picks `size` matches at random, every match as likely as any other, or
+/// all of them when there are fewer, e.g. for representative examples of
+/// a result set rather than its best hits.
+///
+/// This is reservoir sampling in a single pass, in the form that merges
+/// across segments: every match draws a random key and each segment keeps
+/// the `size` smallest, of which the overall `size` smallest are the sample.
+/// Keys are a hash of `seed` and the doc address, so the same seed gives
+/// the same sample of the same segments, whatever the order they are
+/// collected in; segments changing on merges give another one.
+/// The sample comes back in doc address order.
+#[derive(Clone, Debug)]
+pub struct ReservoirSampleCollector {
+    size: usize,
+    seed: u64,
+}
+
+impl ReservoirSampleCollector {
+    pub fn new(size: usize, seed: u64) -> ReservoirSampleCollector {
+        ReservoirSampleCollector { size, seed }
+    }
+}
+
+impl Collector for ReservoirSampleCollector {
+    type Fruit = Vec<DocAddress>;
+    type Child = ReservoirSampleSegmentCollector;
+
+    fn for_segment(
+        &self,
+        segment_ord: SegmentOrdinal,
+        _segment: &SegmentReader,
+    ) -> tantivy::Result<Self::Child> {
+        Ok(ReservoirSampleSegmentCollector {
+            size: self.size,
+            seed: mix(mix(self.seed) ^ u64::from(segment_ord)),
+            segment_ord,
+            reservoir: BinaryHeap::new(),
+        })
+    }
+
+    fn requires_scoring(&self) -> bool {
+        false
+    }
+
+    fn merge_fruits(
+        &self,
+        segment_samples: Vec<Vec<(u64, DocAddress)>>,
+    ) -> tantivy::Result<Vec<DocAddress>> {
+        let mut sample: Vec<_> = segment_samples.into_iter().flatten().collect();
+        sample.sort_unstable();
+        sample.truncate(self.size);
+        let mut sample: Vec<DocAddress> = sample
+            .into_iter()
+            .map(|(_, doc_address)| doc_address)
+            .collect();
+        sample.sort_unstable();
+        Ok(sample)
+    }
+}
+
+/// Per-segment half of [`ReservoirSampleCollector`].
+pub struct ReservoirSampleSegmentCollector {
+    size: usize,
+    seed: u64,
+    segment_ord: SegmentOrdinal,
+    /// The documents with the `size` smallest keys so far, the largest on top.
+    reservoir: BinaryHeap<(u64, DocId)>,
+}
+
+impl SegmentCollector for ReservoirSampleSegmentCollector {
+    type Fruit = Vec<(u64, DocAddress)>;
+
+    fn collect(&mut self, doc: DocId, _score: Score) {
+        let key = mix(self.seed ^ u64::from(doc));
+        if self.reservoir.len() < self.size {
+            self.reservoir.push((key, doc));
+        } else if self
+            .reservoir
+            .peek()
+            .is_some_and(|&(largest, _)| key < largest)
+        {
+            self.reservoir.pop();
+            self.reservoir.push((key, doc));
+        }
+    }
+
+    fn harvest(self) -> Self::Fruit {
+        let segment_ord = self.segment_ord;
+        self.reservoir
+            .into_iter()
+            .map(|(key, doc)| (key, DocAddress::new(segment_ord, doc)))
+            .collect()
+    }
+}
+
+/// The step of the SplitMix64 generator, which [`mix`] adds to its input.
+pub(crate) const SPLITMIX64_GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// The SplitMix64 finalizer, which spreads close inputs over all of `u64`.
+/// The generator's next value after `state` is `mix(state)`.
+pub(crate) fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(SPLITMIX64_GAMMA);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
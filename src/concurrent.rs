@@ -0,0 +1,189 @@
+//! Searching an index while another thread keeps writing to it.
+//!
+//! A tantivy index has a single writer but any number of searchers, and a
+//! searcher is a snapshot: it keeps seeing the segments of the commit it
+//! was created from. New documents only become searchable once they are
+//! committed *and* the reader has reloaded, which it does on its own with
+//! [`ReloadPolicy::OnCommit`], shortly after every commit.
+//!
+//! Every commit adds segments, which a [`MergeScheduler`] can merge in the
+//! background so that searches don't slow down as ingestion goes on.
+
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use serde::Deserialize;
+use tantivy::merge_policy::NoMergePolicy;
+use tantivy::{
+    Index, IndexReader, IndexWriter, Opstamp, ReloadPolicy, Searcher, SegmentId, SegmentMeta,
+    TantivyError,
+};
+use tracing::{info, warn};
+
+use crate::DEFAULT_HEAP_SIZE;
+
+/// An index with a writer that can be shared across threads and a reader
+/// reloading on commit.
+///
+/// Adding documents only needs a read lock on the writer, so several
+/// threads can add at once. Committing needs the write lock.
+#[derive(Clone)]
+pub struct ConcurrentIndex {
+    index: Index,
+    writer: Arc<RwLock<IndexWriter>>,
+    reader: IndexReader,
+}
+
+impl ConcurrentIndex {
+    /// Takes the writer of `index`: there can't be another one while this
+    /// is alive.
+    pub fn new(index: Index) -> tantivy::Result<ConcurrentIndex> {
+        let writer = index.writer(DEFAULT_HEAP_SIZE)?;
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::OnCommit)
+            .try_into()?;
+        Ok(ConcurrentIndex {
+            index,
+            writer: Arc::new(RwLock::new(writer)),
+            reader,
+        })
+    }
+
+    pub fn index(&self) -> &Index {
+        &self.index
+    }
+
+    /// A handle on the writer, to move to the indexing thread.
+    pub fn writer(&self) -> Arc<RwLock<IndexWriter>> {
+        Arc::clone(&self.writer)
+    }
+
+    pub fn reader(&self) -> &IndexReader {
+        &self.reader
+    }
+
+    /// A searcher on the last commit the reader has loaded.
+    pub fn searcher(&self) -> Searcher {
+        self.reader.searcher()
+    }
+
+    /// Commits what was added so far, then reloads the reader right away
+    /// instead of waiting for it to notice, so the next [`Self::searcher`]
+    /// sees the new documents.
+    pub fn commit(&self) -> tantivy::Result<Opstamp> {
+        let opstamp = self
+            .writer
+            .write()
+            .map_err(|_| TantivyError::Poisoned)?
+            .commit()?;
+        self.reader.reload()?;
+        Ok(opstamp)
+    }
+}
+
+/// When a [`MergeScheduler`] merges, as read from a config file:
+///
+/// ```toml
+/// interval_ms = 10_000 # how often the segments are counted
+/// max_segments = 8     # more than this and the smallest ones are merged
+/// ```
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MergeSchedule {
+    pub interval_ms: u64,
+    pub max_segments: usize,
+}
+
+impl Default for MergeSchedule {
+    fn default() -> MergeSchedule {
+        MergeSchedule {
+            interval_ms: 10_000,
+            max_segments: 8,
+        }
+    }
+}
+
+/// A thread merging the segments of a [`ConcurrentIndex`] whenever there
+/// are more than [`MergeSchedule::max_segments`], until it is dropped.
+///
+/// It takes the place of the merge policy of the writer. Merging only
+/// takes the write lock to start a merge, which then runs on tantivy's
+/// merge threads while documents keep being added; new segments are
+/// merged from the next check on.
+pub struct MergeScheduler {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MergeScheduler {
+    /// Starts merging the segments of `index` as `schedule` says.
+    pub fn start(
+        index: &ConcurrentIndex,
+        schedule: MergeSchedule,
+    ) -> tantivy::Result<MergeScheduler> {
+        if schedule.max_segments == 0 {
+            return Err(TantivyError::InvalidArgument(
+                "max_segments must be at least 1".to_string(),
+            ));
+        }
+        index
+            .writer
+            .read()
+            .map_err(|_| TantivyError::Poisoned)?
+            .set_merge_policy(Box::new(NoMergePolicy));
+        let (stop, stopped) = mpsc::channel::<()>();
+        let index = index.clone();
+        let interval = Duration::from_millis(schedule.interval_ms);
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                if let Err(err) = merge_smallest(&index, schedule.max_segments) {
+                    warn!(%err, "scheduled merge failed");
+                }
+            }
+        });
+        Ok(MergeScheduler {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for MergeScheduler {
+    /// Stops the thread, waiting for the merge in progress, if any.
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Merges the fewest, smallest segments that bring `index` back to
+/// `max_segments`, if it has more.
+fn merge_smallest(index: &ConcurrentIndex, max_segments: usize) -> tantivy::Result<()> {
+    // Segment metas keep their files from garbage collection while alive,
+    // so only the ids are kept.
+    let segment_ids: Vec<SegmentId> = {
+        let mut segment_metas = index.index.searchable_segment_metas()?;
+        if segment_metas.len() <= max_segments {
+            return Ok(());
+        }
+        segment_metas.sort_by_key(SegmentMeta::num_docs);
+        let merged = segment_metas.len() - max_segments + 1;
+        segment_metas[..merged]
+            .iter()
+            .map(SegmentMeta::id)
+            .collect()
+    };
+    let merge = index
+        .writer
+        .write()
+        .map_err(|_| TantivyError::Poisoned)?
+        .merge(&segment_ids);
+    merge.wait()?;
+    info!(segments = segment_ids.len(), "merged segments");
+    Ok(())
+}
@@ -0,0 +1,364 @@
+//! Helpers to put values into a `Document`, and to read them back as the
+//! types an output wants.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use tantivy::schema::{Document, Field, FieldType, Schema, Value};
+use tantivy::tokenizer::{PreTokenizedString, Token};
+use tantivy::{Index, TantivyError};
+
+use crate::template::format_value;
+
+/// Adds `tokens` to the text `field` exactly as given.
+///
+/// The field's tokenizer is bypassed at indexing time: what is indexed is
+/// these token texts at these positions, nothing else. `text` is what gets
+/// stored. Since the query parser would tokenize the query with the field's
+/// tokenizer, search pre-tokenized fields with `TermQuery`s over the same tokens.
+pub fn add_pre_tokenized(doc: &mut Document, field: Field, text: &str, tokens: Vec<Token>) {
+    doc.add_pre_tokenized_text(
+        field,
+        PreTokenizedString {
+            text: text.to_string(),
+            tokens,
+        },
+    );
+}
+
+/// Adds `words` as consecutive tokens, stored as the words joined by spaces.
+///
+/// Offsets and positions are computed for that joined text.
+pub fn add_tokens(doc: &mut Document, field: Field, words: &[&str]) {
+    let mut tokens = Vec::with_capacity(words.len());
+    let mut offset = 0;
+    for (position, word) in words.iter().enumerate() {
+        tokens.push(Token {
+            offset_from: offset,
+            offset_to: offset + word.len(),
+            position,
+            text: word.to_string(),
+            position_length: 1,
+        });
+        offset += word.len() + 1;
+    }
+    add_pre_tokenized(doc, field, &words.join(" "), tokens);
+}
+
+/// Adds each of `values` to the text `field`, `position_gap` positions apart.
+///
+/// Tantivy puts a gap of a single position between the values of a
+/// multi-valued field: the phrase `"smith jane"` doesn't match the authors
+/// `John Smith` and `Jane Doe`, but the sloppy `"smith jane"~1` does.
+/// A large gap, like 100, keeps phrases within a single value for any
+/// reasonable slop. The values are tokenized here with the field's tokenizer,
+/// and their positions shifted, see [`add_pre_tokenized`].
+pub fn add_text_values(
+    index: &Index,
+    doc: &mut Document,
+    field: Field,
+    values: &[&str],
+    position_gap: usize,
+) -> tantivy::Result<()> {
+    let mut analyzer = index.tokenizer_for_field(field)?;
+    for (i, value) in values.iter().enumerate() {
+        // The indexer already leaves one position between values.
+        let shift = if i == 0 {
+            0
+        } else {
+            position_gap.saturating_sub(1)
+        };
+        let mut tokens = Vec::new();
+        let mut stream = analyzer.token_stream(value);
+        while stream.advance() {
+            let mut token = stream.token().clone();
+            token.position += shift;
+            tokens.push(token);
+        }
+        add_pre_tokenized(doc, field, value, tokens);
+    }
+    Ok(())
+}
+
+/// Adds the float `value` to `field`, normalized so range queries see it.
+///
+/// Tantivy maps f64 to u64 so that the u64 order is the float order, in which
+/// `-0.0` sorts just below `0.0`: it is stored as `0.0` so that a range
+/// starting at zero includes it. NaN has no place in that order and is rejected.
+pub fn add_f64(doc: &mut Document, field: Field, value: f64) -> tantivy::Result<()> {
+    if value.is_nan() {
+        return Err(TantivyError::InvalidArgument(
+            "NaN cannot be indexed".to_string(),
+        ));
+    }
+    doc.add_f64(field, normalize_zero(value));
+    Ok(())
+}
+
+/// Turns `-0.0` into `0.0`, leaving every other value alone.
+pub(crate) fn normalize_zero(value: f64) -> f64 {
+    if value == 0.0 {
+        0.0
+    } else {
+        value
+    }
+}
+
+/// Adds the opaque binary `value`, e.g. a UUID's 16 bytes, to the bytes `field`.
+///
+/// The bytes are indexed as a single term, so they can only be matched
+/// exactly, with [`crate::query::bytes_query`].
+pub fn add_bytes(doc: &mut Document, field: Field, value: &[u8]) {
+    doc.add_bytes(field, value);
+}
+
+/// Builds a document from a JSON object keyed by field name,
+/// e.g. `serde_json::to_value(&my_struct)`.
+///
+/// Each value must fit its field's type, and an array gives a multi-valued
+/// field. Nested objects go to JSON fields only. Unlike
+/// `Schema::parse_document`, keys that are not fields of `schema` are
+/// errors rather than silently dropped. `null` values are skipped.
+pub fn document_from_value(
+    schema: &Schema,
+    value: &serde_json::Value,
+) -> tantivy::Result<Document> {
+    let object = value.as_object().ok_or_else(|| {
+        TantivyError::InvalidArgument(format!("expected a JSON object, got {value}"))
+    })?;
+    let mut doc = Document::new();
+    for (key, value) in object {
+        let field = schema
+            .get_field(key)
+            .map_err(|_| TantivyError::InvalidArgument(format!("unknown field `{key}`")))?;
+        let field_type = schema.get_field_entry(field).field_type();
+        let values = match value {
+            serde_json::Value::Array(values) => values.as_slice(),
+            value => std::slice::from_ref(value),
+        };
+        for value in values {
+            if value.is_null() {
+                continue;
+            }
+            if value.is_object() && !matches!(field_type, FieldType::JsonObject(_)) {
+                return Err(TantivyError::InvalidArgument(format!(
+                    "field `{key}` is not a JSON field and cannot hold an object"
+                )));
+            }
+            let value = field_type.value_from_json(value.clone()).map_err(|err| {
+                TantivyError::InvalidArgument(format!("invalid value for field `{key}`: {err}"))
+            })?;
+            doc.add_field_value(field, value);
+        }
+    }
+    Ok(doc)
+}
+
+/// Same as [`document_from_value`], serializing `value` first.
+pub fn to_document<T: Serialize>(schema: &Schema, value: &T) -> tantivy::Result<Document> {
+    let value = serde_json::to_value(value)
+        .map_err(|err| TantivyError::InvalidArgument(err.to_string()))?;
+    document_from_value(schema, &value)
+}
+
+/// Checks that every value of `doc` belongs to a field of `schema` and has
+/// that field's type.
+///
+/// The writer takes documents as they are and a mismatch only shows up
+/// while indexing, so this is the way to reject one document upfront.
+pub fn check_document(schema: &Schema, doc: &Document) -> tantivy::Result<()> {
+    let num_fields = schema.fields().count();
+    for field_value in doc.field_values() {
+        let field = field_value.field();
+        if field.field_id() as usize >= num_fields {
+            return Err(TantivyError::SchemaError(format!(
+                "field {} is not in the schema",
+                field.field_id()
+            )));
+        }
+        let field_entry = schema.get_field_entry(field);
+        let matches = matches!(
+            (field_entry.field_type(), field_value.value()),
+            (FieldType::Str(_), Value::Str(_) | Value::PreTokStr(_))
+                | (FieldType::U64(_), Value::U64(_))
+                | (FieldType::I64(_), Value::I64(_))
+                | (FieldType::F64(_), Value::F64(_))
+                | (FieldType::Bool(_), Value::Bool(_))
+                | (FieldType::Date(_), Value::Date(_))
+                | (FieldType::Facet(_), Value::Facet(_))
+                | (FieldType::Bytes(_), Value::Bytes(_))
+                | (FieldType::JsonObject(_), Value::JsonObject(_))
+                | (FieldType::IpAddr(_), Value::IpAddr(_))
+        );
+        if !matches {
+            return Err(TantivyError::SchemaError(format!(
+                "field `{}` expects {:?} values, got {:?}",
+                field_entry.name(),
+                field_entry.field_type().value_type(),
+                field_value.value()
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Appended to a string value cut short by [`truncate_stored`].
+pub const ELLIPSIS: &str = "…";
+
+/// Shortens the string values of `doc` until `schema.to_json(doc)` takes
+/// at most `max_bytes`, for a service that shouldn't return huge documents.
+///
+/// The longest string is cut first, at a character boundary, and marked
+/// with [`ELLIPSIS`], until the document fits. Other values are left
+/// alone, so a document that doesn't fit even with its strings reduced
+/// to the marker comes back as small as it can get, still over the limit.
+pub fn truncate_stored(schema: &Schema, doc: &Document, max_bytes: usize) -> Document {
+    truncate_to_fit(doc, max_bytes, |doc| schema.to_json(doc).len())
+}
+
+/// Same as [`truncate_stored`], for any output of the document: `size`
+/// gives the bytes it takes with the values given, e.g. once rendered
+/// through a template or wrapped in a search hit.
+pub fn truncate_to_fit(
+    doc: &Document,
+    max_bytes: usize,
+    size: impl Fn(&Document) -> usize,
+) -> Document {
+    let mut values: Vec<(Field, Value)> = doc
+        .field_values()
+        .iter()
+        .map(|field_value| (field_value.field(), field_value.value().clone()))
+        .collect();
+    let build = |values: &[(Field, Value)]| {
+        let mut doc = Document::new();
+        for (field, value) in values {
+            doc.add_field_value(*field, value.clone());
+        }
+        doc
+    };
+    loop {
+        let truncated = build(&values);
+        let size = size(&truncated);
+        if size <= max_bytes {
+            return truncated;
+        }
+        // Strings already cut count without their marker, and a lone marker can't shrink.
+        let longest = values
+            .iter_mut()
+            .filter_map(|(_, value)| match value {
+                Value::Str(text) => {
+                    let kept = text.strip_suffix(ELLIPSIS).unwrap_or(text).len();
+                    (kept > 0).then_some((kept, text))
+                }
+                _ => None,
+            })
+            .max_by_key(|(kept, _)| *kept);
+        let Some((kept, text)) = longest else {
+            return truncated;
+        };
+        let mut cut = kept.saturating_sub(size - max_bytes + ELLIPSIS.len());
+        while !text.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        text.truncate(cut);
+        text.push_str(ELLIPSIS);
+    }
+}
+
+/// The type [`project_document`] returns a field as, whatever its type in
+/// the schema.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputType {
+    String,
+    U64,
+    I64,
+    F64,
+    Bool,
+}
+
+/// `value` as an `output` value, `None` if it has no such representation.
+///
+/// Any value has a string form, the one of [`format_value`]. Strings are
+/// parsed into numbers and booleans, surrounding whitespace ignored.
+/// Numbers convert into each other when no digit is lost, so `3.0` is the
+/// `u64` 3 but `3.5` is none, and into booleans when they are 0 or 1.
+pub fn coerce_value(value: &Value, output: OutputType) -> Option<Value> {
+    let value = match value {
+        Value::PreTokStr(pre_tokenized) => Value::Str(pre_tokenized.text.clone()),
+        value => value.clone(),
+    };
+    if output == OutputType::String {
+        return Some(Value::Str(format_value(&value, None)));
+    }
+    match (value, output) {
+        (Value::Str(text), output) => {
+            let text = text.trim();
+            match output {
+                OutputType::U64 => text.parse().ok().map(Value::U64),
+                OutputType::I64 => text.parse().ok().map(Value::I64),
+                OutputType::F64 => text
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|number| number.is_finite())
+                    .map(Value::F64),
+                OutputType::Bool => text.parse().ok().map(Value::Bool),
+                OutputType::String => unreachable!("handled above"),
+            }
+        }
+        (Value::Bool(boolean), OutputType::Bool) => Some(Value::Bool(boolean)),
+        (Value::Bool(boolean), output) => coerce_value(&Value::U64(boolean as u64), output),
+        (Value::U64(number), output) => coerce_integer(number.into(), output),
+        (Value::I64(number), output) => coerce_integer(number.into(), output),
+        (Value::F64(number), OutputType::F64) => Some(Value::F64(number)),
+        (Value::F64(number), output) if number.fract() == 0.0 && number.abs() < 2f64.powi(64) => {
+            coerce_integer(number as i128, output)
+        }
+        _ => None,
+    }
+}
+
+/// An integer as an `output` value, if it fits.
+fn coerce_integer(number: i128, output: OutputType) -> Option<Value> {
+    match output {
+        OutputType::U64 => u64::try_from(number).ok().map(Value::U64),
+        OutputType::I64 => i64::try_from(number).ok().map(Value::I64),
+        OutputType::F64 => {
+            let float = number as f64;
+            (float as i128 == number).then_some(Value::F64(float))
+        }
+        OutputType::Bool => match number {
+            0 => Some(Value::Bool(false)),
+            1 => Some(Value::Bool(true)),
+            _ => None,
+        },
+        OutputType::String => Some(Value::Str(number.to_string())),
+    }
+}
+
+/// The stored values of the fields of `doc` named in `output`, as JSON,
+/// each converted to the type `output` gives it, see [`coerce_value`].
+///
+/// This is for outputs whose types don't follow the schema, like a `year`
+/// stored as a `u64` that a client wants as a string. A value that doesn't
+/// convert is left out, instead of failing the whole document, and so are
+/// the fields not in `output`.
+pub fn project_document(
+    schema: &Schema,
+    doc: &Document,
+    output: &BTreeMap<String, OutputType>,
+) -> BTreeMap<String, Vec<serde_json::Value>> {
+    let mut projected: BTreeMap<String, Vec<serde_json::Value>> = BTreeMap::new();
+    for field_value in doc.field_values() {
+        let name = schema.get_field_name(field_value.field());
+        let Some(&output_type) = output.get(name) else {
+            continue;
+        };
+        let json = coerce_value(field_value.value(), output_type)
+            .and_then(|value| serde_json::to_value(value).ok());
+        if let Some(json) = json {
+            projected.entry(name.to_string()).or_default().push(json);
+        }
+    }
+    projected
+}
@@ -0,0 +1,247 @@
+//! Queries described as JSON, for clients that build them programmatically.
+//!
+//! A [`BoolQuery`] combines clauses the way a `BooleanQuery` does, with
+//! values given as JSON instead of query syntax that needs escaping:
+//!
+//! ```json
+//! {
+//!   "must": [{"match": {"field": "body", "text": "old man"}}],
+//!   "should": [{"phrase": {"field": "title", "text": "the sea"}}],
+//!   "must_not": [{"term": {"field": "tenant", "value": "acme"}}],
+//!   "filters": [{"range": {"field": "year", "gte": 1900, "lt": 2000}}]
+//! }
+//! ```
+//!
+//! Filters must match like `must` clauses but don't add to the score.
+//! A query with nothing but `must_not` clauses matches every other document.
+
+use std::ops::Bound;
+
+use serde::Deserialize;
+use tantivy::query::{
+    AllQuery, BooleanQuery, ConstScoreQuery, EmptyQuery, Occur, PhraseQuery, Query, QueryParser,
+    RangeQuery, TermQuery,
+};
+use tantivy::schema::{Field, FieldType, IndexRecordOption};
+use tantivy::{Index, TantivyError};
+
+use crate::query::{f64_range, remove_stop_words, tokenize};
+use crate::search::exact_term;
+
+/// Clauses combined into a `BooleanQuery`, see the [module docs](self).
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BoolQuery {
+    pub must: Vec<Clause>,
+    pub should: Vec<Clause>,
+    pub must_not: Vec<Clause>,
+    pub filters: Vec<Clause>,
+}
+
+/// One clause of a [`BoolQuery`], written `{"<kind>": {...}}`.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Clause {
+    /// The documents whose `field` holds exactly `value`, untokenized,
+    /// see [`exact_term`] for the types of field. A `value` the field can't
+    /// hold, like `1.5` for a `u64` field, is an error.
+    Term {
+        field: String,
+        value: serde_json::Value,
+    },
+    /// The documents with any of the words of `text` in `field`.
+    Match { field: String, text: String },
+    /// The documents with the words of `text` next to each other in `field`.
+    Phrase { field: String, text: String },
+    /// The documents with a value of the numeric `field` within the bounds
+    /// given, any of `gt`, `gte`, `lt` and `lte`.
+    Range {
+        field: String,
+        gt: Option<serde_json::Number>,
+        gte: Option<serde_json::Number>,
+        lt: Option<serde_json::Number>,
+        lte: Option<serde_json::Number>,
+    },
+    /// A query string, parsed like the `q` of a search.
+    Query(String),
+    /// A nested group of clauses.
+    Bool(BoolQuery),
+}
+
+impl BoolQuery {
+    /// Parses a query from its JSON text.
+    pub fn from_json(json: &str) -> tantivy::Result<BoolQuery> {
+        serde_json::from_str(json)
+            .map_err(|err| TantivyError::InvalidArgument(format!("invalid query: {err}")))
+    }
+
+    /// Compiles the clauses over the fields of `index`, the [`Clause::Query`]
+    /// ones with `query_parser`.
+    pub fn build(
+        &self,
+        index: &Index,
+        query_parser: &QueryParser,
+    ) -> tantivy::Result<Box<dyn Query>> {
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+        for clause in &self.must {
+            clauses.push((Occur::Must, clause.build(index, query_parser)?));
+        }
+        for clause in &self.should {
+            clauses.push((Occur::Should, clause.build(index, query_parser)?));
+        }
+        for clause in &self.filters {
+            let query = ConstScoreQuery::new(clause.build(index, query_parser)?, 0.0);
+            clauses.push((Occur::Must, Box::new(query)));
+        }
+        if clauses.is_empty() {
+            clauses.push((Occur::Must, Box::new(AllQuery)));
+        }
+        for clause in &self.must_not {
+            clauses.push((Occur::MustNot, clause.build(index, query_parser)?));
+        }
+        Ok(Box::new(BooleanQuery::new(clauses)))
+    }
+
+    /// Drops `stop_words` from the query strings and the `match` texts of
+    /// the clauses, nested ones included, see [`remove_stop_words`].
+    pub fn remove_stop_words(&mut self, stop_words: &[&str]) {
+        let clauses = self
+            .must
+            .iter_mut()
+            .chain(&mut self.should)
+            .chain(&mut self.must_not)
+            .chain(&mut self.filters);
+        for clause in clauses {
+            match clause {
+                Clause::Match { text, .. } | Clause::Query(text) => {
+                    *text = remove_stop_words(text, stop_words);
+                }
+                Clause::Bool(bool_query) => bool_query.remove_stop_words(stop_words),
+                Clause::Term { .. } | Clause::Phrase { .. } | Clause::Range { .. } => {}
+            }
+        }
+    }
+}
+
+impl Clause {
+    fn build(&self, index: &Index, query_parser: &QueryParser) -> tantivy::Result<Box<dyn Query>> {
+        let schema = index.schema();
+        let field = |name: &str| {
+            schema
+                .get_field(name)
+                .map_err(|_| TantivyError::InvalidArgument(format!("unknown field `{name}`")))
+        };
+        Ok(match self {
+            Clause::Term { field: name, value } => {
+                let value = match value {
+                    serde_json::Value::String(value) => value.clone(),
+                    value => value.to_string(),
+                };
+                let term = exact_term(&schema, field(name)?, &value)
+                    .map_err(invalid_argument)?
+                    .ok_or_else(|| {
+                        TantivyError::InvalidArgument(format!(
+                            "invalid value `{value}` for field `{name}`"
+                        ))
+                    })?;
+                Box::new(TermQuery::new(term, IndexRecordOption::WithFreqs))
+            }
+            Clause::Match { field: name, text } => {
+                let terms = tokenize(index, field(name)?, text).map_err(invalid_argument)?;
+                Box::new(BooleanQuery::new(
+                    terms
+                        .into_iter()
+                        .map(|term| -> (Occur, Box<dyn Query>) {
+                            let query = TermQuery::new(term, IndexRecordOption::WithFreqs);
+                            (Occur::Should, Box::new(query))
+                        })
+                        .collect(),
+                ))
+            }
+            Clause::Phrase { field: name, text } => {
+                let mut terms = tokenize(index, field(name)?, text).map_err(invalid_argument)?;
+                match terms.len() {
+                    0 => Box::new(EmptyQuery),
+                    1 => Box::new(TermQuery::new(
+                        terms.remove(0),
+                        IndexRecordOption::WithFreqs,
+                    )),
+                    _ => Box::new(PhraseQuery::new(terms)),
+                }
+            }
+            Clause::Range {
+                field: name,
+                gt,
+                gte,
+                lt,
+                lte,
+            } => Box::new(range_query(index, field(name)?, [gt, gte, lt, lte])?),
+            Clause::Query(query_str) => query_parser.parse_query(query_str)?,
+            Clause::Bool(bool_query) => bool_query.build(index, query_parser)?,
+        })
+    }
+}
+
+/// A clause on a field of the wrong type is a mistake in the query, not in
+/// the index: the schema errors of a clause become invalid arguments.
+fn invalid_argument(err: TantivyError) -> TantivyError {
+    match err {
+        TantivyError::SchemaError(message) => TantivyError::InvalidArgument(message),
+        err => err,
+    }
+}
+
+/// The range of `field` between the `[gt, gte, lt, lte]` bounds given.
+fn range_query(
+    index: &Index,
+    field: Field,
+    bounds: [&Option<serde_json::Number>; 4],
+) -> tantivy::Result<RangeQuery> {
+    let schema = index.schema();
+    let field_entry = schema.get_field_entry(field);
+    let name = field_entry.name();
+    match field_entry.field_type() {
+        FieldType::U64(_) => {
+            let (lower, upper) = range_bounds(name, bounds, serde_json::Number::as_u64)?;
+            Ok(RangeQuery::new_u64_bounds(name.to_string(), lower, upper))
+        }
+        FieldType::I64(_) => {
+            let (lower, upper) = range_bounds(name, bounds, serde_json::Number::as_i64)?;
+            Ok(RangeQuery::new_i64_bounds(name.to_string(), lower, upper))
+        }
+        FieldType::F64(_) => {
+            let (lower, upper) = range_bounds(name, bounds, serde_json::Number::as_f64)?;
+            f64_range(&schema, field, lower, upper)
+        }
+        _ => Err(TantivyError::InvalidArgument(format!(
+            "field `{name}` is not a numeric field a range applies to"
+        ))),
+    }
+}
+
+/// The lower and upper bounds of `[gt, gte, lt, lte]`, as values of the
+/// type `convert` gives, at most one per side.
+fn range_bounds<T>(
+    name: &str,
+    [gt, gte, lt, lte]: [&Option<serde_json::Number>; 4],
+    convert: impl Fn(&serde_json::Number) -> Option<T>,
+) -> tantivy::Result<(Bound<T>, Bound<T>)> {
+    let value = |number: &serde_json::Number| {
+        convert(number).ok_or_else(|| {
+            TantivyError::InvalidArgument(format!("invalid bound {number} for field `{name}`"))
+        })
+    };
+    let bound = |exclusive: &Option<serde_json::Number>, inclusive: &Option<serde_json::Number>| {
+        Ok(match (exclusive, inclusive) {
+            (Some(_), Some(_)) => {
+                return Err(TantivyError::InvalidArgument(format!(
+                    "range on `{name}` has two bounds on the same side"
+                )))
+            }
+            (Some(number), None) => Bound::Excluded(value(number)?),
+            (None, Some(number)) => Bound::Included(value(number)?),
+            (None, None) => Bound::Unbounded,
+        })
+    };
+    Ok((bound(gt, gte)?, bound(lt, lte)?))
+}
@@ -0,0 +1,199 @@
+//! Score explanations in a machine-readable form.
+
+use std::collections::HashMap;
+
+use serde_json::{json, Value};
+use tantivy::query::{BooleanQuery, Explanation, Occur, PhraseQuery, Query, TermQuery};
+use tantivy::schema::{IndexRecordOption, Schema, Term, Type};
+use tantivy::{DocAddress, Searcher, TantivyError};
+
+/// Explains the score of `doc_address` for `query` as nested JSON.
+///
+/// Every node is `{"description", "value", "details"}`, where `details`
+/// holds the child nodes, e.g. the `idf` and `tf` factors of a BM25 term
+/// weight. Errors if the document doesn't match the query.
+pub fn search_explain_json(
+    searcher: &Searcher,
+    query: &dyn Query,
+    doc_address: DocAddress,
+) -> tantivy::Result<Value> {
+    let explanation = query.explain(searcher, doc_address)?;
+    explanation_to_json(&explanation)
+}
+
+/// Converts an `Explanation` into `{"description", "value", "details"}` nodes.
+pub fn explanation_to_json(explanation: &Explanation) -> tantivy::Result<Value> {
+    // `Explanation` only exposes its tree through serde.
+    let raw = serde_json::to_value(explanation)
+        .map_err(|err| TantivyError::InternalError(err.to_string()))?;
+    Ok(normalize(&raw))
+}
+
+fn normalize(raw: &Value) -> Value {
+    let details: Vec<Value> = raw["details"]
+        .as_array()
+        .map(|details| details.iter().map(normalize).collect())
+        .unwrap_or_default();
+    json!({
+        "description": raw["description"],
+        "value": raw["value"],
+        "details": details,
+    })
+}
+
+/// Renders a JSON explanation as an indented tree, one node per line.
+pub fn render_explanation(explanation: &Value) -> String {
+    let mut out = String::new();
+    render_node(explanation, 0, &mut out);
+    out
+}
+
+fn render_node(node: &Value, depth: usize, out: &mut String) {
+    let value = node["value"].as_f64().unwrap_or_default();
+    let description = node["description"].as_str().unwrap_or_default();
+    out.push_str(&format!(
+        "{:indent$}{value:.4} {description}\n",
+        "",
+        indent = depth * 2
+    ));
+    for detail in node["details"].as_array().into_iter().flatten() {
+        render_node(detail, depth + 1, out);
+    }
+}
+
+/// Renders the structure of `query` as an indented tree, one clause per line.
+///
+/// Boolean clauses are prefixed with their occurrence (`+` must, `-` must
+/// not, none for should), terms and phrases are shown as `field:value`
+/// with the field names of `schema` and decoded values. Any other query,
+/// e.g. a range or a boost, is shown through its `Debug` representation.
+pub fn render_plan(schema: &Schema, query: &dyn Query) -> String {
+    let mut out = String::new();
+    render_query(schema, query, "", 0, &mut out);
+    out
+}
+
+fn render_query(schema: &Schema, query: &dyn Query, prefix: &str, depth: usize, out: &mut String) {
+    let indent = depth * 2;
+    if let Some(boolean) = query.downcast_ref::<BooleanQuery>() {
+        out.push_str(&format!("{:indent$}{prefix}Boolean\n", ""));
+        for (occur, clause) in boolean.clauses() {
+            let prefix = match occur {
+                Occur::Must => "+",
+                Occur::MustNot => "-",
+                Occur::Should => "",
+            };
+            render_query(schema, clause.as_ref(), prefix, depth + 1, out);
+        }
+    } else if let Some(term) = query.downcast_ref::<TermQuery>() {
+        let term = render_term(schema, term.term());
+        out.push_str(&format!("{:indent$}{prefix}Term({term})\n", ""));
+    } else if let Some(phrase) = query.downcast_ref::<PhraseQuery>() {
+        let terms: Vec<String> = phrase
+            .phrase_terms()
+            .iter()
+            .map(|term| render_term(schema, term))
+            .collect();
+        out.push_str(&format!(
+            "{:indent$}{prefix}Phrase({})\n",
+            "",
+            terms.join(" ")
+        ));
+    } else {
+        out.push_str(&format!("{:indent$}{prefix}{query:?}\n", ""));
+    }
+}
+
+fn render_term(schema: &Schema, term: &Term) -> String {
+    let name = schema.get_field_name(term.field());
+    match term_value(term) {
+        Some(value) if term.typ() == Type::Str => format!("{name}:{value:?}"),
+        Some(value) => format!("{name}:{value}"),
+        None => format!("{term:?}"),
+    }
+}
+
+/// The value of `term`, decoded, for the types a query term usually has.
+fn term_value(term: &Term) -> Option<String> {
+    let value = term.value();
+    match value.typ() {
+        Type::Str => value.as_str().map(str::to_string),
+        Type::U64 => value.as_u64().map(|number| number.to_string()),
+        Type::I64 => value.as_i64().map(|number| number.to_string()),
+        Type::F64 => value.as_f64().map(|number| number.to_string()),
+        Type::Bool => value.as_bool().map(|flag| flag.to_string()),
+        Type::Facet => value.as_facet().map(|facet| facet.to_string()),
+        _ => None,
+    }
+}
+
+/// Extracts the BM25 factors of every term of `query` for `doc_address`,
+/// as a flat map of features for learning to rank.
+///
+/// For a term like `title:sea`, the features are:
+///
+/// - `title:sea.score`: the BM25 weight of the term in the document,
+/// - `title:sea.tf`: its number of occurrences in the field,
+/// - `title:sea.idf`: how rare it is in the index,
+/// - `title:sea.tf_norm`: the term frequency, saturated and normalized by
+///   the field length,
+/// - `title:sea.dl`: the field length, in tokens.
+///
+/// The weights are those of the bare terms, without the boosts of `query`.
+/// A term missing from the document only gets a `score` and a `tf` of 0,
+/// so every term of the query has features.
+pub fn term_features(
+    searcher: &Searcher,
+    query: &dyn Query,
+    doc_address: DocAddress,
+) -> tantivy::Result<HashMap<String, f32>> {
+    let schema = searcher.schema();
+    let mut terms = Vec::new();
+    query.query_terms(&mut |term, _| terms.push(term.clone()));
+
+    let mut features = HashMap::new();
+    for term in terms {
+        let name = schema.get_field_name(term.field());
+        let key = match term_value(&term) {
+            Some(value) => format!("{name}:{value}"),
+            None => format!("{term:?}"),
+        };
+        let term_query = TermQuery::new(term, IndexRecordOption::WithFreqs);
+        let Ok(explanation) = term_query.explain(searcher, doc_address) else {
+            features.insert(format!("{key}.score"), 0.0);
+            features.insert(format!("{key}.tf"), 0.0);
+            continue;
+        };
+        let explanation = explanation_to_json(&explanation)?;
+        features.insert(format!("{key}.score"), node_value(&explanation));
+        for (feature, description) in [
+            ("tf", "freq, occurrences"),
+            ("idf", "idf,"),
+            ("tf_norm", "freq / (freq"),
+            ("dl", "dl,"),
+        ] {
+            if let Some(node) = find_node(&explanation, description) {
+                features.insert(format!("{key}.{feature}"), node_value(node));
+            }
+        }
+    }
+    Ok(features)
+}
+
+/// The first node of the explanation tree whose description starts with `prefix`.
+fn find_node<'a>(node: &'a Value, prefix: &str) -> Option<&'a Value> {
+    if node["description"]
+        .as_str()
+        .is_some_and(|description| description.starts_with(prefix))
+    {
+        return Some(node);
+    }
+    node["details"]
+        .as_array()?
+        .iter()
+        .find_map(|detail| find_node(detail, prefix))
+}
+
+fn node_value(node: &Value) -> f32 {
+    node["value"].as_f64().unwrap_or_default() as f32
+}
@@ -0,0 +1,176 @@
+//! Dumping an index as NDJSON, loading the dump back, reading the stored
+//! documents of a segment by doc id, and comparing the documents of two
+//! indexes.
+//!
+//! Only stored fields can be read back from an index: with the sample
+//! schema the `body` is indexed but not stored, so it is not part of a dump.
+
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+use tantivy::schema::{Document, Field, Schema, Value};
+use tantivy::{DocId, Index, Searcher, SegmentReader, TantivyError};
+use tracing::info;
+
+use crate::ingest::{ingest_ndjson, IngestReport};
+use crate::metrics::Metrics;
+use crate::DEFAULT_HEAP_SIZE;
+
+/// Number of decompressed store blocks each segment keeps cached while exporting.
+const STORE_CACHE_BLOCKS: usize = 1;
+
+/// Writes every live document of `searcher` to `output`, one JSON object per line.
+///
+/// Documents are streamed segment by segment straight from the doc store,
+/// so memory use doesn't grow with the size of the index.
+/// Returns the number of documents written.
+pub fn export_ndjson<W: Write>(searcher: &Searcher, mut output: W) -> tantivy::Result<u64> {
+    let schema = searcher.schema();
+    let mut count = 0;
+    for segment_reader in searcher.segment_readers() {
+        let store_reader = segment_reader.get_store_reader(STORE_CACHE_BLOCKS)?;
+        for doc in store_reader.iter(segment_reader.alive_bitset()) {
+            writeln!(output, "{}", schema.to_json(&doc?))?;
+            count += 1;
+        }
+    }
+    output.flush()?;
+    Ok(count)
+}
+
+/// The live documents of `segment_reader` with a local doc id in `[start, end)`,
+/// in insertion order, for debugging or sampling a segment.
+///
+/// Deleted documents are skipped, and ids past the end of the segment ignored.
+pub fn fetch_doc_range(
+    segment_reader: &SegmentReader,
+    start: DocId,
+    end: DocId,
+) -> tantivy::Result<Vec<Document>> {
+    let store_reader = segment_reader.get_store_reader(STORE_CACHE_BLOCKS)?;
+    (start..end.min(segment_reader.max_doc()))
+        .filter(|&doc_id| !segment_reader.is_deleted(doc_id))
+        .map(|doc_id| store_reader.get(doc_id))
+        .collect()
+}
+
+/// How the documents of two indexes differ, from [`diff_indexes`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IndexDiff {
+    /// Documents whose id is only in the first index.
+    pub only_in_a: u64,
+    /// Documents whose id is only in the second index.
+    pub only_in_b: u64,
+    /// Documents in both whose stored fields differ.
+    pub differing: u64,
+    /// Documents in both with the same stored fields.
+    pub identical: u64,
+    /// Live documents of either index without an id, which can't be compared.
+    pub missing_id: u64,
+    /// Some of the ids only in the first index, in text order.
+    pub sample_only_in_a: Vec<String>,
+    /// Some of the ids only in the second index, in text order.
+    pub sample_only_in_b: Vec<String>,
+    /// Some of the ids of the differing documents, in text order.
+    pub sample_differing: Vec<String>,
+}
+
+/// Compares the live documents of `a` and `b`, matched by the stored value
+/// of their `id_field`, e.g. to check a reindex kept every document.
+///
+/// Documents are equal when they have the same values for the same stored
+/// field names, so the two schemas don't need to be the same. At most
+/// `sample` ids of each kind of difference are kept. The stored fields of
+/// `a` are held in memory while `b` is read.
+pub fn diff_indexes(
+    a: &Searcher,
+    b: &Searcher,
+    id_field: &str,
+    sample: usize,
+) -> tantivy::Result<IndexDiff> {
+    let mut diff = IndexDiff::default();
+    let mut in_a = HashMap::new();
+    diff.missing_id += for_each_keyed_doc(a, id_field, |id, doc| {
+        in_a.insert(id, doc);
+    })?;
+    let mut only_in_b = Vec::new();
+    let mut differing = Vec::new();
+    diff.missing_id += for_each_keyed_doc(b, id_field, |id, doc| match in_a.remove(&id) {
+        None => only_in_b.push(id),
+        Some(doc_a) if doc_a != doc => differing.push(id),
+        Some(_) => diff.identical += 1,
+    })?;
+    let only_in_a = in_a.into_keys().collect();
+
+    let summarize = |mut ids: Vec<String>| {
+        ids.sort();
+        let count = ids.len() as u64;
+        ids.truncate(sample);
+        (count, ids)
+    };
+    (diff.only_in_a, diff.sample_only_in_a) = summarize(only_in_a);
+    (diff.only_in_b, diff.sample_only_in_b) = summarize(only_in_b);
+    (diff.differing, diff.sample_differing) = summarize(differing);
+    Ok(diff)
+}
+
+/// Calls `visit` with the id and the stored fields, as JSON, of every live
+/// document of `searcher`. Returns the number of documents without an id.
+fn for_each_keyed_doc(
+    searcher: &Searcher,
+    id_field: &str,
+    mut visit: impl FnMut(String, String),
+) -> tantivy::Result<u64> {
+    let schema = searcher.schema();
+    let field = id_field_of(schema, id_field)?;
+    let mut missing_id = 0;
+    for segment_reader in searcher.segment_readers() {
+        let store_reader = segment_reader.get_store_reader(STORE_CACHE_BLOCKS)?;
+        for doc in store_reader.iter(segment_reader.alive_bitset()) {
+            let doc = doc?;
+            let Some(id) = doc.get_first(field).map(id_string) else {
+                missing_id += 1;
+                continue;
+            };
+            let named_doc = serde_json::to_string(&schema.to_named_doc(&doc))
+                .map_err(|err| TantivyError::InternalError(err.to_string()))?;
+            visit(id, named_doc);
+        }
+    }
+    Ok(missing_id)
+}
+
+/// The field `name` of `schema`, which must be stored to identify documents.
+fn id_field_of(schema: &Schema, name: &str) -> tantivy::Result<Field> {
+    let field = schema
+        .get_field(name)
+        .map_err(|_| TantivyError::InvalidArgument(format!("unknown id field `{name}`")))?;
+    if !schema.get_field_entry(field).is_stored() {
+        return Err(TantivyError::SchemaError(format!(
+            "id field `{name}` is not stored"
+        )));
+    }
+    Ok(field)
+}
+
+/// An id value as text: strings as they are, other values as JSON.
+fn id_string(value: &Value) -> String {
+    match value {
+        Value::Str(text) => text.clone(),
+        value => serde_json::to_string(value).unwrap_or_default(),
+    }
+}
+
+/// Indexes a dump produced by [`export_ndjson`] into `index` and commits.
+///
+/// Each line goes through [`ingest_ndjson`], which takes the arrays
+/// of the dump as multi-valued fields, so `index` must have a schema
+/// compatible with the exported one.
+pub fn import_ndjson<R: BufRead>(index: &Index, input: R) -> tantivy::Result<IngestReport> {
+    let mut index_writer = index.writer(DEFAULT_HEAP_SIZE)?;
+    let report = ingest_ndjson(&mut index_writer, &index.schema(), input)?;
+    let opstamp = index_writer.commit()?;
+    Metrics::global().observe_commit(report.indexed);
+    info!(docs = report.indexed, opstamp, "committed import");
+    Ok(report)
+}
@@ -0,0 +1,212 @@
+//! Geographic ranking over `lat`/`lon` fast fields.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::ops::Bound;
+
+use tantivy::collector::{Collector, SegmentCollector, TopDocs};
+use tantivy::columnar::Column;
+use tantivy::query::{BooleanQuery, ConstScoreQuery, Occur, Query};
+use tantivy::schema::Field;
+use tantivy::{DocAddress, DocId, Score, Searcher, SegmentOrdinal, SegmentReader, TantivyError};
+
+use crate::query::f64_range;
+
+/// Mean Earth radius, in kilometers.
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Great-circle distance in kilometers between two `(lat, lon)` points in degrees.
+pub fn haversine_km(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (lat1, lon1) = (from.0.to_radians(), from.1.to_radians());
+    let (lat2, lon2) = (to.0.to_radians(), to.1.to_radians());
+    let a = ((lat2 - lat1) / 2.0).sin().powi(2)
+        + lat1.cos() * lat2.cos() * ((lon2 - lon1) / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+/// A match together with its distance to the query point.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GeoHit {
+    pub score: Score,
+    /// `None` when the document has no coordinates.
+    pub distance_km: Option<f64>,
+    pub doc_address: DocAddress,
+}
+
+/// Nearest first, documents without coordinates last.
+fn by_distance(left: &GeoHit, right: &GeoHit) -> Ordering {
+    match (left.distance_km, right.distance_km) {
+        (Some(left), Some(right)) => left.total_cmp(&right),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// A hit ordered by distance, then by doc address, the farthest greatest.
+struct ByDistance(GeoHit);
+
+impl Ord for ByDistance {
+    fn cmp(&self, other: &Self) -> Ordering {
+        by_distance(&self.0, &other.0).then(self.0.doc_address.cmp(&other.0.doc_address))
+    }
+}
+
+impl PartialOrd for ByDistance {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for ByDistance {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for ByDistance {}
+
+/// Collects the `limit` matches closest to `origin`.
+///
+/// Coordinates are read from two `f64` fast fields, in degrees.
+/// The text score of each hit is kept alongside its distance.
+pub struct GeoDistanceCollector {
+    lat_field: String,
+    lon_field: String,
+    origin: (f64, f64),
+    limit: usize,
+}
+
+impl GeoDistanceCollector {
+    pub fn new(lat_field: &str, lon_field: &str, origin: (f64, f64), limit: usize) -> Self {
+        GeoDistanceCollector {
+            lat_field: lat_field.to_string(),
+            lon_field: lon_field.to_string(),
+            origin,
+            limit,
+        }
+    }
+}
+
+impl Collector for GeoDistanceCollector {
+    type Fruit = Vec<GeoHit>;
+    type Child = GeoDistanceSegmentCollector;
+
+    fn for_segment(
+        &self,
+        segment_ord: SegmentOrdinal,
+        segment: &SegmentReader,
+    ) -> tantivy::Result<Self::Child> {
+        let fast_fields = segment.fast_fields();
+        Ok(GeoDistanceSegmentCollector {
+            segment_ord,
+            lat: fast_fields.f64(&self.lat_field)?,
+            lon: fast_fields.f64(&self.lon_field)?,
+            origin: self.origin,
+            limit: self.limit,
+            nearest: BinaryHeap::new(),
+        })
+    }
+
+    fn requires_scoring(&self) -> bool {
+        true
+    }
+
+    fn merge_fruits(&self, segment_hits: Vec<Vec<GeoHit>>) -> tantivy::Result<Vec<GeoHit>> {
+        let mut hits: Vec<GeoHit> = segment_hits.into_iter().flatten().collect();
+        hits.sort_by(by_distance);
+        hits.truncate(self.limit);
+        Ok(hits)
+    }
+}
+
+/// Per-segment half of [`GeoDistanceCollector`].
+pub struct GeoDistanceSegmentCollector {
+    segment_ord: SegmentOrdinal,
+    lat: Column<f64>,
+    lon: Column<f64>,
+    origin: (f64, f64),
+    limit: usize,
+    /// The `limit` nearest hits so far, the farthest on top.
+    nearest: BinaryHeap<ByDistance>,
+}
+
+impl SegmentCollector for GeoDistanceSegmentCollector {
+    type Fruit = Vec<GeoHit>;
+
+    fn collect(&mut self, doc: DocId, score: Score) {
+        let distance_km = match (self.lat.first(doc), self.lon.first(doc)) {
+            (Some(lat), Some(lon)) => Some(haversine_km(self.origin, (lat, lon))),
+            _ => None,
+        };
+        let hit = ByDistance(GeoHit {
+            score,
+            distance_km,
+            doc_address: DocAddress::new(self.segment_ord, doc),
+        });
+        if self.nearest.len() < self.limit {
+            self.nearest.push(hit);
+        } else if self.nearest.peek().is_some_and(|farthest| hit < *farthest) {
+            self.nearest.pop();
+            self.nearest.push(hit);
+        }
+    }
+
+    fn harvest(self) -> Vec<GeoHit> {
+        let nearest = self.nearest.into_sorted_vec();
+        nearest.into_iter().map(|ByDistance(hit)| hit).collect()
+    }
+}
+
+/// A latitude/longitude rectangle, bounds included, in degrees.
+///
+/// A box with `min_lon` greater than `max_lon` crosses the antimeridian:
+/// `min_lon: 170.0, max_lon: -170.0` spans the 20 degrees around it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingBox {
+    pub min_lat: f64,
+    pub max_lat: f64,
+    pub min_lon: f64,
+    pub max_lon: f64,
+}
+
+/// Runs `query` restricted to the documents inside `bbox`, returning the
+/// `limit` best matches.
+///
+/// `lat_field` and `lon_field` are `f64` fast fields in degrees; the range
+/// queries read them without being indexed. They only filter: the scores
+/// are those of `query`.
+pub fn search_in_bbox(
+    searcher: &Searcher,
+    lat_field: Field,
+    lon_field: Field,
+    bbox: BoundingBox,
+    query: &dyn Query,
+    limit: usize,
+) -> tantivy::Result<Vec<(Score, DocAddress)>> {
+    if bbox.min_lat > bbox.max_lat {
+        return Err(TantivyError::InvalidArgument(format!(
+            "min_lat {} is above max_lat {}",
+            bbox.min_lat, bbox.max_lat
+        )));
+    }
+    let schema = searcher.schema();
+    let range = |field, min, max| -> tantivy::Result<Box<dyn Query>> {
+        let range = f64_range(schema, field, Bound::Included(min), Bound::Included(max))?;
+        Ok(Box::new(ConstScoreQuery::new(Box::new(range), 0.0)))
+    };
+    let lon_filter = if bbox.min_lon <= bbox.max_lon {
+        range(lon_field, bbox.min_lon, bbox.max_lon)?
+    } else {
+        Box::new(BooleanQuery::new(vec![
+            (Occur::Should, range(lon_field, bbox.min_lon, 180.0)?),
+            (Occur::Should, range(lon_field, -180.0, bbox.max_lon)?),
+        ]))
+    };
+    let bbox_query = BooleanQuery::new(vec![
+        (Occur::Must, query.box_clone()),
+        (Occur::Must, range(lat_field, bbox.min_lat, bbox.max_lat)?),
+        (Occur::Must, lon_filter),
+    ]);
+    searcher.search(&bbox_query, &TopDocs::with_limit(limit))
+}
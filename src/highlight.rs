@@ -0,0 +1,198 @@
+//! Highlighting the matched terms of a query in the stored text.
+//!
+//! The tokenizer lowercases terms, but highlighting doesn't work on the
+//! indexed terms: `SnippetGenerator` re-tokenizes the *stored* text and
+//! keeps the byte offsets of every token that matches a query term.
+//! The fragment is then cut from the stored text itself, so searching
+//! `old` highlights `Old` in "The Old Man and the Sea", casing intact.
+//!
+//! A field that is indexed with positions but not stored can still be
+//! highlighted: its text is rebuilt from the index, by looking up the
+//! positions of the document in the postings of every term of the field.
+//! That reads the whole term dictionary of the field for each document,
+//! and gives back indexed terms, not text: lowercased, maybe stemmed, and
+//! without punctuation. Storing the field costs disk space, rebuilding it
+//! costs time and fidelity.
+
+use std::collections::{BTreeMap, HashSet};
+
+use tantivy::postings::Postings;
+use tantivy::query::Query;
+use tantivy::schema::{Document, Field, FieldType, IndexRecordOption};
+use tantivy::tokenizer::TextAnalyzer;
+use tantivy::{DocAddress, DocSet, Searcher, Snippet, SnippetGenerator, TantivyError};
+
+/// How many tokens a snippet rebuilt from the index shows.
+const REBUILT_SNIPPET_TOKENS: usize = 20;
+/// How many of them come before the first match.
+const REBUILT_SNIPPET_CONTEXT: u32 = 5;
+
+/// Highlights the matches of one query in one field.
+pub struct Highlighter {
+    snippet_generator: SnippetGenerator,
+    field: Field,
+    stored: bool,
+    tokenizer: TextAnalyzer,
+    /// The terms of the query in `field`, as indexed.
+    query_terms: HashSet<Vec<u8>>,
+    prefix: String,
+    postfix: String,
+}
+
+impl Highlighter {
+    /// Matches will be wrapped in `prefix` and `postfix`, e.g. `"<b>"` and `"</b>"`.
+    ///
+    /// Fails if `field` is neither stored nor indexed with positions,
+    /// as there is then no text to highlight.
+    pub fn new(
+        searcher: &Searcher,
+        query: &dyn Query,
+        field: Field,
+        prefix: &str,
+        postfix: &str,
+    ) -> tantivy::Result<Highlighter> {
+        let field_entry = searcher.schema().get_field_entry(field);
+        let has_positions = match field_entry.field_type() {
+            FieldType::Str(options) => options
+                .get_indexing_options()
+                .is_some_and(|indexing| indexing.index_option().has_positions()),
+            _ => false,
+        };
+        if !field_entry.is_stored() && !has_positions {
+            return Err(TantivyError::SchemaError(format!(
+                "field `{}` must be stored to be highlighted, \
+                 or at least indexed with positions",
+                field_entry.name()
+            )));
+        }
+
+        let mut query_terms = HashSet::new();
+        query.query_terms(&mut |term, _| {
+            if term.field() == field {
+                query_terms.insert(term.serialized_value_bytes().to_vec());
+            }
+        });
+        Ok(Highlighter {
+            snippet_generator: SnippetGenerator::create(searcher, query, field)?,
+            field,
+            stored: field_entry.is_stored(),
+            tokenizer: searcher.index().tokenizer_for_field(field)?,
+            query_terms,
+            prefix: prefix.to_string(),
+            postfix: postfix.to_string(),
+        })
+    }
+
+    /// The best fragment of the field in `doc`, empty when the field is not stored.
+    pub fn snippet(&self, doc: &Document) -> Snippet {
+        let mut snippet = self.snippet_generator.snippet_from_doc(doc);
+        snippet.set_snippet_prefix_postfix(&self.prefix, &self.postfix);
+        snippet
+    }
+
+    /// The highlighted fragment of the document at `doc_address`, as HTML.
+    ///
+    /// It comes from `doc` when the field is stored, and is rebuilt from the
+    /// index otherwise, see the module documentation.
+    pub fn highlight(
+        &self,
+        searcher: &Searcher,
+        doc_address: DocAddress,
+        doc: &Document,
+    ) -> tantivy::Result<String> {
+        if self.stored {
+            return Ok(self.snippet(doc).to_html());
+        }
+
+        let tokens = self.rebuild_tokens(searcher, doc_address)?;
+        let first_match = tokens
+            .iter()
+            .find(|(_, (_, matched))| *matched)
+            .map_or(0, |(&position, _)| position);
+        let start = first_match.saturating_sub(REBUILT_SNIPPET_CONTEXT);
+        let fragment: Vec<String> = tokens
+            .range(start..)
+            .take(REBUILT_SNIPPET_TOKENS)
+            .map(|(_, (text, matched))| {
+                let text = escape_html(text);
+                if *matched {
+                    format!("{}{text}{}", self.prefix, self.postfix)
+                } else {
+                    text
+                }
+            })
+            .collect();
+        Ok(fragment.join(" "))
+    }
+
+    /// How many times the query matches the field in the document at
+    /// `doc_address`, over the whole text and not only the best fragment,
+    /// for a UI showing "3 matches in body".
+    ///
+    /// Every matching token counts once: a term found three times counts 3.
+    pub fn match_count(
+        &self,
+        searcher: &Searcher,
+        doc_address: DocAddress,
+        doc: &Document,
+    ) -> tantivy::Result<usize> {
+        if !self.stored {
+            let tokens = self.rebuild_tokens(searcher, doc_address)?;
+            return Ok(tokens.values().filter(|(_, matched)| *matched).count());
+        }
+        let mut tokenizer = self.tokenizer.clone();
+        let mut count = 0;
+        for text in doc.get_all(self.field).filter_map(|value| value.as_text()) {
+            let mut token_stream = tokenizer.token_stream(text);
+            while token_stream.advance() {
+                if self
+                    .query_terms
+                    .contains(token_stream.token().text.as_bytes())
+                {
+                    count += 1;
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    /// The indexed tokens of the field in one document, by position,
+    /// with whether they match the query.
+    fn rebuild_tokens(
+        &self,
+        searcher: &Searcher,
+        doc_address: DocAddress,
+    ) -> tantivy::Result<BTreeMap<u32, (String, bool)>> {
+        let segment_reader = searcher.segment_reader(doc_address.segment_ord);
+        let inverted_index = segment_reader.inverted_index(self.field)?;
+        let mut tokens = BTreeMap::new();
+        let mut positions = Vec::new();
+        let mut stream = inverted_index.terms().stream()?;
+        while stream.advance() {
+            let mut postings = inverted_index.read_postings_from_terminfo(
+                stream.value(),
+                IndexRecordOption::WithFreqsAndPositions,
+            )?;
+            // Postings start on their first document, and can't seek backwards.
+            if postings.doc() < doc_address.doc_id {
+                postings.seek(doc_address.doc_id);
+            }
+            if postings.doc() != doc_address.doc_id {
+                continue;
+            }
+            postings.positions(&mut positions);
+            let text = String::from_utf8_lossy(stream.key()).into_owned();
+            let matched = self.query_terms.contains(stream.key());
+            for &position in &positions {
+                tokens.insert(position, (text.clone(), matched));
+            }
+        }
+        Ok(tokens)
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
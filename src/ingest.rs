@@ -0,0 +1,597 @@
+//! Loading documents from JSON.
+//!
+//! Documents are read as NDJSON: one JSON object per line,
+//! keyed by field name, e.g. `{"title": "Of Mice and Men", "body": "..."}`.
+//! An array, e.g. `"tags": ["novella", "classic"]`, adds each of its
+//! elements as a value of the field, and a scalar adds a single one.
+//! `null` values, alone or in an array, are skipped.
+//! Files ending in `.gz` are decompressed on the fly, see [`open_input`].
+//! An [`InterruptibleInput`] ends early on shutdown, so what was read so
+//! far can still be committed.
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use flate2::read::GzDecoder;
+use tantivy::directory::error::OpenWriteError;
+use tantivy::merge_policy::NoMergePolicy;
+use tantivy::schema::{DocParsingError, Document, Field, FieldType, Schema};
+use tantivy::{Index, IndexWriter, Opstamp, TantivyError};
+use tracing::{info, warn};
+
+use crate::document::check_document;
+use crate::metrics::Metrics;
+use crate::search::exact_term;
+use crate::template::format_value;
+use crate::transform::Transformers;
+use crate::DEFAULT_HEAP_SIZE;
+
+/// What happened while ingesting a stream of documents.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IngestReport {
+    /// Documents handed to the writer.
+    pub indexed: u64,
+    /// Lines that could not be parsed into a document.
+    pub skipped: u64,
+    /// The numbers of the skipped lines, from 1.
+    pub skipped_lines: Vec<u64>,
+}
+
+impl IngestReport {
+    /// Fails if any line was skipped, listing the first ones, for loads
+    /// where bad data must stop the pipeline rather than go unnoticed.
+    ///
+    /// Call it before committing, so a failed load leaves nothing behind.
+    pub fn fail_on_skipped(&self) -> tantivy::Result<()> {
+        const LISTED: usize = 10;
+        if self.skipped == 0 {
+            return Ok(());
+        }
+        let mut lines: Vec<String> = self
+            .skipped_lines
+            .iter()
+            .take(LISTED)
+            .map(u64::to_string)
+            .collect();
+        if self.skipped_lines.len() > LISTED {
+            lines.push(format!("and {} more", self.skipped_lines.len() - LISTED));
+        }
+        Err(TantivyError::InvalidArgument(format!(
+            "{} malformed lines were skipped: {}",
+            self.skipped,
+            lines.join(", ")
+        )))
+    }
+}
+
+/// Adds every NDJSON line of `input` to `writer`.
+///
+/// Blank lines are ignored and malformed ones are skipped with a warning,
+/// so one bad record doesn't throw away a whole load.
+/// Nothing is committed: that is left to the caller.
+pub fn ingest_ndjson<R: BufRead>(
+    writer: &mut IndexWriter,
+    schema: &Schema,
+    input: R,
+) -> tantivy::Result<IngestReport> {
+    parse_ndjson(schema, input, |doc| writer.add_document(doc).map(|_| ()))
+}
+
+/// Same as [`ingest_ndjson`], stamping every document with the opstamp of
+/// the last commit in the `u64` field `opstamp_field`.
+///
+/// This is what a change feed needs: after seeing commit `n`, the documents
+/// ingested since are exactly those stamped with `n` or more, see
+/// [`crate::search::changes_since`].
+pub fn ingest_stamped<R: BufRead>(
+    writer: &mut IndexWriter,
+    schema: &Schema,
+    input: R,
+    opstamp_field: Field,
+) -> tantivy::Result<IngestReport> {
+    let field_entry = schema.get_field_entry(opstamp_field);
+    if !matches!(field_entry.field_type(), FieldType::U64(_)) {
+        return Err(TantivyError::SchemaError(format!(
+            "field `{}` must be a u64 field to hold opstamps",
+            field_entry.name()
+        )));
+    }
+    // `IndexWriter::commit_opstamp` stays at the commit the writer was
+    // opened on, the metas have the last one.
+    let opstamp = writer.index().load_metas()?.opstamp;
+    parse_ndjson(schema, input, |mut doc| {
+        doc.add_u64(opstamp_field, opstamp);
+        writer.add_document(doc).map(|_| ())
+    })
+}
+
+/// Same as [`ingest_ndjson`], running the values of each document through
+/// `transformers` before it is added.
+pub fn ingest_transformed<R: BufRead>(
+    writer: &mut IndexWriter,
+    schema: &Schema,
+    input: R,
+    transformers: &Transformers,
+) -> tantivy::Result<IngestReport> {
+    parse_ndjson(schema, input, |doc| {
+        writer.add_document(transformers.apply(doc)).map(|_| ())
+    })
+}
+
+/// Same as [`ingest_ndjson`], each document replacing those with the same
+/// value of `id_field`, if any, for loading the same file again.
+///
+/// A later line with the same id as an earlier one replaces it too.
+/// Documents without an id are added as they are, with a warning: nothing
+/// will ever replace them.
+pub fn upsert_ndjson<R: BufRead>(
+    writer: &mut IndexWriter,
+    schema: &Schema,
+    input: R,
+    id_field: Field,
+) -> tantivy::Result<IngestReport> {
+    parse_ndjson(schema, input, |doc| {
+        let id_term = match doc.get_first(id_field) {
+            Some(id) => exact_term(schema, id_field, &format_value(id, None))?,
+            None => None,
+        };
+        match id_term {
+            Some(id_term) => {
+                writer.delete_term(id_term);
+            }
+            None => warn!("adding a document without an id, it can't be replaced"),
+        }
+        writer.add_document(doc).map(|_| ())
+    })
+}
+
+/// Parses every NDJSON line of `input`, handing the documents to `add`.
+fn parse_ndjson<R, F>(schema: &Schema, input: R, add: F) -> tantivy::Result<IngestReport>
+where
+    R: BufRead,
+    F: FnMut(Document) -> tantivy::Result<()>,
+{
+    parse_ndjson_from(schema, input, 0, add).map(|(report, _)| report)
+}
+
+/// Like [`parse_ndjson`], numbering the lines of `input` from `lines_before + 1`.
+///
+/// Also returns how many lines `input` had, blank ones included.
+fn parse_ndjson_from<R, F>(
+    schema: &Schema,
+    input: R,
+    lines_before: u64,
+    mut add: F,
+) -> tantivy::Result<(IngestReport, u64)>
+where
+    R: BufRead,
+    F: FnMut(Document) -> tantivy::Result<()>,
+{
+    let mut report = IngestReport::default();
+    let mut line_number = lines_before;
+    for line in input.lines() {
+        let line = line?;
+        line_number += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse_document(schema, &line) {
+            Ok(doc) => {
+                add(doc)?;
+                report.indexed += 1;
+            }
+            Err(err) => {
+                warn!(line = line_number, %err, "skipping malformed line");
+                report.skipped += 1;
+                report.skipped_lines.push(line_number);
+            }
+        }
+    }
+    Ok((report, line_number - lines_before))
+}
+
+/// Parses one NDJSON line like `Schema::parse_document`, skipping `null`s.
+fn parse_document(schema: &Schema, line: &str) -> Result<Document, DocParsingError> {
+    let mut object: serde_json::Map<String, serde_json::Value> = serde_json::from_str(line)
+        .map_err(|_| DocParsingError::InvalidJson(line.chars().take(20).collect()))?;
+    object.retain(|_, value| !value.is_null());
+    for value in object.values_mut() {
+        if let serde_json::Value::Array(values) = value {
+            values.retain(|value| !value.is_null());
+        }
+    }
+    schema.json_object_to_doc(object)
+}
+
+/// Adds each of `docs` to `writer`, returning the opstamp or the error of each.
+///
+/// A document that doesn't fit the schema is rejected on its own instead
+/// of failing the whole batch, see [`check_document`]. Nothing is committed.
+pub fn add_documents(writer: &IndexWriter, docs: Vec<Document>) -> Vec<tantivy::Result<Opstamp>> {
+    let schema = writer.index().schema();
+    docs.into_iter()
+        .map(|doc| {
+            check_document(&schema, &doc)?;
+            writer.add_document(doc)
+        })
+        .collect()
+}
+
+/// Same as [`ingest_ndjson`], reading from the file at `path`.
+pub fn ingest_file(
+    writer: &mut IndexWriter,
+    schema: &Schema,
+    path: &Path,
+) -> tantivy::Result<IngestReport> {
+    ingest_ndjson(writer, schema, open_input(path)?)
+}
+
+/// Opens the file at `path` for reading, gunzipping it if it ends in `.gz`.
+pub fn open_input(path: &Path) -> io::Result<Box<dyn BufRead + Send>> {
+    let file = File::open(path)?;
+    if path.extension().is_some_and(|extension| extension == "gz") {
+        Ok(Box::new(BufReader::new(GzDecoder::new(file))))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+/// Creates a writer whose segments are exactly what [`ingest_in_segments`] commits.
+///
+/// Each indexing thread writes its own segment, so there is a single one,
+/// and segments are never merged in the background.
+pub fn segmenting_writer(index: &Index) -> tantivy::Result<IndexWriter> {
+    let writer = index.writer_with_num_threads(1, DEFAULT_HEAP_SIZE)?;
+    writer.set_merge_policy(Box::new(NoMergePolicy));
+    Ok(writer)
+}
+
+/// Same as [`ingest_ndjson`], committing every `docs_per_segment` documents.
+///
+/// With a [`segmenting_writer`], each commit adds one segment, so ingesting
+/// N documents ends up with N / `docs_per_segment` segments, plus one for
+/// the remainder once the caller commits it.
+/// More segments means more to search in parallel, but more to merge later.
+pub fn ingest_in_segments<R: BufRead>(
+    writer: &mut IndexWriter,
+    schema: &Schema,
+    input: R,
+    docs_per_segment: u64,
+) -> tantivy::Result<IngestReport> {
+    if docs_per_segment == 0 {
+        return Err(TantivyError::InvalidArgument(
+            "docs_per_segment must be at least 1".to_string(),
+        ));
+    }
+    let mut pending = 0;
+    parse_ndjson(schema, input, |doc| {
+        writer.add_document(doc)?;
+        pending += 1;
+        if pending == docs_per_segment {
+            let opstamp = writer.commit()?;
+            Metrics::global().observe_commit(pending);
+            info!(docs = pending, opstamp, "committed segment");
+            pending = 0;
+        }
+        Ok(())
+    })
+}
+
+/// How far a resumable load got: the last committed batch and its opstamp.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub batch: u64,
+    pub opstamp: Opstamp,
+}
+
+impl Checkpoint {
+    /// Reads the checkpoint stored in the sidecar file at `path`, if there is one.
+    pub fn load(path: &Path) -> tantivy::Result<Option<Checkpoint>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(path)?;
+        let mut values = content.split_whitespace().map(str::parse::<u64>);
+        match (values.next(), values.next()) {
+            (Some(Ok(batch)), Some(Ok(opstamp))) => Ok(Some(Checkpoint { batch, opstamp })),
+            _ => Err(TantivyError::InvalidArgument(format!(
+                "corrupted checkpoint file {}",
+                path.display()
+            ))),
+        }
+    }
+
+    /// Writes the checkpoint to `path`, replacing the previous one atomically.
+    pub fn save(&self, path: &Path) -> tantivy::Result<()> {
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, format!("{} {}\n", self.batch, self.opstamp))?;
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+}
+
+/// Commits everything added to `writer` so far and returns the commit opstamp.
+///
+/// The commit goes through `prepare_commit` so the batch number is stored
+/// as the commit payload, next to the documents it describes.
+pub fn commit_batch(writer: &mut IndexWriter, batch: u64) -> tantivy::Result<Opstamp> {
+    let mut prepared_commit = writer.prepare_commit()?;
+    prepared_commit.set_payload(&batch.to_string());
+    let opstamp = prepared_commit.commit()?;
+    info!(batch, opstamp, "committed batch");
+    Ok(opstamp)
+}
+
+/// Commits `writer`, retrying up to `retries` times if the commit fails on IO.
+///
+/// The wait before each retry starts at `backoff` and doubles every time.
+/// Only IO failures of the commit itself are retried, as they may be
+/// transient on networked filesystems; any other error, or the last IO
+/// one, is returned as is. A failure of an indexing thread, found while
+/// preparing the commit, is never retried: the documents of that thread
+/// are gone, and committing the others would lose them without an error.
+/// The writer is rolled back to the last commit instead.
+pub fn commit_with_retry(
+    writer: &mut IndexWriter,
+    retries: u32,
+    backoff: Duration,
+) -> tantivy::Result<Opstamp> {
+    let mut attempt = 0;
+    loop {
+        let prepared_commit = match writer.prepare_commit() {
+            Ok(prepared_commit) => prepared_commit,
+            Err(err) => {
+                warn!(%err, "indexing failed, rolling back");
+                if let Err(rollback_err) = writer.rollback() {
+                    warn!(%rollback_err, "rollback failed");
+                }
+                return Err(err);
+            }
+        };
+        match prepared_commit.commit() {
+            Err(err) if attempt < retries && is_io_error(&err) => {
+                let delay = backoff.saturating_mul(1 << attempt.min(16));
+                warn!(%err, ?delay, attempt, "commit failed, retrying");
+                thread::sleep(delay);
+                attempt += 1;
+            }
+            result => {
+                if let Ok(opstamp) = result {
+                    info!(opstamp, attempt, "committed");
+                }
+                return result;
+            }
+        }
+    }
+}
+
+fn is_io_error(err: &TantivyError) -> bool {
+    matches!(
+        err,
+        TantivyError::IoError(_) | TantivyError::OpenWriteError(OpenWriteError::IoError { .. })
+    )
+}
+
+/// Ingests and commits `batches` one at a time, recording progress in `sidecar`.
+///
+/// Batches up to the checkpoint found in `sidecar` are skipped, so a load
+/// that crashed can simply be run again. The checkpoint is written after
+/// the commit returns: a crash in between replays the last batch.
+pub fn ingest_batches<I, R>(
+    writer: &mut IndexWriter,
+    schema: &Schema,
+    batches: I,
+    sidecar: &Path,
+) -> tantivy::Result<Option<Checkpoint>>
+where
+    I: IntoIterator<Item = R>,
+    R: BufRead,
+{
+    let mut checkpoint = Checkpoint::load(sidecar)?;
+    for (batch, input) in (0u64..).zip(batches) {
+        if checkpoint.is_some_and(|done| batch <= done.batch) {
+            continue;
+        }
+        ingest_ndjson(writer, schema, input)?;
+        let opstamp = commit_batch(writer, batch)?;
+        let done = Checkpoint { batch, opstamp };
+        done.save(sidecar)?;
+        checkpoint = Some(done);
+    }
+    Ok(checkpoint)
+}
+
+/// Ingests `batches`, committing each one, while the next is being parsed.
+///
+/// A commit happens in two phases:
+///
+/// - `prepare_commit` waits for the indexing threads, then writes and fsyncs
+///   the new segments. They are on disk but not part of the index yet: after a
+///   crash, the index still rolls back to the previous commit.
+/// - `commit` on the `PreparedCommit` atomically swaps `meta.json`. From then
+///   on the batch is durable, and readers see it once they reload.
+///
+/// The `PreparedCommit` mutably borrows the writer, so documents of batch N+1
+/// cannot be added while batch N is being committed. What can overlap is the
+/// parsing of batch N+1, which happens on another thread during the commit.
+///
+/// Returns the opstamp of every commit, in order, and what was ingested over
+/// all the batches, the skipped lines numbered as if the batches were one input.
+pub fn ingest_pipelined<I, R>(
+    writer: &mut IndexWriter,
+    schema: &Schema,
+    batches: I,
+) -> tantivy::Result<(Vec<Opstamp>, IngestReport)>
+where
+    I: IntoIterator<Item = R>,
+    R: BufRead + Send,
+{
+    let parse_batch = |input: R, lines_before: u64| {
+        let mut docs = Vec::new();
+        parse_ndjson_from(schema, input, lines_before, |doc| {
+            docs.push(doc);
+            Ok(())
+        })
+        .map(|(batch_report, lines)| (docs, batch_report, lines))
+    };
+
+    let mut opstamps = Vec::new();
+    let mut report = IngestReport::default();
+    let mut lines_before = 0;
+    let mut batches = batches.into_iter();
+    thread::scope(|scope| {
+        let mut next_batch = batches.next().map(|input| parse_batch(input, 0));
+        while let Some(parsed) = next_batch {
+            let (docs, batch_report, lines) = parsed?;
+            for doc in docs {
+                writer.add_document(doc)?;
+            }
+            report.indexed += batch_report.indexed;
+            report.skipped += batch_report.skipped;
+            report.skipped_lines.extend(batch_report.skipped_lines);
+            lines_before += lines;
+            let parsing = batches
+                .next()
+                .map(|input| scope.spawn(move || parse_batch(input, lines_before)));
+
+            let prepared_commit = writer.prepare_commit()?;
+            let opstamp = prepared_commit.commit()?;
+            info!(opstamp, "committed batch");
+            opstamps.push(opstamp);
+
+            next_batch = parsing.map(|handle| handle.join().expect("parsing thread panicked"));
+        }
+        Ok((opstamps, report))
+    })
+}
+
+/// How often an [`InterruptibleInput`] waiting for a line checks for a shutdown.
+const SHUTDOWN_POLL: Duration = Duration::from_millis(100);
+
+/// A flag telling a load to stop, shared by every clone.
+#[derive(Clone, Debug, Default)]
+pub struct Shutdown(Arc<AtomicBool>);
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        Shutdown::default()
+    }
+
+    /// A flag requested on SIGINT or SIGTERM, instead of the process dying.
+    ///
+    /// There is one signal handler per process, so this fails if one was
+    /// already installed.
+    pub fn on_signal() -> tantivy::Result<Shutdown> {
+        let shutdown = Shutdown::new();
+        let requested = shutdown.clone();
+        ctrlc::set_handler(move || {
+            warn!("shutdown requested");
+            requested.request();
+        })
+        .map_err(|err| TantivyError::SystemError(format!("cannot handle signals: {err}")))?;
+        Ok(shutdown)
+    }
+
+    pub fn request(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// An input that ends at the next line boundary once `shutdown` is requested.
+///
+/// The input is read line by line on a thread of its own, so even a load
+/// blocked waiting on stdin notices the shutdown, within 100ms. The
+/// lines already read are still handed over, the documents can be committed
+/// as usual, and only the line being read when the shutdown came is lost.
+///
+/// The reading thread is never joined: there is no way to interrupt a
+/// blocking read, so after a shutdown the thread may stay blocked until the
+/// next line or the end of the process. That is fine for the CLI, which
+/// exits once the load is done, and nothing else holds the input meanwhile.
+pub struct InterruptibleInput {
+    lines: Receiver<io::Result<Vec<u8>>>,
+    line: Vec<u8>,
+    consumed: usize,
+    shutdown: Shutdown,
+}
+
+impl InterruptibleInput {
+    pub fn new<R: BufRead + Send + 'static>(
+        mut input: R,
+        shutdown: Shutdown,
+    ) -> InterruptibleInput {
+        let (tx, lines) = mpsc::sync_channel(1);
+        let stop = shutdown.clone();
+        thread::spawn(move || loop {
+            if stop.is_requested() {
+                break;
+            }
+            let mut line = Vec::new();
+            match input.read_until(b'\n', &mut line) {
+                Ok(0) => break,
+                Ok(_) => {
+                    if tx.send(Ok(line)).is_err() {
+                        break;
+                    }
+                }
+                Err(err) => {
+                    let _ = tx.send(Err(err));
+                    break;
+                }
+            }
+        });
+        InterruptibleInput {
+            lines,
+            line: Vec::new(),
+            consumed: 0,
+            shutdown,
+        }
+    }
+}
+
+impl Read for InterruptibleInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for InterruptibleInput {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.consumed == self.line.len() {
+            // Once shut down, only the lines already read are left to take.
+            let line = if self.shutdown.is_requested() {
+                match self.lines.try_recv() {
+                    Ok(line) => line,
+                    Err(_) => return Ok(&[]),
+                }
+            } else {
+                match self.lines.recv_timeout(SHUTDOWN_POLL) {
+                    Ok(line) => line,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => return Ok(&[]),
+                }
+            };
+            self.line = line?;
+            self.consumed = 0;
+        }
+        Ok(&self.line[self.consumed..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.consumed = (self.consumed + amount).min(self.line.len());
+    }
+}
@@ -0,0 +1,39 @@
+//! Small, reusable helpers built while exploring `tantivy`.
+//!
+//! `main.rs` walks through the basic example step by step;
+//! the modules here hold the pieces that are worth reusing.
+
+pub mod analyzer;
+pub mod args;
+pub mod cache;
+pub mod collector;
+pub mod concurrent;
+pub mod document;
+pub mod dsl;
+pub mod explain;
+pub mod export;
+pub mod geo;
+pub mod highlight;
+pub mod ingest;
+pub mod maintenance;
+pub mod memory;
+pub mod metrics;
+pub mod query;
+pub mod rerank;
+pub mod schema;
+pub mod scoring;
+pub mod search;
+pub mod server;
+pub mod suggest;
+pub mod synonyms;
+pub mod template;
+pub mod terms;
+#[doc(hidden)]
+pub mod test_support;
+pub mod transform;
+pub mod update;
+pub mod warmup;
+pub mod watch;
+
+/// Memory budget given to index writers: 50MB is already plenty.
+pub const DEFAULT_HEAP_SIZE: usize = 50_000_000;
@@ -2,10 +2,53 @@
 
 #[macro_use]
 extern crate tantivy;
-use tantivy::{collector::TopDocs, query::QueryParser, schema::*, Index, ReloadPolicy};
+
+mod cli;
+
+use tantivy::{
+    collector::TopDocs, query::QueryParser, schema::*, Index, ReloadPolicy, TantivyError,
+};
+use tantivy_basics::args::Args;
+use tantivy_basics::query::phrase_prefix_query;
+use tantivy_basics::scoring::{self, Scoring};
 use tempfile::TempDir;
 
 fn main() -> tantivy::Result<()> {
+    let mut args = Args::from_env();
+    args.load_config()?;
+    cli::init_logging(&args)?;
+    match args.command() {
+        Some("index") => cli::index(&args),
+        Some("search") => cli::search(&args),
+        Some("clear") => cli::clear(&args),
+        Some("optimize") => cli::optimize(&args),
+        Some("snapshot") => cli::snapshot(&args),
+        Some("export") => cli::export(&args),
+        Some("import") => cli::import(&args),
+        Some("diff") => cli::diff(&args),
+        Some("top-terms") => cli::top_terms(&args),
+        Some("warm") => cli::warm(&args),
+        Some("changes") => cli::changes(&args),
+        Some("migrate") => cli::migrate(&args),
+        Some("count-by") => cli::count_by(&args),
+        Some("serve") => cli::serve(&args),
+        Some("watch") => cli::watch(&args),
+        Some(other) => Err(TantivyError::InvalidArgument(format!(
+            "unknown command `{other}`"
+        ))),
+        // Without a command, walk through the basic example.
+        None => demo(&args),
+    }
+}
+
+fn demo(args: &Args) -> tantivy::Result<()> {
+    // The scoring model can be picked on the command line: `--scoring tfidf`.
+    let scoring: Scoring = args
+        .value("--scoring")
+        .map(|value| value.parse())
+        .transpose()?
+        .unwrap_or_default();
+
     // Temporary directory for index
     let index_path = TempDir::new()?;
 
@@ -124,7 +167,9 @@ fn main() -> tantivy::Result<()> {
     // Keeping track of our top 10 best documents is the role of the TopDocs.
     //
     // We can now perform our query.
-    let top_docs = searcher.search(&query, &TopDocs::with_limit(10))?;
+    // With BM25 this is a plain `TopDocs` search, TF-IDF recomputes
+    // the score of each match from the postings.
+    let top_docs = scoring::search(&searcher, &query, 10, scoring)?;
 
     // The actual documents still need to be retrieved from Tantivy’s store.
     // Since the body field was not configured as stored, the document returned will only contain a title.
@@ -133,5 +178,17 @@ fn main() -> tantivy::Result<()> {
         println!("{}", schema.to_json(&retrieved_doc));
     }
 
+    // PHRASE PREFIX
+    //
+    // For "search as you type", the last word is usually incomplete.
+    // A phrase prefix query requires the first words to match exactly
+    // and treats the last one as a prefix, so "old ma" finds "The Old Man and the Sea".
+    if let Some(query) = phrase_prefix_query(&index, title, "old ma")? {
+        for (_score, doc_address) in searcher.search(&query, &TopDocs::with_limit(10))? {
+            let retrieved_doc = searcher.doc(doc_address)?;
+            println!("{}", schema.to_json(&retrieved_doc));
+        }
+    }
+
     Ok(())
 }
@@ -0,0 +1,437 @@
+//! Housekeeping operations on an existing index.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tantivy::directory::{Directory, MmapDirectory};
+use tantivy::merge_policy::NoMergePolicy;
+use tantivy::schema::{Document, FieldEntry, Schema, Term};
+use tantivy::{Index, IndexReader, IndexWriter, SegmentId, SegmentMeta, TantivyError};
+use tracing::{info, warn};
+
+use crate::DEFAULT_HEAP_SIZE;
+
+/// The file holding the last commit.
+const META_FILE: &str = "meta.json";
+/// The file listing the files the index manages.
+const MANAGED_FILE: &str = ".managed.json";
+
+/// Opens the index at `path`, or creates it there with `schema`.
+///
+/// When an index already exists with another schema, tantivy only reports
+/// that the two are different. This lists the differences instead, one per
+/// field, in a `SchemaError` starting with `schema mismatch`.
+pub fn open_or_create(path: &Path, schema: Schema) -> tantivy::Result<Index> {
+    fs::create_dir_all(path)?;
+    let directory = MmapDirectory::open(path)?;
+    if !Index::exists(&directory)? {
+        return Index::create(directory, schema, Default::default());
+    }
+    let index = Index::open(directory)?;
+    let differences = schema_differences(&index.schema(), &schema);
+    if !differences.is_empty() {
+        return Err(TantivyError::SchemaError(format!(
+            "schema mismatch for the index at {}: {}",
+            path.display(),
+            differences.join("; ")
+        )));
+    }
+    Ok(index)
+}
+
+/// Describes, field by field, how `expected` differs from the schema
+/// `existing` of an index.
+pub fn schema_differences(existing: &Schema, expected: &Schema) -> Vec<String> {
+    let mut differences = Vec::new();
+    for (_, existing_entry) in existing.fields() {
+        let name = existing_entry.name();
+        match expected.get_field(name) {
+            Err(_) => differences.push(format!("field `{name}` is in the index only")),
+            Ok(field) => {
+                let expected_entry = expected.get_field_entry(field);
+                if expected_entry != existing_entry {
+                    differences.push(format!(
+                        "field `{name}` is {} in the index but {} in the schema",
+                        describe(existing_entry),
+                        describe(expected_entry)
+                    ));
+                }
+            }
+        }
+    }
+    for (_, expected_entry) in expected.fields() {
+        if existing.get_field(expected_entry.name()).is_err() {
+            differences.push(format!(
+                "field `{}` is in the schema only",
+                expected_entry.name()
+            ));
+        }
+    }
+    if differences.is_empty() && existing != expected {
+        differences.push("the fields are the same, but not in the same order".to_string());
+    }
+    differences
+}
+
+fn describe(entry: &FieldEntry) -> String {
+    serde_json::to_string(entry).unwrap_or_else(|_| format!("{entry:?}"))
+}
+
+/// Deletes every document of `index` and commits, returning how many there were.
+///
+/// The directory, the schema and the index settings are left untouched,
+/// so the index can be filled again right away.
+pub fn clear(index: &Index) -> tantivy::Result<u64> {
+    let previous_count = index.reader()?.searcher().num_docs();
+    let mut index_writer = index.writer(DEFAULT_HEAP_SIZE)?;
+    index_writer.delete_all_documents()?;
+    let opstamp = index_writer.commit()?;
+    info!(deleted = previous_count, opstamp, "cleared index");
+    Ok(previous_count)
+}
+
+/// Size and fragmentation of an index before and after a merge.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CompactionReport {
+    pub segments_before: usize,
+    pub segments_after: usize,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+impl CompactionReport {
+    /// Disk space given back by the merge. Deleted documents are only
+    /// really removed when their segment is merged, so this is mostly them.
+    pub fn bytes_reclaimed(&self) -> u64 {
+        self.bytes_before.saturating_sub(self.bytes_after)
+    }
+}
+
+/// Total size of the files the index manages: segments, deletes, fast fields...
+pub fn index_size(index: &Index) -> tantivy::Result<u64> {
+    let directory = index.directory();
+    let mut total = 0;
+    for path in directory.list_managed_files() {
+        // Files may be garbage collected while we are listing them.
+        if let Ok(file_handle) = directory.get_file_handle(&path) {
+            total += file_handle.len() as u64;
+        }
+    }
+    Ok(total)
+}
+
+/// Roughly how many bytes an index of `doc_count` documents of
+/// `avg_doc_bytes` bytes of JSON each will take on disk.
+///
+/// This assumes the index ends up about as large as the input, stored
+/// fields being compressed but postings, positions and fast fields coming
+/// on top, plus half of that while segments are still being written.
+/// Real sizes depend heavily on the schema: treat it as an order of magnitude.
+pub fn estimate_index_size(doc_count: u64, avg_doc_bytes: u64) -> u64 {
+    let input_bytes = doc_count.saturating_mul(avg_doc_bytes);
+    input_bytes.saturating_add(input_bytes / 2)
+}
+
+/// Checks that the filesystem of `path` has room for `estimated_bytes` more.
+///
+/// Fails if it doesn't, so a long ingest is not started only to die near
+/// the end. Warns if there is room for the index but not for merging it:
+/// a merge rewrites its segments before deleting the old ones, so it
+/// temporarily needs as much space again.
+pub fn check_disk_space(path: &Path, estimated_bytes: u64) -> tantivy::Result<()> {
+    let available = fs2::available_space(path)?;
+    if available < estimated_bytes {
+        return Err(TantivyError::SystemError(format!(
+            "not enough disk space in {}: about {estimated_bytes} bytes needed, \
+             {available} available",
+            path.display()
+        )));
+    }
+    if available < estimated_bytes.saturating_mul(2) {
+        warn!(
+            available,
+            needed = estimated_bytes,
+            "little disk space left for merging segments"
+        );
+    }
+    Ok(())
+}
+
+/// Merges every searchable segment of `index` into one and
+/// removes the files that are no longer used.
+///
+/// A lone segment is rewritten too if it has deleted documents, so that
+/// they are purged, see [`refresh_statistics`].
+pub fn optimize(index: &Index) -> tantivy::Result<CompactionReport> {
+    let segments_before = index.searchable_segment_ids()?.len();
+    let bytes_before = index_size(index)?;
+
+    let mut index_writer = index.writer(DEFAULT_HEAP_SIZE)?;
+    merge_all(index, &mut index_writer)?;
+    index_writer.garbage_collect_files().wait()?;
+    index_writer.wait_merging_threads()?;
+
+    Ok(CompactionReport {
+        segments_before,
+        segments_after: index.searchable_segment_ids()?.len(),
+        bytes_before,
+        bytes_after: index_size(index)?,
+    })
+}
+
+/// Makes the index `index_writer` writes read-optimized once a bulk load
+/// is over: commits, merges every segment into one like [`optimize`], then
+/// reloads `reader` so that its next searcher uses the merged segment.
+///
+/// The writer is consumed, since it has to wait for its merging threads.
+/// Each step is logged as it starts, merging being the long one.
+pub fn finalize(
+    mut index_writer: IndexWriter,
+    reader: &IndexReader,
+) -> tantivy::Result<CompactionReport> {
+    let index = index_writer.index().clone();
+    let opstamp = index_writer.commit()?;
+    let segments_before = index.searchable_segment_ids()?.len();
+    let bytes_before = index_size(&index)?;
+    info!(opstamp, segments = segments_before, "committed, merging");
+    merge_all(&index, &mut index_writer)?;
+    info!("merged, collecting unused files");
+    index_writer.garbage_collect_files().wait()?;
+    index_writer.wait_merging_threads()?;
+    reader.reload()?;
+    let report = CompactionReport {
+        segments_before,
+        segments_after: index.searchable_segment_ids()?.len(),
+        bytes_before,
+        bytes_after: index_size(&index)?,
+    };
+    info!(
+        segments = report.segments_after,
+        bytes = report.bytes_after,
+        "finalized"
+    );
+    Ok(report)
+}
+
+/// Merges the searchable segments of `index` into one, if there are
+/// several, or if the only one has deleted documents to purge.
+fn merge_all(index: &Index, index_writer: &mut IndexWriter) -> tantivy::Result<()> {
+    // Segment metas are tracked while alive, and their files kept from
+    // garbage collection, so only the ids outlive this statement.
+    let (segment_ids, has_deletes) = {
+        let segment_metas = index.searchable_segment_metas()?;
+        let segment_ids: Vec<SegmentId> = segment_metas.iter().map(SegmentMeta::id).collect();
+        (
+            segment_ids,
+            segment_metas.iter().any(SegmentMeta::has_deletes),
+        )
+    };
+    if segment_ids.len() > 1 || has_deletes {
+        index_writer.merge(&segment_ids).wait()?;
+    }
+    Ok(())
+}
+
+/// How a merge changed the statistics of a sample term, see [`refresh_statistics`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StatisticsReport {
+    pub compaction: CompactionReport,
+    /// Documents containing the term according to the index, before the merge.
+    pub doc_freq_before: u64,
+    pub doc_freq_after: u64,
+}
+
+/// Merges `index` with [`optimize`], reporting the document frequency of
+/// `sample` before and after.
+///
+/// Deleting a document only marks it as deleted: until its segment is
+/// merged, it still counts in the document frequencies, and in the total
+/// number of documents, that BM25 computes the idf from. After heavy
+/// deletes, the terms of the deleted documents look more common than they
+/// are and score too low. Merging rewrites the segments without the deleted
+/// documents, which brings the statistics back in line.
+pub fn refresh_statistics(index: &Index, sample: &Term) -> tantivy::Result<StatisticsReport> {
+    // Each reader is dropped before the next step: the files of the
+    // segments it was searching would be kept from garbage collection.
+    let doc_freq_before = index.reader()?.searcher().doc_freq(sample)?;
+    let compaction = optimize(index)?;
+    let doc_freq_after = index.reader()?.searcher().doc_freq(sample)?;
+    info!(doc_freq_before, doc_freq_after, "refreshed statistics");
+    Ok(StatisticsReport {
+        compaction,
+        doc_freq_before,
+        doc_freq_after,
+    })
+}
+
+/// What [`snapshot`] did.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SnapshotReport {
+    /// Files hard-linked into the snapshot.
+    pub linked: usize,
+    /// Files copied because they couldn't be linked, e.g. across filesystems.
+    pub copied: usize,
+}
+
+/// Takes a point-in-time copy of the index at `from`, open as `index`,
+/// into the new directory `to`.
+///
+/// Segment files are never modified once written, so hard-linking them is
+/// as good as a copy, and nearly free. To make sure the files don't change
+/// while they are linked, this holds the writer, with merges off, commits,
+/// and collects the files no commit uses anymore. Then it links every file
+/// the index manages and `meta.json` last, so an interrupted snapshot has
+/// no `meta.json` and doesn't open.
+pub fn snapshot(index: &Index, from: &Path, to: &Path) -> tantivy::Result<SnapshotReport> {
+    if to.exists() && fs::read_dir(to)?.next().is_some() {
+        return Err(TantivyError::InvalidArgument(format!(
+            "snapshot directory {} is not empty",
+            to.display()
+        )));
+    }
+    let mut index_writer = index.writer(DEFAULT_HEAP_SIZE)?;
+    index_writer.set_merge_policy(Box::new(NoMergePolicy));
+    index_writer.commit()?;
+    index_writer.garbage_collect_files().wait()?;
+
+    let mut files: Vec<PathBuf> = index.directory().list_managed_files().into_iter().collect();
+    files.sort();
+    files.retain(|file| file != Path::new(META_FILE));
+    files.push(PathBuf::from(MANAGED_FILE));
+    files.push(PathBuf::from(META_FILE));
+
+    fs::create_dir_all(to)?;
+    let mut report = SnapshotReport::default();
+    for file in files {
+        let source = from.join(&file);
+        if !source.exists() {
+            continue;
+        }
+        let target = to.join(&file);
+        if fs::hard_link(&source, &target).is_ok() {
+            report.linked += 1;
+        } else {
+            fs::copy(&source, &target)?;
+            report.copied += 1;
+        }
+    }
+    info!(linked = report.linked, copied = report.copied, to = %to.display(), "took snapshot");
+    Ok(report)
+}
+
+/// What [`migrate_add_field`] did.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MigrationReport {
+    /// Documents copied to the new index.
+    pub documents: u64,
+    /// Fields of the new schema the old one didn't have.
+    pub added_fields: Vec<String>,
+}
+
+/// Rebuilds the index at `path` with `new_schema`, which must be the old
+/// schema plus new fields.
+///
+/// tantivy can't change the schema of an index in place: segments are
+/// written for one schema, and opening an index with another one fails.
+/// So this is a full rebuild: every stored document is copied into a new
+/// index next to the old one, with the new fields left empty, then the new
+/// index replaces the old one. It needs the space of both while it runs.
+///
+/// Only stored values can be copied. Fields that are indexed but not stored
+/// would come back empty, so they make the migration fail unless
+/// `drop_unstored` is set. Custom tokenizers are not persisted, so
+/// `register_tokenizers` is called on both indexes before use.
+///
+/// A migration that died while swapping the directories left the old index
+/// at `<path>.old`: when nothing is at `path`, it is moved back and the
+/// migration starts over. When both are there, which is which can't be
+/// told, so this fails until `<path>.old` is removed.
+pub fn migrate_add_field(
+    path: &Path,
+    new_schema: &Schema,
+    register_tokenizers: impl Fn(&Index) -> tantivy::Result<()>,
+    drop_unstored: bool,
+) -> tantivy::Result<MigrationReport> {
+    let old_path = path.with_extension("old");
+    if old_path.exists() {
+        if path.exists() {
+            return Err(TantivyError::InvalidArgument(format!(
+                "{} is left from an interrupted migration, remove it once {} is checked",
+                old_path.display(),
+                path.display()
+            )));
+        }
+        warn!(old_path = %old_path.display(), "restoring the index of an interrupted migration");
+        fs::rename(&old_path, path)?;
+    }
+    let old_index = Index::open_in_dir(path)?;
+    register_tokenizers(&old_index)?;
+    let old_schema = old_index.schema();
+    if old_schema == *new_schema {
+        return Ok(MigrationReport::default());
+    }
+
+    let mut unstored = Vec::new();
+    for (_, old_entry) in old_schema.fields() {
+        let kept = new_schema
+            .get_field(old_entry.name())
+            .is_ok_and(|field| new_schema.get_field_entry(field) == old_entry);
+        if !kept {
+            return Err(TantivyError::SchemaError(format!(
+                "field `{}` is missing or different in the new schema, \
+                 only adding fields is supported",
+                old_entry.name()
+            )));
+        }
+        if old_entry.is_indexed() && !old_entry.is_stored() {
+            unstored.push(old_entry.name().to_string());
+        }
+    }
+    if !unstored.is_empty() && !drop_unstored {
+        return Err(TantivyError::SchemaError(format!(
+            "fields {unstored:?} are not stored and would be lost by the migration"
+        )));
+    }
+    let added_fields = new_schema
+        .fields()
+        .map(|(_, entry)| entry.name().to_string())
+        .filter(|name| old_schema.get_field(name).is_err())
+        .collect();
+
+    let new_path = path.with_extension("migrating");
+    if new_path.exists() {
+        fs::remove_dir_all(&new_path)?;
+    }
+    fs::create_dir_all(&new_path)?;
+    let new_index = Index::create_in_dir(&new_path, new_schema.clone())?;
+    register_tokenizers(&new_index)?;
+    let mut index_writer = new_index.writer(DEFAULT_HEAP_SIZE)?;
+    let mut documents = 0;
+    let searcher = old_index.reader()?.searcher();
+    for segment_reader in searcher.segment_readers() {
+        let store_reader = segment_reader.get_store_reader(1)?;
+        for old_doc in store_reader.iter(segment_reader.alive_bitset()) {
+            let mut new_doc = Document::new();
+            for field_value in old_doc?.field_values() {
+                let name = old_schema.get_field_name(field_value.field());
+                new_doc.add_field_value(new_schema.get_field(name)?, field_value.value().clone());
+            }
+            index_writer.add_document(new_doc)?;
+            documents += 1;
+        }
+    }
+    index_writer.commit()?;
+    index_writer.wait_merging_threads()?;
+    drop(searcher);
+    drop(old_index);
+
+    // Swap the directories, keeping the old index until the new one is in place.
+    fs::rename(path, &old_path)?;
+    fs::rename(&new_path, path)?;
+    fs::remove_dir_all(&old_path)?;
+    info!(documents, ?added_fields, "migrated index");
+    Ok(MigrationReport {
+        documents,
+        added_fields,
+    })
+}
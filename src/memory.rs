@@ -0,0 +1,199 @@
+//! Full-text search over a slice of structs, without writing a schema.
+//!
+//! ```
+//! use serde::Serialize;
+//! use tantivy_basics::memory::InMemorySearch;
+//!
+//! #[derive(Serialize)]
+//! struct Book {
+//!     title: String,
+//!     year: u64,
+//! }
+//!
+//! let books = vec![
+//!     Book { title: "The Old Man and the Sea".to_string(), year: 1952 },
+//!     Book { title: "Of Mice and Men".to_string(), year: 1937 },
+//! ];
+//! let search = InMemorySearch::new(&books).unwrap();
+//! let found = search.search("sea").unwrap();
+//! assert_eq!(found.len(), 1);
+//! assert_eq!(found[0].year, 1952);
+//! assert_eq!(search.search("year:1937").unwrap()[0].title, "Of Mice and Men");
+//! ```
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use serde_json::Value;
+use tantivy::collector::TopDocs;
+use tantivy::query::QueryParser;
+use tantivy::schema::{Field, FieldType};
+use tantivy::{Index, IndexReader, TantivyError};
+
+use crate::document::document_from_value;
+use crate::schema::SchemaSpec;
+
+/// The field holding the position of each item in the slice.
+const POSITION_FIELD: &str = "_position";
+
+/// A RAM index over `items`, returning references to the matching ones.
+///
+/// The schema comes from the JSON shape of the items, as `serde_json`
+/// serializes them: strings are full text, searched by bare words, numbers
+/// and booleans are indexed to be matched as `field:value`, and nested
+/// objects are JSON fields. Fields that are sometimes null or missing are
+/// fine, but a field must have the same kind of value in every item, the
+/// integers of a field mixing them with floats being taken as floats.
+pub struct InMemorySearch<'a, T> {
+    items: &'a [T],
+    index: Index,
+    reader: IndexReader,
+    search_fields: Vec<Field>,
+}
+
+impl<'a, T: Serialize> InMemorySearch<'a, T> {
+    /// Indexes `items`, which must serialize to JSON objects.
+    pub fn new(items: &'a [T]) -> tantivy::Result<InMemorySearch<'a, T>> {
+        let mut values = items
+            .iter()
+            .map(serde_json::to_value)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| TantivyError::InvalidArgument(err.to_string()))?;
+        let kinds = infer_kinds(&values)?;
+        // A key that is always null has no kind, nor field, to go to.
+        for value in &mut values {
+            if let Value::Object(object) = value {
+                object.retain(|key, _| kinds.contains_key(key));
+            }
+        }
+        let mut spec = SchemaSpec::new().u64_field(POSITION_FIELD).fast();
+        for (name, kind) in kinds {
+            spec = match kind {
+                Kind::Text => spec.text_field(&name),
+                Kind::U64 => spec.u64_field(&name),
+                Kind::I64 => spec.i64_field(&name),
+                Kind::F64 => spec.f64_field(&name),
+                Kind::Bool => spec.bool_field(&name),
+                Kind::Json => spec.json_field(&name),
+            }
+            .indexed();
+        }
+        let schema = spec.build()?;
+        let search_fields = schema
+            .fields()
+            .filter(|(_, entry)| matches!(entry.field_type(), FieldType::Str(_)))
+            .map(|(field, _)| field)
+            .collect();
+
+        let index = Index::create_in_ram(schema.clone());
+        let mut index_writer = index.writer_with_num_threads(1, crate::DEFAULT_HEAP_SIZE)?;
+        let position = schema.get_field(POSITION_FIELD)?;
+        for (i, value) in values.iter().enumerate() {
+            let mut doc = document_from_value(&schema, value)?;
+            doc.add_u64(position, i as u64);
+            index_writer.add_document(doc)?;
+        }
+        index_writer.commit()?;
+        let reader = index.reader()?;
+        Ok(InMemorySearch {
+            items,
+            index,
+            reader,
+            search_fields,
+        })
+    }
+
+    /// Every item matching `query`, most relevant first.
+    ///
+    /// `query` uses the query parser syntax, bare words searching every
+    /// text field.
+    pub fn search(&self, query: &str) -> tantivy::Result<Vec<&'a T>> {
+        let query_parser = QueryParser::for_index(&self.index, self.search_fields.clone());
+        let query = query_parser.parse_query(query)?;
+        let searcher = self.reader.searcher();
+        let limit = self.items.len().max(1);
+        let mut found = Vec::new();
+        for (_score, doc_address) in searcher.search(&query, &TopDocs::with_limit(limit))? {
+            let positions = searcher
+                .segment_reader(doc_address.segment_ord)
+                .fast_fields()
+                .u64(POSITION_FIELD)?;
+            if let Some(position) = positions.first(doc_address.doc_id) {
+                found.push(&self.items[position as usize]);
+            }
+        }
+        Ok(found)
+    }
+}
+
+/// The kind of field a JSON value goes to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Text,
+    U64,
+    I64,
+    F64,
+    Bool,
+    Json,
+}
+
+impl Kind {
+    /// The kind of a single value, the elements of an array each counting.
+    fn of(value: &Value) -> Option<Kind> {
+        Some(match value {
+            Value::Null | Value::Array(_) => return None,
+            Value::String(_) => Kind::Text,
+            Value::Number(number) if number.is_u64() => Kind::U64,
+            Value::Number(number) if number.is_i64() => Kind::I64,
+            Value::Number(_) => Kind::F64,
+            Value::Bool(_) => Kind::Bool,
+            Value::Object(_) => Kind::Json,
+        })
+    }
+
+    /// The kind that holds both, if any: integers widen to `i64`, then `f64`.
+    fn merge(self, other: Kind) -> Option<Kind> {
+        use Kind::*;
+        match (self, other) {
+            (a, b) if a == b => Some(a),
+            (U64, I64) | (I64, U64) => Some(I64),
+            (U64 | I64, F64) | (F64, U64 | I64) => Some(F64),
+            _ => None,
+        }
+    }
+}
+
+/// The kind of every key of `values`, which must all be objects.
+fn infer_kinds(values: &[Value]) -> tantivy::Result<BTreeMap<String, Kind>> {
+    let mut kinds: BTreeMap<String, Kind> = BTreeMap::new();
+    for value in values {
+        let object = value.as_object().ok_or_else(|| {
+            TantivyError::InvalidArgument(format!(
+                "expected items serializing to objects, got {value}"
+            ))
+        })?;
+        for (key, value) in object {
+            if key == POSITION_FIELD {
+                return Err(TantivyError::InvalidArgument(format!(
+                    "`{POSITION_FIELD}` is reserved and can't be a field of the items"
+                )));
+            }
+            let elements = match value {
+                Value::Array(elements) => elements.as_slice(),
+                value => std::slice::from_ref(value),
+            };
+            for kind in elements.iter().filter_map(Kind::of) {
+                let merged = match kinds.get(key) {
+                    Some(&known) => known.merge(kind).ok_or_else(|| {
+                        TantivyError::InvalidArgument(format!(
+                            "field `{key}` has values of different types"
+                        ))
+                    })?,
+                    None => kind,
+                };
+                kinds.insert(key.clone(), merged);
+            }
+        }
+    }
+    Ok(kinds)
+}
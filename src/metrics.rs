@@ -0,0 +1,88 @@
+//! Prometheus metrics of the search and indexing paths.
+//!
+//! The metrics live in one process-wide [`Metrics`], updated by whoever
+//! searches or indexes, and rendered in the Prometheus text format for a
+//! `/metrics` endpoint, see [`crate::server`].
+
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use prometheus::{Encoder, Histogram, HistogramOpts, IntCounter, Registry, TextEncoder};
+
+/// The counters and histograms of the service.
+pub struct Metrics {
+    registry: Registry,
+    pub queries: IntCounter,
+    /// Query latency, in seconds.
+    pub query_latency: Histogram,
+    pub commits: IntCounter,
+    pub documents_indexed: IntCounter,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+impl Metrics {
+    /// Creates a set of metrics, registered in a registry of its own.
+    pub fn new() -> Metrics {
+        let registry = Registry::new();
+        let queries =
+            IntCounter::new("search_queries_total", "Queries searched.").expect("valid metric");
+        let query_latency = Histogram::with_opts(HistogramOpts::new(
+            "search_query_duration_seconds",
+            "Time spent searching a query.",
+        ))
+        .expect("valid metric");
+        let commits =
+            IntCounter::new("index_commits_total", "Index commits.").expect("valid metric");
+        let documents_indexed =
+            IntCounter::new("index_documents_total", "Documents added to the index.")
+                .expect("valid metric");
+        for metric in [&queries, &commits, &documents_indexed] {
+            registry
+                .register(Box::new(metric.clone()))
+                .expect("metric registered once");
+        }
+        registry
+            .register(Box::new(query_latency.clone()))
+            .expect("metric registered once");
+        Metrics {
+            registry,
+            queries,
+            query_latency,
+            commits,
+            documents_indexed,
+        }
+    }
+
+    /// The metrics of the process.
+    pub fn global() -> &'static Metrics {
+        &METRICS
+    }
+
+    /// Counts a query that took `elapsed`.
+    pub fn observe_query(&self, elapsed: Duration) {
+        self.queries.inc();
+        self.query_latency.observe(elapsed.as_secs_f64());
+    }
+
+    /// Counts a commit of `documents` new documents.
+    pub fn observe_commit(&self, documents: u64) {
+        self.commits.inc();
+        self.documents_indexed.inc_by(documents);
+    }
+
+    /// Every metric, in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("encoding to a Vec doesn't fail");
+        String::from_utf8(buffer).expect("the text format is UTF-8")
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Metrics {
+        Metrics::new()
+    }
+}
@@ -0,0 +1,697 @@
+//! Query builders that go beyond what the `QueryParser` gives us.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Bound;
+use std::str::FromStr;
+
+use tantivy::query::{
+    BooleanQuery, BoostQuery, FuzzyTermQuery, Occur, PhrasePrefixQuery, PhraseQuery, Query,
+    QueryParser, QueryParserError, RangeQuery, TermQuery,
+};
+use tantivy::schema::{Field, IndexRecordOption, Schema, Term};
+use tantivy::{query_grammar, Index, Score, Searcher, TantivyError};
+
+use crate::document::normalize_zero;
+use crate::terms::similar_terms;
+
+/// Parses `input` like `query_parser`, but never fails, for a public search box.
+///
+/// Input the parser rejects, like `sea AND`, `title:` or `((`, is retried
+/// stripped of its query syntax: special characters and `AND`/`OR`/`NOT`
+/// become separators, so what is left are plain words searched in the
+/// default fields. If even that fails, the words the parser could make
+/// sense of are kept, which may match nothing.
+pub fn parse_lenient(query_parser: &QueryParser, input: &str) -> Box<dyn Query> {
+    if let Ok(query) = query_parser.parse_query(input) {
+        return query;
+    }
+    let words = strip_query_syntax(input);
+    match query_parser.parse_query(&words) {
+        Ok(query) => query,
+        Err(_) => query_parser.parse_query_lenient(&words).0,
+    }
+}
+
+/// Keeps the plain words of `input`, dropping operators and special characters.
+fn strip_query_syntax(input: &str) -> String {
+    input
+        .split(|c: char| c.is_whitespace() || "+-&|!(){}[]^\"~*?:\\/'<>=".contains(c))
+        .filter(|word| !word.is_empty() && !matches!(*word, "AND" | "OR" | "NOT" | "IN" | "TO"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Drops the bare words of `input` that are one of `stop_words`, ignoring
+/// case, before it is parsed.
+///
+/// This ignores words for one query without reindexing, e.g. `the` in
+/// `the sea`. Words in a phrase are kept, as dropping them would change
+/// what the phrase matches. Operators and groups left with nothing to
+/// apply to are dropped too, e.g. `sea AND (the)` becomes `sea`.
+/// A query made only of stop words is returned as is, rather than emptied.
+pub fn remove_stop_words(input: &str, stop_words: &[&str]) -> String {
+    let is_operator = |token: &str| matches!(token, "AND" | "OR" | "NOT");
+    let mut kept: Vec<String> = Vec::new();
+    let mut in_phrase = false;
+    for token in input.split_whitespace() {
+        let quoted = in_phrase || token.contains('"');
+        in_phrase ^= token.matches('"').count() % 2 == 1;
+        let inner = token.trim_start_matches('(').trim_end_matches(')');
+        let word = inner.trim_start_matches(['+', '-']);
+        let word = word.split_once(':').map_or(word, |(_, word)| word);
+        if quoted
+            || !stop_words
+                .iter()
+                .any(|stop| stop.eq_ignore_ascii_case(word))
+        {
+            kept.push(token.to_string());
+            continue;
+        }
+        let open = &token[..token.len() - token.trim_start_matches('(').len()];
+        let close = &token[token.trim_end_matches(')').len()..];
+        // The operator before the word has nothing to apply to anymore.
+        if open.is_empty() && kept.last().is_some_and(|token| is_operator(token)) {
+            kept.pop();
+        }
+        kept.extend(open.chars().chain(close.chars()).map(String::from));
+    }
+    // Operators at the start, or right after another one or a `(`, connect nothing.
+    let mut cleaned: Vec<String> = Vec::with_capacity(kept.len());
+    for mut token in kept {
+        // A group left empty goes away with the operator before it.
+        while token.starts_with(')') && cleaned.last().is_some_and(|last| last == "(") {
+            cleaned.pop();
+            token.remove(0);
+            if cleaned.last().is_some_and(|last| is_operator(last)) {
+                cleaned.pop();
+            }
+        }
+        if token.is_empty() {
+            continue;
+        }
+        let dangling = matches!(token.as_str(), "AND" | "OR")
+            && cleaned
+                .last()
+                .is_none_or(|last| is_operator(last) || last.ends_with('('));
+        if !dangling {
+            cleaned.push(token);
+        }
+    }
+    while cleaned.last().is_some_and(|token| is_operator(token)) {
+        cleaned.pop();
+    }
+    if cleaned
+        .iter()
+        .all(|token| is_operator(token) || token.trim_matches(['(', ')']).is_empty())
+    {
+        return input.to_string();
+    }
+    cleaned.join(" ")
+}
+
+/// Rewrites the `alias:term` clauses of `input` into `field:term`, before
+/// it is parsed, with `aliases` mapping each alias to the field it stands for.
+///
+/// This keeps queries using the old name of a renamed field working. The
+/// subfields of a JSON field follow it, `alias.path:term` becoming
+/// `field.path:term`, and the text of phrases is left alone.
+pub fn resolve_field_aliases(input: &str, aliases: &BTreeMap<String, String>) -> String {
+    let mut resolved = String::with_capacity(input.len());
+    let mut in_phrase = false;
+    for token in input.split_inclusive(char::is_whitespace) {
+        let clause = token.trim_start_matches(['(', '+', '-']);
+        let alias = clause
+            .split_once(':')
+            .map(|(field, _)| field.split('.').next().unwrap_or(field))
+            .filter(|_| !in_phrase);
+        match alias.and_then(|alias| Some((alias, aliases.get(alias)?))) {
+            Some((alias, canonical)) => {
+                resolved.push_str(&token[..token.len() - clause.len()]);
+                resolved.push_str(canonical);
+                resolved.push_str(&clause[alias.len()..]);
+            }
+            None => resolved.push_str(token),
+        }
+        in_phrase ^= token.matches('"').count() % 2 == 1;
+    }
+    resolved
+}
+
+/// Why a query string was rejected, and where.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QueryError {
+    /// Offset in characters of the offending part of the query, from 0,
+    /// when it can be told.
+    pub offset: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.offset {
+            Some(offset) => write!(f, "{} at position {offset}", self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+impl From<QueryError> for TantivyError {
+    fn from(err: QueryError) -> TantivyError {
+        TantivyError::InvalidArgument(err.to_string())
+    }
+}
+
+/// Checks that `query_parser` accepts `input`, pointing at what is wrong if not,
+/// e.g. `unexpected ')' at position 12`.
+///
+/// Syntax errors, like a dangling `AND`, come with the position the grammar
+/// stopped at. Errors found once the syntax is fine, like an unknown field,
+/// point at the field name.
+pub fn validate_query(query_parser: &QueryParser, input: &str) -> Result<(), QueryError> {
+    let trimmed = input.trim_end();
+    let last_word = trimmed.rsplit(char::is_whitespace).next().unwrap_or("");
+    if matches!(last_word, "AND" | "OR" | "NOT") && trimmed.len() > last_word.len() {
+        let pos = trimmed.len() - last_word.len();
+        return Err(QueryError {
+            offset: Some(input[..pos].chars().count()),
+            message: format!("dangling '{last_word}'"),
+        });
+    }
+    let (_, syntax_errors) = query_grammar::parse_query_lenient(input);
+    if let Some(err) = syntax_errors.first() {
+        let offset = input[..err.pos.min(input.len())].chars().count();
+        let message = match input[err.pos.min(input.len())..].chars().next() {
+            Some(unexpected) if err.message == "unparsed end of query" => {
+                format!("unexpected '{unexpected}'")
+            }
+            None => format!("{} at the end of the query", err.message),
+            Some(_) => err.message.clone(),
+        };
+        return Err(QueryError {
+            offset: (offset < input.chars().count()).then_some(offset),
+            message,
+        });
+    }
+    let err = match query_parser.parse_query(input) {
+        Ok(_) => return Ok(()),
+        Err(err) => err,
+    };
+    let field = match &err {
+        QueryParserError::FieldDoesNotExist(field)
+        | QueryParserError::FieldNotIndexed(field)
+        | QueryParserError::FieldDoesNotHavePositionsIndexed(field) => Some(field.as_str()),
+        _ => None,
+    };
+    let offset = field
+        .and_then(|field| input.find(&format!("{field}:")))
+        .map(|pos| input[..pos].chars().count());
+    Err(QueryError {
+        offset,
+        message: err.to_string(),
+    })
+}
+
+/// Limits on the size of a query string, checked before it is parsed.
+///
+/// Parsing, and above all running, a query costs more with every term, so
+/// a public endpoint should refuse the huge ones instead of trying.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueryLimits {
+    /// Maximum length, in characters.
+    pub max_chars: usize,
+    /// Maximum number of terms, operators like `AND` aside.
+    pub max_terms: usize,
+}
+
+impl Default for QueryLimits {
+    fn default() -> QueryLimits {
+        QueryLimits {
+            max_chars: 1024,
+            max_terms: 32,
+        }
+    }
+}
+
+impl QueryLimits {
+    /// Fails if `query_str` is longer, or has more terms, than allowed.
+    ///
+    /// Terms are counted roughly: words separated by whitespace, parentheses
+    /// or quotes, so every word of a phrase counts.
+    pub fn check(&self, query_str: &str) -> tantivy::Result<()> {
+        let chars = query_str.chars().count();
+        if chars > self.max_chars {
+            return Err(TantivyError::InvalidArgument(format!(
+                "query is {chars} characters long, the limit is {}",
+                self.max_chars
+            )));
+        }
+        let terms = query_str
+            .split(|c: char| c.is_whitespace() || matches!(c, '(' | ')' | '"'))
+            .filter(|word| !word.is_empty() && !matches!(*word, "AND" | "OR" | "NOT"))
+            .count();
+        if terms > self.max_terms {
+            return Err(TantivyError::InvalidArgument(format!(
+                "query has {terms} terms, the limit is {}",
+                self.max_terms
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Field weights given with a query, like `title:3,body:1`, or fixed for
+/// every query by the schema config.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FieldBoosts(pub Vec<(String, Score)>);
+
+impl FromStr for FieldBoosts {
+    type Err = TantivyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut boosts = Vec::new();
+        for pair in s.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let boost = pair
+                .split_once(':')
+                .and_then(|(field, boost)| Some((field, boost.parse::<Score>().ok()?)))
+                .filter(|(_, boost)| boost.is_finite() && *boost >= 0.0);
+            let Some((field, boost)) = boost else {
+                return Err(TantivyError::InvalidArgument(format!(
+                    "invalid field boost `{pair}`, expected `field:weight`"
+                )));
+            };
+            boosts.push((field.to_string(), boost));
+        }
+        Ok(FieldBoosts(boosts))
+    }
+}
+
+impl FieldBoosts {
+    /// A fresh parser over `default_fields`, the fields weighted as given.
+    ///
+    /// Boosts apply to what the query matches in the field, whether it
+    /// names it or not, so a weighted field needn't be a default one.
+    pub fn query_parser(
+        &self,
+        index: &Index,
+        default_fields: Vec<Field>,
+    ) -> tantivy::Result<QueryParser> {
+        let schema = index.schema();
+        let mut query_parser = QueryParser::for_index(index, default_fields);
+        for (name, boost) in &self.0 {
+            let field = schema.get_field(name).map_err(|_| {
+                TantivyError::InvalidArgument(format!("unknown field `{name}` in boosts"))
+            })?;
+            query_parser.set_field_boost(field, *boost);
+        }
+        Ok(query_parser)
+    }
+}
+
+/// Runs `text` through the tokenizer configured for `field`
+/// and returns the resulting terms, in order.
+pub fn tokenize(index: &Index, field: Field, text: &str) -> tantivy::Result<Vec<Term>> {
+    let mut analyzer = index.tokenizer_for_field(field)?;
+    let mut stream = analyzer.token_stream(text);
+    let mut terms = Vec::new();
+    while stream.advance() {
+        terms.push(Term::from_field_text(field, &stream.token().text));
+    }
+    Ok(terms)
+}
+
+/// Builds a "search as you type" query for a multi-word input.
+///
+/// Every token but the last must match exactly and in order,
+/// the last one is treated as a prefix: `"old ma"` matches `"The Old Man and the Sea"`.
+///
+/// The field must be indexed with positions (`TEXT` does that).
+/// Returns `None` when `text` yields no tokens.
+pub fn phrase_prefix_query(
+    index: &Index,
+    field: Field,
+    text: &str,
+) -> tantivy::Result<Option<Box<dyn Query>>> {
+    let terms = tokenize(index, field, text)?;
+    if terms.is_empty() {
+        return Ok(None);
+    }
+    // With a single token this degrades to a plain prefix query.
+    Ok(Some(Box::new(PhrasePrefixQuery::new(terms))))
+}
+
+/// Matches the documents where the phrase `text` appears in any of `fields`.
+///
+/// Each field gets its own phrase query, tokenized with that field's tokenizer,
+/// and the phrases are `should` clauses: the phrase must be whole within one
+/// field, it doesn't match across the end of the title and the start of the body.
+/// The fields must be indexed with positions (`TEXT` does that).
+/// Returns `None` when `text` yields no tokens.
+pub fn multi_field_phrase_query(
+    index: &Index,
+    fields: &[Field],
+    text: &str,
+) -> tantivy::Result<Option<Box<dyn Query>>> {
+    let mut clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+    for &field in fields {
+        let mut terms = tokenize(index, field, text)?;
+        let query: Box<dyn Query> = match terms.len() {
+            0 => continue,
+            // A phrase query needs at least two terms.
+            1 => Box::new(TermQuery::new(
+                terms.remove(0),
+                IndexRecordOption::WithFreqs,
+            )),
+            _ => Box::new(PhraseQuery::new(terms)),
+        };
+        clauses.push((Occur::Should, query));
+    }
+    if clauses.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Box::new(BooleanQuery::new(clauses))))
+}
+
+/// Matches the documents with every word of `text` in at least one of
+/// `fields`, each field weighted by its boost.
+///
+/// Each word is a `should` over the fields, tokenized with each field's
+/// tokenizer, and the words are `must`s: `john smith` matches `john` in
+/// the title and `smith` in the body, which searching the title, then the
+/// body, for both words would miss. Words that yield no token in any
+/// field are ignored. Returns `None` when no word is left.
+pub fn cross_field_query(
+    index: &Index,
+    fields: &[(Field, Score)],
+    text: &str,
+) -> tantivy::Result<Option<Box<dyn Query>>> {
+    let mut words: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+    for word in text.split_whitespace() {
+        let mut alternatives: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+        for &(field, boost) in fields {
+            for term in tokenize(index, field, word)? {
+                let term_query = TermQuery::new(term, IndexRecordOption::WithFreqs);
+                alternatives.push((
+                    Occur::Should,
+                    Box::new(BoostQuery::new(Box::new(term_query), boost)),
+                ));
+            }
+        }
+        if !alternatives.is_empty() {
+            words.push((Occur::Must, Box::new(BooleanQuery::new(alternatives))));
+        }
+    }
+    if words.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Box::new(BooleanQuery::new(words))))
+}
+
+/// Matches the phrase `text` in `field`, and each word of `term_boosts` on
+/// its own, with its boost, e.g. `"old man"` plus `man` boosted 2.0.
+///
+/// All the clauses are `should`, so documents with only a boosted word
+/// match too. They usually rank under the phrase matches, which score the
+/// phrase on top of the boosted words it contains, unless a large boost or
+/// many repetitions of the word outweigh the phrase clause. The word
+/// clauses score with term frequency, favoring documents where the word
+/// appears again outside the phrase. Boosted words are tokenized like the
+/// field; one that yields no token is ignored. Returns `None` when nothing
+/// is left to match.
+pub fn phrase_with_term_boosts(
+    index: &Index,
+    field: Field,
+    text: &str,
+    term_boosts: &[(&str, Score)],
+) -> tantivy::Result<Option<BooleanQuery>> {
+    let mut clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+    let mut phrase_terms = tokenize(index, field, text)?;
+    match phrase_terms.len() {
+        0 => {}
+        1 => clauses.push((
+            Occur::Should,
+            Box::new(TermQuery::new(
+                phrase_terms.remove(0),
+                IndexRecordOption::WithFreqs,
+            )),
+        )),
+        _ => clauses.push((Occur::Should, Box::new(PhraseQuery::new(phrase_terms)))),
+    }
+    for &(word, boost) in term_boosts {
+        for term in tokenize(index, field, word)? {
+            let term_query = TermQuery::new(term, IndexRecordOption::WithFreqs);
+            clauses.push((
+                Occur::Should,
+                Box::new(BoostQuery::new(Box::new(term_query), boost)),
+            ));
+        }
+    }
+    if clauses.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(BooleanQuery::new(clauses)))
+}
+
+/// How much [`with_exact_match_boost`] weights the exact match clause.
+pub const EXACT_MATCH_BOOST: Score = 10.0;
+
+/// Adds to `query` a `should` clause for the whole of `text` as a phrase in
+/// `field`, weighted by `boost`, so a title equal to the query ranks first.
+///
+/// The clause only adds to the score: what matches is still up to `query`.
+/// A phrase matches titles that merely contain `text` too, but BM25 favors
+/// short fields, so the title made of nothing else scores it most. `text`
+/// is tokenized like `field`, which needs positions for a phrase of several
+/// words; when it yields no token, `query` is returned as is.
+pub fn with_exact_match_boost(
+    index: &Index,
+    query: Box<dyn Query>,
+    field: Field,
+    text: &str,
+    boost: Score,
+) -> tantivy::Result<Box<dyn Query>> {
+    let mut terms = tokenize(index, field, text)?;
+    let exact: Box<dyn Query> = match terms.len() {
+        0 => return Ok(query),
+        1 => Box::new(TermQuery::new(
+            terms.remove(0),
+            IndexRecordOption::WithFreqs,
+        )),
+        _ => Box::new(PhraseQuery::new(terms)),
+    };
+    Ok(Box::new(BooleanQuery::new(vec![
+        (Occur::Must, query),
+        (Occur::Should, Box::new(BoostQuery::new(exact, boost))),
+    ])))
+}
+
+/// Builds a query matching every document with at least one value in `field`.
+///
+/// This is an unbounded range: over the fast field for numeric fields,
+/// over the whole term dictionary otherwise. Either way the field must be
+/// indexed or fast, there is nothing to look at if it is only stored.
+pub fn exists_query(schema: &Schema, field: Field) -> tantivy::Result<Box<dyn Query>> {
+    let field_entry = schema.get_field_entry(field);
+    if !field_entry.is_indexed() && !field_entry.is_fast() {
+        return Err(TantivyError::SchemaError(format!(
+            "field `{}` must be indexed or fast to test for existence",
+            field_entry.name()
+        )));
+    }
+    Ok(Box::new(RangeQuery::new_term_bounds(
+        field_entry.name().to_string(),
+        field_entry.field_type().value_type(),
+        &Bound::Unbounded,
+        &Bound::Unbounded,
+    )))
+}
+
+/// Matches the documents whose bool `field` is `value`, e.g. `published: true`.
+///
+/// Bools are indexed as the terms `true` and `false`, so the field must be indexed.
+/// To filter a text query, combine this with it as a `must` clause.
+pub fn bool_filter(field: Field, value: bool) -> TermQuery {
+    TermQuery::new(
+        Term::from_field_bool(field, value),
+        IndexRecordOption::Basic,
+    )
+}
+
+/// Matches the documents whose f64 `field` lies between `lower` and `upper`,
+/// e.g. `price` in `Included(-5.0)..Excluded(10.0)`.
+///
+/// Negative and positive values compare as floats, `-0.0` bounds are taken
+/// as `0.0` like [`crate::document::add_f64`] does. NaN bounds are rejected.
+pub fn f64_range(
+    schema: &Schema,
+    field: Field,
+    lower: Bound<f64>,
+    upper: Bound<f64>,
+) -> tantivy::Result<RangeQuery> {
+    let normalize = |bound: Bound<f64>| match bound {
+        Bound::Included(value) | Bound::Excluded(value) if value.is_nan() => Err(
+            TantivyError::InvalidArgument("NaN is not a valid range bound".to_string()),
+        ),
+        Bound::Included(value) => Ok(Bound::Included(normalize_zero(value))),
+        Bound::Excluded(value) => Ok(Bound::Excluded(normalize_zero(value))),
+        Bound::Unbounded => Ok(Bound::Unbounded),
+    };
+    Ok(RangeQuery::new_f64_bounds(
+        schema.get_field_name(field).to_string(),
+        normalize(lower)?,
+        normalize(upper)?,
+    ))
+}
+
+/// Matches the documents whose bytes `field` holds exactly `value`.
+///
+/// The field must be indexed. There is no query syntax for bytes,
+/// so this is the way to look documents up by a binary key.
+pub fn bytes_query(field: Field, value: &[u8]) -> TermQuery {
+    TermQuery::new(
+        Term::from_field_bytes(field, value),
+        IndexRecordOption::Basic,
+    )
+}
+
+/// How many edits a fuzzy term may be away from what was typed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FuzzyDistance {
+    Fixed(u8),
+    /// Scales with the length of the term, like most search engines do:
+    /// exact up to 2 characters, 1 edit up to 5, 2 edits beyond.
+    Auto,
+}
+
+impl FuzzyDistance {
+    /// The edit distance to use for `term`.
+    pub fn for_term(self, term: &str) -> u8 {
+        match self {
+            FuzzyDistance::Fixed(distance) => distance,
+            FuzzyDistance::Auto => match term.chars().count() {
+                0..=2 => 0,
+                3..=5 => 1,
+                _ => 2,
+            },
+        }
+    }
+}
+
+impl FromStr for FuzzyDistance {
+    type Err = TantivyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "auto" {
+            return Ok(FuzzyDistance::Auto);
+        }
+        s.parse().map(FuzzyDistance::Fixed).map_err(|_| {
+            TantivyError::InvalidArgument(format!(
+                "invalid fuzzy distance `{s}`, expected `auto` or a number"
+            ))
+        })
+    }
+}
+
+/// Words of a [`fuzzy_phrase_query`] a word may stand for, at most.
+pub const MAX_FUZZY_EXPANSIONS: usize = 10;
+/// Phrases a [`fuzzy_phrase_query`] tries, at most.
+pub const MAX_FUZZY_PHRASES: usize = 100;
+
+/// Matches the words of `text` next to each other in `field`, each word
+/// up to `distance` edits away from what was typed: `old mn` matches
+/// `old man`, but not `man` and `old` apart or in the other order.
+///
+/// tantivy has no phrase query whose terms are fuzzy, so this approximates
+/// one: each word is expanded into the terms of the field's dictionaries
+/// within `distance`, like a `FuzzyTermQuery` would, keeping the
+/// [`MAX_FUZZY_EXPANSIONS`] most frequent, and the query is the union of
+/// the phrases of every combination of them, up to [`MAX_FUZZY_PHRASES`].
+/// Past these limits, rare spellings are missed. The field needs positions,
+/// and distances over 2 count as 2. Returns `None` when `text` yields no
+/// token, or a word has no term close enough to match anything.
+pub fn fuzzy_phrase_query(
+    searcher: &Searcher,
+    field: Field,
+    text: &str,
+    distance: FuzzyDistance,
+) -> tantivy::Result<Option<Box<dyn Query>>> {
+    let mut expansions: Vec<Vec<Term>> = Vec::new();
+    for term in tokenize(searcher.index(), field, text)? {
+        let value = term.value();
+        let Some(word) = value.as_str() else {
+            continue;
+        };
+        let word_distance = distance.for_term(word).min(2);
+        let similar = similar_terms(searcher, field, word, word_distance, MAX_FUZZY_EXPANSIONS)?;
+        if similar.is_empty() {
+            return Ok(None);
+        }
+        expansions.push(
+            similar
+                .into_iter()
+                .map(|(similar, _)| Term::from_field_text(field, &similar))
+                .collect(),
+        );
+    }
+    let mut phrases: Vec<Vec<Term>> = vec![Vec::new()];
+    for alternatives in &expansions {
+        phrases = phrases
+            .iter()
+            .flat_map(|phrase| {
+                alternatives.iter().map(move |term| {
+                    let mut phrase = phrase.clone();
+                    phrase.push(term.clone());
+                    phrase
+                })
+            })
+            .take(MAX_FUZZY_PHRASES)
+            .collect();
+    }
+    let clauses: Vec<(Occur, Box<dyn Query>)> = phrases
+        .into_iter()
+        .filter(|phrase| !phrase.is_empty())
+        .map(|mut phrase| -> (Occur, Box<dyn Query>) {
+            let query: Box<dyn Query> = if phrase.len() == 1 {
+                Box::new(TermQuery::new(
+                    phrase.remove(0),
+                    IndexRecordOption::WithFreqs,
+                ))
+            } else {
+                Box::new(PhraseQuery::new(phrase))
+            };
+            (Occur::Should, query)
+        })
+        .collect();
+    if clauses.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Box::new(BooleanQuery::new(clauses))))
+}
+
+/// Builds a typo-tolerant query: every token of `text`, in any of `fields`,
+/// may be up to `distance` edits away (a transposition counts as one edit).
+///
+/// Tokens are alternatives, like bare words given to the query parser.
+pub fn fuzzy_query(
+    index: &Index,
+    fields: &[Field],
+    text: &str,
+    distance: FuzzyDistance,
+) -> tantivy::Result<Box<dyn Query>> {
+    let mut clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+    for &field in fields {
+        for term in tokenize(index, field, text)? {
+            let term_distance = term
+                .value()
+                .as_str()
+                .map_or(0, |text| distance.for_term(text));
+            clauses.push((
+                Occur::Should,
+                Box::new(FuzzyTermQuery::new(term, term_distance, true)),
+            ));
+        }
+    }
+    Ok(Box::new(BooleanQuery::new(clauses)))
+}
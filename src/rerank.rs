@@ -0,0 +1,66 @@
+//! Reranking the top documents once they are collected.
+//!
+//! tantivy scores every match while collecting, so only cheap scores fit
+//! there. A costlier model, like learned weights over the stored fields,
+//! is better applied to the few top hits afterwards: search for more hits
+//! than will be shown, rerank them and keep the best.
+
+use tantivy::collector::TopDocs;
+use tantivy::query::Query;
+use tantivy::{Score, Searcher};
+
+use crate::search::SearchHit;
+
+/// A stage reordering search hits, after collection.
+pub trait Reranker {
+    /// Returns `hits` in their new order, usually with new scores.
+    fn rerank(&self, hits: Vec<SearchHit>) -> Vec<SearchHit>;
+}
+
+/// Multiplies by `boost` the score of the hits where a stored `field`
+/// contains `text` verbatim, ignoring case, e.g. a title containing the
+/// whole query string.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VerbatimBoost {
+    pub field: String,
+    pub text: String,
+    pub boost: Score,
+}
+
+impl Reranker for VerbatimBoost {
+    fn rerank(&self, mut hits: Vec<SearchHit>) -> Vec<SearchHit> {
+        let text = self.text.to_lowercase();
+        for hit in &mut hits {
+            let contains_text = hit.fields.get(&self.field).is_some_and(|values| {
+                values
+                    .iter()
+                    .filter_map(|value| value.as_str())
+                    .any(|value| value.to_lowercase().contains(&text))
+            });
+            if contains_text {
+                hit.score *= self.boost;
+            }
+        }
+        // The sort is stable: hits left as they were keep their order.
+        hits.sort_by(|left, right| right.score.total_cmp(&left.score));
+        hits
+    }
+}
+
+/// Runs `query`, then hands its `limit` best hits to `reranker`, if any.
+pub fn search_reranked(
+    searcher: &Searcher,
+    query: &dyn Query,
+    limit: usize,
+    reranker: Option<&dyn Reranker>,
+) -> tantivy::Result<Vec<SearchHit>> {
+    let hits = searcher
+        .search(query, &TopDocs::with_limit(limit))?
+        .into_iter()
+        .map(|(score, doc_address)| SearchHit::fetch(searcher, score, doc_address))
+        .collect::<tantivy::Result<Vec<_>>>()?;
+    Ok(match reranker {
+        Some(reranker) => reranker.rerank(hits),
+        None => hits,
+    })
+}
@@ -0,0 +1,567 @@
+//! The schema used throughout the examples, and schemas described in configuration.
+//!
+//! A schema config is a TOML file listing the fields and,
+//! optionally, the tokenizer chains the text fields refer to:
+//!
+//! ```toml
+//! [[fields]]
+//! name = "title"
+//! type = "text"
+//! stored = true
+//! tokenizer = "lowercase_only"
+//!
+//! [[fields]]
+//! name = "year"
+//! type = "u64"
+//! fast = true
+//!
+//! # Only searched by explicit `id:...` queries.
+//! [[fields]]
+//! name = "id"
+//! type = "string"
+//! stored = true
+//! searchable = false
+//!
+//! [tokenizers.lowercase_only]
+//! filters = ["lowercase"]
+//! ```
+//!
+//! `boost = 2.0` on a text field weights its matches, for every query
+//! parsed by [`SchemaConfig::make_query_parser`].
+//!
+//! `fieldnorms = false` on a text field makes its length irrelevant to
+//! BM25, so a match in a long title scores like one in a short title.
+//!
+//! A top-level `max_token_length = 64` makes the `default` tokenizer, used
+//! by text fields without a `tokenizer`, keep tokens up to 64 bytes
+//! instead of tantivy's 39.
+//!
+//! An `[aliases]` table keeps queries on the old name of a renamed field
+//! working, `headline = "title"` making `headline:sea` search `title`.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+use tantivy::query::QueryParser;
+use tantivy::schema::{
+    DateOptions, FacetOptions, Field, FieldType, IndexRecordOption, JsonObjectOptions,
+    NumericOptions, Schema, TextFieldIndexing, TextOptions, FAST, INDEXED, STORED, STRING, TEXT,
+};
+use tantivy::{Index, Score, TantivyError};
+
+use crate::analyzer::{
+    default_with_max_token_length, raw_lowercase, TokenizerConfig, RAW_LOWERCASE,
+};
+use crate::query::FieldBoosts;
+
+/// Builds the schema of the basic example:
+/// a stored `title` and a `body` that is indexed but not stored,
+/// plus an optional publication `year`, a `published` flag,
+/// `lat`/`lon` coordinates in degrees, free-form `metadata`,
+/// the `tenant` owning the document, a `price`, case-insensitive `tags`,
+/// a hierarchical `category` facet like `/fiction/classics`, an opaque
+/// binary `key`, written as base64 in JSON, a numeric `id`, and the
+/// `opstamp` of the last commit before the document was ingested.
+///
+/// `title` and `body` are indexed with positions, which phrase queries
+/// like `"old man" sea` need: the parser rejects phrases on a field without.
+/// Text fields of a [`SchemaConfig`] always have them.
+///
+/// `tags` uses the [`RAW_LOWERCASE`] tokenizer, so indexes with this schema
+/// need [`register_sample_tokenizers`] before documents can be added.
+pub fn sample_schema() -> Schema {
+    let mut schema_builder = Schema::builder();
+    schema_builder.add_text_field("title", TEXT | STORED);
+    schema_builder.add_text_field("body", TEXT);
+    schema_builder.add_u64_field("year", INDEXED | STORED | FAST);
+    schema_builder.add_bool_field("published", INDEXED | STORED | FAST);
+    schema_builder.add_f64_field("lat", STORED | FAST);
+    schema_builder.add_f64_field("lon", STORED | FAST);
+    schema_builder.add_json_field("metadata", json_object_options());
+    schema_builder.add_text_field("tenant", STRING | STORED);
+    schema_builder.add_f64_field("price", INDEXED | STORED | FAST);
+    schema_builder.add_text_field(
+        "tags",
+        TextOptions::default().set_stored().set_indexing_options(
+            TextFieldIndexing::default()
+                .set_tokenizer(RAW_LOWERCASE)
+                .set_index_option(IndexRecordOption::Basic),
+        ),
+    );
+    schema_builder.add_facet_field("category", FacetOptions::default().set_stored());
+    schema_builder.add_bytes_field("key", INDEXED | STORED);
+    schema_builder.add_u64_field("id", INDEXED | STORED | FAST);
+    schema_builder.add_u64_field("opstamp", INDEXED | STORED | FAST);
+    schema_builder.build()
+}
+
+/// The fields bareword queries search in [`sample_schema`]:
+/// every indexed text field but `tenant`, which only scoped searches filter on.
+pub fn sample_search_fields(schema: &Schema) -> Vec<Field> {
+    schema
+        .fields()
+        .filter(|&(field, entry)| is_indexed_text(schema, field) && entry.name() != "tenant")
+        .map(|(field, _)| field)
+        .collect()
+}
+
+fn is_indexed_text(schema: &Schema, field: Field) -> bool {
+    let entry = schema.get_field_entry(field);
+    matches!(entry.field_type(), FieldType::Str(_)) && entry.is_indexed()
+}
+
+/// Registers the tokenizers [`sample_schema`] refers to in `index`.
+///
+/// Like any custom tokenizer, this has to be done every time the index is opened.
+pub fn register_sample_tokenizers(index: &Index) {
+    index.tokenizers().register(RAW_LOWERCASE, raw_lowercase());
+}
+
+/// Options for a stored, searchable JSON field.
+///
+/// Every JSON value is indexed by its type: numbers as numeric terms,
+/// strings through the `default` tokenizer. For a path query like
+/// `metadata.rating:5` the query parser looks for both the number `5` and
+/// the text `"5"`, so it matches whichever way the document spelled it.
+/// Range queries are not supported on JSON fields by the parser.
+pub fn json_object_options() -> JsonObjectOptions {
+    JsonObjectOptions::default()
+        .set_stored()
+        .set_indexing_options(
+            TextFieldIndexing::default()
+                .set_tokenizer("default")
+                .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+        )
+        .set_fast(None)
+}
+
+/// The type of a configured field.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldKind {
+    /// Tokenized full text.
+    Text,
+    /// A single untokenized value, like `STRING`.
+    String,
+    U64,
+    I64,
+    F64,
+    Bool,
+    /// A point in time, written in JSON as an RFC 3339 string like
+    /// `2023-05-01T12:00:00Z`.
+    Date,
+    /// A JSON object, with subfields searchable as `field.path:value`.
+    Json,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// One field of a [`SchemaConfig`].
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct FieldConfig {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: FieldKind,
+    #[serde(default = "default_true")]
+    pub indexed: bool,
+    #[serde(default)]
+    pub stored: bool,
+    #[serde(default)]
+    pub fast: bool,
+    /// Whether bareword queries search this field, when it is indexed text.
+    /// With `false` it is only searched by explicit `field:term` queries.
+    #[serde(default = "default_true")]
+    pub searchable: bool,
+    /// Name of the tokenizer for `text` and `string` fields: one of the
+    /// chains of [`SchemaConfig::tokenizers`], [`RAW_LOWERCASE`]
+    /// or a tantivy built-in like `en_stem`.
+    pub tokenizer: Option<String>,
+    /// Whether BM25 normalizes the term frequencies of this text field by
+    /// its length. Without field norms, every document counts as having
+    /// the same length.
+    #[serde(default = "default_true")]
+    pub fieldnorms: bool,
+    /// Weight of the matches in this text field, for every query, like
+    /// the [`FieldBoosts`] of a single query.
+    pub boost: Option<Score>,
+}
+
+/// A schema, and the tokenizers it needs, loaded from configuration.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct SchemaConfig {
+    pub fields: Vec<FieldConfig>,
+    #[serde(default)]
+    pub tokenizers: BTreeMap<String, TokenizerConfig>,
+    /// Makes the `default` tokenizer keep tokens up to this many bytes,
+    /// instead of tantivy's 39.
+    pub max_token_length: Option<usize>,
+    /// Other names queries can use for a field, mapped to its name,
+    /// see [`resolve_field_aliases`](crate::query::resolve_field_aliases).
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+}
+
+impl SchemaConfig {
+    /// Parses a schema config from TOML.
+    pub fn from_toml(content: &str) -> tantivy::Result<SchemaConfig> {
+        toml::from_str(content)
+            .map_err(|err| TantivyError::InvalidArgument(format!("invalid schema config: {err}")))
+    }
+
+    /// Reads and parses the schema config at `path`.
+    pub fn load(path: &Path) -> tantivy::Result<SchemaConfig> {
+        SchemaConfig::from_toml(&fs::read_to_string(path)?)
+    }
+
+    /// Compiles the fields into a tantivy `Schema`.
+    ///
+    /// Fails if an alias is the name of a field, or stands for no field.
+    pub fn build(&self) -> tantivy::Result<Schema> {
+        for (alias, name) in &self.aliases {
+            if self.fields.iter().any(|field| &field.name == alias) {
+                return Err(TantivyError::SchemaError(format!(
+                    "alias `{alias}` is already the name of a field"
+                )));
+            }
+            if !self.fields.iter().any(|field| &field.name == name) {
+                return Err(TantivyError::SchemaError(format!(
+                    "alias `{alias}` is for unknown field `{name}`"
+                )));
+            }
+        }
+        build_fields(&self.fields)
+    }
+
+    /// The fields bareword queries search in `schema`, built from this config:
+    /// the indexed text fields that are `searchable`.
+    pub fn default_search_fields(&self, schema: &Schema) -> Vec<Field> {
+        self.fields
+            .iter()
+            .filter(|field| field.searchable)
+            .filter_map(|field| schema.get_field(&field.name).ok())
+            .filter(|&field| is_indexed_text(schema, field))
+            .collect()
+    }
+
+    /// The `boost` of every field that has one.
+    pub fn field_boosts(&self) -> FieldBoosts {
+        FieldBoosts(
+            self.fields
+                .iter()
+                .filter_map(|field| Some((field.name.clone(), field.boost?)))
+                .collect(),
+        )
+    }
+
+    /// A parser over the [default search fields](Self::default_search_fields)
+    /// of `index`, weighted by the configured [boosts](Self::field_boosts).
+    pub fn make_query_parser(&self, index: &Index) -> tantivy::Result<QueryParser> {
+        let schema = index.schema();
+        self.field_boosts()
+            .query_parser(index, self.default_search_fields(&schema))
+    }
+
+    /// Registers the configured tokenizer chains in `index`.
+    ///
+    /// Tokenizers are not persisted with the index,
+    /// so this has to be done every time the index is opened.
+    /// Fails if a field refers to a tokenizer that is neither configured nor built in.
+    pub fn register_tokenizers(&self, index: &Index) -> tantivy::Result<()> {
+        index.tokenizers().register(RAW_LOWERCASE, raw_lowercase());
+        if let Some(max_token_length) = self.max_token_length {
+            index
+                .tokenizers()
+                .register("default", default_with_max_token_length(max_token_length));
+        }
+        for (name, tokenizer) in &self.tokenizers {
+            index.tokenizers().register(name, tokenizer.build());
+        }
+        for field in &self.fields {
+            if let Some(name) = &field.tokenizer {
+                if index.tokenizers().get(name).is_none() {
+                    return Err(TantivyError::InvalidArgument(format!(
+                        "field `{}` uses unknown tokenizer `{name}`",
+                        field.name
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks that `fields` make sense together, then compiles them into a `Schema`.
+fn build_fields(fields: &[FieldConfig]) -> tantivy::Result<Schema> {
+    let mut schema_builder = Schema::builder();
+    for (i, field) in fields.iter().enumerate() {
+        if fields[..i].iter().any(|other| other.name == field.name) {
+            return Err(TantivyError::SchemaError(format!(
+                "field `{}` is defined twice",
+                field.name
+            )));
+        }
+        if !field.indexed && !field.stored && !field.fast {
+            return Err(TantivyError::SchemaError(format!(
+                "field `{}` is neither indexed, stored nor fast",
+                field.name
+            )));
+        }
+        match field.kind {
+            FieldKind::Text | FieldKind::String | FieldKind::Json
+                if field.tokenizer.is_some() && !field.indexed =>
+            {
+                return Err(TantivyError::SchemaError(format!(
+                    "field `{}` has a tokenizer but is not indexed",
+                    field.name
+                )));
+            }
+            _ if field
+                .boost
+                .is_some_and(|boost| !boost.is_finite() || boost < 0.0) =>
+            {
+                return Err(TantivyError::SchemaError(format!(
+                    "field `{}` has a negative or infinite boost",
+                    field.name
+                )));
+            }
+            FieldKind::Text => {
+                schema_builder.add_text_field(&field.name, field.text_options());
+            }
+            _ if field.boost.is_some() => {
+                return Err(TantivyError::SchemaError(format!(
+                    "field `{}` is not full text and cannot have a boost",
+                    field.name
+                )));
+            }
+            FieldKind::String => {
+                schema_builder.add_text_field(&field.name, field.text_options());
+            }
+            FieldKind::Json => {
+                schema_builder.add_json_field(&field.name, field.json_options());
+            }
+            _ if field.tokenizer.is_some() => {
+                return Err(TantivyError::SchemaError(format!(
+                    "field `{}` is numeric and cannot have a tokenizer",
+                    field.name
+                )));
+            }
+            _ if !field.fieldnorms => {
+                return Err(TantivyError::SchemaError(format!(
+                    "field `{}` is numeric and has no field norms to disable",
+                    field.name
+                )));
+            }
+            FieldKind::U64 => {
+                schema_builder.add_u64_field(&field.name, field.numeric_options());
+            }
+            FieldKind::I64 => {
+                schema_builder.add_i64_field(&field.name, field.numeric_options());
+            }
+            FieldKind::F64 => {
+                schema_builder.add_f64_field(&field.name, field.numeric_options());
+            }
+            FieldKind::Bool => {
+                schema_builder.add_bool_field(&field.name, field.numeric_options());
+            }
+            FieldKind::Date => {
+                schema_builder.add_date_field(&field.name, field.date_options());
+            }
+        }
+    }
+    Ok(schema_builder.build())
+}
+
+/// A fluent way to write a schema:
+///
+/// ```
+/// use tantivy_basics::schema::SchemaSpec;
+///
+/// let schema = SchemaSpec::new()
+///     .text_field("title").stored().indexed()
+///     .u64_field("year").fast()
+///     .build()
+///     .unwrap();
+/// assert!(schema.get_field("year").is_ok());
+/// ```
+///
+/// Each `*_field` call starts a new field and the options that follow
+/// apply to it. Nothing is enabled by default. Conflicting options,
+/// like a tokenizer on a numeric field, are reported by [`SchemaSpec::build`].
+#[derive(Clone, Debug, Default)]
+pub struct SchemaSpec {
+    fields: Vec<FieldConfig>,
+    error: Option<String>,
+}
+
+impl SchemaSpec {
+    pub fn new() -> SchemaSpec {
+        SchemaSpec::default()
+    }
+
+    /// Starts a tokenized full text field.
+    pub fn text_field(self, name: &str) -> SchemaSpec {
+        self.field(name, FieldKind::Text)
+    }
+
+    /// Starts an untokenized string field.
+    pub fn string_field(self, name: &str) -> SchemaSpec {
+        self.field(name, FieldKind::String)
+    }
+
+    pub fn u64_field(self, name: &str) -> SchemaSpec {
+        self.field(name, FieldKind::U64)
+    }
+
+    pub fn i64_field(self, name: &str) -> SchemaSpec {
+        self.field(name, FieldKind::I64)
+    }
+
+    pub fn f64_field(self, name: &str) -> SchemaSpec {
+        self.field(name, FieldKind::F64)
+    }
+
+    pub fn bool_field(self, name: &str) -> SchemaSpec {
+        self.field(name, FieldKind::Bool)
+    }
+
+    pub fn date_field(self, name: &str) -> SchemaSpec {
+        self.field(name, FieldKind::Date)
+    }
+
+    pub fn json_field(self, name: &str) -> SchemaSpec {
+        self.field(name, FieldKind::Json)
+    }
+
+    pub fn indexed(self) -> SchemaSpec {
+        self.set("indexed", |field| field.indexed = true)
+    }
+
+    pub fn stored(self) -> SchemaSpec {
+        self.set("stored", |field| field.stored = true)
+    }
+
+    pub fn fast(self) -> SchemaSpec {
+        self.set("fast", |field| field.fast = true)
+    }
+
+    /// Tokenizes the current field with the tokenizer registered as `name`.
+    pub fn tokenizer(self, name: &str) -> SchemaSpec {
+        self.set("tokenizer", |field| {
+            field.tokenizer = Some(name.to_string())
+        })
+    }
+
+    /// Leaves the length of the current text field out of its BM25 scores.
+    pub fn without_fieldnorms(self) -> SchemaSpec {
+        self.set("without_fieldnorms", |field| field.fieldnorms = false)
+    }
+
+    /// Compiles the fields into a tantivy `Schema`.
+    pub fn build(self) -> tantivy::Result<Schema> {
+        if let Some(error) = self.error {
+            return Err(TantivyError::SchemaError(error));
+        }
+        build_fields(&self.fields)
+    }
+
+    fn field(mut self, name: &str, kind: FieldKind) -> SchemaSpec {
+        self.fields.push(FieldConfig {
+            name: name.to_string(),
+            kind,
+            indexed: false,
+            stored: false,
+            fast: false,
+            searchable: true,
+            tokenizer: None,
+            fieldnorms: true,
+            boost: None,
+        });
+        self
+    }
+
+    fn set(mut self, option: &str, apply: impl FnOnce(&mut FieldConfig)) -> SchemaSpec {
+        match self.fields.last_mut() {
+            Some(field) => apply(field),
+            None => {
+                self.error
+                    .get_or_insert_with(|| format!("`{option}` was set before any field"));
+            }
+        }
+        self
+    }
+}
+
+impl FieldConfig {
+    fn text_indexing(&self) -> TextFieldIndexing {
+        match self.kind {
+            FieldKind::String => TextFieldIndexing::default()
+                .set_tokenizer(self.tokenizer.as_deref().unwrap_or("raw"))
+                .set_index_option(IndexRecordOption::Basic)
+                .set_fieldnorms(self.fieldnorms),
+            _ => TextFieldIndexing::default()
+                .set_tokenizer(self.tokenizer.as_deref().unwrap_or("default"))
+                .set_index_option(IndexRecordOption::WithFreqsAndPositions)
+                .set_fieldnorms(self.fieldnorms),
+        }
+    }
+
+    fn text_options(&self) -> TextOptions {
+        let mut options = TextOptions::default();
+        if self.indexed {
+            options = options.set_indexing_options(self.text_indexing());
+        }
+        if self.stored {
+            options = options.set_stored();
+        }
+        if self.fast {
+            options = options.set_fast(None);
+        }
+        options
+    }
+
+    fn json_options(&self) -> JsonObjectOptions {
+        let mut options = JsonObjectOptions::default();
+        if self.indexed {
+            options = options.set_indexing_options(self.text_indexing());
+        }
+        if self.stored {
+            options = options.set_stored();
+        }
+        if self.fast {
+            options = options.set_fast(None);
+        }
+        options
+    }
+
+    fn numeric_options(&self) -> NumericOptions {
+        let mut options = NumericOptions::default();
+        if self.indexed {
+            options = options.set_indexed();
+        }
+        if self.stored {
+            options = options.set_stored();
+        }
+        if self.fast {
+            options = options.set_fast();
+        }
+        options
+    }
+
+    fn date_options(&self) -> DateOptions {
+        let mut options = DateOptions::default();
+        if self.indexed {
+            options = options.set_indexed();
+        }
+        if self.stored {
+            options = options.set_stored();
+        }
+        if self.fast {
+            options = options.set_fast();
+        }
+        options
+    }
+}
@@ -0,0 +1,410 @@
+//! Choosing how matches get ranked.
+//!
+//! tantivy only ships BM25: every `Weight` scores with it and there is
+//! no similarity plug-in point like Lucene's. To rank with classic TF-IDF
+//! we let the query decide *which* documents match, then recompute the
+//! score ourselves from the postings through `TopDocs::custom_score`.
+
+use std::cmp::Reverse;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use tantivy::collector::{
+    CustomScorer, CustomSegmentScorer, ScoreSegmentTweaker, ScoreTweaker, TopDocs,
+};
+use tantivy::columnar::Column;
+use tantivy::postings::{Postings, SegmentPostings};
+use tantivy::query::Query;
+use tantivy::schema::{IndexRecordOption, Term};
+use tantivy::{DateTime, DocAddress, DocId, DocSet, Score, Searcher, SegmentReader, TantivyError};
+
+/// The similarity used to rank matching documents.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Scoring {
+    /// tantivy's built-in scoring.
+    #[default]
+    Bm25,
+    /// `sum(sqrt(tf) * idf)` over the query terms, with no length normalization.
+    TfIdf,
+}
+
+impl FromStr for Scoring {
+    type Err = TantivyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "bm25" => Ok(Scoring::Bm25),
+            "tfidf" | "tf-idf" => Ok(Scoring::TfIdf),
+            other => Err(TantivyError::InvalidArgument(format!(
+                "unknown scoring model `{other}`, expected `bm25` or `tfidf`"
+            ))),
+        }
+    }
+}
+
+/// Runs `query` and returns the `limit` best documents under `scoring`.
+pub fn search(
+    searcher: &Searcher,
+    query: &dyn Query,
+    limit: usize,
+    scoring: Scoring,
+) -> tantivy::Result<Vec<(Score, DocAddress)>> {
+    match scoring {
+        Scoring::Bm25 => searcher.search(query, &TopDocs::with_limit(limit)),
+        Scoring::TfIdf => {
+            let scorer = TfIdfScorer::for_query(searcher, query)?;
+            searcher.search(query, &TopDocs::with_limit(limit).custom_score(scorer))
+        }
+    }
+}
+
+/// Keeps only the results scoring at least `min_score`.
+///
+/// This runs after collection: a `TopDocs` limit of 10 followed by a
+/// threshold returns at most 10 results, possibly none.
+pub fn filter_min_score(
+    top_docs: Vec<(Score, DocAddress)>,
+    min_score: Score,
+) -> Vec<(Score, DocAddress)> {
+    top_docs
+        .into_iter()
+        .filter(|&(score, _)| score >= min_score)
+        .collect()
+}
+
+/// The scores of `top_docs` divided by `max_score`, or by the best of them
+/// when `None`, so they go from 0 to 1 for display.
+///
+/// BM25 scores have no upper bound and mean nothing across queries: this
+/// only tells how close each hit comes to the best one. Scores over
+/// `max_score` count as 1, and every score is 0 when it is not positive.
+pub fn normalize_scores(top_docs: &[(Score, DocAddress)], max_score: Option<Score>) -> Vec<Score> {
+    let max_score = max_score.unwrap_or_else(|| {
+        top_docs
+            .iter()
+            .map(|&(score, _)| score)
+            .fold(0.0, Score::max)
+    });
+    top_docs
+        .iter()
+        .map(|&(score, _)| {
+            if max_score > 0.0 {
+                (score / max_score).clamp(0.0, 1.0)
+            } else {
+                0.0
+            }
+        })
+        .collect()
+}
+
+/// Computes TF-IDF scores for the terms of a query.
+pub struct TfIdfScorer {
+    terms: Vec<(Term, Score)>,
+}
+
+impl TfIdfScorer {
+    /// Collects the query terms and their idf over the whole index.
+    pub fn for_query(searcher: &Searcher, query: &dyn Query) -> tantivy::Result<Self> {
+        let mut query_terms = Vec::new();
+        query.query_terms(&mut |term, _| query_terms.push(term.clone()));
+        query_terms.sort();
+        query_terms.dedup();
+
+        let num_docs = searcher.num_docs() as Score;
+        let mut terms = Vec::with_capacity(query_terms.len());
+        for term in query_terms {
+            let doc_freq = searcher.doc_freq(&term)? as Score;
+            let idf = 1.0 + (num_docs / (doc_freq + 1.0)).ln();
+            terms.push((term, idf));
+        }
+        Ok(TfIdfScorer { terms })
+    }
+}
+
+impl CustomScorer<Score> for TfIdfScorer {
+    type Child = TfIdfSegmentScorer;
+
+    fn segment_scorer(&self, segment_reader: &SegmentReader) -> tantivy::Result<Self::Child> {
+        let mut postings = Vec::with_capacity(self.terms.len());
+        for (term, idf) in &self.terms {
+            let inverted_index = segment_reader.inverted_index(term.field())?;
+            if let Some(term_postings) =
+                inverted_index.read_postings(term, IndexRecordOption::WithFreqs)?
+            {
+                postings.push((term_postings, *idf));
+            }
+        }
+        Ok(TfIdfSegmentScorer { postings })
+    }
+}
+
+/// Per-segment half of [`TfIdfScorer`].
+///
+/// Documents are scored in increasing doc id order,
+/// so each postings list only ever moves forward.
+pub struct TfIdfSegmentScorer {
+    postings: Vec<(SegmentPostings, Score)>,
+}
+
+impl CustomSegmentScorer<Score> for TfIdfSegmentScorer {
+    fn score(&mut self, doc: DocId) -> Score {
+        let mut score = 0.0;
+        for (postings, idf) in &mut self.postings {
+            if postings.doc() < doc {
+                postings.seek(doc);
+            }
+            if postings.doc() == doc {
+                score += (postings.term_freq() as Score).sqrt() * *idf;
+            }
+        }
+        score
+    }
+}
+
+/// Multiplies the BM25 score by a recency boost read from a `u64` year fast field.
+///
+/// The boost halves every `half_life_years`: a book from this year keeps its
+/// score, one from `half_life_years` ago keeps half of it. Documents without a
+/// year rank as if infinitely old, after every dated match.
+#[derive(Clone, Debug)]
+pub struct RecencyBoost {
+    pub year_field: String,
+    pub current_year: u64,
+    pub half_life_years: f32,
+}
+
+impl RecencyBoost {
+    /// The factor applied to the score of a document from `year`.
+    pub fn factor(&self, year: Option<u64>) -> Score {
+        match year {
+            Some(year) => {
+                let age = self.current_year.saturating_sub(year) as Score;
+                0.5f32.powf(age / self.half_life_years)
+            }
+            None => 0.0,
+        }
+    }
+}
+
+/// Runs `query` and returns the `limit` best documents once boosted by recency.
+pub fn search_with_recency(
+    searcher: &Searcher,
+    query: &dyn Query,
+    limit: usize,
+    boost: &RecencyBoost,
+) -> tantivy::Result<Vec<(Score, DocAddress)>> {
+    searcher.search(
+        query,
+        &TopDocs::with_limit(limit).tweak_score(boost.clone()),
+    )
+}
+
+impl ScoreTweaker<Score> for RecencyBoost {
+    type Child = RecencySegmentBoost;
+
+    fn segment_tweaker(&self, segment_reader: &SegmentReader) -> tantivy::Result<Self::Child> {
+        Ok(RecencySegmentBoost {
+            years: segment_reader.fast_fields().u64(&self.year_field)?,
+            boost: self.clone(),
+        })
+    }
+}
+
+/// Per-segment half of [`RecencyBoost`].
+pub struct RecencySegmentBoost {
+    years: Column<u64>,
+    boost: RecencyBoost,
+}
+
+impl ScoreSegmentTweaker<Score> for RecencySegmentBoost {
+    fn score(&mut self, doc: DocId, score: Score) -> Score {
+        score * self.boost.factor(self.years.first(doc))
+    }
+}
+
+/// Multiplies the BM25 score by `exp(-lambda * age_days)`, the age being
+/// read from a date fast field, for news-like ranking where fresh matters.
+///
+/// Unlike [`RecencyBoost`], the decay is smooth at the scale of days:
+/// with `lambda` 0.1, a week old article keeps half of its score, a month
+/// old one 5%. Documents dated after `now` count as brand new, and those
+/// without a date rank after every dated match.
+#[derive(Clone, Debug)]
+pub struct TimeDecay {
+    pub created_field: String,
+    pub now: DateTime,
+    pub lambda: f32,
+}
+
+impl TimeDecay {
+    /// The factor applied to the score of a document created at `created`.
+    pub fn factor(&self, created: Option<DateTime>) -> Score {
+        match created {
+            Some(created) => {
+                let age_secs = self.now.into_timestamp_secs() - created.into_timestamp_secs();
+                let age_days = age_secs.max(0) as Score / 86_400.0;
+                (-self.lambda * age_days).exp()
+            }
+            None => 0.0,
+        }
+    }
+}
+
+/// Runs `query` and returns the `limit` best documents once decayed by age.
+pub fn search_with_time_decay(
+    searcher: &Searcher,
+    query: &dyn Query,
+    limit: usize,
+    decay: &TimeDecay,
+) -> tantivy::Result<Vec<(Score, DocAddress)>> {
+    searcher.search(
+        query,
+        &TopDocs::with_limit(limit).tweak_score(decay.clone()),
+    )
+}
+
+impl ScoreTweaker<Score> for TimeDecay {
+    type Child = TimeDecaySegment;
+
+    fn segment_tweaker(&self, segment_reader: &SegmentReader) -> tantivy::Result<Self::Child> {
+        Ok(TimeDecaySegment {
+            created: segment_reader.fast_fields().date(&self.created_field)?,
+            decay: self.clone(),
+        })
+    }
+}
+
+/// Per-segment half of [`TimeDecay`].
+pub struct TimeDecaySegment {
+    created: Column<DateTime>,
+    decay: TimeDecay,
+}
+
+impl ScoreSegmentTweaker<Score> for TimeDecaySegment {
+    fn score(&mut self, doc: DocId, score: Score) -> Score {
+        score * self.decay.factor(self.created.first(doc))
+    }
+}
+
+/// Breaks score ties by the `u64` fast field `id_field`, lowest id first,
+/// so equal scores come back in the same order whatever the segment layout.
+///
+/// Without it, ties are in doc address order, which changes as segments
+/// get created and merged. Documents without an id come after the others.
+#[derive(Clone, Debug)]
+pub struct IdTieBreak {
+    pub id_field: String,
+}
+
+/// Runs `query` and returns the `limit` best documents, ties broken by id.
+pub fn search_stable(
+    searcher: &Searcher,
+    query: &dyn Query,
+    limit: usize,
+    tie_break: &IdTieBreak,
+) -> tantivy::Result<Vec<(Score, DocAddress)>> {
+    let top_docs = searcher.search(
+        query,
+        &TopDocs::with_limit(limit).tweak_score(tie_break.clone()),
+    )?;
+    Ok(top_docs
+        .into_iter()
+        .map(|((score, _), doc_address)| (score, doc_address))
+        .collect())
+}
+
+impl ScoreTweaker<(Score, Reverse<u64>)> for IdTieBreak {
+    type Child = IdSegmentTieBreak;
+
+    fn segment_tweaker(&self, segment_reader: &SegmentReader) -> tantivy::Result<Self::Child> {
+        Ok(IdSegmentTieBreak {
+            ids: segment_reader.fast_fields().u64(&self.id_field)?,
+        })
+    }
+}
+
+/// Per-segment half of [`IdTieBreak`].
+pub struct IdSegmentTieBreak {
+    ids: Column<u64>,
+}
+
+impl ScoreSegmentTweaker<(Score, Reverse<u64>)> for IdSegmentTieBreak {
+    fn score(&mut self, doc: DocId, score: Score) -> (Score, Reverse<u64>) {
+        (score, Reverse(self.ids.first(doc).unwrap_or(u64::MAX)))
+    }
+}
+
+/// Ranks matches by a function of `u64` fast fields alone, ignoring text
+/// relevance: the query only decides which documents match.
+///
+/// `metric` gets the values of `fields` for a document, in the same order,
+/// `None` where it has no value, e.g. `|values| values[0].unwrap_or(0) as f64`
+/// to rank by a popularity count.
+pub struct FastFieldMetric<F> {
+    fields: Vec<String>,
+    metric: Arc<F>,
+}
+
+impl<F> FastFieldMetric<F>
+where
+    F: Fn(&[Option<u64>]) -> f64 + Send + Sync + 'static,
+{
+    pub fn new(fields: &[&str], metric: F) -> FastFieldMetric<F> {
+        FastFieldMetric {
+            fields: fields.iter().map(|field| field.to_string()).collect(),
+            metric: Arc::new(metric),
+        }
+    }
+}
+
+/// Runs `query` and returns the `limit` documents ranking highest under `metric`.
+pub fn search_by_metric<F>(
+    searcher: &Searcher,
+    query: &dyn Query,
+    limit: usize,
+    metric: FastFieldMetric<F>,
+) -> tantivy::Result<Vec<(f64, DocAddress)>>
+where
+    F: Fn(&[Option<u64>]) -> f64 + Send + Sync + 'static,
+{
+    searcher.search(query, &TopDocs::with_limit(limit).custom_score(metric))
+}
+
+impl<F> CustomScorer<f64> for FastFieldMetric<F>
+where
+    F: Fn(&[Option<u64>]) -> f64 + Send + Sync + 'static,
+{
+    type Child = FastFieldSegmentMetric<F>;
+
+    fn segment_scorer(&self, segment_reader: &SegmentReader) -> tantivy::Result<Self::Child> {
+        let columns = self
+            .fields
+            .iter()
+            .map(|field| segment_reader.fast_fields().u64(field))
+            .collect::<tantivy::Result<Vec<_>>>()?;
+        Ok(FastFieldSegmentMetric {
+            values: vec![None; columns.len()],
+            columns,
+            metric: Arc::clone(&self.metric),
+        })
+    }
+}
+
+/// Per-segment half of [`FastFieldMetric`].
+pub struct FastFieldSegmentMetric<F> {
+    columns: Vec<Column<u64>>,
+    values: Vec<Option<u64>>,
+    metric: Arc<F>,
+}
+
+impl<F> CustomSegmentScorer<f64> for FastFieldSegmentMetric<F>
+where
+    F: Fn(&[Option<u64>]) -> f64 + Send + Sync + 'static,
+{
+    fn score(&mut self, doc: DocId) -> f64 {
+        for (value, column) in self.values.iter_mut().zip(&self.columns) {
+            *value = column.first(doc);
+        }
+        (self.metric)(&self.values)
+    }
+}
@@ -0,0 +1,847 @@
+//! Search helpers that combine a user query with structured constraints,
+//! and a structured form of their results.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Bound;
+use std::sync::mpsc::Sender;
+
+use rayon::prelude::*;
+use tantivy::collector::{
+    Collector, Count, CustomScorer, CustomSegmentScorer, DocSetCollector, FacetCollector,
+    FacetCounts, ScoreSegmentTweaker, ScoreTweaker, TopDocs,
+};
+use tantivy::columnar::Column;
+use tantivy::query::{
+    AllQuery, BooleanQuery, EnableScoring, Occur, Query, QueryParser, RangeQuery, TermQuery,
+};
+use tantivy::schema::{Document, Facet, Field, FieldType, IndexRecordOption, Schema, Term};
+use tantivy::{
+    DocAddress, DocId, DocSet, Opstamp, Order, Score, Searcher, SegmentId, SegmentOrdinal,
+    SegmentReader, TantivyError, TERMINATED,
+};
+
+use tracing::info;
+
+use crate::collector::{
+    Group, GroupCollector, Missing, ResultSet, ResultSetCollector, SortByFieldCollector,
+    ValueCountCollector, WithinCollector,
+};
+use crate::query::{f64_range, fuzzy_query, FuzzyDistance};
+
+/// A search result with every stored value of the document.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchHit {
+    pub score: Score,
+    pub doc_address: DocAddress,
+    /// The values of each stored field, by field name. A field given several
+    /// times in the document has all of its values, in insertion order.
+    pub fields: HashMap<String, Vec<serde_json::Value>>,
+    /// How many times the query matches each highlighted field,
+    /// see [`crate::highlight::Highlighter::match_count`].
+    pub match_counts: HashMap<String, usize>,
+    /// The id of the segment the hit comes from, whose ordinal in the
+    /// searcher is that of `doc_address`.
+    pub segment_id: SegmentId,
+}
+
+impl SearchHit {
+    /// Builds the hit for `doc`, retrieved at `doc_address` in `searcher`.
+    pub fn new(
+        searcher: &Searcher,
+        score: Score,
+        doc_address: DocAddress,
+        doc: &Document,
+    ) -> tantivy::Result<SearchHit> {
+        let schema = searcher.schema();
+        let segment_id = searcher
+            .segment_reader(doc_address.segment_ord)
+            .segment_id();
+        let mut fields = HashMap::new();
+        for (name, values) in schema.to_named_doc(doc).0 {
+            let values = values
+                .iter()
+                .map(serde_json::to_value)
+                .collect::<Result<_, _>>()
+                .map_err(|err| TantivyError::InternalError(err.to_string()))?;
+            fields.insert(name, values);
+        }
+        Ok(SearchHit {
+            score,
+            doc_address,
+            fields,
+            match_counts: HashMap::new(),
+            segment_id,
+        })
+    }
+
+    /// Where the hit comes from, for debugging how documents are spread
+    /// over segments: `{"ord": 0, "id": "<segment id>"}`.
+    pub fn segment_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "ord": self.doc_address.segment_ord,
+            "id": self.segment_id.uuid_string(),
+        })
+    }
+
+    /// Retrieves the document at `doc_address` and builds its hit.
+    pub fn fetch(
+        searcher: &Searcher,
+        score: Score,
+        doc_address: DocAddress,
+    ) -> tantivy::Result<SearchHit> {
+        let doc = searcher.doc(doc_address)?;
+        SearchHit::new(searcher, score, doc_address, &doc)
+    }
+}
+
+/// Retrieves the stored fields of the document at `doc_address`, without a query.
+///
+/// Doc addresses change when segments are merged, so one kept outside the
+/// index only stays valid for the searcher it was obtained from, or until
+/// the next merge. Unlike `Searcher::doc`, an address pointing past the
+/// segments is an error rather than a panic.
+pub fn fetch(searcher: &Searcher, doc_address: DocAddress) -> tantivy::Result<Document> {
+    let segment_reader = searcher
+        .segment_readers()
+        .get(doc_address.segment_ord as usize)
+        .ok_or_else(|| {
+            TantivyError::InvalidArgument(format!(
+                "no segment {} in a searcher of {} segments",
+                doc_address.segment_ord,
+                searcher.segment_readers().len()
+            ))
+        })?;
+    if doc_address.doc_id >= segment_reader.max_doc() {
+        return Err(TantivyError::InvalidArgument(format!(
+            "no document {} in segment {}, which has {}",
+            doc_address.doc_id,
+            doc_address.segment_ord,
+            segment_reader.max_doc()
+        )));
+    }
+    searcher.doc(doc_address)
+}
+
+/// Same as [`fetch`], with the address given as segment ordinal and local doc id.
+pub fn fetch_local(
+    searcher: &Searcher,
+    segment_ord: SegmentOrdinal,
+    doc_id: DocId,
+) -> tantivy::Result<Document> {
+    fetch(searcher, DocAddress::new(segment_ord, doc_id))
+}
+
+/// Whether a live document has `id` in `id_field`, see [`exact_term`] for
+/// the field types ids can have.
+///
+/// This reads the postings of the id term segment by segment and stops at
+/// the first document that isn't deleted, without scoring or collecting.
+pub fn exists(searcher: &Searcher, id_field: Field, id: &str) -> tantivy::Result<bool> {
+    let Some(term) = exact_term(searcher.schema(), id_field, id)? else {
+        return Ok(false);
+    };
+    for segment_reader in searcher.segment_readers() {
+        let inverted_index = segment_reader.inverted_index(id_field)?;
+        let Some(mut postings) = inverted_index.read_postings(&term, IndexRecordOption::Basic)?
+        else {
+            continue;
+        };
+        let mut doc = postings.doc();
+        while doc != TERMINATED {
+            if !segment_reader.is_deleted(doc) {
+                return Ok(true);
+            }
+            doc = postings.advance();
+        }
+    }
+    Ok(false)
+}
+
+/// The term of `field` holding exactly `value`, for string, facet, `u64`,
+/// `i64` and `bool` fields. String fields are not tokenized: `value` must be
+/// the whole indexed term. `None` when `value` can't be a value of the
+/// field, like `abc` for a `u64` field, as nothing can match it.
+pub fn exact_term(schema: &Schema, field: Field, value: &str) -> tantivy::Result<Option<Term>> {
+    let field_entry = schema.get_field_entry(field);
+    Ok(match field_entry.field_type() {
+        FieldType::Str(_) => Some(Term::from_field_text(field, value)),
+        FieldType::Facet(_) => Facet::from_text(value)
+            .ok()
+            .map(|facet| Term::from_facet(field, &facet)),
+        FieldType::U64(_) => value
+            .parse()
+            .ok()
+            .map(|value| Term::from_field_u64(field, value)),
+        FieldType::I64(_) => value
+            .parse()
+            .ok()
+            .map(|value| Term::from_field_i64(field, value)),
+        FieldType::Bool(_) => value
+            .parse()
+            .ok()
+            .map(|value| Term::from_field_bool(field, value)),
+        _ => {
+            return Err(TantivyError::SchemaError(format!(
+                "field `{}` can't be matched exactly",
+                field_entry.name()
+            )))
+        }
+    })
+}
+
+/// Returns every document matching all of `constraints` exactly, like
+/// `category = "/fiction" AND language = "en"`, in doc address order.
+///
+/// Each constraint is a `must` term query, see [`exact_term`] for the values.
+pub fn exact_match_all(
+    searcher: &Searcher,
+    constraints: &[(Field, &str)],
+) -> tantivy::Result<Vec<DocAddress>> {
+    let mut clauses: Vec<(Occur, Box<dyn Query>)> = Vec::with_capacity(constraints.len());
+    for &(field, value) in constraints {
+        let Some(term) = exact_term(searcher.schema(), field, value)? else {
+            return Ok(Vec::new());
+        };
+        clauses.push((
+            Occur::Must,
+            Box::new(TermQuery::new(term, IndexRecordOption::Basic)),
+        ));
+    }
+    let mut doc_addresses: Vec<DocAddress> = searcher
+        .search(&BooleanQuery::new(clauses), &DocSetCollector)?
+        .into_iter()
+        .collect();
+    doc_addresses.sort();
+    Ok(doc_addresses)
+}
+
+/// Runs `query_str` restricted to the documents of `tenant`.
+///
+/// The parsed query and a term query on `tenant_field` are both `must`
+/// clauses, so nothing the user types, not even `tenant:other`,
+/// can widen the search to another tenant's documents.
+pub fn search_scoped(
+    searcher: &Searcher,
+    query_parser: &QueryParser,
+    tenant_field: Field,
+    tenant: &str,
+    query_str: &str,
+    limit: usize,
+) -> tantivy::Result<Vec<(Score, DocAddress)>> {
+    let tenant_filter = Filter::Term(Term::from_field_text(tenant_field, tenant));
+    search_with_filter(searcher, query_parser, query_str, &[tenant_filter], limit)
+}
+
+/// A structured constraint on the documents a search may return.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Filter {
+    /// The document has this exact term, e.g. `Term::from_field_bool(published, true)`.
+    Term(Term),
+    U64Range {
+        field: Field,
+        lower: Bound<u64>,
+        upper: Bound<u64>,
+    },
+    I64Range {
+        field: Field,
+        lower: Bound<i64>,
+        upper: Bound<i64>,
+    },
+    /// See [`crate::query::f64_range`] for how floats compare.
+    F64Range {
+        field: Field,
+        lower: Bound<f64>,
+        upper: Bound<f64>,
+    },
+}
+
+impl Filter {
+    /// The query matching the documents that pass the filter.
+    pub fn to_query(&self, schema: &Schema) -> tantivy::Result<Box<dyn Query>> {
+        let field_name = |field: Field| schema.get_field_name(field).to_string();
+        Ok(match self {
+            Filter::Term(term) => Box::new(TermQuery::new(term.clone(), IndexRecordOption::Basic)),
+            Filter::U64Range {
+                field,
+                lower,
+                upper,
+            } => Box::new(RangeQuery::new_u64_bounds(
+                field_name(*field),
+                *lower,
+                *upper,
+            )),
+            Filter::I64Range {
+                field,
+                lower,
+                upper,
+            } => Box::new(RangeQuery::new_i64_bounds(
+                field_name(*field),
+                *lower,
+                *upper,
+            )),
+            Filter::F64Range {
+                field,
+                lower,
+                upper,
+            } => Box::new(f64_range(schema, *field, *lower, *upper)?),
+        })
+    }
+}
+
+/// Runs `query_str` restricted to the documents passing every one of `filters`,
+/// e.g. `sea` published from 1950 on.
+///
+/// The parsed query and the filters are all `must` clauses. Filters match
+/// terms or ranges, which score a constant, so they narrow the results
+/// without changing their order much.
+pub fn search_with_filter(
+    searcher: &Searcher,
+    query_parser: &QueryParser,
+    query_str: &str,
+    filters: &[Filter],
+    limit: usize,
+) -> tantivy::Result<Vec<(Score, DocAddress)>> {
+    let mut clauses = vec![(Occur::Must, query_parser.parse_query(query_str)?)];
+    for filter in filters {
+        clauses.push((Occur::Must, filter.to_query(searcher.schema())?));
+    }
+    searcher.search(&BooleanQuery::new(clauses), &TopDocs::with_limit(limit))
+}
+
+/// Runs `query`, and if its best hit scores under `floor`, also runs
+/// the words of `text` as a [`fuzzy_query`] over `fields` and merges both.
+///
+/// A low best score usually means the words were misspelled and only
+/// matched by chance, so the typo-tolerant hits are added to, not swapped
+/// for, the exact ones. Fuzzy terms score a constant 1 each, so a document
+/// found both ways keeps its best score, and the merge is ordered like
+/// `TopDocs`: by score, then by doc address.
+pub fn search_with_fuzzy_fallback(
+    searcher: &Searcher,
+    query: &dyn Query,
+    fields: &[Field],
+    text: &str,
+    limit: usize,
+    floor: Score,
+    distance: FuzzyDistance,
+) -> tantivy::Result<Vec<(Score, DocAddress)>> {
+    let top_docs = searcher.search(query, &TopDocs::with_limit(limit))?;
+    if top_docs.first().is_some_and(|&(score, _)| score >= floor) {
+        return Ok(top_docs);
+    }
+    let fuzzy = fuzzy_query(searcher.index(), fields, text, distance)?;
+    let fuzzy_docs = searcher.search(&fuzzy, &TopDocs::with_limit(limit))?;
+    info!(
+        best_score = top_docs.first().map(|&(score, _)| score),
+        fuzzy_hits = fuzzy_docs.len(),
+        "best score under the relevance floor, merged fuzzy hits"
+    );
+    Ok(merge_top_docs(top_docs, fuzzy_docs, limit))
+}
+
+/// Merges two lists of hits, keeping the best score of each document.
+fn merge_top_docs(
+    first: Vec<(Score, DocAddress)>,
+    second: Vec<(Score, DocAddress)>,
+    limit: usize,
+) -> Vec<(Score, DocAddress)> {
+    let mut best: HashMap<DocAddress, Score> = HashMap::new();
+    for (score, doc_address) in first.into_iter().chain(second) {
+        let best_score = best.entry(doc_address).or_insert(score);
+        *best_score = best_score.max(score);
+    }
+    let mut merged: Vec<(Score, DocAddress)> = best
+        .into_iter()
+        .map(|(doc_address, score)| (score, doc_address))
+        .collect();
+    merged.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+    merged.truncate(limit);
+    merged
+}
+
+/// A cheap upper bound of the number of documents `query` matches: the sum
+/// of the document frequencies of its terms, at most the number of documents.
+///
+/// Nothing is scored or even iterated, only the term dictionaries are read,
+/// so this is fit for deciding how to run a query. The bound is loose for
+/// intersections and counts deleted documents. A query without terms, like
+/// a range, a fuzzy query or `*`, may match anything: there is no estimate,
+/// and `None` is returned.
+pub fn estimate_hits(searcher: &Searcher, query: &dyn Query) -> tantivy::Result<Option<u64>> {
+    let mut terms = BTreeSet::new();
+    query.query_terms(&mut |term, _| {
+        terms.insert(term.clone());
+    });
+    if terms.is_empty() {
+        return Ok(None);
+    }
+    let mut estimate = 0;
+    for term in &terms {
+        estimate += searcher.doc_freq(term)?;
+    }
+    Ok(Some(estimate.min(searcher.num_docs())))
+}
+
+/// Everything a results page shows, from a single search.
+pub struct SearchSummary {
+    pub top_docs: Vec<(Score, DocAddress)>,
+    /// Number of matching documents, not only the top ones.
+    pub count: usize,
+    /// Counts of the matching documents under each of the requested facets.
+    pub facets: FacetCounts,
+}
+
+/// Runs `query` once to get the top `limit` documents, the total number of
+/// matches, and the facet counts of `facet_field` under each of `facet_roots`.
+///
+/// The three collectors are combined into a tuple collector, so every match
+/// is visited a single time instead of once per collector.
+pub fn search_summary(
+    searcher: &Searcher,
+    query: &dyn Query,
+    limit: usize,
+    facet_field: Field,
+    facet_roots: &[&str],
+) -> tantivy::Result<SearchSummary> {
+    let mut facet_collector =
+        FacetCollector::for_field(searcher.schema().get_field_name(facet_field));
+    for root in facet_roots {
+        facet_collector.add_facet(*root);
+    }
+    let (top_docs, count, facets) =
+        searcher.search(query, &(TopDocs::with_limit(limit), Count, facet_collector))?;
+    Ok(SearchSummary {
+        top_docs,
+        count,
+        facets,
+    })
+}
+
+/// Drills down into `facet_path` of `facet_field`: runs `query_str` on the
+/// documents under that facet, e.g. `/fiction`, and counts them by its
+/// sub-facets, like `/fiction/classics`, for the next level of navigation.
+///
+/// A facet term matches every document under it, however deep, so
+/// `/fiction` matches documents in `/fiction/classics` too.
+pub fn search_with_facet(
+    searcher: &Searcher,
+    query_parser: &QueryParser,
+    query_str: &str,
+    facet_field: Field,
+    facet_path: &str,
+    limit: usize,
+) -> tantivy::Result<SearchSummary> {
+    let facet = Facet::from_text(facet_path).map_err(|err| {
+        TantivyError::InvalidArgument(format!("invalid facet `{facet_path}`: {err}"))
+    })?;
+    let query = BooleanQuery::new(vec![
+        (Occur::Must, query_parser.parse_query(query_str)?),
+        (
+            Occur::Must,
+            Box::new(TermQuery::new(
+                Term::from_facet(facet_field, &facet),
+                IndexRecordOption::Basic,
+            )),
+        ),
+    ]);
+    search_summary(searcher, &query, limit, facet_field, &[&facet.to_string()])
+}
+
+/// The facets of `facet_field` exactly `depth` levels under `prefix`, with
+/// the number of documents matching `query` under each, in facet order.
+///
+/// A depth of 1 gives the children of `prefix`, e.g. `/a/b` and `/a/c` for
+/// `/a`, 2 their children, and so on. As with any facet count, a document
+/// counts once under each facet it is in or under, so the count of `/a/b`
+/// includes the documents of `/a/b/c`. `FacetCollector` only counts one
+/// level at a time, so this searches once per level.
+pub fn facet_counts_at_depth(
+    searcher: &Searcher,
+    query: &dyn Query,
+    facet_field: Field,
+    prefix: &str,
+    depth: usize,
+) -> tantivy::Result<Vec<(String, u64)>> {
+    if depth == 0 {
+        return Err(TantivyError::InvalidArgument(
+            "facet depth must be at least 1".to_string(),
+        ));
+    }
+    let prefix = Facet::from_text(prefix)
+        .map_err(|err| TantivyError::InvalidArgument(format!("invalid facet `{prefix}`: {err}")))?;
+    let field_name = searcher.schema().get_field_name(facet_field);
+    let mut level = vec![(prefix, 0)];
+    for _ in 0..depth {
+        if level.is_empty() {
+            break;
+        }
+        let mut facet_collector = FacetCollector::for_field(field_name);
+        for (facet, _) in &level {
+            facet_collector.add_facet(facet.clone());
+        }
+        let facet_counts = searcher.search(query, &facet_collector)?;
+        level = level
+            .iter()
+            .flat_map(|(facet, _)| facet_counts.get(facet.clone()))
+            .map(|(facet, count)| (facet.clone(), count))
+            .collect();
+    }
+    level.sort();
+    Ok(level
+        .into_iter()
+        .map(|(facet, count)| (facet.to_string(), count))
+        .collect())
+}
+
+/// Same as searching with `TopDocs::with_limit(limit)`, one segment per
+/// rayon task, the per-segment top docs being merged at the end.
+///
+/// This only pays off with several segments to spread over the cores:
+/// on a merged index there is a single task, plus the overhead.
+pub fn search_parallel(
+    searcher: &Searcher,
+    query: &dyn Query,
+    limit: usize,
+) -> tantivy::Result<Vec<(Score, DocAddress)>> {
+    let collector = TopDocs::with_limit(limit);
+    let weight = query.weight(EnableScoring::enabled_from_searcher(searcher))?;
+    let segment_fruits = searcher
+        .segment_readers()
+        .par_iter()
+        .enumerate()
+        .map(|(segment_ord, segment_reader)| {
+            collector.collect_segment(
+                weight.as_ref(),
+                segment_ord as SegmentOrdinal,
+                segment_reader,
+            )
+        })
+        .collect::<tantivy::Result<Vec<_>>>()?;
+    collector.merge_fruits(segment_fruits)
+}
+
+/// Sends every document matching `query` over `tx` as soon as it is read
+/// from the store, rather than collecting them first, and returns how many
+/// were sent.
+///
+/// Hits come segment by segment in doc id order, not by score: only the
+/// doc ids and scores of one segment are buffered. Dropping the receiver
+/// stops the search.
+pub fn search_stream(
+    searcher: &Searcher,
+    query: &dyn Query,
+    tx: Sender<SearchHit>,
+) -> tantivy::Result<usize> {
+    let weight = query.weight(EnableScoring::enabled_from_searcher(searcher))?;
+    let mut sent = 0;
+    for (segment_ord, segment_reader) in searcher.segment_readers().iter().enumerate() {
+        let mut matches: Vec<(DocId, Score)> = Vec::new();
+        weight.for_each(segment_reader, &mut |doc_id, score| {
+            if !segment_reader.is_deleted(doc_id) {
+                matches.push((doc_id, score));
+            }
+        })?;
+        let store_reader = segment_reader.get_store_reader(1)?;
+        for (doc_id, score) in matches {
+            let doc_address = DocAddress::new(segment_ord as SegmentOrdinal, doc_id);
+            let doc = store_reader.get(doc_id)?;
+            let hit = SearchHit::new(searcher, score, doc_address, &doc)?;
+            if tx.send(hit).is_err() {
+                return Ok(sent);
+            }
+            sent += 1;
+        }
+    }
+    Ok(sent)
+}
+
+/// Runs `query` and returns the `groups` best groups of matches sharing a
+/// value of the `u64` fast field `group_field`, each with its `per_group`
+/// best documents. See [`GroupCollector`].
+pub fn search_grouped(
+    searcher: &Searcher,
+    query: &dyn Query,
+    group_field: Field,
+    groups: usize,
+    per_group: usize,
+) -> tantivy::Result<Vec<Group>> {
+    let field_name = searcher.schema().get_field_name(group_field);
+    searcher.search(query, &GroupCollector::new(field_name, groups, per_group))
+}
+
+/// Collects every document matching `query`, to refine with [`search_within`].
+pub fn result_set(searcher: &Searcher, query: &dyn Query) -> tantivy::Result<ResultSet> {
+    searcher.search(query, &ResultSetCollector)
+}
+
+/// The documents matching both `a` and `b`.
+///
+/// Like the other set operations, this collects each query into a
+/// [`ResultSet`] and combines the bitsets, so the queries can come from
+/// anywhere, and the result can be combined further or searched with
+/// [`search_within`]. For a single search, a `BooleanQuery` of `must`s
+/// gives the same documents with scores.
+pub fn query_intersection(
+    searcher: &Searcher,
+    a: &dyn Query,
+    b: &dyn Query,
+) -> tantivy::Result<ResultSet> {
+    Ok(result_set(searcher, a)?.intersection(&result_set(searcher, b)?))
+}
+
+/// The documents matching `a`, `b` or both.
+pub fn query_union(
+    searcher: &Searcher,
+    a: &dyn Query,
+    b: &dyn Query,
+) -> tantivy::Result<ResultSet> {
+    Ok(result_set(searcher, a)?.union(&result_set(searcher, b)?))
+}
+
+/// The documents matching `a` but not `b`.
+pub fn query_difference(
+    searcher: &Searcher,
+    a: &dyn Query,
+    b: &dyn Query,
+) -> tantivy::Result<ResultSet> {
+    Ok(result_set(searcher, a)?.difference(&result_set(searcher, b)?))
+}
+
+/// Runs `query` on the documents of `within` only, returning the `limit`
+/// best matches, like a search within the results of a previous query.
+///
+/// `within` must come from [`result_set`] with the same searcher.
+/// Scores are those of `query` alone.
+pub fn search_within(
+    searcher: &Searcher,
+    within: &ResultSet,
+    query: &dyn Query,
+    limit: usize,
+) -> tantivy::Result<Vec<(Score, DocAddress)>> {
+    let collector = WithinCollector::new(within.clone(), TopDocs::with_limit(limit));
+    searcher.search(query, &collector)
+}
+
+/// Returns the documents ingested after the commit `opstamp`, those stamped
+/// with it or a later one, oldest first, with the opstamp they are stamped with.
+///
+/// A load stamps all of its documents alike, so changes are ordered by
+/// stamp, then by doc address. At most `limit` of them are returned: to get
+/// the next page, pass the stamp and the address of the last change
+/// returned as `opstamp` and `after`, the page starting right after it.
+/// Addresses are only valid for the searcher they came from, so a feed
+/// resuming with a new searcher passes the stamp alone, which returns the
+/// other changes of that stamp again.
+///
+/// `opstamp_field` is the fast field filled by [`crate::ingest::ingest_stamped`].
+pub fn changes_since(
+    searcher: &Searcher,
+    opstamp_field: Field,
+    opstamp: Opstamp,
+    after: Option<DocAddress>,
+    limit: usize,
+) -> tantivy::Result<Vec<(Opstamp, DocAddress)>> {
+    let field_name = searcher.schema().get_field_name(opstamp_field);
+    let query = RangeQuery::new_u64_bounds(
+        field_name.to_string(),
+        Bound::Included(opstamp),
+        Bound::Unbounded,
+    );
+    let order = ChangeOrder {
+        field: field_name.to_string(),
+        after: after.map(|doc_address| (opstamp, doc_address)),
+        segment_ids: searcher
+            .segment_readers()
+            .iter()
+            .map(SegmentReader::segment_id)
+            .collect(),
+    };
+    let changes = searcher.search(&query, &TopDocs::with_limit(limit).custom_score(order))?;
+    // The changes up to the cursor rank last, so they are only here when
+    // fewer than `limit` changes come after it.
+    Ok(changes
+        .into_iter()
+        .filter_map(|(stamp, doc_address)| Some((stamp?.0, doc_address)))
+        .collect())
+}
+
+/// Ranks the oldest changes first, and those up to `after`, if any, last,
+/// as `None`.
+struct ChangeOrder {
+    field: String,
+    after: Option<(Opstamp, DocAddress)>,
+    segment_ids: Vec<SegmentId>,
+}
+
+impl CustomScorer<Option<Reverse<Opstamp>>> for ChangeOrder {
+    type Child = SegmentChangeOrder;
+
+    fn segment_scorer(&self, segment_reader: &SegmentReader) -> tantivy::Result<Self::Child> {
+        let segment_ord = self
+            .segment_ids
+            .iter()
+            .position(|&segment_id| segment_id == segment_reader.segment_id())
+            .ok_or_else(|| {
+                TantivyError::InvalidArgument("the cursor comes from another searcher".to_string())
+            })?;
+        Ok(SegmentChangeOrder {
+            stamps: segment_reader.fast_fields().u64(&self.field)?,
+            after: self.after,
+            segment_ord: segment_ord as SegmentOrdinal,
+        })
+    }
+}
+
+struct SegmentChangeOrder {
+    stamps: Column<u64>,
+    after: Option<(Opstamp, DocAddress)>,
+    segment_ord: SegmentOrdinal,
+}
+
+impl CustomSegmentScorer<Option<Reverse<Opstamp>>> for SegmentChangeOrder {
+    fn score(&mut self, doc: DocId) -> Option<Reverse<Opstamp>> {
+        let stamp = self.stamps.first(doc)?;
+        let change = (stamp, DocAddress::new(self.segment_ord, doc));
+        match self.after {
+            Some(after) if change <= after => None,
+            _ => Some(Reverse(stamp)),
+        }
+    }
+}
+
+/// Returns the top `limit` documents matching `query` sorted by the `u64`
+/// fast field `field`, with their value, see [`SortByFieldCollector`].
+///
+/// Documents without a value for `field` come first or last, as `missing`
+/// says, whatever the order.
+pub fn search_sorted(
+    searcher: &Searcher,
+    query: &dyn Query,
+    field: Field,
+    order: Order,
+    missing: Missing,
+    limit: usize,
+) -> tantivy::Result<Vec<(Option<u64>, DocAddress)>> {
+    let field_entry = searcher.schema().get_field_entry(field);
+    if !matches!(field_entry.field_type(), FieldType::U64(_)) || !field_entry.is_fast() {
+        return Err(TantivyError::SchemaError(format!(
+            "field `{}` must be a fast u64 field to sort by",
+            field_entry.name()
+        )));
+    }
+    let collector = SortByFieldCollector::new(field_entry.name(), order, missing, limit);
+    searcher.search(query, &collector)
+}
+
+/// Counts the documents of every value of `field`, most frequent first.
+///
+/// For a facet field, these are the top-level facets, like `/fiction`.
+/// A string field must be fast, its values are read from the fast field.
+pub fn count_by_field(searcher: &Searcher, field: Field) -> tantivy::Result<Vec<(String, u64)>> {
+    let field_entry = searcher.schema().get_field_entry(field);
+    let mut counts: Vec<(String, u64)> = match field_entry.field_type() {
+        FieldType::Facet(_) => {
+            let mut facet_collector = FacetCollector::for_field(field_entry.name());
+            facet_collector.add_facet("/");
+            let facet_counts = searcher.search(&AllQuery, &facet_collector)?;
+            facet_counts
+                .get("/")
+                .map(|(facet, count)| (facet.to_string(), count))
+                .collect()
+        }
+        FieldType::Str(_) if field_entry.is_fast() => searcher
+            .search(&AllQuery, &ValueCountCollector::new(field_entry.name()))?
+            .into_iter()
+            .collect(),
+        _ => {
+            return Err(TantivyError::SchemaError(format!(
+                "field `{}` must be a facet or a fast string field to count by",
+                field_entry.name()
+            )))
+        }
+    };
+    counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+    Ok(counts)
+}
+
+/// Returns the `limit` matches of `query` ranking right after the hit
+/// `last_score`, `last_doc`, the last one of the previous page.
+///
+/// Hits are ranked by score, then by doc address for equal scores, like
+/// `TopDocs` does. Instead of collecting and skipping every hit of the
+/// previous pages, as an offset does, this drops the hits ranking before
+/// the cursor, so a deep page costs as much as the first one. The cursor
+/// is only valid for the searcher the previous page came from.
+pub fn search_after(
+    searcher: &Searcher,
+    query: &dyn Query,
+    last_score: Score,
+    last_doc: DocAddress,
+    limit: usize,
+) -> tantivy::Result<Vec<(Score, DocAddress)>> {
+    let cursor = Cursor {
+        score: last_score,
+        doc_address: last_doc,
+        segment_ids: searcher
+            .segment_readers()
+            .iter()
+            .map(SegmentReader::segment_id)
+            .collect(),
+    };
+    let top_docs = searcher.search(query, &TopDocs::with_limit(limit).tweak_score(cursor))?;
+    // The hits before the cursor rank last, so they are only here when
+    // fewer than `limit` hits come after it.
+    Ok(top_docs
+        .into_iter()
+        .filter(|(score, _)| *score != Score::NEG_INFINITY)
+        .collect())
+}
+
+/// Scores the hits ranking before it, or on it, as negative infinity.
+///
+/// A tweaker only sees segment readers, so it keeps their ids in searcher
+/// order to tell the segment ordinal of each.
+#[derive(Clone)]
+struct Cursor {
+    score: Score,
+    doc_address: DocAddress,
+    segment_ids: Vec<SegmentId>,
+}
+
+impl ScoreTweaker<Score> for Cursor {
+    type Child = SegmentCursor;
+
+    fn segment_tweaker(&self, segment_reader: &SegmentReader) -> tantivy::Result<Self::Child> {
+        let segment_ord = self
+            .segment_ids
+            .iter()
+            .position(|&segment_id| segment_id == segment_reader.segment_id())
+            .ok_or_else(|| {
+                TantivyError::InvalidArgument("the cursor comes from another searcher".to_string())
+            })?;
+        Ok(SegmentCursor {
+            score: self.score,
+            doc_address: self.doc_address,
+            segment_ord: segment_ord as SegmentOrdinal,
+        })
+    }
+}
+
+struct SegmentCursor {
+    score: Score,
+    doc_address: DocAddress,
+    segment_ord: SegmentOrdinal,
+}
+
+impl ScoreSegmentTweaker<Score> for SegmentCursor {
+    fn score(&mut self, doc: DocId, score: Score) -> Score {
+        let after_cursor = score < self.score
+            || (score == self.score && DocAddress::new(self.segment_ord, doc) > self.doc_address);
+        if after_cursor {
+            score
+        } else {
+            Score::NEG_INFINITY
+        }
+    }
+}
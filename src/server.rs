@@ -0,0 +1,360 @@
+//! A small HTTP search service over one index.
+//!
+//! Routes:
+//!
+//! - `GET /search?q=<query>[&limit=<n>][&boost=<field:weight,...>]`: the
+//!   top hits as JSON, `{"hits": [{"score": .., "doc": {..}, "_segment":
+//!   {..}}]}`, `_segment` being the ordinal and id of the segment of the
+//!   hit. 10 hits by default and at most 1000, see
+//!   [`SearchServer::with_max_limit`]. `boost=title:3,body:1` weights the
+//!   fields for this query only, and `stop=the,of` ignores these words in
+//!   it. `normalize=top` adds a `normalized_score` to each hit, its score
+//!   divided by the best one, and `normalize=<score>` divides by that score
+//!   instead.
+//! - `POST /search[?limit=<n>][&boost=...][&stop=...][&normalize=...]`: the
+//!   same, for the JSON [`BoolQuery`] of the body, like `{"must": [{"match":
+//!   {"field": "body", "text": "old man"}}]}`, `stop` applying to its query
+//!   strings and `match` texts.
+//! - `GET /metrics`: the [`Metrics`] in the Prometheus text format.
+//! - `GET /healthz`: 200 as soon as the server is up, its reader open.
+//! - `GET /readyz`: 200 once [`SearchServer::warm_up`] has completed
+//!   and a trivial query still succeeds, 503 until then.
+//!
+//! Requests are served one at a time, by [`SearchServer::serve`]. Routing
+//! is kept apart in [`SearchServer::handle`], which takes the request path
+//! and query string, so it can be exercised without a socket.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+use serde_json::json;
+use tantivy::collector::{Count, TopDocs};
+use tantivy::query::{AllQuery, Query, QueryParser};
+use tantivy::schema::Field;
+use tantivy::{Index, IndexReader, Score, TantivyError};
+use tracing::{info, warn};
+
+use crate::dsl::BoolQuery;
+use crate::metrics::Metrics;
+use crate::query::{remove_stop_words, validate_query, FieldBoosts};
+use crate::scoring::normalize_scores;
+use crate::search::SearchHit;
+use crate::warmup::warm_cache;
+
+/// What to answer to a request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    fn json(status: u16, body: serde_json::Value) -> Response {
+        Response {
+            status,
+            content_type: "application/json",
+            body: body.to_string(),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Response {
+        Response::json(status, json!({ "error": message }))
+    }
+}
+
+/// The largest `limit` a search may ask for, by default.
+pub const DEFAULT_MAX_LIMIT: usize = 1000;
+
+/// Serves searches over `index`, bareword queries searching `search_fields`.
+pub struct SearchServer {
+    index: Index,
+    reader: IndexReader,
+    search_fields: Vec<Field>,
+    ready: AtomicBool,
+    max_limit: usize,
+    strict_limit: bool,
+}
+
+impl SearchServer {
+    /// The reader reloads on every commit, so the service picks up the
+    /// documents another process indexes.
+    pub fn new(index: Index, search_fields: Vec<Field>) -> tantivy::Result<SearchServer> {
+        let reader = index.reader()?;
+        Ok(SearchServer {
+            index,
+            reader,
+            search_fields,
+            ready: AtomicBool::new(false),
+            max_limit: DEFAULT_MAX_LIMIT,
+            strict_limit: false,
+        })
+    }
+
+    /// Caps the `limit` of searches at `max_limit` instead of [`DEFAULT_MAX_LIMIT`].
+    ///
+    /// A search asking for more gets `max_limit` hits, with a `warning`
+    /// in the response, unless the limit is [strict](Self::with_strict_limit).
+    /// A `max_limit` of 0 is taken as 1.
+    pub fn with_max_limit(mut self, max_limit: usize) -> SearchServer {
+        self.max_limit = max_limit.max(1);
+        self
+    }
+
+    /// Rejects the searches asking for more than the max limit with a 400,
+    /// instead of clamping their limit.
+    pub fn with_strict_limit(mut self) -> SearchServer {
+        self.strict_limit = true;
+        self
+    }
+
+    /// Runs the warm-up queries of the file at `queries`, if any, see
+    /// [`warm_cache`], then marks the server ready.
+    ///
+    /// It only takes `&self`, so it can run on another thread while
+    /// [`SearchServer::serve`] already answers, `/readyz` with a 503.
+    pub fn warm_up(&self, queries: Option<&Path>) -> tantivy::Result<()> {
+        if let Some(queries) = queries {
+            let query_parser = QueryParser::for_index(&self.index, self.search_fields.clone());
+            warm_cache(&self.reader.searcher(), &query_parser, queries, 10)?;
+        }
+        self.ready.store(true, Ordering::Release);
+        info!("ready");
+        Ok(())
+    }
+
+    /// Answers the `GET` request for `url`, a path with an optional query string.
+    pub fn handle(&self, url: &str) -> Response {
+        let (path, query_string) = url.split_once('?').unwrap_or((url, ""));
+        let params = parse_query_string(query_string);
+        match path {
+            "/search" => search_response(self.search(&params)),
+            "/healthz" => Response::json(200, json!({ "status": "ok" })),
+            "/readyz" => self.readiness(),
+            "/metrics" => Response {
+                status: 200,
+                content_type: "text/plain; version=0.0.4",
+                body: Metrics::global().render(),
+            },
+            _ => Response::error(404, &format!("no route for {path}")),
+        }
+    }
+
+    /// Answers the `POST` request for `url` with `body`.
+    pub fn handle_post(&self, url: &str, body: &str) -> Response {
+        let (path, query_string) = url.split_once('?').unwrap_or((url, ""));
+        let params = parse_query_string(query_string);
+        match path {
+            "/search" => search_response(self.search_structured(&params, body)),
+            _ => Response::error(405, &format!("{path} only answers GET")),
+        }
+    }
+
+    fn readiness(&self) -> Response {
+        if !self.ready.load(Ordering::Acquire) {
+            return Response::error(503, "warming up");
+        }
+        match self.reader.searcher().search(&AllQuery, &Count) {
+            Ok(_) => Response::json(200, json!({ "status": "ready" })),
+            Err(err) => {
+                warn!(%err, "readiness query failed");
+                Response::error(503, &err.to_string())
+            }
+        }
+    }
+
+    fn search(&self, params: &HashMap<String, String>) -> tantivy::Result<Response> {
+        let mut query_str = params
+            .get("q")
+            .cloned()
+            .ok_or_else(|| TantivyError::InvalidArgument("missing `q` parameter".to_string()))?;
+        if let Some(stop_words) = params.get("stop") {
+            let stop_words: Vec<&str> = stop_words.split(',').map(str::trim).collect();
+            query_str = remove_stop_words(&query_str, &stop_words);
+        }
+        let started_at = Instant::now();
+        let query_parser = self.query_parser(params)?;
+        validate_query(&query_parser, &query_str)?;
+        let query = query_parser.parse_query(&query_str)?;
+        self.respond_hits(query.as_ref(), params, started_at)
+    }
+
+    /// Searches the [`BoolQuery`] of the JSON `body`, its query strings
+    /// parsed like the `q` of a `GET`.
+    fn search_structured(
+        &self,
+        params: &HashMap<String, String>,
+        body: &str,
+    ) -> tantivy::Result<Response> {
+        let mut bool_query = BoolQuery::from_json(body)?;
+        if let Some(stop_words) = params.get("stop") {
+            let stop_words: Vec<&str> = stop_words.split(',').map(str::trim).collect();
+            bool_query.remove_stop_words(&stop_words);
+        }
+        let started_at = Instant::now();
+        let query_parser = self.query_parser(params)?;
+        let query = bool_query.build(&self.index, &query_parser)?;
+        self.respond_hits(query.as_ref(), params, started_at)
+    }
+
+    /// A parser of the query strings of a request, weighted as `boost` says.
+    fn query_parser(&self, params: &HashMap<String, String>) -> tantivy::Result<QueryParser> {
+        let boosts: FieldBoosts = match params.get("boost") {
+            Some(boosts) => boosts.parse()?,
+            None => FieldBoosts::default(),
+        };
+        boosts.query_parser(&self.index, self.search_fields.clone())
+    }
+
+    /// The `limit` best hits of `query`, 10 by default.
+    fn respond_hits(
+        &self,
+        query: &dyn Query,
+        params: &HashMap<String, String>,
+        started_at: Instant,
+    ) -> tantivy::Result<Response> {
+        let mut limit = match params.get("limit") {
+            Some(limit) => match limit.parse() {
+                Ok(limit) if limit > 0 => limit,
+                _ => {
+                    return Err(TantivyError::InvalidArgument(format!(
+                        "invalid limit `{limit}`, expected a positive number"
+                    )))
+                }
+            },
+            None => 10,
+        };
+        let mut warning = None;
+        if limit > self.max_limit {
+            if self.strict_limit {
+                return Err(TantivyError::InvalidArgument(format!(
+                    "limit {limit} is over the maximum of {}",
+                    self.max_limit
+                )));
+            }
+            warning = Some(format!(
+                "limit {limit} was clamped to the maximum of {}",
+                self.max_limit
+            ));
+            limit = self.max_limit;
+        }
+        let normalize = match params.get("normalize").map(String::as_str) {
+            None => None,
+            Some("top") => Some(None),
+            Some(max_score) => match max_score.parse::<Score>() {
+                Ok(max_score) if max_score > 0.0 => Some(Some(max_score)),
+                _ => {
+                    return Err(TantivyError::InvalidArgument(format!(
+                        "invalid normalize `{max_score}`, expected `top` or a positive score"
+                    )))
+                }
+            },
+        };
+        let searcher = self.reader.searcher();
+        let top_docs = searcher.search(query, &TopDocs::with_limit(limit))?;
+        Metrics::global().observe_query(started_at.elapsed());
+
+        let normalized_scores = normalize.map(|max_score| normalize_scores(&top_docs, max_score));
+        let mut hits = Vec::with_capacity(top_docs.len());
+        for (i, (score, doc_address)) in top_docs.into_iter().enumerate() {
+            let hit = SearchHit::fetch(&searcher, score, doc_address)?;
+            let mut hit = json!({
+                "score": hit.score,
+                "doc": hit.fields,
+                "_segment": hit.segment_json(),
+            });
+            if let Some(normalized_scores) = &normalized_scores {
+                hit["normalized_score"] = json!(normalized_scores[i]);
+            }
+            hits.push(hit);
+        }
+        let mut body = json!({ "hits": hits });
+        if let Some(warning) = warning {
+            body["warning"] = json!(warning);
+        }
+        Ok(Response::json(200, body))
+    }
+
+    /// Listens on `addr`, like `127.0.0.1:8080`, and serves requests until
+    /// the process is stopped.
+    pub fn serve(&self, addr: &str) -> tantivy::Result<()> {
+        let server = tiny_http::Server::http(addr).map_err(|err| {
+            TantivyError::InvalidArgument(format!("cannot listen on {addr}: {err}"))
+        })?;
+        info!(addr, "serving");
+        for mut request in server.incoming_requests() {
+            let response = match request.method() {
+                tiny_http::Method::Get => self.handle(request.url()),
+                tiny_http::Method::Post => {
+                    let mut body = String::new();
+                    match request.as_reader().read_to_string(&mut body) {
+                        Ok(_) => self.handle_post(request.url(), &body),
+                        Err(err) => Response::error(400, &format!("unreadable body: {err}")),
+                    }
+                }
+                _ => Response::error(405, "only GET and POST are supported"),
+            };
+            let content_type = tiny_http::Header::from_bytes("Content-Type", response.content_type)
+                .expect("valid header");
+            let http_response = tiny_http::Response::from_string(response.body)
+                .with_status_code(response.status)
+                .with_header(content_type);
+            if let Err(err) = request.respond(http_response) {
+                warn!(%err, "failed to respond");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The response to a search, its parameters and query being the client's
+/// fault if they were invalid.
+fn search_response(result: tantivy::Result<Response>) -> Response {
+    match result {
+        Ok(response) => response,
+        Err(TantivyError::InvalidArgument(message)) => Response::error(400, &message),
+        Err(err) => {
+            warn!(%err, "search failed");
+            Response::error(500, &err.to_string())
+        }
+    }
+}
+
+/// Decodes `a=1&b=x+y` into its parameters, percent-escapes included.
+fn parse_query_string(query_string: &str) -> HashMap<String, String> {
+    query_string
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect()
+}
+
+fn percent_decode(text: &str) -> String {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut input = text.bytes();
+    while let Some(byte) = input.next() {
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex: Vec<u8> = input.clone().take(2).collect();
+                match std::str::from_utf8(&hex)
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                {
+                    Some(decoded) if hex.len() == 2 => {
+                        bytes.push(decoded);
+                        input.nth(1);
+                    }
+                    _ => bytes.push(b'%'),
+                }
+            }
+            _ => bytes.push(byte),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
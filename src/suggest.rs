@@ -0,0 +1,159 @@
+//! "Did you mean" suggestions from the term dictionary.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use tantivy::schema::Field;
+use tantivy::{Index, Searcher};
+
+use crate::query::{tokenize, FuzzyDistance};
+
+/// Returns the term of `field` closest to `word`, if one is at most
+/// `max_distance` edits away.
+///
+/// Among equally close terms, the one in the most documents wins.
+/// This scans the whole term dictionary of the field.
+pub fn suggest(
+    searcher: &Searcher,
+    field: Field,
+    word: &str,
+    max_distance: usize,
+) -> tantivy::Result<Option<String>> {
+    Ok(closest_term(searcher, field, word, max_distance)?.map(|candidate| candidate.term))
+}
+
+/// A dictionary term close to a misspelled word.
+struct Candidate {
+    term: String,
+    distance: usize,
+    doc_freq: u64,
+}
+
+impl Candidate {
+    /// Orders candidates best first: closest, then most frequent.
+    fn rank(&self) -> (usize, Reverse<u64>, &str) {
+        (self.distance, Reverse(self.doc_freq), &self.term)
+    }
+}
+
+fn closest_term(
+    searcher: &Searcher,
+    field: Field,
+    word: &str,
+    max_distance: usize,
+) -> tantivy::Result<Option<Candidate>> {
+    let word: Vec<char> = word.chars().collect();
+    let mut candidates: HashMap<String, Candidate> = HashMap::new();
+    for segment_reader in searcher.segment_readers() {
+        let inverted_index = segment_reader.inverted_index(field)?;
+        let mut stream = inverted_index.terms().stream()?;
+        while stream.advance() {
+            let Ok(term) = std::str::from_utf8(stream.key()) else {
+                continue;
+            };
+            let doc_freq = u64::from(stream.value().doc_freq);
+            if let Some(candidate) = candidates.get_mut(term) {
+                candidate.doc_freq += doc_freq;
+                continue;
+            }
+            let term_chars: Vec<char> = term.chars().collect();
+            if term_chars.len().abs_diff(word.len()) > max_distance {
+                continue;
+            }
+            let distance = edit_distance(&word, &term_chars);
+            if distance <= max_distance {
+                let term = term.to_string();
+                candidates.insert(
+                    term.clone(),
+                    Candidate {
+                        term,
+                        distance,
+                        doc_freq,
+                    },
+                );
+            }
+        }
+    }
+    Ok(candidates
+        .into_values()
+        .min_by(|a, b| a.rank().cmp(&b.rank())))
+}
+
+/// Spell-corrects the plain words of `query_str` that match nothing in `fields`.
+///
+/// Each unknown word is replaced by the closest term of any of the fields,
+/// within the [`FuzzyDistance::Auto`] distance for its length. Words using
+/// query syntax, like `title:sea` or `"old man"`, the `AND`, `OR` and `NOT`
+/// operators, and words with no close term are kept as they are.
+/// Returns `None` when there is nothing to correct.
+pub fn correct_query(
+    index: &Index,
+    searcher: &Searcher,
+    fields: &[Field],
+    query_str: &str,
+) -> tantivy::Result<Option<String>> {
+    let mut words = Vec::new();
+    let mut corrected = false;
+    for word in query_str.split_whitespace() {
+        if matches!(word, "AND" | "OR" | "NOT")
+            || !word.chars().all(char::is_alphanumeric)
+            || is_known(index, searcher, fields, word)?
+        {
+            words.push(word.to_string());
+            continue;
+        }
+        let lowercase = word.to_lowercase();
+        let max_distance = usize::from(FuzzyDistance::Auto.for_term(&lowercase));
+        let mut best: Option<Candidate> = None;
+        for &field in fields {
+            if let Some(candidate) = closest_term(searcher, field, &lowercase, max_distance)? {
+                if best
+                    .as_ref()
+                    .is_none_or(|best| candidate.rank() < best.rank())
+                {
+                    best = Some(candidate);
+                }
+            }
+        }
+        match best {
+            Some(candidate) => {
+                words.push(candidate.term);
+                corrected = true;
+            }
+            None => words.push(word.to_string()),
+        }
+    }
+    Ok(corrected.then(|| words.join(" ")))
+}
+
+/// Whether `word` is in the term dictionary of one of `fields`.
+fn is_known(
+    index: &Index,
+    searcher: &Searcher,
+    fields: &[Field],
+    word: &str,
+) -> tantivy::Result<bool> {
+    for &field in fields {
+        for term in tokenize(index, field, word)? {
+            if searcher.doc_freq(&term)? > 0 {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// The Levenshtein distance between `a` and `b`.
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, a_char) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
@@ -0,0 +1,99 @@
+//! Expanding query terms with their synonyms, for better recall.
+//!
+//! Synonyms are listed one term per line:
+//!
+//! ```text
+//! # comments and blank lines are ignored
+//! sea: ocean, seas
+//! ship: boat
+//! ```
+//!
+//! Expansion happens on the parsed query, so terms are matched in their
+//! indexed form: write the file lowercased for the `default` tokenizer.
+//! Expansion goes one way only, `sea` finds `ocean` but not the other way
+//! around unless `ocean: sea` is listed as well.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use tantivy::query::{BooleanQuery, Occur, Query, TermQuery};
+use tantivy::schema::{IndexRecordOption, Term};
+use tantivy::TantivyError;
+
+/// Synonyms per term.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SynonymMap {
+    synonyms: HashMap<String, Vec<String>>,
+}
+
+impl SynonymMap {
+    /// Parses the `term: syn1, syn2` lines of `content`.
+    pub fn parse(content: &str) -> tantivy::Result<SynonymMap> {
+        let mut synonyms = HashMap::new();
+        for (line_number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((term, list)) = line.split_once(':') else {
+                return Err(TantivyError::InvalidArgument(format!(
+                    "line {}: expected `term: synonym, ...`",
+                    line_number + 1
+                )));
+            };
+            let list = list
+                .split(',')
+                .map(str::trim)
+                .filter(|synonym| !synonym.is_empty())
+                .map(str::to_string);
+            synonyms
+                .entry(term.trim().to_string())
+                .or_insert_with(Vec::new)
+                .extend(list);
+        }
+        Ok(SynonymMap { synonyms })
+    }
+
+    /// Reads and parses the synonym file at `path`.
+    pub fn load(path: &Path) -> tantivy::Result<SynonymMap> {
+        SynonymMap::parse(&fs::read_to_string(path)?)
+    }
+
+    /// The synonyms of `term`, if it has any.
+    pub fn get(&self, term: &str) -> &[String] {
+        self.synonyms.get(term).map_or(&[], Vec::as_slice)
+    }
+
+    /// Rewrites `query` so every term with synonyms becomes
+    /// a `should` group of the term and its synonyms, in the same field.
+    ///
+    /// Boolean queries are rewritten clause by clause.
+    /// Other queries, phrases included, are left as they are.
+    pub fn expand(&self, query: Box<dyn Query>) -> Box<dyn Query> {
+        if let Some(boolean_query) = query.downcast_ref::<BooleanQuery>() {
+            let clauses = boolean_query
+                .clauses()
+                .iter()
+                .map(|(occur, clause)| (*occur, self.expand(clause.box_clone())))
+                .collect();
+            return Box::new(BooleanQuery::new(clauses));
+        }
+        if let Some(term_query) = query.downcast_ref::<TermQuery>() {
+            let term = term_query.term();
+            let synonyms = term.value().as_str().map_or(&[][..], |text| self.get(text));
+            if !synonyms.is_empty() {
+                let mut clauses = vec![(Occur::Should, query.box_clone())];
+                for synonym in synonyms {
+                    let synonym_term = Term::from_field_text(term.field(), synonym);
+                    clauses.push((
+                        Occur::Should,
+                        Box::new(TermQuery::new(synonym_term, IndexRecordOption::WithFreqs)),
+                    ));
+                }
+                return Box::new(BooleanQuery::new(clauses));
+            }
+        }
+        query
+    }
+}
@@ -0,0 +1,90 @@
+//! Human readable output through a small format string.
+//!
+//! A template is text with `{placeholder}`s, e.g. `"{score:.2} — {title} ({year})"`:
+//!
+//! - `{score}` is the score of the hit,
+//! - `{name}` is the stored value of the field `name`, multiple values joined by `, `:
+//!   numbers as is, dates in ISO 8601 like `2023-05-01T12:00:00Z`,
+//! - `:.N` after a placeholder rounds numbers to `N` decimals,
+//! - `{{` and `}}` are literal braces.
+//!
+//! Unknown or missing fields render as an empty string rather than an error,
+//! since not every document has every field.
+
+use tantivy::schema::{Document, Schema, Value};
+use tantivy::time::format_description::well_known::Rfc3339;
+use tantivy::Score;
+
+/// Renders `template` for one search hit.
+pub fn render(template: &str, schema: &Schema, score: Score, doc: &Document) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        out.push_str(&rest[..start]);
+        let tail = &rest[start..];
+        if tail.starts_with("{{") || tail.starts_with("}}") {
+            out.push_str(&tail[..1]);
+            rest = &tail[2..];
+            continue;
+        }
+        match (tail.starts_with('{'), tail.find('}')) {
+            (true, Some(end)) => {
+                out.push_str(&placeholder(&tail[1..end], schema, score, doc));
+                rest = &tail[end + 1..];
+            }
+            // A lone brace is kept as is.
+            _ => {
+                out.push_str(&tail[..1]);
+                rest = &tail[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn placeholder(spec: &str, schema: &Schema, score: Score, doc: &Document) -> String {
+    let (name, precision) = match spec.split_once(':') {
+        Some((name, format)) => (name, format.strip_prefix('.').and_then(|n| n.parse().ok())),
+        None => (spec, None),
+    };
+    if name == "score" {
+        return format_number(score as f64, precision);
+    }
+    let Ok(field) = schema.get_field(name) else {
+        return String::new();
+    };
+    doc.get_all(field)
+        .map(|value| format_value(value, precision))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Formats a stored value for display, rounding floats to `precision` decimals if given.
+pub fn format_value(value: &Value, precision: Option<usize>) -> String {
+    match value {
+        Value::Str(text) => text.clone(),
+        Value::PreTokStr(pre_tokenized) => pre_tokenized.text.clone(),
+        Value::U64(number) => number.to_string(),
+        Value::I64(number) => number.to_string(),
+        Value::F64(number) => format_number(*number, precision),
+        Value::Bool(boolean) => boolean.to_string(),
+        Value::Facet(facet) => facet.to_string(),
+        Value::Date(date) => date
+            .into_utc()
+            .format(&Rfc3339)
+            .unwrap_or_else(|_| date.into_timestamp_secs().to_string()),
+        other => match serde_json::to_value(other) {
+            Ok(serde_json::Value::String(text)) => text,
+            Ok(json) => json.to_string(),
+            Err(_) => String::new(),
+        },
+    }
+}
+
+fn format_number(number: f64, precision: Option<usize>) -> String {
+    match precision {
+        Some(precision) => format!("{number:.precision$}"),
+        None => number.to_string(),
+    }
+}
@@ -0,0 +1,163 @@
+//! What the term dictionary says about a corpus.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use levenshtein_automata::{Distance, LevenshteinAutomatonBuilder, DFA, SINK_STATE};
+use tantivy::postings::Postings;
+use tantivy::schema::{Field, FieldType, IndexRecordOption, Term};
+use tantivy::{DocAddress, DocSet, Searcher, TantivyError};
+use tantivy_fst::Automaton;
+
+/// The largest edit distance of [`similar_terms`], past which the
+/// automaton gets too big to build on the fly.
+const MAX_DISTANCE: u8 = 2;
+
+/// Returns the `n` terms of the text `field` found in the most documents,
+/// with their document frequency, most frequent first.
+///
+/// Every segment's term dictionary is streamed once, so this reads the whole
+/// dictionary of the field; frequencies still count deleted documents until
+/// their segments are merged. Ties are broken alphabetically.
+pub fn top_terms(
+    searcher: &Searcher,
+    field: Field,
+    n: usize,
+) -> tantivy::Result<Vec<(String, u64)>> {
+    check_text_field(searcher, field)?;
+    let mut doc_freqs: HashMap<Vec<u8>, u64> = HashMap::new();
+    for segment_reader in searcher.segment_readers() {
+        let inverted_index = segment_reader.inverted_index(field)?;
+        let mut stream = inverted_index.terms().stream()?;
+        while stream.advance() {
+            *doc_freqs.entry(stream.key().to_vec()).or_default() +=
+                u64::from(stream.value().doc_freq);
+        }
+    }
+
+    Ok(most_frequent(doc_freqs, n))
+}
+
+/// Returns up to `limit` terms of the text `field` at most `distance` edits
+/// away from `term`, with their document frequency, most frequent first.
+///
+/// Unlike [`top_terms`], only the parts of the term dictionaries a
+/// Levenshtein automaton can match are streamed. `term` is taken as it is
+/// stored, so it should already be lowercased like the field's tokens.
+/// `distance` goes up to 2, and `term` itself is part of the result when
+/// it is in the dictionary.
+pub fn similar_terms(
+    searcher: &Searcher,
+    field: Field,
+    term: &str,
+    distance: u8,
+    limit: usize,
+) -> tantivy::Result<Vec<(String, u64)>> {
+    check_text_field(searcher, field)?;
+    if distance > MAX_DISTANCE {
+        return Err(TantivyError::InvalidArgument(format!(
+            "edit distance {distance} is over the maximum of {MAX_DISTANCE}"
+        )));
+    }
+    let automaton_builder = LevenshteinAutomatonBuilder::new(distance, false);
+
+    let mut doc_freqs: HashMap<Vec<u8>, u64> = HashMap::new();
+    for segment_reader in searcher.segment_readers() {
+        let inverted_index = segment_reader.inverted_index(field)?;
+        let automaton = LevenshteinDfa(automaton_builder.build_dfa(term));
+        let mut stream = inverted_index.terms().search(automaton).into_stream()?;
+        while stream.advance() {
+            *doc_freqs.entry(stream.key().to_vec()).or_default() +=
+                u64::from(stream.value().doc_freq);
+        }
+    }
+    Ok(most_frequent(doc_freqs, limit))
+}
+
+/// The positions of `term` in `field` of the document at `doc_address`,
+/// counted in tokens from 0, for showing how close the matches are.
+///
+/// `term` is taken as it is stored, like in [`similar_terms`]. The field
+/// must be indexed with positions; a document without the term has none.
+pub fn term_positions(
+    searcher: &Searcher,
+    field: Field,
+    doc_address: DocAddress,
+    term: &str,
+) -> tantivy::Result<Vec<u32>> {
+    check_text_field(searcher, field)?;
+    let field_entry = searcher.schema().get_field_entry(field);
+    let has_positions = field_entry
+        .field_type()
+        .get_index_record_option()
+        .is_some_and(|option| option.has_positions());
+    if !has_positions {
+        return Err(TantivyError::SchemaError(format!(
+            "field `{}` is not indexed with positions",
+            field_entry.name()
+        )));
+    }
+    let segment_reader = searcher
+        .segment_readers()
+        .get(doc_address.segment_ord as usize)
+        .ok_or_else(|| {
+            TantivyError::InvalidArgument(format!("no segment {}", doc_address.segment_ord))
+        })?;
+    let inverted_index = segment_reader.inverted_index(field)?;
+    let term = Term::from_field_text(field, term);
+    let mut positions = Vec::new();
+    if let Some(mut postings) =
+        inverted_index.read_postings(&term, IndexRecordOption::WithFreqsAndPositions)?
+    {
+        if postings.seek(doc_address.doc_id) == doc_address.doc_id {
+            postings.positions(&mut positions);
+        }
+    }
+    Ok(positions)
+}
+
+/// Fails unless `field` is an indexed text field, with a term dictionary.
+fn check_text_field(searcher: &Searcher, field: Field) -> tantivy::Result<()> {
+    let field_entry = searcher.schema().get_field_entry(field);
+    if !matches!(field_entry.field_type(), FieldType::Str(_)) || !field_entry.is_indexed() {
+        return Err(TantivyError::SchemaError(format!(
+            "field `{}` is not an indexed text field",
+            field_entry.name()
+        )));
+    }
+    Ok(())
+}
+
+/// The `n` most frequent of `doc_freqs`, ties broken alphabetically.
+fn most_frequent(doc_freqs: HashMap<Vec<u8>, u64>, n: usize) -> Vec<(String, u64)> {
+    let mut terms: Vec<(String, u64)> = doc_freqs
+        .into_iter()
+        .map(|(term, doc_freq)| (String::from_utf8_lossy(&term).into_owned(), doc_freq))
+        .collect();
+    terms.sort_by(|(a, a_freq), (b, b_freq)| (Reverse(a_freq), a).cmp(&(Reverse(b_freq), b)));
+    terms.truncate(n);
+    terms
+}
+
+/// A Levenshtein DFA walked by the term dictionary.
+struct LevenshteinDfa(DFA);
+
+impl Automaton for LevenshteinDfa {
+    type State = u32;
+
+    fn start(&self) -> u32 {
+        self.0.initial_state()
+    }
+
+    fn is_match(&self, state: &u32) -> bool {
+        matches!(self.0.distance(*state), Distance::Exact(_))
+    }
+
+    fn can_match(&self, state: &u32) -> bool {
+        *state != SINK_STATE
+    }
+
+    fn accept(&self, state: &u32, byte: u8) -> u32 {
+        self.0.transition(*state, byte)
+    }
+}
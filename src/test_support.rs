@@ -0,0 +1,68 @@
+//! Reproducible documents for tests and benchmarks.
+//!
+//! The corpus comes from a seeded pseudo-random generator of our own, so
+//! a seed gives the same documents on every machine and every version of
+//! the dependencies.
+
+use tantivy::doc;
+use tantivy::schema::Document;
+
+use crate::collector::{mix, SPLITMIX64_GAMMA};
+use crate::schema::sample_schema;
+
+/// The words titles and bodies are made of.
+const WORDS: [&str; 32] = [
+    "old", "man", "sea", "mice", "men", "river", "north", "ice", "fish", "boat", "storm", "island",
+    "king", "war", "peace", "night", "city", "road", "garden", "winter", "letter", "house",
+    "ghost", "forest", "ship", "star", "mountain", "wolf", "mirror", "bridge", "stone", "fire",
+];
+
+/// `n` documents of the [`sample_schema`], each with a `title` of 2 to 6
+/// words, a `body` of 10 to 40 words and a `year` from 1800 to 2023.
+///
+/// The same `seed` always gives the same documents.
+pub fn generate_corpus(n: usize, seed: u64) -> Vec<Document> {
+    let schema = sample_schema();
+    let title = schema
+        .get_field("title")
+        .expect("sample schema has a title");
+    let body = schema.get_field("body").expect("sample schema has a body");
+    let year = schema.get_field("year").expect("sample schema has a year");
+    let mut rng = SplitMix64(seed);
+    (0..n)
+        .map(|_| {
+            let title_words = rng.between(2, 6);
+            let body_words = rng.between(10, 40);
+            doc!(
+                title => rng.words(title_words),
+                body => rng.words(body_words),
+                year => rng.between(1800, 2023),
+            )
+        })
+        .collect()
+}
+
+/// The SplitMix64 generator: tiny, fast, and good enough for test data,
+/// with the finalizer the sampling collectors use.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        let value = mix(self.0);
+        self.0 = self.0.wrapping_add(SPLITMIX64_GAMMA);
+        value
+    }
+
+    /// A number from `low` to `high`, both included.
+    fn between(&mut self, low: u64, high: u64) -> u64 {
+        low + self.next() % (high - low + 1)
+    }
+
+    /// `count` words of [`WORDS`], separated by spaces.
+    fn words(&mut self, count: u64) -> String {
+        (0..count)
+            .map(|_| WORDS[self.next() as usize % WORDS.len()])
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
@@ -0,0 +1,128 @@
+//! Rewriting field values before they are indexed, e.g. to strip markup.
+//!
+//! Transformers are registered per field and run on every value of the
+//! field, in registration order, when documents go through
+//! [`crate::ingest::ingest_transformed`]. What they return is what gets
+//! indexed and, for stored fields, what searches return.
+
+use tantivy::schema::{Document, Field, Value};
+
+/// Rewrites one value of a field.
+pub type Transformer = Box<dyn Fn(Field, Value) -> Value + Send + Sync>;
+
+/// The transformers of each field.
+#[derive(Default)]
+pub struct Transformers {
+    transformers: Vec<(Field, Transformer)>,
+}
+
+impl Transformers {
+    pub fn new() -> Transformers {
+        Transformers::default()
+    }
+
+    /// Runs `transformer` on the values of `field`, after those registered before.
+    pub fn register<F>(&mut self, field: Field, transformer: F) -> &mut Transformers
+    where
+        F: Fn(Field, Value) -> Value + Send + Sync + 'static,
+    {
+        self.transformers.push((field, Box::new(transformer)));
+        self
+    }
+
+    /// `doc` with every value of a field with transformers rewritten.
+    pub fn apply(&self, doc: Document) -> Document {
+        if self.transformers.is_empty() {
+            return doc;
+        }
+        let mut transformed = Document::new();
+        for field_value in doc {
+            let field = field_value.field();
+            let value = self
+                .transformers
+                .iter()
+                .filter(|(transformed_field, _)| *transformed_field == field)
+                .fold(field_value.value, |value, (_, transformer)| {
+                    transformer(field, value)
+                });
+            transformed.add_field_value(field, value);
+        }
+        transformed
+    }
+}
+
+/// Turns an HTML string value into its text: tags are dropped, along with
+/// what `<script>` and `<style>` hold, and the common entities decoded.
+///
+/// Tags become spaces, so `a<br>b` stays two words, and whitespace is
+/// collapsed. Values that are not strings are left alone.
+pub fn strip_html(_field: Field, value: Value) -> Value {
+    let Value::Str(html) = value else {
+        return value;
+    };
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html.as_str();
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start]);
+        text.push(' ');
+        let tag = &rest[start..];
+        let end = tag.find('>').map_or(tag.len(), |end| end + 1);
+        let name = tag[1..end]
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        rest = &tag[end..];
+        if !tag.starts_with("</") && matches!(name.as_str(), "script" | "style") {
+            let closing = format!("</{name}");
+            let skipped = rest
+                .to_ascii_lowercase()
+                .find(&closing)
+                .unwrap_or(rest.len());
+            rest = &rest[skipped..];
+        }
+    }
+    text.push_str(rest);
+    let text = decode_entities(&text);
+    Value::Str(text.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+/// Decodes the named entities HTML text is usually written with, and `&#n;`.
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest.find(';').map(|end| &rest[1..end]);
+        let character = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => {
+                let code = entity.strip_prefix('#')?;
+                let code = match code.strip_prefix(['x', 'X']) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                    None => code.parse().ok()?,
+                };
+                char::from_u32(code)
+            }
+        });
+        match (character, entity) {
+            (Some(character), Some(entity)) => {
+                decoded.push(character);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
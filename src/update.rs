@@ -0,0 +1,67 @@
+//! Changing documents already in the index.
+//!
+//! tantivy never modifies a document in place: updating one means deleting
+//! it and adding its new version, in the same commit so no reader sees the
+//! document missing or twice.
+
+use tantivy::collector::TopDocs;
+use tantivy::query::TermQuery;
+use tantivy::schema::{Document, Field, IndexRecordOption, Value};
+use tantivy::{IndexWriter, Opstamp, Searcher, TantivyError};
+use tracing::warn;
+
+use crate::document::check_document;
+use crate::search::exact_term;
+
+/// Replaces the values of `field` by `new_value` in the document whose
+/// `id_field` is `id`, keeping its other fields as they are.
+///
+/// The document is rebuilt from its stored fields, so `id_field` must be
+/// stored, and the values of fields that are indexed but not stored are
+/// lost. Returns the opstamp of the new version, or `None` when there is
+/// no document with that id. Nothing is committed; until then `searcher`
+/// and other readers still see the old version.
+pub fn update_field(
+    writer: &IndexWriter,
+    searcher: &Searcher,
+    id_field: Field,
+    id: &str,
+    field: Field,
+    new_value: Value,
+) -> tantivy::Result<Option<Opstamp>> {
+    let schema = searcher.schema();
+    let id_entry = schema.get_field_entry(id_field);
+    if !id_entry.is_stored() {
+        return Err(TantivyError::SchemaError(format!(
+            "id field `{}` must be stored to rebuild documents",
+            id_entry.name()
+        )));
+    }
+    let Some(id_term) = exact_term(schema, id_field, id)? else {
+        return Ok(None);
+    };
+    let query = TermQuery::new(id_term.clone(), IndexRecordOption::Basic);
+    let Some(&(_, doc_address)) = searcher.search(&query, &TopDocs::with_limit(1))?.first() else {
+        return Ok(None);
+    };
+
+    let mut updated = Document::new();
+    for field_value in searcher.doc(doc_address)?.field_values() {
+        if field_value.field() != field {
+            updated.add_field_value(field_value.field(), field_value.value().clone());
+        }
+    }
+    updated.add_field_value(field, new_value);
+    check_document(schema, &updated)?;
+
+    let unstored: Vec<&str> = schema
+        .fields()
+        .filter(|(_, entry)| entry.is_indexed() && !entry.is_stored())
+        .map(|(_, entry)| entry.name())
+        .collect();
+    if !unstored.is_empty() {
+        warn!(id, ?unstored, "updated document loses its unstored fields");
+    }
+    writer.delete_term(id_term);
+    writer.add_document(updated).map(Some)
+}
@@ -0,0 +1,70 @@
+//! Running known hot queries ahead of traffic.
+//!
+//! After a restart, the index files are not in the OS page cache yet and the
+//! first searches pay for reading them from disk. Running the most frequent
+//! queries once at startup pays that price before any user does.
+
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use tantivy::collector::TopDocs;
+use tantivy::query::QueryParser;
+use tantivy::Searcher;
+use tracing::{info, warn};
+
+/// What a warm-up did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WarmupReport {
+    pub queries: usize,
+    /// Queries that failed to parse and were skipped.
+    pub skipped: usize,
+    pub elapsed: Duration,
+}
+
+/// Runs every query of the file at `path`, one per line, against `searcher`,
+/// fetching the top `limit` documents of each.
+///
+/// Blank lines and lines starting with `#` are ignored, and queries that
+/// don't parse are skipped with a warning: a stale entry in the list
+/// shouldn't prevent a service from starting.
+pub fn warm_cache(
+    searcher: &Searcher,
+    query_parser: &QueryParser,
+    path: &Path,
+    limit: usize,
+) -> tantivy::Result<WarmupReport> {
+    let started_at = Instant::now();
+    let mut queries = 0;
+    let mut skipped = 0;
+    for line in fs::read_to_string(path)?.lines() {
+        let query_str = line.trim();
+        if query_str.is_empty() || query_str.starts_with('#') {
+            continue;
+        }
+        match query_parser.parse_query(query_str) {
+            Ok(query) => {
+                for (_, doc_address) in searcher.search(&query, &TopDocs::with_limit(limit))? {
+                    searcher.doc(doc_address)?;
+                }
+                queries += 1;
+            }
+            Err(err) => {
+                warn!(query = query_str, %err, "skipping warm-up query");
+                skipped += 1;
+            }
+        }
+    }
+    let report = WarmupReport {
+        queries,
+        skipped,
+        elapsed: started_at.elapsed(),
+    };
+    info!(
+        queries,
+        skipped,
+        elapsed_ms = report.elapsed.as_secs_f64() * 1000.0,
+        "warmed up"
+    );
+    Ok(report)
+}
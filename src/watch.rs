@@ -0,0 +1,147 @@
+//! Keeping an index in sync with an NDJSON file while it is being edited.
+//!
+//! A [`FileWatcher`] loads the file again whenever it changes, replacing
+//! the documents by id, see [`upsert_ndjson`]. Documents taken out of the
+//! file stay in the index: only what the file holds is replaced.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use tantivy::schema::Field;
+use tantivy::TantivyError;
+use tracing::{info, warn};
+
+use crate::concurrent::ConcurrentIndex;
+use crate::ingest::{upsert_ndjson, IngestReport};
+use crate::metrics::Metrics;
+
+/// A thread loading a file into a [`ConcurrentIndex`] when it changes,
+/// until it is dropped.
+///
+/// Saving a file is often several writes, or a write then a rename, so
+/// the file is only loaded once no change came for the debounce delay.
+/// Each load is one commit.
+pub struct FileWatcher {
+    watcher: Option<RecommendedWatcher>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FileWatcher {
+    /// Loads the file at `path` into `index`, then again after every
+    /// change, once `debounce` has passed without another one.
+    /// Documents are replaced by their value of `id_field`.
+    pub fn start(
+        index: &ConcurrentIndex,
+        path: &Path,
+        id_field: Field,
+        debounce: Duration,
+    ) -> tantivy::Result<FileWatcher> {
+        let file_name = path.file_name().map(ToOwned::to_owned).ok_or_else(|| {
+            TantivyError::InvalidArgument(format!("{} is not a file", path.display()))
+        })?;
+        // Editors often replace the file rather than write to it, which a
+        // watch on the file itself would miss: the directory is watched.
+        let directory = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender).map_err(watch_error)?;
+        watcher
+            .watch(&directory, RecursiveMode::NonRecursive)
+            .map_err(watch_error)?;
+
+        let index = index.clone();
+        let path = path.to_path_buf();
+        let thread = thread::spawn(move || {
+            let load = || match load(&index, &path, id_field) {
+                Ok(report) => {
+                    info!(path = %path.display(), indexed = report.indexed, skipped = report.skipped, "reloaded");
+                }
+                Err(err) => warn!(%err, path = %path.display(), "reload failed"),
+            };
+            load();
+            let changed = |event: notify::Result<notify::Event>| match event {
+                Ok(event) => event
+                    .paths
+                    .iter()
+                    .any(|changed| changed.file_name() == Some(file_name.as_os_str())),
+                Err(err) => {
+                    warn!(%err, "watch error");
+                    false
+                }
+            };
+            while let Ok(event) = events.recv() {
+                if !changed(event) {
+                    continue;
+                }
+                if !settle(&events, debounce) {
+                    return;
+                }
+                load();
+            }
+        });
+        Ok(FileWatcher {
+            watcher: Some(watcher),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for FileWatcher {
+    /// Stops watching, waiting for the load in progress, if any.
+    fn drop(&mut self) {
+        // The watcher holds the sender: dropping it ends the thread's loop.
+        drop(self.watcher.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Waits until no event came for `debounce`. Returns `false` if the
+/// watcher was dropped meanwhile.
+fn settle<T>(events: &Receiver<T>, debounce: Duration) -> bool {
+    loop {
+        match events.recv_timeout(debounce) {
+            Ok(_) => continue,
+            Err(RecvTimeoutError::Timeout) => return true,
+            Err(RecvTimeoutError::Disconnected) => return false,
+        }
+    }
+}
+
+/// Upserts the documents of the file at `path` and commits them.
+///
+/// If the file can't be read to the end, the writer is rolled back, so
+/// that the documents read before the error aren't committed with the
+/// next load. Uncommitted changes from elsewhere are dropped with them.
+fn load(index: &ConcurrentIndex, path: &Path, id_field: Field) -> tantivy::Result<IngestReport> {
+    let input = BufReader::new(File::open(path)?);
+    let schema = index.index().schema();
+    let report = {
+        let writer = index.writer();
+        let mut writer = writer.write().map_err(|_| TantivyError::Poisoned)?;
+        match upsert_ndjson(&mut writer, &schema, input, id_field) {
+            Ok(report) => report,
+            Err(err) => {
+                if let Err(rollback_err) = writer.rollback() {
+                    warn!(%rollback_err, "rollback failed");
+                }
+                return Err(err);
+            }
+        }
+    };
+    index.commit()?;
+    Metrics::global().observe_commit(report.indexed);
+    Ok(report)
+}
+
+fn watch_error(err: notify::Error) -> TantivyError {
+    TantivyError::SystemError(format!("cannot watch for changes: {err}"))
+}
@@ -0,0 +1,4032 @@
+//! The full index, commit, search cycle of the basic example.
+
+#[macro_use]
+extern crate tantivy;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, PipeReader, Read, Write};
+use std::ops::Bound;
+use std::path::Path;
+use std::slice;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Serialize;
+use serde_json::json;
+use tantivy::collector::{Count, TopDocs};
+use tantivy::directory::error::{DeleteError, OpenReadError, OpenWriteError};
+use tantivy::directory::{
+    Directory, FileHandle, RamDirectory, WatchCallback, WatchHandle, WritePtr,
+};
+use tantivy::merge_policy::NoMergePolicy;
+use tantivy::query::{AllQuery, BooleanQuery, Occur, Query, QueryParser, RangeQuery, TermQuery};
+use tantivy::schema::{
+    Document, Facet, Field, IndexRecordOption, Schema, Term, Value, FAST, INDEXED, STORED, STRING,
+    TEXT,
+};
+use tantivy::tokenizer::Token;
+use tantivy::{DateTime, DocAddress, Index, IndexSettings, Order, Score, Searcher, TantivyError};
+use tantivy_basics::args::{Args, CliConfig};
+use tantivy_basics::collector::{
+    CappedCountCollector, HitCount, Missing, ReservoirSampleCollector, ResultSet,
+    ScoreHistogramCollector,
+};
+use tantivy_basics::concurrent::{ConcurrentIndex, MergeSchedule, MergeScheduler};
+use tantivy_basics::document::{
+    add_bytes, add_f64, add_pre_tokenized, add_text_values, add_tokens, coerce_value,
+    project_document, truncate_stored, truncate_to_fit, OutputType, ELLIPSIS,
+};
+use tantivy_basics::explain::{render_plan, search_explain_json, term_features};
+use tantivy_basics::export::{diff_indexes, export_ndjson, fetch_doc_range, import_ndjson};
+use tantivy_basics::geo::{haversine_km, search_in_bbox, BoundingBox, GeoDistanceCollector};
+use tantivy_basics::highlight::Highlighter;
+use tantivy_basics::ingest::{
+    add_documents, commit_with_retry, ingest_file, ingest_in_segments, ingest_ndjson,
+    ingest_pipelined, ingest_stamped, ingest_transformed, segmenting_writer, InterruptibleInput,
+    Shutdown,
+};
+use tantivy_basics::maintenance::{
+    clear, finalize, migrate_add_field, open_or_create, optimize, refresh_statistics, snapshot,
+};
+use tantivy_basics::memory::InMemorySearch;
+use tantivy_basics::metrics::Metrics;
+use tantivy_basics::query::{
+    bool_filter, bytes_query, cross_field_query, exists_query, f64_range, fuzzy_phrase_query,
+    fuzzy_query, multi_field_phrase_query, parse_lenient, phrase_prefix_query,
+    phrase_with_term_boosts, remove_stop_words, resolve_field_aliases, with_exact_match_boost,
+    FieldBoosts, FuzzyDistance, QueryLimits, EXACT_MATCH_BOOST,
+};
+use tantivy_basics::rerank::{search_reranked, Reranker, VerbatimBoost};
+use tantivy_basics::schema::{register_sample_tokenizers, sample_schema, SchemaConfig, SchemaSpec};
+use tantivy_basics::scoring::{
+    self, search_by_metric, search_stable, search_with_recency, search_with_time_decay,
+    FastFieldMetric, IdTieBreak, RecencyBoost, Scoring, TimeDecay,
+};
+use tantivy_basics::search::{
+    changes_since, count_by_field, estimate_hits, exact_match_all, exists, facet_counts_at_depth,
+    query_difference, query_intersection, query_union, result_set, search_after, search_grouped,
+    search_scoped, search_sorted, search_stream, search_with_facet, search_with_filter,
+    search_with_fuzzy_fallback, search_within, Filter, SearchHit,
+};
+use tantivy_basics::server::SearchServer;
+use tantivy_basics::suggest::correct_query;
+use tantivy_basics::synonyms::SynonymMap;
+use tantivy_basics::template;
+use tantivy_basics::terms::{similar_terms, term_positions};
+use tantivy_basics::test_support::generate_corpus;
+use tantivy_basics::transform::{strip_html, Transformers};
+use tantivy_basics::update::update_field;
+use tantivy_basics::watch::FileWatcher;
+
+fn sample_index() -> tantivy::Result<Index> {
+    let schema = sample_schema();
+    let title = schema.get_field("title")?;
+    let body = schema.get_field("body")?;
+    let index = Index::create_in_ram(schema);
+    register_sample_tokenizers(&index);
+
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(
+        title => "The Old Man and the Sea",
+        body => "He was an old man who fished alone in a skiff in the Gulf Stream and \
+                 he had gone eighty-four days now without taking a fish.",
+    ))?;
+    index_writer.add_document(doc!(
+        title => "Of Mice and Men",
+        body => "A few miles south of Soledad, the Salinas River drops in close to the \
+                 hillside bank and runs deep and green.",
+    ))?;
+    index_writer.add_document(doc!(
+        title => "Frankenstein",
+        body => "You will rejoice to hear that no disaster has accompanied the \
+                 commencement of an enterprise which you have regarded with such evil \
+                 forebodings. I am already far north of London, and as I walk in the \
+                 streets of Petersburgh, I feel a cold northern breeze.",
+    ))?;
+    index_writer.commit()?;
+    Ok(index)
+}
+
+fn search_titles(index: &Index, searcher: &Searcher, query: &str) -> tantivy::Result<Vec<String>> {
+    let schema = index.schema();
+    let title = schema.get_field("title")?;
+    let body = schema.get_field("body")?;
+    let query = QueryParser::for_index(index, vec![title, body]).parse_query(query)?;
+
+    let mut titles = Vec::new();
+    for (_score, doc_address) in searcher.search(&query, &TopDocs::with_limit(10))? {
+        let doc = searcher.doc(doc_address)?;
+        let value = doc.get_first(title).and_then(|value| value.as_text());
+        titles.push(value.unwrap_or_default().to_string());
+    }
+    Ok(titles)
+}
+
+#[test]
+fn committed_documents_are_searchable() -> tantivy::Result<()> {
+    let index = sample_index()?;
+    let searcher = index.reader()?.searcher();
+    assert_eq!(searcher.num_docs(), 3);
+    assert_eq!(
+        search_titles(&index, &searcher, "sea whale")?,
+        ["The Old Man and the Sea"]
+    );
+    Ok(())
+}
+
+#[test]
+fn results_come_back_in_relevance_order() -> tantivy::Result<()> {
+    let index = sample_index()?;
+    let searcher = index.reader()?.searcher();
+    // "man" only appears in the first book, "north" only in the third,
+    // which has a much longer body: the shorter match wins.
+    assert_eq!(
+        search_titles(&index, &searcher, "man north")?,
+        ["The Old Man and the Sea", "Frankenstein"]
+    );
+    assert_eq!(
+        search_titles(&index, &searcher, "miles")?,
+        ["Of Mice and Men"]
+    );
+    Ok(())
+}
+
+#[test]
+fn quoted_phrases_mix_with_loose_terms() -> tantivy::Result<()> {
+    let index = sample_index()?;
+    let searcher = index.reader()?.searcher();
+    // Quoted words must be adjacent and in order, loose ones are OR-ed.
+    assert!(search_titles(&index, &searcher, "\"old sea\"")?.is_empty());
+    assert!(search_titles(&index, &searcher, "\"man old\"")?.is_empty());
+    assert_eq!(
+        search_titles(&index, &searcher, "old sea")?,
+        ["The Old Man and the Sea"]
+    );
+    let mut titles = search_titles(&index, &searcher, "\"old man\" frankenstein")?;
+    titles.sort();
+    assert_eq!(titles, ["Frankenstein", "The Old Man and the Sea"]);
+    assert_eq!(
+        search_titles(&index, &searcher, "\"old man\" sea")?,
+        ["The Old Man and the Sea"]
+    );
+    Ok(())
+}
+
+#[test]
+fn uncommitted_documents_are_invisible() -> tantivy::Result<()> {
+    let index = sample_index()?;
+    let title = index.schema().get_field("title")?;
+    let reader = index.reader()?;
+
+    let index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(title => "Moby Dick"))?;
+    reader.reload()?;
+    assert!(search_titles(&index, &reader.searcher(), "moby")?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn export_then_import_round_trips() -> tantivy::Result<()> {
+    let index = sample_index()?;
+    let searcher = index.reader()?.searcher();
+    let mut dump = Vec::new();
+    assert_eq!(export_ndjson(&searcher, &mut dump)?, 3);
+
+    let imported = Index::create_in_ram(sample_schema());
+    register_sample_tokenizers(&imported);
+    let metrics = Metrics::global();
+    let (commits, documents) = (metrics.commits.get(), metrics.documents_indexed.get());
+    let report = import_ndjson(&imported, &dump[..])?;
+    assert_eq!((report.indexed, report.skipped), (3, 0));
+    // Other tests may commit meanwhile, so the counters grow at least this much.
+    assert!(metrics.commits.get() > commits);
+    assert!(metrics.documents_indexed.get() >= documents + 3);
+
+    let imported_searcher = imported.reader()?.searcher();
+    assert_eq!(imported_searcher.num_docs(), searcher.num_docs());
+    // The body is not stored, so only title matches survive the round trip.
+    for query in ["sea", "mice men", "frankenstein"] {
+        assert_eq!(
+            search_titles(&imported, &imported_searcher, query)?,
+            search_titles(&index, &searcher, query)?
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn doc_ranges_come_back_in_insertion_order() -> tantivy::Result<()> {
+    let index = sample_index()?;
+    let title = index.schema().get_field("title")?;
+    let searcher = index.reader()?.searcher();
+    let segment_reader = searcher.segment_reader(0);
+    let titles = |start, end| -> tantivy::Result<Vec<String>> {
+        let docs = fetch_doc_range(segment_reader, start, end)?;
+        Ok(docs
+            .iter()
+            .filter_map(|doc| doc.get_first(title)?.as_text().map(str::to_string))
+            .collect())
+    };
+    assert_eq!(titles(1, 3)?, ["Of Mice and Men", "Frankenstein"]);
+    assert_eq!(titles(0, 1)?, ["The Old Man and the Sea"]);
+    assert_eq!(titles(2, 100)?, ["Frankenstein"]);
+    assert!(titles(3, 3)?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn server_is_live_before_it_is_ready() -> tantivy::Result<()> {
+    let index = sample_index()?;
+    let title = index.schema().get_field("title")?;
+    let server = SearchServer::new(index, vec![title])?;
+    assert_eq!(server.handle("/healthz").status, 200);
+    assert_eq!(server.handle("/readyz").status, 503);
+
+    server.warm_up(None)?;
+    assert_eq!(server.handle("/healthz").status, 200);
+    let readiness = server.handle("/readyz");
+    assert_eq!(readiness.status, 200);
+    assert_eq!(readiness.body, r#"{"status":"ready"}"#);
+    Ok(())
+}
+
+#[test]
+fn large_stored_fields_are_truncated_to_the_byte_limit() -> tantivy::Result<()> {
+    let mut schema_builder = Schema::builder();
+    let title = schema_builder.add_text_field("title", TEXT | STORED);
+    let body = schema_builder.add_text_field("body", TEXT | STORED);
+    let schema = schema_builder.build();
+    let index = Index::create_in_ram(schema.clone());
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(
+        title => "Moby-Dick",
+        body => "Call me Ishmael. Naïve whaling lore, ".repeat(30_000),
+    ))?;
+    index_writer.commit()?;
+
+    let searcher = index.reader()?.searcher();
+    let query = QueryParser::for_index(&index, vec![body]).parse_query("ishmael")?;
+    let (_score, doc_address) = searcher.search(&query, &TopDocs::with_limit(1))?[0];
+    let doc = searcher.doc(doc_address)?;
+    assert!(schema.to_json(&doc).len() > 1_000_000);
+
+    let truncated = truncate_stored(&schema, &doc, 1024);
+    let json = schema.to_json(&truncated);
+    assert!(json.len() <= 1024);
+    assert!(
+        json.len() > 1000,
+        "cut much more than needed: {}",
+        json.len()
+    );
+    let body_text = truncated.get_first(body).and_then(|value| value.as_text());
+    assert!(body_text.unwrap_or_default().ends_with(ELLIPSIS));
+    let title_text = truncated.get_first(title).and_then(|value| value.as_text());
+    assert_eq!(title_text, Some("Moby-Dick"));
+
+    // A hit under the limit comes back as is.
+    assert_eq!(
+        schema.to_json(&truncate_stored(&schema, &doc, usize::MAX)),
+        schema.to_json(&doc)
+    );
+    Ok(())
+}
+
+#[test]
+fn similar_terms_are_one_edit_away() -> tantivy::Result<()> {
+    let index = sample_index()?;
+    let title = index.schema().get_field("title")?;
+    let searcher = index.reader()?.searcher();
+    let similar = similar_terms(&searcher, title, "man", 1, 10)?;
+    assert_eq!(similar, [("man".to_string(), 1), ("men".to_string(), 1)]);
+    assert!(similar_terms(&searcher, title, "man", 3, 10).is_err());
+    Ok(())
+}
+
+#[test]
+fn aliased_fields_search_like_their_canonical_name() -> tantivy::Result<()> {
+    let config = SchemaConfig::from_toml(
+        r#"
+        [[fields]]
+        name = "title"
+        type = "text"
+        stored = true
+
+        [[fields]]
+        name = "body"
+        type = "text"
+
+        [aliases]
+        headline = "title"
+        "#,
+    )?;
+    let schema = config.build()?;
+    let title = schema.get_field("title")?;
+    let index = Index::create_in_ram(schema);
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(title => "The Old Man and the Sea"))?;
+    index_writer.add_document(doc!(title => "Of Mice and Men"))?;
+    index_writer.commit()?;
+
+    let searcher = index.reader()?.searcher();
+    let aliased = resolve_field_aliases("headline:sea OR (+headline:mice)", &config.aliases);
+    assert_eq!(aliased, "title:sea OR (+title:mice)");
+    assert_eq!(
+        search_titles(&index, &searcher, &aliased)?,
+        search_titles(&index, &searcher, "title:sea OR (+title:mice)")?
+    );
+    assert_eq!(search_titles(&index, &searcher, &aliased)?.len(), 2);
+    Ok(())
+}
+
+#[test]
+fn streamed_hits_all_arrive() -> tantivy::Result<()> {
+    let index = sample_index()?;
+    let schema = index.schema();
+    let body = schema.get_field("body")?;
+    let searcher = index.reader()?.searcher();
+    let query = QueryParser::for_index(&index, vec![body]).parse_query("the")?;
+
+    let (tx, rx) = mpsc::channel();
+    let sent = thread::scope(|scope| {
+        let sender = scope.spawn(|| search_stream(&searcher, query.as_ref(), tx));
+        let mut titles: Vec<String> = rx
+            .iter()
+            .map(|hit| {
+                hit.fields["title"][0]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string()
+            })
+            .collect();
+        titles.sort();
+        assert_eq!(
+            titles,
+            ["Frankenstein", "Of Mice and Men", "The Old Man and the Sea"]
+        );
+        sender.join().unwrap()
+    })?;
+    assert_eq!(sent, 3);
+    Ok(())
+}
+
+#[test]
+fn configured_boosts_rank_title_matches_higher() -> tantivy::Result<()> {
+    let config = |boost: &str| {
+        SchemaConfig::from_toml(&format!(
+            r#"
+            [[fields]]
+            name = "title"
+            type = "text"
+            stored = true
+            {boost}
+
+            [[fields]]
+            name = "body"
+            type = "text"
+            "#
+        ))
+    };
+    let boosted = config("boost = 5.0")?;
+    let schema = boosted.build()?;
+    let title = schema.get_field("title")?;
+    let body = schema.get_field("body")?;
+    let index = Index::create_in_ram(schema);
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(
+        title => "Moby-Dick",
+        body => "The whale, the whale, the white whale.",
+    ))?;
+    index_writer.add_document(doc!(
+        title => "Whale Rider",
+        body => "A girl from a village of the east coast wants to become its chief.",
+    ))?;
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+
+    let best_title = |query_parser: QueryParser| -> tantivy::Result<String> {
+        let query = query_parser.parse_query("whale")?;
+        let (_score, doc_address) = searcher.search(&query, &TopDocs::with_limit(1))?[0];
+        let doc = searcher.doc(doc_address)?;
+        Ok(doc
+            .get_first(title)
+            .and_then(|value| value.as_text())
+            .unwrap_or_default()
+            .to_string())
+    };
+    assert_eq!(
+        best_title(config("")?.make_query_parser(&index)?)?,
+        "Moby-Dick"
+    );
+    assert_eq!(
+        best_title(boosted.make_query_parser(&index)?)?,
+        "Whale Rider"
+    );
+    Ok(())
+}
+
+/// Documents, then a shutdown while waiting on a pipe for more.
+struct InterruptedInput {
+    docs: Cursor<&'static str>,
+    shutdown: Shutdown,
+    waiting: PipeReader,
+}
+
+impl Read for InterruptedInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.docs.read(buf)? {
+            0 => {
+                self.shutdown.request();
+                self.waiting.read(buf)
+            }
+            read => Ok(read),
+        }
+    }
+}
+
+#[test]
+fn documents_read_before_a_shutdown_are_committed() -> tantivy::Result<()> {
+    let dir = tempfile::TempDir::new()?;
+    let index = Index::create_in_dir(dir.path(), sample_schema())?;
+    register_sample_tokenizers(&index);
+    let (waiting, _never_written) = io::pipe()?;
+    let shutdown = Shutdown::new();
+    let input = InterruptedInput {
+        docs: Cursor::new(
+            "{\"title\": \"The Old Man and the Sea\"}\n{\"title\": \"Of Mice and Men\"}\n",
+        ),
+        shutdown: shutdown.clone(),
+        waiting,
+    };
+
+    let mut index_writer = index.writer(15_000_000)?;
+    let input = InterruptibleInput::new(BufReader::new(input), shutdown.clone());
+    let report = ingest_ndjson(&mut index_writer, &index.schema(), input)?;
+    assert!(shutdown.is_requested());
+    assert_eq!(report.indexed, 2);
+    index_writer.commit()?;
+    drop(index_writer);
+
+    let reopened = Index::open_in_dir(dir.path())?;
+    register_sample_tokenizers(&reopened);
+    let searcher = reopened.reader()?.searcher();
+    assert_eq!(search_titles(&reopened, &searcher, "sea mice")?.len(), 2);
+    Ok(())
+}
+
+#[test]
+fn term_positions_match_the_indexed_text() -> tantivy::Result<()> {
+    let index = sample_index()?;
+    let title = index.schema().get_field("title")?;
+    let searcher = index.reader()?.searcher();
+    let query = QueryParser::for_index(&index, vec![title]).parse_query("sea")?;
+    let (_score, doc_address) = searcher.search(&query, &TopDocs::with_limit(1))?[0];
+
+    let text = "The Old Man and the Sea";
+    for term in ["the", "man", "sea", "moby"] {
+        let expected: Vec<u32> = (0..)
+            .zip(text.split(' '))
+            .filter(|(_, word)| word.eq_ignore_ascii_case(term))
+            .map(|(position, _)| position)
+            .collect();
+        assert_eq!(
+            term_positions(&searcher, title, doc_address, term)?,
+            expected
+        );
+    }
+    assert_eq!(
+        term_positions(&searcher, title, doc_address, "the")?,
+        [0, 4]
+    );
+    Ok(())
+}
+
+#[test]
+fn html_is_stripped_before_indexing() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let body = schema.get_field("body")?;
+    let index = Index::create_in_ram(schema.clone());
+    register_sample_tokenizers(&index);
+    let mut transformers = Transformers::new();
+    transformers.register(body, strip_html);
+
+    let mut index_writer = index.writer(15_000_000)?;
+    let input = r#"{"title": "Greeting", "body": "<p class=\"intro\">hello</p>"}"#;
+    let report = ingest_transformed(&mut index_writer, &schema, input.as_bytes(), &transformers)?;
+    assert_eq!(report.indexed, 1);
+    index_writer.commit()?;
+
+    let searcher = index.reader()?.searcher();
+    assert_eq!(
+        search_titles(&index, &searcher, "body:hello")?,
+        ["Greeting"]
+    );
+    assert!(search_titles(&index, &searcher, "body:p body:intro")?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn scheduled_merges_bound_the_segment_count() -> tantivy::Result<()> {
+    let index = Index::create_in_ram(sample_schema());
+    register_sample_tokenizers(&index);
+    let index = ConcurrentIndex::new(index)?;
+    let schedule: MergeSchedule = toml::from_str("interval_ms = 20\nmax_segments = 3")
+        .map_err(|err| tantivy::TantivyError::InvalidArgument(err.to_string()))?;
+    let scheduler = MergeScheduler::start(&index, schedule)?;
+
+    let mut most_segments = 0;
+    for doc in generate_corpus(40, 7) {
+        index.writer().read().unwrap().add_document(doc)?;
+        index.commit()?;
+        most_segments = most_segments.max(index.index().searchable_segment_ids()?.len());
+        thread::sleep(std::time::Duration::from_millis(5));
+    }
+    thread::sleep(std::time::Duration::from_millis(200));
+    drop(scheduler);
+
+    assert!(most_segments <= 10, "up to {most_segments} segments");
+    assert!(index.index().searchable_segment_ids()?.len() <= 3);
+    index.reader().reload()?;
+    assert_eq!(index.searcher().num_docs(), 40);
+    Ok(())
+}
+
+/// The titles of the hits of the structured query `body`, posted to `server`.
+fn posted_titles(server: &SearchServer, body: &str) -> Vec<String> {
+    let response = server.handle_post("/search", body);
+    assert_eq!(response.status, 200, "{}", response.body);
+    let response: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    let mut titles: Vec<String> = response["hits"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|hit| hit["doc"]["title"][0].as_str().unwrap().to_string())
+        .collect();
+    titles.sort();
+    titles
+}
+
+fn catalog_server() -> tantivy::Result<SearchServer> {
+    let schema = sample_schema();
+    let title = schema.get_field("title")?;
+    let body = schema.get_field("body")?;
+    let year = schema.get_field("year")?;
+    let tenant = schema.get_field("tenant")?;
+    let index = Index::create_in_ram(schema);
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(
+        title => "The Old Man and the Sea",
+        body => "An old man fishes alone in the Gulf Stream.",
+        year => 1952u64,
+        tenant => "scribner",
+    ))?;
+    index_writer.add_document(doc!(
+        title => "Of Mice and Men",
+        body => "Two men travel to find work on a ranch.",
+        year => 1937u64,
+        tenant => "covici",
+    ))?;
+    index_writer.add_document(doc!(
+        title => "Frankenstein",
+        body => "A man builds a creature at sea and in the north.",
+        year => 1818u64,
+        tenant => "lackington",
+    ))?;
+    index_writer.commit()?;
+    SearchServer::new(index, vec![title, body])
+}
+
+#[test]
+fn structured_queries_compile_each_clause() -> tantivy::Result<()> {
+    let server = catalog_server()?;
+    let titles = |body: &str| posted_titles(&server, body);
+    assert_eq!(
+        titles(r#"{"must": [{"term": {"field": "tenant", "value": "covici"}}]}"#),
+        ["Of Mice and Men"]
+    );
+    assert_eq!(
+        titles(r#"{"must": [{"term": {"field": "year", "value": 1818}}]}"#),
+        ["Frankenstein"]
+    );
+    assert_eq!(
+        titles(r#"{"should": [{"match": {"field": "body", "text": "Ranch creature"}}]}"#),
+        ["Frankenstein", "Of Mice and Men"]
+    );
+    assert_eq!(
+        titles(r#"{"must": [{"phrase": {"field": "body", "text": "old man"}}]}"#),
+        ["The Old Man and the Sea"]
+    );
+    assert_eq!(
+        titles(r#"{"filters": [{"range": {"field": "year", "gte": 1900, "lt": 1952}}]}"#),
+        ["Of Mice and Men"]
+    );
+    assert_eq!(
+        titles(r#"{"must_not": [{"query": "title:sea OR title:mice"}]}"#),
+        ["Frankenstein"]
+    );
+
+    // Clauses the fields can't answer are the client's mistake.
+    for invalid in [
+        r#"{"must": [{"term": {"field": "price", "value": 1.5}}]}"#,
+        r#"{"must": [{"term": {"field": "year", "value": "abc"}}]}"#,
+        r#"{"must": [{"term": {"field": "year", "value": 1.5}}]}"#,
+        r#"{"must": [{"match": {"field": "price", "text": "cheap"}}]}"#,
+        r#"{"must": [{"phrase": {"field": "year", "text": "nineteen hundred"}}]}"#,
+        r#"{"must": [{"range": {"field": "title", "gte": 1}}]}"#,
+    ] {
+        let response = server.handle_post("/search", invalid);
+        assert_eq!(response.status, 400, "{invalid}: {}", response.body);
+    }
+    Ok(())
+}
+
+#[test]
+fn structured_queries_combine_clauses() -> tantivy::Result<()> {
+    let server = catalog_server()?;
+    let body = r#"{
+        "must": [{"match": {"field": "body", "text": "man men"}}],
+        "should": [{"phrase": {"field": "title", "text": "old man"}}],
+        "must_not": [{"term": {"field": "tenant", "value": "lackington"}}],
+        "filters": [{"bool": {"should": [
+            {"range": {"field": "year", "gt": 1950}},
+            {"range": {"field": "year", "lte": 1937}}
+        ]}}]
+    }"#;
+    assert_eq!(
+        posted_titles(&server, body),
+        ["Of Mice and Men", "The Old Man and the Sea"]
+    );
+
+    // The `should` phrase decides the order, the filter adds nothing to the score.
+    let response: serde_json::Value =
+        serde_json::from_str(&server.handle_post("/search?limit=1", body).body).unwrap();
+    assert_eq!(
+        response["hits"][0]["doc"]["title"][0],
+        "The Old Man and the Sea"
+    );
+
+    let invalid = server.handle_post("/search", r#"{"must": [{"fuzzy": {}}]}"#);
+    assert_eq!(invalid.status, 400);
+    let unknown_field = server.handle_post(
+        "/search",
+        r#"{"must": [{"match": {"field": "isbn", "text": "1"}}]}"#,
+    );
+    assert_eq!(unknown_field.status, 400);
+
+    let the_sea = r#"{"must": [{"match": {"field": "body", "text": "the sea"}}]}"#;
+    assert_eq!(
+        posted_titles(&server, the_sea),
+        ["Frankenstein", "The Old Man and the Sea"]
+    );
+    let response: serde_json::Value =
+        serde_json::from_str(&server.handle_post("/search?stop=the", the_sea).body).unwrap();
+    assert_eq!(response["hits"].as_array().unwrap().len(), 1);
+    assert_eq!(response["hits"][0]["doc"]["title"][0], "Frankenstein");
+    Ok(())
+}
+
+#[test]
+fn cross_field_queries_match_words_split_across_fields() -> tantivy::Result<()> {
+    let index = sample_index()?;
+    let schema = index.schema();
+    let title = schema.get_field("title")?;
+    let body = schema.get_field("body")?;
+    let searcher = index.reader()?.searcher();
+    let titles = |query: &dyn Query| -> tantivy::Result<Vec<String>> {
+        let mut titles = Vec::new();
+        for (_score, doc_address) in searcher.search(query, &TopDocs::with_limit(10))? {
+            let doc = searcher.doc(doc_address)?;
+            let value = doc.get_first(title).and_then(|value| value.as_text());
+            titles.push(value.unwrap_or_default().to_string());
+        }
+        Ok(titles)
+    };
+
+    // `mice` is only in the title, `soledad` only in the body.
+    for field in [title, body] {
+        let mut query_parser = QueryParser::for_index(&index, vec![field]);
+        query_parser.set_conjunction_by_default();
+        assert!(titles(query_parser.parse_query("mice soledad")?.as_ref())?.is_empty());
+    }
+    let fields = [(title, 2.0), (body, 1.0)];
+    let query = cross_field_query(&index, &fields, "mice soledad")?.unwrap();
+    assert_eq!(titles(query.as_ref())?, ["Of Mice and Men"]);
+    let query = cross_field_query(&index, &fields, "mice frankenstein")?.unwrap();
+    assert!(titles(query.as_ref())?.is_empty());
+    assert!(cross_field_query(&index, &fields, "  ")?.is_none());
+    Ok(())
+}
+
+#[test]
+fn the_same_seed_generates_the_same_corpus() {
+    let schema = sample_schema();
+    let to_json = |docs: Vec<tantivy::schema::Document>| -> Vec<String> {
+        docs.iter().map(|doc| schema.to_json(doc)).collect()
+    };
+    let corpus = to_json(generate_corpus(50, 42));
+    assert_eq!(corpus.len(), 50);
+    assert_eq!(corpus, to_json(generate_corpus(50, 42)));
+    assert_ne!(corpus, to_json(generate_corpus(50, 43)));
+}
+
+#[test]
+fn limits_over_the_cap_are_clamped_with_a_warning() -> tantivy::Result<()> {
+    let server = catalog_server()?.with_max_limit(2);
+    let search = |limit: usize| -> serde_json::Value {
+        let response = server.handle(&format!("/search?q=man+men+sea&limit={limit}"));
+        assert_eq!(response.status, 200);
+        serde_json::from_str(&response.body).unwrap()
+    };
+    let at_cap = search(2);
+    assert_eq!(at_cap["hits"].as_array().unwrap().len(), 2);
+    assert!(at_cap.get("warning").is_none());
+    let over_cap = search(3);
+    assert_eq!(over_cap["hits"].as_array().unwrap().len(), 2);
+    assert_eq!(
+        over_cap["warning"],
+        "limit 3 was clamped to the maximum of 2"
+    );
+    Ok(())
+}
+
+#[test]
+fn strict_limits_over_the_cap_are_rejected() -> tantivy::Result<()> {
+    let server = catalog_server()?.with_max_limit(2).with_strict_limit();
+    assert_eq!(server.handle("/search?q=man&limit=2").status, 200);
+    let over_cap = server.handle("/search?q=man&limit=3");
+    assert_eq!(over_cap.status, 400);
+    assert_eq!(
+        over_cap.body,
+        r#"{"error":"limit 3 is over the maximum of 2"}"#
+    );
+    let posted = server.handle_post("/search?limit=3", "{}");
+    assert_eq!(posted.status, 400);
+    Ok(())
+}
+
+#[test]
+fn index_diffs_classify_documents_by_id() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let id = schema.get_field("id")?;
+    let title = schema.get_field("title")?;
+    let index_with = |books: &[(u64, &str)]| -> tantivy::Result<Index> {
+        let index = Index::create_in_ram(schema.clone());
+        register_sample_tokenizers(&index);
+        let mut index_writer = index.writer(15_000_000)?;
+        for &(book_id, book_title) in books {
+            index_writer.add_document(doc!(id => book_id, title => book_title))?;
+        }
+        index_writer.add_document(doc!(title => "Anonymous"))?;
+        index_writer.commit()?;
+        Ok(index)
+    };
+    let a = index_with(&[
+        (1, "The Old Man and the Sea"),
+        (2, "Of Mice and Men"),
+        (3, "Frankenstein"),
+        (4, "East of Eden"),
+    ])?;
+    let b = index_with(&[
+        (2, "Of Mice and Men"),
+        (3, "Frankenstein; or, The Modern Prometheus"),
+        (5, "Cannery Row"),
+        (6, "Tortilla Flat"),
+    ])?;
+
+    let diff = diff_indexes(&a.reader()?.searcher(), &b.reader()?.searcher(), "id", 1)?;
+    assert_eq!(diff.only_in_a, 2);
+    assert_eq!(diff.sample_only_in_a, ["1"]);
+    assert_eq!(diff.only_in_b, 2);
+    assert_eq!(diff.sample_only_in_b, ["5"]);
+    assert_eq!(diff.differing, 1);
+    assert_eq!(diff.sample_differing, ["3"]);
+    assert_eq!(diff.identical, 1);
+    assert_eq!(diff.missing_id, 2);
+    Ok(())
+}
+
+#[test]
+fn hyphenated_words_stay_whole_and_optionally_split() -> tantivy::Result<()> {
+    let titles_matching = |hyphens: &str, query: &str| -> tantivy::Result<Vec<String>> {
+        let config = SchemaConfig::from_toml(&format!(
+            r#"
+            [[fields]]
+            name = "title"
+            type = "text"
+            stored = true
+            tokenizer = "compounds"
+
+            [[fields]]
+            name = "body"
+            type = "text"
+
+            [tokenizers.compounds]
+            filters = ["lowercase"]
+            hyphens = "{hyphens}"
+            "#
+        ))?;
+        let schema = config.build()?;
+        let title = schema.get_field("title")?;
+        let index = Index::create_in_ram(schema);
+        config.register_tokenizers(&index)?;
+        let mut index_writer = index.writer(15_000_000)?;
+        index_writer.add_document(doc!(title => "A State-of-the-Art Lighthouse"))?;
+        index_writer.add_document(doc!(title => "The Art of the State"))?;
+        index_writer.commit()?;
+        let searcher = index.reader()?.searcher();
+        search_titles(&index, &searcher, query)
+    };
+
+    for hyphens in ["keep", "both"] {
+        assert_eq!(
+            titles_matching(hyphens, "title:state-of-the-art")?,
+            ["A State-of-the-Art Lighthouse"]
+        );
+    }
+    assert_eq!(
+        titles_matching("keep", "title:art")?,
+        ["The Art of the State"]
+    );
+    assert_eq!(titles_matching("both", "title:art")?.len(), 2);
+    assert_eq!(titles_matching("both", "title:lighthouse")?.len(), 1);
+    assert_eq!(titles_matching("split", "title:state-of-the-art")?.len(), 1);
+    Ok(())
+}
+
+#[test]
+fn normalized_scores_are_relative_to_the_top_hit() -> tantivy::Result<()> {
+    let server = catalog_server()?;
+    let hits = |url: &str| -> Vec<serde_json::Value> {
+        let response = server.handle(url);
+        assert_eq!(response.status, 200, "{}", response.body);
+        let response: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+        response["hits"].as_array().unwrap().clone()
+    };
+    let normalized = hits("/search?q=old+man+sea&normalize=top");
+    assert!(normalized.len() > 1);
+    let top_score = normalized[0]["score"].as_f64().unwrap();
+    assert_eq!(normalized[0]["normalized_score"], 1.0);
+    for hit in &normalized[1..] {
+        let normalized_score = hit["normalized_score"].as_f64().unwrap();
+        let expected = hit["score"].as_f64().unwrap() / top_score;
+        assert!((normalized_score - expected).abs() < 1e-6);
+        assert!(normalized_score < 1.0);
+    }
+
+    let halved = hits(&format!(
+        "/search?q=old+man+sea&normalize={}",
+        top_score * 2.0
+    ));
+    let normalized_score = halved[0]["normalized_score"].as_f64().unwrap();
+    assert!((normalized_score - 0.5).abs() < 1e-6);
+    assert!(hits("/search?q=old+man+sea")[0]
+        .get("normalized_score")
+        .is_none());
+    assert_eq!(server.handle("/search?q=sea&normalize=0").status, 400);
+    Ok(())
+}
+
+#[test]
+fn phonetic_fields_match_names_that_sound_alike() -> tantivy::Result<()> {
+    let config = SchemaConfig::from_toml(
+        r#"
+        [[fields]]
+        name = "title"
+        type = "text"
+        stored = true
+        tokenizer = "phonetic"
+
+        [[fields]]
+        name = "body"
+        type = "text"
+
+        [tokenizers.phonetic]
+        filters = ["lowercase", "soundex"]
+        "#,
+    )?;
+    let schema = config.build()?;
+    let title = schema.get_field("title")?;
+    let body = schema.get_field("body")?;
+    let index = Index::create_in_ram(schema);
+    config.register_tokenizers(&index)?;
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(title => "John Smith", body => "Letters of John Smith"))?;
+    index_writer.add_document(doc!(title => "Mary Jones", body => "Letters of Mary Jones"))?;
+    index_writer.commit()?;
+
+    let searcher = index.reader()?.searcher();
+    for name in ["smith", "Smyth", "SMITHE"] {
+        assert_eq!(
+            search_titles(&index, &searcher, &format!("title:{name}"))?,
+            ["John Smith"]
+        );
+    }
+    assert_eq!(
+        search_titles(&index, &searcher, "title:jonez")?,
+        ["Mary Jones"]
+    );
+    assert!(search_titles(&index, &searcher, "title:brown")?.is_empty());
+    assert!(search_titles(&index, &searcher, "body:smyth")?.is_empty());
+    assert_eq!(search_titles(&index, &searcher, "body:smith")?.len(), 1);
+    Ok(())
+}
+
+#[test]
+fn facet_counts_roll_up_at_every_depth() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let title = schema.get_field("title")?;
+    let category = schema.get_field("category")?;
+    let index = Index::create_in_ram(schema);
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    for (book_title, path) in [
+        ("Of Mice and Men", "/fiction/classics/american"),
+        ("The Old Man and the Sea", "/fiction/classics/american"),
+        ("Frankenstein", "/fiction/classics/english"),
+        ("Dune", "/fiction/modern/scifi"),
+        ("The Guns of August", "/nonfiction/history/europe"),
+    ] {
+        index_writer.add_document(doc!(
+            title => book_title,
+            category => Facet::from(path),
+        ))?;
+    }
+    index_writer.commit()?;
+
+    let searcher = index.reader()?.searcher();
+    let counts = |prefix: &str, depth: usize| {
+        facet_counts_at_depth(&searcher, &AllQuery, category, prefix, depth)
+    };
+    let owned = |counts: &[(&str, u64)]| -> Vec<(String, u64)> {
+        counts
+            .iter()
+            .map(|&(facet, count)| (facet.to_string(), count))
+            .collect()
+    };
+    assert_eq!(
+        counts("/", 1)?,
+        owned(&[("/fiction", 4), ("/nonfiction", 1)])
+    );
+    assert_eq!(
+        counts("/fiction", 1)?,
+        owned(&[("/fiction/classics", 3), ("/fiction/modern", 1)])
+    );
+    assert_eq!(
+        counts("/fiction", 2)?,
+        owned(&[
+            ("/fiction/classics/american", 2),
+            ("/fiction/classics/english", 1),
+            ("/fiction/modern/scifi", 1),
+        ])
+    );
+    let leaves = counts("/", 3)?;
+    assert_eq!(leaves.len(), 4);
+    assert_eq!(leaves.iter().map(|(_, count)| count).sum::<u64>(), 5);
+    assert!(counts("/fiction/modern/scifi", 1)?.is_empty());
+    assert!(counts("/fiction", 0).is_err());
+    Ok(())
+}
+
+#[test]
+fn strict_ingestion_fails_on_a_malformed_line() -> tantivy::Result<()> {
+    let input = concat!(
+        r#"{"title": "The Old Man and the Sea"}"#,
+        "\n",
+        r#"{"title": "Of Mice and Men""#,
+        "\n",
+        r#"{"title": "Frankenstein"}"#,
+        "\n",
+    );
+    let index = Index::create_in_ram(sample_schema());
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    let report = ingest_ndjson(&mut index_writer, &index.schema(), input.as_bytes())?;
+    assert_eq!((report.indexed, report.skipped), (2, 1));
+    assert_eq!(report.skipped_lines, [2]);
+
+    let err = report.fail_on_skipped().unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("1 malformed lines were skipped: 2"));
+    let clean = ingest_ndjson(
+        &mut index_writer,
+        &index.schema(),
+        r#"{"title": "Moby Dick"}"#.as_bytes(),
+    )?;
+    assert!(clean.fail_on_skipped().is_ok());
+    Ok(())
+}
+
+#[test]
+fn exact_title_matches_outrank_higher_term_scores() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let title = schema.get_field("title")?;
+    let body = schema.get_field("body")?;
+    let index = Index::create_in_ram(schema);
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(title => "The Sea", body => "A short novel."))?;
+    index_writer.add_document(doc!(
+        title => "Sea, Sea, Sea",
+        body => "The sea, the sea: the sea was all the sea ever was.",
+    ))?;
+    index_writer.commit()?;
+
+    let searcher = index.reader()?.searcher();
+    let query_parser = QueryParser::for_index(&index, vec![title, body]);
+    let top_title = |query: &dyn Query| -> tantivy::Result<String> {
+        let (_, doc_address) = searcher.search(query, &TopDocs::with_limit(1))?[0];
+        let doc = searcher.doc(doc_address)?;
+        Ok(doc.get_first(title).unwrap().as_text().unwrap().to_string())
+    };
+    let query = query_parser.parse_query("the sea")?;
+    assert_eq!(top_title(query.as_ref())?, "Sea, Sea, Sea");
+    let boosted = with_exact_match_boost(&index, query, title, "the sea", EXACT_MATCH_BOOST)?;
+    assert_eq!(top_title(boosted.as_ref())?, "The Sea");
+    assert_eq!(searcher.search(boosted.as_ref(), &Count)?, 2);
+    Ok(())
+}
+
+#[test]
+fn hit_estimates_bound_the_actual_count() -> tantivy::Result<()> {
+    let index = sample_index()?;
+    let searcher = index.reader()?.searcher();
+    let schema = index.schema();
+    let query_parser = QueryParser::for_index(
+        &index,
+        vec![schema.get_field("title")?, schema.get_field("body")?],
+    );
+    for (query_str, expected_estimate) in [
+        ("frankenstein", 1),
+        ("title:sea title:mice", 2),
+        ("+title:old +title:man", 2),
+        ("the", 3),
+        ("zeppelin", 0),
+    ] {
+        let query = query_parser.parse_query(query_str)?;
+        let estimate = estimate_hits(&searcher, query.as_ref())?;
+        let count = searcher.search(query.as_ref(), &Count)? as u64;
+        assert_eq!(estimate, Some(expected_estimate), "{query_str}");
+        assert!(
+            count <= expected_estimate,
+            "{query_str}: {count} > {expected_estimate}"
+        );
+    }
+    // Without terms to count, there is no estimate.
+    for query_str in ["*", "year:[1900 TO 2000]"] {
+        let query = query_parser.parse_query(query_str)?;
+        assert_eq!(
+            estimate_hits(&searcher, query.as_ref())?,
+            None,
+            "{query_str}"
+        );
+    }
+    let title = schema.get_field("title")?;
+    let fuzzy = fuzzy_query(&index, &[title], "frankenstien", FuzzyDistance::Auto)?;
+    assert_eq!(estimate_hits(&searcher, &fuzzy)?, None);
+    Ok(())
+}
+
+#[test]
+fn projected_fields_coerce_between_numbers_and_strings() -> tantivy::Result<()> {
+    let mut schema_builder = Schema::builder();
+    let year = schema_builder.add_u64_field("year", STORED);
+    let pages = schema_builder.add_text_field("pages", STORED);
+    let title = schema_builder.add_text_field("title", TEXT | STORED);
+    let schema = schema_builder.build();
+    let doc = doc!(year => 1952u64, pages => " 127 ", title => "The Old Man and the Sea");
+
+    let output = [
+        ("year".to_string(), OutputType::String),
+        ("pages".to_string(), OutputType::U64),
+        ("title".to_string(), OutputType::U64),
+    ]
+    .into_iter()
+    .collect();
+    let projected = project_document(&schema, &doc, &output);
+    assert_eq!(projected["year"], [serde_json::json!("1952")]);
+    assert_eq!(projected["pages"], [serde_json::json!(127)]);
+    // A title is no number: it is left out rather than failing the document.
+    assert!(!projected.contains_key("title"));
+
+    assert_eq!(
+        coerce_value(&Value::Str("1952".to_string()), OutputType::U64),
+        Some(Value::U64(1952))
+    );
+    assert_eq!(
+        coerce_value(&Value::U64(1952), OutputType::String),
+        Some(Value::Str("1952".to_string()))
+    );
+    assert_eq!(
+        coerce_value(&Value::Str("-3".to_string()), OutputType::U64),
+        None
+    );
+    assert_eq!(
+        coerce_value(&Value::F64(3.0), OutputType::U64),
+        Some(Value::U64(3))
+    );
+    assert_eq!(coerce_value(&Value::F64(3.5), OutputType::U64), None);
+    Ok(())
+}
+
+#[test]
+fn watched_files_are_reindexed_when_they_change() -> tantivy::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("books.ndjson");
+    std::fs::write(&path, r#"{"id": 1, "title": "The Old Man and the Sea"}"#)?;
+    let index = Index::create_in_ram(sample_schema());
+    register_sample_tokenizers(&index);
+    let id = index.schema().get_field("id")?;
+    let index = ConcurrentIndex::new(index)?;
+    let _watcher = FileWatcher::start(&index, &path, id, Duration::from_millis(50))?;
+
+    // Waits for the watcher to commit what `check` expects.
+    let eventually = |check: &dyn Fn(&[String]) -> bool| -> tantivy::Result<bool> {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while Instant::now() < deadline {
+            index.reader().reload()?;
+            let searcher = index.searcher();
+            if check(&search_titles(index.index(), &searcher, "*")?) {
+                return Ok(true);
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        Ok(false)
+    };
+    assert!(eventually(&|titles| titles == ["The Old Man and the Sea"])?);
+
+    std::fs::write(
+        &path,
+        concat!(
+            r#"{"id": 1, "title": "Moby Dick"}"#,
+            "\n",
+            r#"{"id": 2, "title": "Of Mice and Men"}"#,
+        ),
+    )?;
+    assert!(eventually(&|titles| {
+        let mut titles = titles.to_vec();
+        titles.sort();
+        titles == ["Moby Dick", "Of Mice and Men"]
+    })?);
+    assert!(search_titles(index.index(), &index.searcher(), "sea")?.is_empty());
+
+    // A file that can't be read to the end changes nothing, not even with
+    // the next load.
+    let mut broken = br#"{"id": 1, "title": "Dracula"}"#.to_vec();
+    broken.extend_from_slice(b"\n\xff\xfe\n");
+    std::fs::write(&path, broken)?;
+    thread::sleep(Duration::from_millis(500));
+    std::fs::write(&path, r#"{"id": 2, "title": "Emma"}"#)?;
+    assert!(eventually(&|titles| {
+        let mut titles = titles.to_vec();
+        titles.sort();
+        titles == ["Emma", "Moby Dick"]
+    })?);
+    Ok(())
+}
+
+#[test]
+fn fuzzy_phrases_tolerate_typos_but_keep_word_order() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let title = schema.get_field("title")?;
+    let index = Index::create_in_ram(schema);
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(title => "The Old Man and the Sea"))?;
+    index_writer.add_document(doc!(title => "A Man of the Old West"))?;
+    index_writer.add_document(doc!(title => "Old Men at Sea"))?;
+    index_writer.commit()?;
+
+    let searcher = index.reader()?.searcher();
+    let titles = |text: &str| -> tantivy::Result<Vec<String>> {
+        let Some(query) = fuzzy_phrase_query(&searcher, title, text, FuzzyDistance::Fixed(1))?
+        else {
+            return Ok(Vec::new());
+        };
+        let mut titles = Vec::new();
+        for (_, doc_address) in searcher.search(query.as_ref(), &TopDocs::with_limit(10))? {
+            let doc = searcher.doc(doc_address)?;
+            titles.push(doc.get_first(title).unwrap().as_text().unwrap().to_string());
+        }
+        titles.sort();
+        Ok(titles)
+    };
+    assert_eq!(
+        titles("old mn")?,
+        ["Old Men at Sea", "The Old Man and the Sea"]
+    );
+    assert_eq!(titles("olf man and")?, ["The Old Man and the Sea"]);
+    // The words are all there, but not next to each other in this order.
+    assert!(titles("mn old")?.is_empty());
+    assert!(titles("sea old man")?.is_empty());
+    assert!(titles("zqx wvu")?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn reservoir_samples_are_seeded_and_uniform() -> tantivy::Result<()> {
+    let index = Index::create_in_ram(sample_schema());
+    register_sample_tokenizers(&index);
+    let year = index.schema().get_field("year")?;
+    let mut index_writer = index.writer(15_000_000)?;
+    for (i, doc) in generate_corpus(40, 42).into_iter().enumerate() {
+        index_writer.add_document(doc)?;
+        // A few segments, to sample across them.
+        if i % 10 == 9 {
+            index_writer.commit()?;
+        }
+    }
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+    let sample = |size: usize, seed: u64| {
+        searcher.search(&AllQuery, &ReservoirSampleCollector::new(size, seed))
+    };
+
+    let first = sample(5, 7)?;
+    assert_eq!(first.len(), 5);
+    assert_eq!(first, sample(5, 7)?);
+    assert_ne!(first, sample(5, 8)?);
+    assert_eq!(sample(100, 7)?.len(), 40);
+    let years = searcher.search(
+        &RangeQuery::new_u64_bounds("year".to_string(), Bound::Unbounded, Bound::Excluded(1900)),
+        &ReservoirSampleCollector::new(5, 7),
+    )?;
+    for doc_address in years {
+        let doc = searcher.doc(doc_address)?;
+        assert!(doc.get_first(year).unwrap().as_u64().unwrap() < 1900);
+    }
+
+    // Each of the 40 documents is in a sample of 10 a quarter of the time.
+    let mut times_sampled: HashMap<_, u32> = HashMap::new();
+    for seed in 0..2000 {
+        for doc_address in sample(10, seed)? {
+            *times_sampled.entry(doc_address).or_default() += 1;
+        }
+    }
+    assert_eq!(times_sampled.len(), 40);
+    for times in times_sampled.values() {
+        assert!(
+            (400..=600).contains(times),
+            "sampled {times} times out of 2000"
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn hits_report_the_segment_they_come_from() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let title = schema.get_field("title")?;
+    let index = Index::create_in_ram(schema);
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.set_merge_policy(Box::new(NoMergePolicy));
+    index_writer.add_document(doc!(title => "The Old Man and the Sea"))?;
+    index_writer.commit()?;
+    index_writer.add_document(doc!(title => "The Sea of Monsters"))?;
+    index_writer.commit()?;
+
+    let searcher = index.reader()?.searcher();
+    assert_eq!(searcher.segment_readers().len(), 2);
+    let query = QueryParser::for_index(&index, vec![title]).parse_query("sea")?;
+    let mut segment_ids = Vec::new();
+    for (score, doc_address) in searcher.search(query.as_ref(), &TopDocs::with_limit(10))? {
+        let hit = SearchHit::fetch(&searcher, score, doc_address)?;
+        let segment_reader = searcher.segment_reader(doc_address.segment_ord);
+        assert_eq!(hit.segment_id, segment_reader.segment_id());
+        segment_ids.push(hit.segment_id);
+    }
+    assert_eq!(segment_ids.len(), 2);
+    assert_ne!(segment_ids[0], segment_ids[1]);
+
+    let server = SearchServer::new(index, vec![title])?;
+    let response: serde_json::Value =
+        serde_json::from_str(&server.handle("/search?q=sea").body).unwrap();
+    let mut reported: Vec<(u64, String)> = response["hits"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|hit| {
+            let segment = &hit["_segment"];
+            (
+                segment["ord"].as_u64().unwrap(),
+                segment["id"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect();
+    reported.sort();
+    let expected: Vec<(u64, String)> = searcher
+        .segment_readers()
+        .iter()
+        .enumerate()
+        .map(|(ord, segment_reader)| (ord as u64, segment_reader.segment_id().uuid_string()))
+        .collect();
+    assert_eq!(reported, expected);
+    Ok(())
+}
+
+#[test]
+fn finalized_indexes_have_one_segment_and_the_same_results() -> tantivy::Result<()> {
+    let index = Index::create_in_ram(sample_schema());
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.set_merge_policy(Box::new(NoMergePolicy));
+    for (i, doc) in generate_corpus(60, 42).into_iter().enumerate() {
+        index_writer.add_document(doc)?;
+        if i % 20 == 19 {
+            index_writer.commit()?;
+        }
+    }
+    let reader = index.reader()?;
+    // Merging renumbers the documents, which reorders the ones with the
+    // same score: every hit is compared, ties in title order.
+    let title = index.schema().get_field("title")?;
+    let query_parser = QueryParser::for_index(&index, vec![title]);
+    let results = |searcher: &Searcher| -> tantivy::Result<Vec<Vec<(f32, String)>>> {
+        let mut results = Vec::new();
+        for query in ["sea", "old man", "storm island", "+king -war"] {
+            let query = query_parser.parse_query(query)?;
+            let mut hits = Vec::new();
+            for (score, doc_address) in searcher.search(&query, &TopDocs::with_limit(60))? {
+                let doc = searcher.doc(doc_address)?;
+                let value = doc.get_first(title).and_then(|value| value.as_text());
+                hits.push((score, value.unwrap_or_default().to_string()));
+            }
+            hits.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+            results.push(hits);
+        }
+        Ok(results)
+    };
+    let before = results(&reader.searcher())?;
+    assert_eq!(reader.searcher().segment_readers().len(), 3);
+
+    let report = finalize(index_writer, &reader)?;
+    assert_eq!((report.segments_before, report.segments_after), (3, 1));
+    let searcher = reader.searcher();
+    assert_eq!(searcher.segment_readers().len(), 1);
+    assert_eq!(searcher.num_docs(), 60);
+    assert_eq!(results(&searcher)?, before);
+    Ok(())
+}
+
+#[test]
+fn score_histograms_need_at_least_one_bucket() -> tantivy::Result<()> {
+    assert!(ScoreHistogramCollector::new(0.0, 1.0, 0).is_err());
+    assert!(ScoreHistogramCollector::new(0.0, 0.0, 10).is_err());
+    assert!(ScoreHistogramCollector::new(0.0, f32::INFINITY, 10).is_err());
+
+    let index = sample_index()?;
+    let searcher = index.reader()?.searcher();
+    let histogram = searcher.search(&AllQuery, &ScoreHistogramCollector::new(0.0, 0.5, 4)?)?;
+    // `AllQuery` scores every document 1.0, in the third range.
+    assert_eq!(histogram.counts, [0, 0, 3, 0]);
+    Ok(())
+}
+
+#[test]
+fn change_feeds_return_the_batches_after_a_commit() -> tantivy::Result<()> {
+    let index = Index::create_in_ram(sample_schema());
+    register_sample_tokenizers(&index);
+    let schema = index.schema();
+    let opstamp_field = schema.get_field("opstamp")?;
+    let mut index_writer = index.writer(15_000_000)?;
+    let first = "{\"title\": \"The Old Man and the Sea\"}\n{\"title\": \"Of Mice and Men\"}\n";
+    ingest_stamped(&mut index_writer, &schema, first.as_bytes(), opstamp_field)?;
+    let first_commit = index_writer.commit()?;
+    let second: String = (0..5)
+        .map(|i| format!("{{\"title\": \"Frankenstein {i}\"}}\n"))
+        .collect();
+    ingest_stamped(&mut index_writer, &schema, second.as_bytes(), opstamp_field)?;
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+    let title = schema.get_field("title")?;
+    let titles = |changes: &[(u64, tantivy::DocAddress)]| -> tantivy::Result<Vec<String>> {
+        changes
+            .iter()
+            .map(|&(_, doc_address)| {
+                let doc = searcher.doc(doc_address)?;
+                Ok(doc
+                    .get_first(title)
+                    .and_then(|value| value.as_text())
+                    .unwrap_or_default()
+                    .to_string())
+            })
+            .collect()
+    };
+
+    assert_eq!(
+        changes_since(&searcher, opstamp_field, 0, None, 10)?.len(),
+        7
+    );
+    let changes = changes_since(&searcher, opstamp_field, first_commit, None, 10)?;
+    assert!(changes.iter().all(|&(stamp, _)| stamp == first_commit));
+    let expected: Vec<String> = (0..5).map(|i| format!("Frankenstein {i}")).collect();
+    assert_eq!(titles(&changes)?, expected);
+
+    // The batch is larger than a page: each page starts after the last change.
+    let mut paged = Vec::new();
+    let mut cursor = None;
+    loop {
+        let page = changes_since(&searcher, opstamp_field, first_commit, cursor, 2)?;
+        let Some(&(_, last)) = page.last() else {
+            break;
+        };
+        assert!(page.len() <= 2);
+        paged.extend(page);
+        cursor = Some(last);
+    }
+    assert_eq!(paged, changes);
+    Ok(())
+}
+
+#[test]
+fn gzipped_files_are_ingested_like_plain_ones() -> tantivy::Result<()> {
+    let dir = tempfile::TempDir::new()?;
+    let plain = dir.path().join("docs.ndjson");
+    let gzipped = dir.path().join("docs.ndjson.gz");
+    let lines = "{\"title\": \"The Old Man and the Sea\"}\n{\"title\": \"Of Mice and Men\"}\n";
+    std::fs::write(&plain, lines)?;
+    let mut encoder = GzEncoder::new(File::create(&gzipped)?, Compression::default());
+    encoder.write_all(lines.as_bytes())?;
+    encoder.finish()?;
+
+    for path in [&plain, &gzipped] {
+        let index = Index::create_in_ram(sample_schema());
+        register_sample_tokenizers(&index);
+        let mut index_writer = index.writer(15_000_000)?;
+        let report = ingest_file(&mut index_writer, &index.schema(), path)?;
+        assert_eq!((report.indexed, report.skipped), (2, 0));
+        index_writer.commit()?;
+        let searcher = index.reader()?.searcher();
+        assert_eq!(
+            search_titles(&index, &searcher, "mice")?,
+            ["Of Mice and Men"]
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn phrase_prefixes_complete_the_last_word() -> tantivy::Result<()> {
+    let index = sample_index()?;
+    let title = index.schema().get_field("title")?;
+    let searcher = index.reader()?.searcher();
+    let titles = |text: &str| -> tantivy::Result<Vec<String>> {
+        let Some(query) = phrase_prefix_query(&index, title, text)? else {
+            return Ok(Vec::new());
+        };
+        let mut titles = Vec::new();
+        for (_score, doc_address) in searcher.search(&query, &TopDocs::with_limit(10))? {
+            let doc = searcher.doc(doc_address)?;
+            let value = doc.get_first(title).and_then(|value| value.as_text());
+            titles.push(value.unwrap_or_default().to_string());
+        }
+        Ok(titles)
+    };
+    assert_eq!(titles("old ma")?, ["The Old Man and the Sea"]);
+    assert_eq!(titles("Old Man and the S")?, ["The Old Man and the Sea"]);
+    // The words before the last one must be whole, and in order.
+    assert!(titles("ol ma")?.is_empty());
+    assert!(titles("man old")?.is_empty());
+    assert!(titles("")?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn bm25_and_tf_idf_rank_long_documents_differently() -> tantivy::Result<()> {
+    let mut schema_builder = Schema::builder();
+    let text = schema_builder.add_text_field("text", TEXT | STORED);
+    let index = Index::create_in_ram(schema_builder.build());
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(text => "short sea"))?;
+    let filler = "and the waves rolled on ".repeat(8);
+    index_writer.add_document(doc!(text => format!("long sea sea sea sea {filler}")))?;
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+    let query = QueryParser::for_index(&index, vec![text]).parse_query("sea")?;
+
+    let ranked = |scoring| -> tantivy::Result<Vec<String>> {
+        let mut firsts = Vec::new();
+        for (_score, doc_address) in scoring::search(&searcher, &query, 10, scoring)? {
+            let doc = searcher.doc(doc_address)?;
+            let value = doc.get_first(text).and_then(|value| value.as_text());
+            let first = value
+                .unwrap_or_default()
+                .split(' ')
+                .next()
+                .unwrap_or_default();
+            firsts.push(first.to_string());
+        }
+        Ok(firsts)
+    };
+    // BM25 saturates the term frequency and normalizes by length,
+    // TF-IDF rewards the four occurrences of the long text.
+    assert_eq!(ranked(Scoring::Bm25)?, ["short", "long"]);
+    assert_eq!(ranked(Scoring::TfIdf)?, ["long", "short"]);
+    Ok(())
+}
+
+#[test]
+fn existence_queries_skip_documents_without_the_field() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let title = schema.get_field("title")?;
+    let year = schema.get_field("year")?;
+    let tenant = schema.get_field("tenant")?;
+    let index = Index::create_in_ram(schema.clone());
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(title => "Dated", year => 1952u64))?;
+    index_writer.add_document(doc!(title => "Undated", tenant => "acme"))?;
+    index_writer.add_document(doc!(title => "Year zero", year => 0u64))?;
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+
+    let matching = |field| -> tantivy::Result<Vec<String>> {
+        let query = exists_query(&schema, field)?;
+        let mut titles = Vec::new();
+        for (_score, doc_address) in searcher.search(&query, &TopDocs::with_limit(10))? {
+            let doc = searcher.doc(doc_address)?;
+            let value = doc.get_first(title).and_then(|value| value.as_text());
+            titles.push(value.unwrap_or_default().to_string());
+        }
+        titles.sort();
+        Ok(titles)
+    };
+    assert_eq!(matching(year)?, ["Dated", "Year zero"]);
+    assert_eq!(matching(tenant)?, ["Undated"]);
+    Ok(())
+}
+
+#[test]
+fn configured_tokenizers_apply_to_their_own_field() -> tantivy::Result<()> {
+    let config = SchemaConfig::from_toml(
+        r#"
+        [[fields]]
+        name = "title"
+        type = "text"
+        stored = true
+        tokenizer = "lowercase_only"
+
+        [[fields]]
+        name = "body"
+        type = "text"
+        tokenizer = "stemmed"
+
+        [tokenizers.lowercase_only]
+        filters = ["lowercase"]
+
+        [tokenizers.stemmed]
+        filters = ["lowercase", "stem"]
+        "#,
+    )?;
+    let schema = config.build()?;
+    let title = schema.get_field("title")?;
+    let body = schema.get_field("body")?;
+    let index = Index::create_in_ram(schema);
+    config.register_tokenizers(&index)?;
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(title => "Fishing Boats", body => "Fishing boats"))?;
+    index_writer.commit()?;
+
+    let searcher = index.reader()?.searcher();
+    // Both fields lowercase, only the body stems `fishing` to `fish`.
+    assert_eq!(search_titles(&index, &searcher, "title:FISHING")?.len(), 1);
+    assert_eq!(search_titles(&index, &searcher, "body:FISHING")?.len(), 1);
+    assert!(search_titles(&index, &searcher, "title:fish")?.is_empty());
+    assert_eq!(search_titles(&index, &searcher, "body:fish")?.len(), 1);
+    assert!(search_titles(&index, &searcher, "title:boat")?.is_empty());
+    assert_eq!(search_titles(&index, &searcher, "body:boat")?.len(), 1);
+
+    let unknown = SchemaConfig::from_toml(
+        r#"
+        [[fields]]
+        name = "title"
+        type = "text"
+        tokenizer = "missing"
+        "#,
+    )?;
+    let index = Index::create_in_ram(unknown.build()?);
+    assert!(unknown.register_tokenizers(&index).is_err());
+    Ok(())
+}
+
+#[test]
+fn pre_tokenized_text_is_indexed_as_given() -> tantivy::Result<()> {
+    let mut schema_builder = Schema::builder();
+    let title = schema_builder.add_text_field("title", TEXT | STORED);
+    let index = Index::create_in_ram(schema_builder.build());
+    let mut index_writer = index.writer(15_000_000)?;
+    let mut doc = Document::new();
+    add_tokens(&mut doc, title, &["C++", "Rust-Lang"]);
+    index_writer.add_document(doc)?;
+    let mut doc = Document::new();
+    let token = Token {
+        offset_from: 0,
+        offset_to: 8,
+        position: 0,
+        text: "New York".to_string(),
+        position_length: 1,
+    };
+    add_pre_tokenized(&mut doc, title, "New York", vec![token]);
+    index_writer.add_document(doc)?;
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+
+    let count = |text: &str| -> tantivy::Result<usize> {
+        let term = Term::from_field_text(title, text);
+        searcher.search(&TermQuery::new(term, IndexRecordOption::Basic), &Count)
+    };
+    // The default tokenizer would have lowercased and split all of these.
+    for token in ["C++", "Rust-Lang", "New York"] {
+        assert_eq!(count(token)?, 1, "{token}");
+    }
+    for term in ["c", "rust", "lang", "new", "york", "new york"] {
+        assert_eq!(count(term)?, 0, "{term}");
+    }
+    let (_score, doc_address) = searcher.search(&AllQuery, &TopDocs::with_limit(1))?[0];
+    let stored = searcher.doc(doc_address)?;
+    assert_eq!(
+        stored.get_first(title).and_then(|value| value.as_text()),
+        Some("C++ Rust-Lang")
+    );
+    Ok(())
+}
+
+#[test]
+fn geo_hits_come_back_nearest_first() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let title = schema.get_field("title")?;
+    let lat = schema.get_field("lat")?;
+    let lon = schema.get_field("lon")?;
+    let index = Index::create_in_ram(schema);
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(title => "Cafe in Lyon", lat => 45.76, lon => 4.84))?;
+    index_writer.add_document(doc!(title => "Cafe in Paris", lat => 48.86, lon => 2.35))?;
+    index_writer.add_document(doc!(title => "Cafe somewhere"))?;
+    index_writer.add_document(doc!(title => "Cafe in London", lat => 51.51, lon => -0.13))?;
+    index_writer.add_document(doc!(title => "Bakery in Paris", lat => 48.86, lon => 2.35))?;
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+    let query = QueryParser::for_index(&index, vec![title]).parse_query("cafe")?;
+
+    // From Orléans, south of Paris.
+    let orleans = (47.90, 1.91);
+    let hits = searcher.search(
+        &query,
+        &GeoDistanceCollector::new("lat", "lon", orleans, 10),
+    )?;
+    let mut titles = Vec::new();
+    for hit in &hits {
+        let doc = searcher.doc(hit.doc_address)?;
+        let value = doc.get_first(title).and_then(|value| value.as_text());
+        titles.push(value.unwrap_or_default().to_string());
+        assert!(hit.score > 0.0);
+    }
+    assert_eq!(
+        titles,
+        [
+            "Cafe in Paris",
+            "Cafe in Lyon",
+            "Cafe in London",
+            "Cafe somewhere"
+        ]
+    );
+    let paris_km = hits[0].distance_km.unwrap_or_default();
+    assert!((paris_km - haversine_km(orleans, (48.86, 2.35))).abs() < 1e-9);
+    assert!((100.0..120.0).contains(&paris_km), "{paris_km}");
+    assert_eq!(hits[3].distance_km, None);
+
+    let nearest = searcher.search(&query, &GeoDistanceCollector::new("lat", "lon", orleans, 1))?;
+    assert_eq!(nearest, hits[..1]);
+    let two_nearest =
+        searcher.search(&query, &GeoDistanceCollector::new("lat", "lon", orleans, 2))?;
+    assert_eq!(two_nearest, hits[..2]);
+    Ok(())
+}
+
+#[test]
+fn cleared_indexes_keep_their_directory_and_schema() -> tantivy::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let schema = sample_schema();
+    let title = schema.get_field("title")?;
+    let index = open_or_create(dir.path(), schema.clone())?;
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    for i in 0..5 {
+        index_writer.add_document(doc!(title => format!("Book {i}")))?;
+    }
+    index_writer.commit()?;
+    drop(index_writer);
+
+    assert_eq!(clear(&index)?, 5);
+    assert_eq!(index.reader()?.searcher().num_docs(), 0);
+
+    // The index reopens as it was, and takes documents right away.
+    drop(index);
+    let index = open_or_create(dir.path(), schema.clone())?;
+    assert_eq!(index.schema(), schema);
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(title => "Refilled"))?;
+    index_writer.commit()?;
+    assert_eq!(index.reader()?.searcher().num_docs(), 1);
+    Ok(())
+}
+
+#[test]
+fn pipelined_batches_add_up_to_one_report() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let index = Index::create_in_ram(schema.clone());
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    let batches = [
+        "{\"title\": \"First\"}\n{\"title\": \n{\"title\": \"Second\"}\n",
+        "\n{\"title\": \"Third\"}\nnot json\n",
+        "{\"title\": \"Fourth\"}\n",
+    ];
+    let (opstamps, report) = ingest_pipelined(
+        &mut index_writer,
+        &schema,
+        batches.iter().map(|batch| batch.as_bytes()),
+    )?;
+
+    assert_eq!(opstamps.len(), 3);
+    assert!(opstamps.windows(2).all(|pair| pair[0] < pair[1]));
+    assert_eq!(report.indexed, 4);
+    assert_eq!(report.skipped, 2);
+    // Lines count on from one batch to the next, blank ones included.
+    assert_eq!(report.skipped_lines, [2, 6]);
+    assert_eq!(index.reader()?.searcher().num_docs(), 4);
+    Ok(())
+}
+
+#[test]
+fn json_path_queries_match_numbers_and_strings() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let title = schema.get_field("title")?;
+    let metadata = schema.get_field("metadata")?;
+    let index = Index::create_in_ram(schema.clone());
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    for line in [
+        r#"{"title": "Number", "metadata": {"rating": 5, "format": "paperback"}}"#,
+        r#"{"title": "String", "metadata": {"rating": "5", "format": "Hardcover"}}"#,
+        r#"{"title": "Other", "metadata": {"rating": 3, "format": "ebook"}}"#,
+    ] {
+        index_writer.add_document(schema.parse_document(line)?)?;
+    }
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+
+    let titles = |query: &str| -> tantivy::Result<Vec<String>> {
+        let query = QueryParser::for_index(&index, vec![title, metadata]).parse_query(query)?;
+        let mut titles = Vec::new();
+        for (_score, doc_address) in searcher.search(&query, &TopDocs::with_limit(10))? {
+            let doc = searcher.doc(doc_address)?;
+            let value = doc.get_first(title).and_then(|value| value.as_text());
+            titles.push(value.unwrap_or_default().to_string());
+        }
+        titles.sort();
+        Ok(titles)
+    };
+    assert_eq!(titles("metadata.rating:5")?, ["Number", "String"]);
+    assert_eq!(titles("metadata.rating:3")?, ["Other"]);
+    assert!(titles("metadata.rating:4")?.is_empty());
+    assert_eq!(titles("metadata.format:hardcover")?, ["String"]);
+    assert!(titles("metadata.missing:5")?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn highlights_keep_the_stored_casing() -> tantivy::Result<()> {
+    let index = sample_index()?;
+    let title = index.schema().get_field("title")?;
+    let searcher = index.reader()?.searcher();
+    let query = QueryParser::for_index(&index, vec![title]).parse_query("old SEA")?;
+    let highlighter = Highlighter::new(&searcher, query.as_ref(), title, "<b>", "</b>")?;
+
+    let (_score, doc_address) = searcher.search(&query, &TopDocs::with_limit(1))?[0];
+    let doc = searcher.doc(doc_address)?;
+    assert_eq!(
+        highlighter.highlight(&searcher, doc_address, &doc)?,
+        "The <b>Old</b> Man and the <b>Sea</b>"
+    );
+    assert_eq!(highlighter.match_count(&searcher, doc_address, &doc)?, 2);
+    Ok(())
+}
+
+#[test]
+fn published_filters_exclude_drafts() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let title = schema.get_field("title")?;
+    let published = schema.get_field("published")?;
+    let index = Index::create_in_ram(schema);
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(title => "Sea stories", published => true))?;
+    index_writer.add_document(doc!(title => "Sea drafts", published => false))?;
+    index_writer.add_document(doc!(title => "Sea notes"))?;
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+    let text = QueryParser::for_index(&index, vec![title]).parse_query("sea")?;
+
+    let titles = |value| -> tantivy::Result<Vec<String>> {
+        let query = BooleanQuery::new(vec![
+            (Occur::Must, text.box_clone()),
+            (Occur::Must, Box::new(bool_filter(published, value))),
+        ]);
+        let mut titles = Vec::new();
+        for (_score, doc_address) in searcher.search(&query, &TopDocs::with_limit(10))? {
+            let doc = searcher.doc(doc_address)?;
+            let value = doc.get_first(title).and_then(|value| value.as_text());
+            titles.push(value.unwrap_or_default().to_string());
+        }
+        Ok(titles)
+    };
+    // A document without the flag is neither published nor a draft.
+    assert_eq!(titles(true)?, ["Sea stories"]);
+    assert_eq!(titles(false)?, ["Sea drafts"]);
+    assert_eq!(
+        search_titles(&index, &searcher, "published:true")?,
+        ["Sea stories"]
+    );
+    Ok(())
+}
+
+#[test]
+fn synonym_expansion_finds_documents_with_only_the_synonym() -> tantivy::Result<()> {
+    let mut schema_builder = Schema::builder();
+    let title = schema_builder.add_text_field("title", TEXT | STORED);
+    let index = Index::create_in_ram(schema_builder.build());
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(title => "The Old Man and the Sea"))?;
+    index_writer.add_document(doc!(title => "Tales of the Ocean"))?;
+    index_writer.add_document(doc!(title => "Tales of the Desert"))?;
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+    let query_parser = QueryParser::for_index(&index, vec![title]);
+    let synonyms = SynonymMap::parse("# one way only\nsea: ocean, seas\n")?;
+
+    let titles = |query: &str, expand: bool| -> tantivy::Result<Vec<String>> {
+        let mut query = query_parser.parse_query(query)?;
+        if expand {
+            query = synonyms.expand(query);
+        }
+        let mut titles = Vec::new();
+        for (_score, doc_address) in searcher.search(&query, &TopDocs::with_limit(10))? {
+            let doc = searcher.doc(doc_address)?;
+            let value = doc.get_first(title).and_then(|value| value.as_text());
+            titles.push(value.unwrap_or_default().to_string());
+        }
+        titles.sort();
+        Ok(titles)
+    };
+    assert_eq!(titles("sea", false)?, ["The Old Man and the Sea"]);
+    assert_eq!(
+        titles("sea", true)?,
+        ["Tales of the Ocean", "The Old Man and the Sea"]
+    );
+    // Expanded terms keep their occurrence inside boolean queries.
+    assert_eq!(titles("+tales +sea", true)?, ["Tales of the Ocean"]);
+    assert_eq!(titles("ocean", true)?, ["Tales of the Ocean"]);
+    Ok(())
+}
+
+#[test]
+fn recency_boosts_rank_newer_books_first() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let title = schema.get_field("title")?;
+    let year = schema.get_field("year")?;
+    let index = Index::create_in_ram(schema);
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(title => "Undated sea"))?;
+    index_writer.add_document(doc!(title => "Older sea", year => 1990u64))?;
+    index_writer.add_document(doc!(title => "Newer sea", year => 2020u64))?;
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+    let query = QueryParser::for_index(&index, vec![title]).parse_query("sea")?;
+
+    let titles = |top_docs: Vec<(Score, DocAddress)>| -> tantivy::Result<Vec<String>> {
+        let mut titles = Vec::new();
+        for (_score, doc_address) in top_docs {
+            let doc = searcher.doc(doc_address)?;
+            let value = doc.get_first(title).and_then(|value| value.as_text());
+            titles.push(value.unwrap_or_default().to_string());
+        }
+        Ok(titles)
+    };
+    // The text scores tie, so BM25 alone keeps the insertion order.
+    let plain = searcher.search(&query, &TopDocs::with_limit(10))?;
+    assert_eq!(titles(plain)?, ["Undated sea", "Older sea", "Newer sea"]);
+
+    let boost = RecencyBoost {
+        year_field: "year".to_string(),
+        current_year: 2020,
+        half_life_years: 10.0,
+    };
+    let boosted = search_with_recency(&searcher, &query, 10, &boost)?;
+    let (newer, older) = (boosted[0].0, boosted[1].0);
+    assert_eq!(titles(boosted)?, ["Newer sea", "Older sea", "Undated sea"]);
+    // Three half-lives apart.
+    assert!((older / newer - 0.125).abs() < 1e-6);
+    Ok(())
+}
+
+#[test]
+fn scoped_searches_never_leak_another_tenant() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let title = schema.get_field("title")?;
+    let tenant = schema.get_field("tenant")?;
+    let index = Index::create_in_ram(schema);
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(title => "Sea charts of A", tenant => "a"))?;
+    index_writer.add_document(doc!(title => "Sea charts of B", tenant => "b"))?;
+    index_writer.add_document(doc!(title => "Harbor maps of B", tenant => "b"))?;
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+    let query_parser = QueryParser::for_index(&index, vec![title, tenant]);
+
+    let tenants = |query: &str| -> tantivy::Result<Vec<String>> {
+        let top_docs = search_scoped(&searcher, &query_parser, tenant, "a", query, 10)?;
+        let mut tenants = Vec::new();
+        for (_score, doc_address) in top_docs {
+            let doc = searcher.doc(doc_address)?;
+            let value = doc.get_first(tenant).and_then(|value| value.as_text());
+            tenants.push(value.unwrap_or_default().to_string());
+        }
+        Ok(tenants)
+    };
+    assert_eq!(tenants("sea charts")?, ["a"]);
+    for query in ["harbor", "tenant:b", "sea OR tenant:b", "*"] {
+        assert!(tenants(query)?.iter().all(|found| found == "a"), "{query}");
+    }
+    assert!(tenants("harbor")?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn auto_fuzzy_distances_scale_with_the_term_length() -> tantivy::Result<()> {
+    for (term, distance) in [
+        ("", 0),
+        ("ab", 0),
+        ("abc", 1),
+        ("abcde", 1),
+        ("abcdef", 2),
+        ("abcdefghijkl", 2),
+        // Characters are counted, not bytes.
+        ("éé", 0),
+        ("ééééé", 1),
+    ] {
+        assert_eq!(FuzzyDistance::Auto.for_term(term), distance, "{term}");
+    }
+    assert_eq!(FuzzyDistance::Fixed(2).for_term("ab"), 2);
+    assert_eq!("auto".parse::<FuzzyDistance>()?, FuzzyDistance::Auto);
+    assert_eq!("1".parse::<FuzzyDistance>()?, FuzzyDistance::Fixed(1));
+    assert!("two".parse::<FuzzyDistance>().is_err());
+
+    let index = sample_index()?;
+    let title = index.schema().get_field("title")?;
+    let searcher = index.reader()?.searcher();
+    let count = |text: &str| -> tantivy::Result<usize> {
+        let query = fuzzy_query(&index, &[title], text, FuzzyDistance::Auto)?;
+        searcher.search(&query, &Count)
+    };
+    // `ol` has to be exact, `olx` and `mann` may be one edit off `old`
+    // and `man`, and `frankenstien` two off `frankenstein`.
+    assert_eq!(count("ol")?, 0);
+    assert_eq!(count("olx")?, 1);
+    assert_eq!(count("mann")?, 1);
+    assert_eq!(count("mxxn")?, 0);
+    assert_eq!(count("frankenstien")?, 1);
+    Ok(())
+}
+
+#[test]
+fn json_explanations_break_bm25_down() -> tantivy::Result<()> {
+    let index = sample_index()?;
+    let title = index.schema().get_field("title")?;
+    let searcher = index.reader()?.searcher();
+    let query = QueryParser::for_index(&index, vec![title]).parse_query("sea")?;
+    let (score, doc_address) = searcher.search(&query, &TopDocs::with_limit(1))?[0];
+
+    let explanation = search_explain_json(&searcher, query.as_ref(), doc_address)?;
+    let value = explanation["value"].as_f64().unwrap_or_default();
+    assert!((value - f64::from(score)).abs() < 1e-4);
+    let mut descriptions = Vec::new();
+    let mut nodes = vec![&explanation];
+    while let Some(node) = nodes.pop() {
+        assert!(node["value"].is_number());
+        descriptions.push(node["description"].as_str().unwrap_or_default().to_string());
+        nodes.extend(node["details"].as_array().into_iter().flatten());
+    }
+    for factor in ["idf", "freq", "dl"] {
+        assert!(
+            descriptions
+                .iter()
+                .any(|description| description.starts_with(factor)),
+            "{factor} in {descriptions:?}"
+        );
+    }
+
+    let frankenstein = DocAddress::new(0, 2);
+    assert!(search_explain_json(&searcher, query.as_ref(), frankenstein).is_err());
+    Ok(())
+}
+
+#[test]
+fn float_ranges_order_negative_and_positive_prices() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let price = schema.get_field("price")?;
+    let index = Index::create_in_ram(schema.clone());
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    let prices = [-1e9, -2.5, -0.0, 0.5, 2.5, 1e9, f64::MIN_POSITIVE];
+    for value in prices {
+        let mut doc = Document::new();
+        add_f64(&mut doc, price, value)?;
+        index_writer.add_document(doc)?;
+    }
+    assert!(add_f64(&mut Document::new(), price, f64::NAN).is_err());
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+
+    let prices_in = |lower, upper| -> tantivy::Result<Vec<f64>> {
+        let query = f64_range(&schema, price, lower, upper)?;
+        let mut prices = Vec::new();
+        for (_score, doc_address) in searcher.search(&query, &TopDocs::with_limit(10))? {
+            let doc = searcher.doc(doc_address)?;
+            prices.extend(doc.get_first(price).and_then(|value| value.as_f64()));
+        }
+        prices.sort_by(f64::total_cmp);
+        Ok(prices)
+    };
+    assert_eq!(
+        prices_in(Bound::Included(-2.5), Bound::Included(2.5))?,
+        [-2.5, 0.0, f64::MIN_POSITIVE, 0.5, 2.5]
+    );
+    assert_eq!(
+        prices_in(Bound::Excluded(-2.5), Bound::Excluded(2.5))?,
+        [0.0, f64::MIN_POSITIVE, 0.5]
+    );
+    assert_eq!(
+        prices_in(Bound::Unbounded, Bound::Excluded(0.0))?,
+        [-1e9, -2.5]
+    );
+    // `-0.0` was indexed as `0.0`, and a `-0.0` bound means `0.0`.
+    assert_eq!(
+        prices_in(Bound::Included(0.0), Bound::Included(0.0))?,
+        [0.0]
+    );
+    assert_eq!(
+        prices_in(Bound::Included(-0.0), Bound::Excluded(f64::MIN_POSITIVE))?,
+        [0.0]
+    );
+    assert_eq!(
+        prices_in(Bound::Unbounded, Bound::Excluded(-0.0))?,
+        [-1e9, -2.5]
+    );
+    assert_eq!(prices_in(Bound::Excluded(2.5), Bound::Unbounded)?, [1e9]);
+    assert!(prices_in(Bound::Included(3.0), Bound::Included(-3.0))?.is_empty());
+    assert!(f64_range(&schema, price, Bound::Included(f64::NAN), Bound::Unbounded).is_err());
+    assert!(f64_range(&schema, price, Bound::Unbounded, Bound::Excluded(f64::NAN)).is_err());
+    Ok(())
+}
+
+#[test]
+fn documents_split_into_the_requested_segments() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let index = Index::create_in_ram(schema.clone());
+    register_sample_tokenizers(&index);
+    let mut index_writer = segmenting_writer(&index)?;
+    let input: String = (0..25)
+        .map(|i| format!("{{\"title\": \"Book {i}\"}}\n"))
+        .collect();
+    let report = ingest_in_segments(&mut index_writer, &schema, input.as_bytes(), 10)?;
+    assert_eq!(report.indexed, 25);
+    // Two full segments, and the remainder once committed.
+    assert_eq!(index.searchable_segment_ids()?.len(), 2);
+    index_writer.commit()?;
+    assert_eq!(index.searchable_segment_ids()?.len(), 3);
+
+    let searcher = index.reader()?.searcher();
+    let mut sizes: Vec<u32> = searcher
+        .segment_readers()
+        .iter()
+        .map(|segment_reader| segment_reader.num_docs())
+        .collect();
+    sizes.sort();
+    assert_eq!(sizes, [5, 10, 10]);
+
+    assert!(ingest_in_segments(&mut index_writer, &schema, input.as_bytes(), 0).is_err());
+    Ok(())
+}
+
+#[test]
+fn tags_match_in_any_case_but_stay_whole() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let title = schema.get_field("title")?;
+    let tags = schema.get_field("tags")?;
+    let index = Index::create_in_ram(schema);
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(title => "Skyline", tags => "NewYork"))?;
+    index_writer.add_document(doc!(title => "Subway", tags => "New York", tags => "Transit"))?;
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+    let query_parser = QueryParser::for_index(&index, vec![tags]);
+
+    let titles = |query: &str| -> tantivy::Result<Vec<String>> {
+        let query = query_parser.parse_query(query)?;
+        let mut titles = Vec::new();
+        for (_score, doc_address) in searcher.search(&query, &TopDocs::with_limit(10))? {
+            let doc = searcher.doc(doc_address)?;
+            let value = doc.get_first(title).and_then(|value| value.as_text());
+            titles.push(value.unwrap_or_default().to_string());
+        }
+        Ok(titles)
+    };
+    for query in ["newyork", "NEWYORK", "NewYork"] {
+        assert_eq!(titles(query)?, ["Skyline"], "{query}");
+    }
+    assert_eq!(titles("\"NEW YORK\"")?, ["Subway"]);
+    assert_eq!(titles("transit")?, ["Subway"]);
+    // The space is part of the tag, neither word matches on its own.
+    assert!(titles("new")?.is_empty());
+    assert!(titles("york")?.is_empty());
+
+    let (_score, doc_address) = searcher.search(
+        &query_parser.parse_query("transit")?,
+        &TopDocs::with_limit(1),
+    )?[0];
+    let doc = searcher.doc(doc_address)?;
+    let stored: Vec<&str> = doc
+        .get_all(tags)
+        .filter_map(|value| value.as_text())
+        .collect();
+    assert_eq!(stored, ["New York", "Transit"]);
+    Ok(())
+}
+
+#[test]
+fn hits_return_every_value_of_multi_valued_fields() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let title = schema.get_field("title")?;
+    let body = schema.get_field("body")?;
+    let tags = schema.get_field("tags")?;
+    let year = schema.get_field("year")?;
+    let index = Index::create_in_ram(schema);
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(
+        title => "The Old Man and the Sea",
+        body => "Not stored",
+        tags => "Fishing",
+        tags => "Classics",
+        tags => "Cuba",
+        year => 1952u64,
+    ))?;
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+
+    let hit = SearchHit::fetch(&searcher, 1.0, DocAddress::new(0, 0))?;
+    assert_eq!(
+        hit.fields["tags"],
+        [json!("Fishing"), json!("Classics"), json!("Cuba")]
+    );
+    assert_eq!(hit.fields["title"], [json!("The Old Man and the Sea")]);
+    assert_eq!(hit.fields["year"], [json!(1952)]);
+    // Only stored fields come back.
+    assert!(!hit.fields.contains_key("body"));
+    assert_eq!(hit.fields.len(), 3);
+    Ok(())
+}
+
+#[test]
+fn unsearchable_fields_only_match_explicit_queries() -> tantivy::Result<()> {
+    let config = SchemaConfig::from_toml(
+        r#"
+        [[fields]]
+        name = "title"
+        type = "text"
+        stored = true
+
+        [[fields]]
+        name = "sku"
+        type = "string"
+        stored = true
+        searchable = false
+        "#,
+    )?;
+    let schema = config.build()?;
+    let title = schema.get_field("title")?;
+    let sku = schema.get_field("sku")?;
+    let index = Index::create_in_ram(schema.clone());
+    config.register_tokenizers(&index)?;
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(title => "Fishing rod", sku => "ab17"))?;
+    index_writer.add_document(doc!(title => "Fishing reel", sku => "xk42"))?;
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+    assert_eq!(config.default_search_fields(&schema), [title]);
+    let query_parser = config.make_query_parser(&index)?;
+
+    let count = |query: &str| -> tantivy::Result<usize> {
+        searcher.search(&query_parser.parse_query(query)?, &Count)
+    };
+    assert_eq!(count("xk42")?, 0);
+    assert_eq!(count("sku:xk42")?, 1);
+    assert_eq!(count("fishing")?, 2);
+    Ok(())
+}
+
+#[test]
+fn multi_field_phrases_match_in_the_title_or_the_body() -> tantivy::Result<()> {
+    let index = sample_index()?;
+    let schema = index.schema();
+    let title = schema.get_field("title")?;
+    let body = schema.get_field("body")?;
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(title => "The Salinas River", body => "A travel guide."))?;
+    index_writer.add_document(doc!(title => "River trips", body => "From Salinas, by car."))?;
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+
+    let titles = |text: &str| -> tantivy::Result<Vec<String>> {
+        let Some(query) = multi_field_phrase_query(&index, &[title, body], text)? else {
+            return Ok(Vec::new());
+        };
+        let mut titles = Vec::new();
+        for (_score, doc_address) in searcher.search(&query, &TopDocs::with_limit(10))? {
+            let doc = searcher.doc(doc_address)?;
+            let value = doc.get_first(title).and_then(|value| value.as_text());
+            titles.push(value.unwrap_or_default().to_string());
+        }
+        titles.sort();
+        Ok(titles)
+    };
+    assert_eq!(
+        titles("salinas river")?,
+        ["Of Mice and Men", "The Salinas River"]
+    );
+    assert!(titles("river salinas")?.is_empty());
+    // The end of the title and the start of the body are not a phrase.
+    assert!(titles("trips from")?.is_empty());
+    assert_eq!(titles("river")?.len(), 3);
+    Ok(())
+}
+
+#[test]
+fn query_limits_accept_up_to_the_boundary() {
+    let limits = QueryLimits {
+        max_chars: 20,
+        max_terms: 3,
+    };
+    assert!(limits.check("").is_ok());
+    assert!(limits.check(&"a".repeat(20)).is_ok());
+    assert!(limits.check(&"a".repeat(21)).is_err());
+    // Characters are counted, not bytes.
+    assert!(limits.check(&"é".repeat(20)).is_ok());
+
+    assert!(limits.check("old man sea").is_ok());
+    assert!(limits.check("old man sea fish").is_err());
+    // Operators don't count, every word of a phrase does.
+    assert!(limits.check("old AND man OR sea").is_ok());
+    assert!(limits.check("(old man) NOT sea").is_ok());
+    assert!(limits.check("\"the old man\" sea").is_err());
+
+    let default = QueryLimits::default();
+    assert!(default.check(&"sea ".repeat(32)).is_ok());
+    assert!(default.check(&"sea ".repeat(33)).is_err());
+}
+
+#[test]
+fn filters_narrow_text_searches() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let title = schema.get_field("title")?;
+    let year = schema.get_field("year")?;
+    let price = schema.get_field("price")?;
+    let published = schema.get_field("published")?;
+    let index = Index::create_in_ram(schema);
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    for (name, book_year, book_price, is_published) in [
+        ("Sea 1930", 1930u64, 5.0, true),
+        ("Sea 1952", 1952, 12.5, true),
+        ("Sea 1975", 1975, 30.0, false),
+        ("Desert 1960", 1960, 8.0, true),
+    ] {
+        index_writer.add_document(doc!(
+            title => name,
+            year => book_year,
+            price => book_price,
+            published => is_published,
+        ))?;
+    }
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+    let query_parser = QueryParser::for_index(&index, vec![title]);
+
+    let titles = |query: &str, filters: &[Filter]| -> tantivy::Result<Vec<String>> {
+        let mut titles = Vec::new();
+        for (_score, doc_address) in
+            search_with_filter(&searcher, &query_parser, query, filters, 10)?
+        {
+            let doc = searcher.doc(doc_address)?;
+            let value = doc.get_first(title).and_then(|value| value.as_text());
+            titles.push(value.unwrap_or_default().to_string());
+        }
+        titles.sort();
+        Ok(titles)
+    };
+    let from_1950 = Filter::U64Range {
+        field: year,
+        lower: Bound::Included(1950),
+        upper: Bound::Unbounded,
+    };
+    let under_20 = Filter::F64Range {
+        field: price,
+        lower: Bound::Unbounded,
+        upper: Bound::Excluded(20.0),
+    };
+    let is_published = Filter::Term(Term::from_field_bool(published, true));
+    assert_eq!(titles("sea", &[])?, ["Sea 1930", "Sea 1952", "Sea 1975"]);
+    assert_eq!(
+        titles("sea", slice::from_ref(&from_1950))?,
+        ["Sea 1952", "Sea 1975"]
+    );
+    assert_eq!(
+        titles("sea", &[from_1950.clone(), under_20.clone()])?,
+        ["Sea 1952"]
+    );
+    assert_eq!(
+        titles("sea", &[under_20, is_published.clone()])?,
+        ["Sea 1930", "Sea 1952"]
+    );
+    assert_eq!(
+        titles("desert", &[from_1950, is_published])?,
+        ["Desert 1960"]
+    );
+    Ok(())
+}
+
+#[test]
+fn binary_keys_find_their_document() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let title = schema.get_field("title")?;
+    let key = schema.get_field("key")?;
+    let index = Index::create_in_ram(schema);
+    register_sample_tokenizers(&index);
+    let uuid: [u8; 16] = [
+        0x67, 0xe5, 0x50, 0x44, 0x10, 0xb1, 0x42, 0x6f, 0x92, 0x47, 0xbb, 0x68, 0x0e, 0x5f, 0xe0,
+        0xc8,
+    ];
+    let mut other = uuid;
+    other[15] = 0;
+    let mut index_writer = index.writer(15_000_000)?;
+    for (name, bytes) in [("Keyed", &uuid[..]), ("Other", &other[..])] {
+        let mut doc = doc!(title => name);
+        add_bytes(&mut doc, key, bytes);
+        index_writer.add_document(doc)?;
+    }
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+
+    let top_docs = searcher.search(&bytes_query(key, &uuid), &TopDocs::with_limit(10))?;
+    assert_eq!(top_docs.len(), 1);
+    let doc = searcher.doc(top_docs[0].1)?;
+    assert_eq!(
+        doc.get_first(title).and_then(|value| value.as_text()),
+        Some("Keyed")
+    );
+    assert_eq!(
+        doc.get_first(key).and_then(|value| value.as_bytes()),
+        Some(&uuid[..])
+    );
+    // Keys only match whole.
+    assert_eq!(searcher.search(&bytes_query(key, &uuid[..8]), &Count)?, 0);
+    Ok(())
+}
+
+#[test]
+fn grouped_results_keep_the_best_documents_of_each_group() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let title = schema.get_field("title")?;
+    let year = schema.get_field("year")?;
+    let index = Index::create_in_ram(schema);
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(title => "sea tale", year => 1952u64))?;
+    index_writer.add_document(doc!(title => "sea story of a long voyage", year => 1930u64))?;
+    index_writer.commit()?;
+    // The groups span both segments.
+    index_writer.add_document(doc!(title => "sea", year => 1952u64))?;
+    index_writer.add_document(doc!(title => "sea tale told again", year => 1952u64))?;
+    index_writer.add_document(doc!(title => "desert", year => 1930u64))?;
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+    let query = QueryParser::for_index(&index, vec![title]).parse_query("sea")?;
+
+    let titles = |top_docs: &[(Score, DocAddress)]| -> tantivy::Result<Vec<String>> {
+        let mut titles = Vec::new();
+        for &(_score, doc_address) in top_docs {
+            let doc = searcher.doc(doc_address)?;
+            let value = doc.get_first(title).and_then(|value| value.as_text());
+            titles.push(value.unwrap_or_default().to_string());
+        }
+        Ok(titles)
+    };
+    let groups = search_grouped(&searcher, &query, year, 10, 2)?;
+    assert_eq!(groups.len(), 2);
+    assert_eq!(groups[0].value, Some(1952));
+    assert_eq!(groups[0].count, 3);
+    assert_eq!(titles(&groups[0].top_docs)?, ["sea", "sea tale"]);
+    assert_eq!(groups[1].value, Some(1930));
+    assert_eq!(groups[1].count, 1);
+    assert_eq!(titles(&groups[1].top_docs)?, ["sea story of a long voyage"]);
+
+    let best = search_grouped(&searcher, &query, year, 1, 1)?;
+    assert_eq!(best.len(), 1);
+    assert_eq!(best[0].count, 3);
+    assert_eq!(titles(&best[0].top_docs)?, ["sea"]);
+    Ok(())
+}
+
+#[test]
+fn phrases_do_not_span_separate_values() -> tantivy::Result<()> {
+    let mut schema_builder = Schema::builder();
+    let authors = schema_builder.add_text_field("authors", TEXT | STORED);
+    let name = schema_builder.add_text_field("name", STORED);
+    let index = Index::create_in_ram(schema_builder.build());
+    let mut index_writer = index.writer(15_000_000)?;
+    let mut gapped = doc!(name => "gapped");
+    add_text_values(
+        &index,
+        &mut gapped,
+        authors,
+        &["John Smith", "Jane Doe"],
+        100,
+    )?;
+    index_writer.add_document(gapped)?;
+    index_writer.add_document(doc!(
+        name => "default",
+        authors => "John Smith",
+        authors => "Jane Doe",
+    ))?;
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+    let query_parser = QueryParser::for_index(&index, vec![authors]);
+
+    let names = |query: &str| -> tantivy::Result<Vec<String>> {
+        let query = query_parser.parse_query(query)?;
+        let mut names = Vec::new();
+        for (_score, doc_address) in searcher.search(&query, &TopDocs::with_limit(10))? {
+            let doc = searcher.doc(doc_address)?;
+            let value = doc.get_first(name).and_then(|value| value.as_text());
+            names.push(value.unwrap_or_default().to_string());
+        }
+        names.sort();
+        Ok(names)
+    };
+    assert_eq!(names("\"john smith\"")?, ["default", "gapped"]);
+    assert_eq!(names("\"jane doe\"")?, ["default", "gapped"]);
+    assert!(names("\"smith jane\"")?.is_empty());
+    // With the default gap of one position, a little slop crosses values.
+    assert_eq!(names("\"smith jane\"~1")?, ["default"]);
+    assert_eq!(names("\"smith jane\"~50")?, ["default"]);
+
+    // The values are stored as given, not as shifted tokens.
+    let doc = searcher.doc(DocAddress::new(0, 0))?;
+    let stored: Vec<&str> = doc
+        .get_all(authors)
+        .filter_map(|value| value.as_text())
+        .collect();
+    assert_eq!(stored, ["John Smith", "Jane Doe"]);
+    Ok(())
+}
+
+#[test]
+fn score_ties_are_broken_by_id_whatever_the_segments() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let title = schema.get_field("title")?;
+    let id = schema.get_field("id")?;
+    let index = Index::create_in_ram(schema);
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.set_merge_policy(Box::new(NoMergePolicy));
+    index_writer.add_document(doc!(title => "same sea"))?;
+    for segment in [[5u64, 3], [9, 1], [7, 4]] {
+        for book_id in segment {
+            index_writer.add_document(doc!(title => "same sea", id => book_id))?;
+        }
+        index_writer.commit()?;
+    }
+    drop(index_writer);
+    let tie_break = IdTieBreak {
+        id_field: "id".to_string(),
+    };
+    let query = QueryParser::for_index(&index, vec![title]).parse_query("sea")?;
+
+    let ids = |limit| -> tantivy::Result<Vec<Option<u64>>> {
+        let searcher = index.reader()?.searcher();
+        let mut ids = Vec::new();
+        for (_score, doc_address) in search_stable(&searcher, &query, limit, &tie_break)? {
+            ids.push(
+                searcher
+                    .doc(doc_address)?
+                    .get_first(id)
+                    .and_then(|value| value.as_u64()),
+            );
+        }
+        Ok(ids)
+    };
+    let expected = [Some(1), Some(3), Some(4), Some(5), Some(7), Some(9), None];
+    assert_eq!(ids(10)?, expected);
+    assert_eq!(ids(3)?, expected[..3]);
+    // Merging changes every doc address, not the order.
+    optimize(&index)?;
+    assert_eq!(index.searchable_segment_ids()?.len(), 1);
+    assert_eq!(ids(10)?, expected);
+    Ok(())
+}
+
+#[test]
+fn documents_are_counted_per_category() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let category = schema.get_field("category")?;
+    let tenant = schema.get_field("tenant")?;
+    let index = Index::create_in_ram(schema);
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    for path in [
+        "/fiction/classics",
+        "/fiction/thrillers",
+        "/fiction/classics",
+        "/poetry",
+        "/history/europe",
+        "/history",
+    ] {
+        index_writer.add_document(doc!(category => Facet::from(path)))?;
+    }
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+
+    // Sub-categories count in their top-level one, equal counts by name.
+    assert_eq!(
+        count_by_field(&searcher, category)?,
+        [
+            ("/fiction".to_string(), 3),
+            ("/history".to_string(), 2),
+            ("/poetry".to_string(), 1),
+        ]
+    );
+    // `tenant` is a string field, but not fast.
+    assert!(count_by_field(&searcher, tenant).is_err());
+
+    let mut schema_builder = Schema::builder();
+    let genre = schema_builder.add_text_field("genre", STRING | FAST);
+    let index = Index::create_in_ram(schema_builder.build());
+    let mut index_writer = index.writer(15_000_000)?;
+    for value in ["poetry", "drama", "poetry", "essay", "drama", "poetry"] {
+        index_writer.add_document(doc!(genre => value))?;
+    }
+    index_writer.commit()?;
+    assert_eq!(
+        count_by_field(&index.reader()?.searcher(), genre)?,
+        [
+            ("poetry".to_string(), 3),
+            ("drama".to_string(), 2),
+            ("essay".to_string(), 1),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn tokens_over_the_length_limit_are_dropped() -> tantivy::Result<()> {
+    let count_tokens = |max_token_length: Option<usize>, token: &str| -> tantivy::Result<usize> {
+        let limit =
+            max_token_length.map_or(String::new(), |max| format!("max_token_length = {max}"));
+        let config = SchemaConfig::from_toml(&format!(
+            r#"
+            {limit}
+
+            [[fields]]
+            name = "body"
+            type = "text"
+
+            [[fields]]
+            name = "blob"
+            type = "text"
+            tokenizer = "blobs"
+
+            [tokenizers.blobs]
+            filters = ["lowercase"]
+            {limit}
+            "#
+        ))?;
+        let schema = config.build()?;
+        let body = schema.get_field("body")?;
+        let blob = schema.get_field("blob")?;
+        let index = Index::create_in_ram(schema);
+        config.register_tokenizers(&index)?;
+        let mut index_writer = index.writer(15_000_000)?;
+        index_writer.add_document(doc!(body => token, blob => token))?;
+        index_writer.commit()?;
+        let searcher = index.reader()?.searcher();
+        let mut found = 0;
+        for field in [body, blob] {
+            let term = Term::from_field_text(field, token);
+            found += searcher.search(&TermQuery::new(term, IndexRecordOption::Basic), &Count)?;
+        }
+        Ok(found)
+    };
+    let token = |len| "x".repeat(len);
+    assert_eq!(count_tokens(Some(40), &token(40))?, 2);
+    assert_eq!(count_tokens(Some(40), &token(41))?, 0);
+    assert_eq!(count_tokens(Some(40), &token(50))?, 0);
+    assert_eq!(count_tokens(Some(64), &token(50))?, 2);
+    // Without a limit, the chain keeps everything and
+    // tantivy's `default` tokenizer drops tokens of 40 bytes.
+    assert_eq!(count_tokens(None, &token(39))?, 2);
+    assert_eq!(count_tokens(None, &token(40))?, 1);
+    Ok(())
+}
+
+#[test]
+fn searches_within_results_only_narrow_them() -> tantivy::Result<()> {
+    let index = sample_index()?;
+    let title = index.schema().get_field("title")?;
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(title => "Old Times"))?;
+    index_writer.add_document(doc!(title => "Sea of Stories"))?;
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+    let query_parser = QueryParser::for_index(&index, vec![title]);
+
+    let sea = result_set(&searcher, query_parser.parse_query("sea")?.as_ref())?;
+    assert_eq!(sea.len(), 2);
+    let old = query_parser.parse_query("old")?;
+    assert_eq!(searcher.search(&old, &Count)?, 2);
+    let refined = search_within(&searcher, &sea, old.as_ref(), 10)?;
+    let mut titles = Vec::new();
+    for (_score, doc_address) in refined {
+        assert!(sea.contains(doc_address));
+        let doc = searcher.doc(doc_address)?;
+        let value = doc.get_first(title).and_then(|value| value.as_text());
+        titles.push(value.unwrap_or_default().to_string());
+    }
+    assert_eq!(titles, ["The Old Man and the Sea"]);
+
+    let frankenstein = query_parser.parse_query("frankenstein")?;
+    assert!(search_within(&searcher, &sea, frankenstein.as_ref(), 10)?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn fast_field_metrics_rank_regardless_of_text_relevance() -> tantivy::Result<()> {
+    let mut schema_builder = Schema::builder();
+    let title = schema_builder.add_text_field("title", TEXT | STORED);
+    let views = schema_builder.add_u64_field("views", FAST);
+    let likes = schema_builder.add_u64_field("likes", FAST);
+    let index = Index::create_in_ram(schema_builder.build());
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(title => "sea", views => 10u64, likes => 1u64))?;
+    index_writer.add_document(doc!(title => "long tales told at sea", views => 500u64))?;
+    index_writer.add_document(doc!(title => "sea sea sea"))?;
+    index_writer.add_document(doc!(title => "desert", views => 9000u64))?;
+    index_writer.add_document(doc!(title => "sea breeze", views => 10u64, likes => 50u64))?;
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+    let query = QueryParser::for_index(&index, vec![title]).parse_query("sea")?;
+
+    let titles = |doc_addresses: Vec<DocAddress>| -> tantivy::Result<Vec<String>> {
+        let mut titles = Vec::new();
+        for doc_address in doc_addresses {
+            let doc = searcher.doc(doc_address)?;
+            let value = doc.get_first(title).and_then(|value| value.as_text());
+            titles.push(value.unwrap_or_default().to_string());
+        }
+        Ok(titles)
+    };
+    let by_bm25: Vec<DocAddress> = searcher
+        .search(&query, &TopDocs::with_limit(10))?
+        .into_iter()
+        .map(|(_score, doc_address)| doc_address)
+        .collect();
+    assert_eq!(titles(by_bm25)?[0], "sea sea sea");
+
+    let popularity = FastFieldMetric::new(&["views", "likes"], |values| {
+        values[0].unwrap_or(0) as f64 + 10.0 * values[1].unwrap_or(0) as f64
+    });
+    let top_docs = search_by_metric(&searcher, &query, 10, popularity)?;
+    let metrics: Vec<f64> = top_docs.iter().map(|(metric, _)| *metric).collect();
+    assert_eq!(metrics, [510.0, 500.0, 20.0, 0.0]);
+    let by_metric = top_docs
+        .into_iter()
+        .map(|(_metric, doc_address)| doc_address)
+        .collect();
+    // `desert` has the most views, but doesn't match.
+    assert_eq!(
+        titles(by_metric)?,
+        ["sea breeze", "long tales told at sea", "sea", "sea sea sea"]
+    );
+    Ok(())
+}
+
+#[test]
+fn lenient_parsing_never_fails() -> tantivy::Result<()> {
+    let index = sample_index()?;
+    let schema = index.schema();
+    let title = schema.get_field("title")?;
+    let body = schema.get_field("body")?;
+    let searcher = index.reader()?.searcher();
+    let query_parser = QueryParser::for_index(&index, vec![title, body]);
+
+    for input in [
+        "sea AND",
+        "title:",
+        "((",
+        "\"old man",
+        "sea^",
+        "nofield:sea",
+        "-",
+        "",
+    ] {
+        assert!(
+            query_parser.parse_query(input).is_err() || input.is_empty(),
+            "{input}"
+        );
+        let query = parse_lenient(&query_parser, input);
+        searcher.search(query.as_ref(), &Count)?;
+    }
+    // What is left of the input is searched as plain words.
+    let count = |input: &str| searcher.search(parse_lenient(&query_parser, input).as_ref(), &Count);
+    assert_eq!(count("sea AND")?, 1);
+    assert_eq!(count("((frankenstein")?, 1);
+    assert_eq!(count("\"old man")?, 1);
+    assert_eq!(count("title:")?, 0);
+    // Valid queries are parsed as usual.
+    assert_eq!(count("sea OR frankenstein")?, 2);
+    Ok(())
+}
+
+#[test]
+fn searches_see_documents_from_another_thread_once_committed() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let title = schema.get_field("title")?;
+    let index = Index::create_in_ram(schema);
+    register_sample_tokenizers(&index);
+    let index = ConcurrentIndex::new(index)?;
+    let (added_tx, added_rx) = mpsc::channel();
+    let (commit_tx, commit_rx) = mpsc::channel();
+
+    let writer = index.writer();
+    let indexing = thread::spawn(move || -> tantivy::Result<()> {
+        for i in 0..10 {
+            writer
+                .read()
+                .unwrap()
+                .add_document(doc!(title => format!("Book {i}")))?;
+        }
+        added_tx.send(()).ok();
+        commit_rx.recv().ok();
+        // Committed straight on the writer: the reader notices on its own.
+        writer.write().unwrap().commit()?;
+        Ok(())
+    });
+
+    added_rx
+        .recv_timeout(Duration::from_secs(10))
+        .expect("documents were added");
+    let before = index.searcher();
+    assert_eq!(before.num_docs(), 0);
+    commit_tx.send(()).ok();
+    indexing.join().expect("indexing thread panicked")?;
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while index.searcher().num_docs() < 10 {
+        assert!(Instant::now() < deadline, "the reader never reloaded");
+        thread::sleep(Duration::from_millis(10));
+    }
+    // A searcher is a snapshot of the commit it was created from.
+    assert_eq!(before.num_docs(), 0);
+    assert_eq!(index.searcher().num_docs(), 10);
+    Ok(())
+}
+
+#[test]
+fn batches_report_each_document_on_its_own() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let title = schema.get_field("title")?;
+    let year = schema.get_field("year")?;
+    let index = Index::create_in_ram(schema);
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    let mut wrong_type = Document::new();
+    wrong_type.add_text(year, "1952");
+    let mut unknown_field = Document::new();
+    unknown_field.add_text(Field::from_field_id(999), "nowhere");
+    let docs = vec![
+        doc!(title => "First", year => 1952u64),
+        wrong_type,
+        doc!(title => "Second"),
+        unknown_field,
+    ];
+
+    let results = add_documents(&index_writer, docs);
+    assert_eq!(results.len(), 4);
+    assert!(results[0].is_ok());
+    assert!(matches!(results[1], Err(TantivyError::SchemaError(_))));
+    assert!(results[2].is_ok());
+    assert!(matches!(results[3], Err(TantivyError::SchemaError(_))));
+    // Each accepted document got its own opstamp.
+    assert!(results[0].as_ref().ok() < results[2].as_ref().ok());
+
+    index_writer.commit()?;
+    assert_eq!(
+        search_titles(&index, &index.reader()?.searcher(), "first second")?.len(),
+        2
+    );
+    Ok(())
+}
+
+#[test]
+fn bounding_boxes_keep_the_documents_inside() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let title = schema.get_field("title")?;
+    let lat = schema.get_field("lat")?;
+    let lon = schema.get_field("lon")?;
+    let index = Index::create_in_ram(schema);
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    for (name, point) in [
+        ("Cafe in Paris", Some((48.86, 2.35))),
+        ("Cafe in Lyon", Some((45.76, 4.84))),
+        ("Cafe in Berlin", Some((52.52, 13.40))),
+        ("Cafe on the edge", Some((51.0, 2.35))),
+        ("Cafe in Fiji", Some((-17.7, 178.0))),
+        ("Cafe in Samoa", Some((-13.8, -172.0))),
+        ("Cafe somewhere", None),
+    ] {
+        let mut doc = doc!(title => name);
+        if let Some((point_lat, point_lon)) = point {
+            doc.add_f64(lat, point_lat);
+            doc.add_f64(lon, point_lon);
+        }
+        index_writer.add_document(doc)?;
+    }
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+    let query = QueryParser::for_index(&index, vec![title]).parse_query("cafe")?;
+
+    let titles = |bbox| -> tantivy::Result<Vec<String>> {
+        let mut titles = Vec::new();
+        for (_score, doc_address) in search_in_bbox(&searcher, lat, lon, bbox, query.as_ref(), 10)?
+        {
+            let doc = searcher.doc(doc_address)?;
+            let value = doc.get_first(title).and_then(|value| value.as_text());
+            titles.push(value.unwrap_or_default().to_string());
+        }
+        titles.sort();
+        Ok(titles)
+    };
+    // France, bounds included.
+    let france = BoundingBox {
+        min_lat: 42.0,
+        max_lat: 51.0,
+        min_lon: -5.0,
+        max_lon: 8.0,
+    };
+    assert_eq!(
+        titles(france)?,
+        ["Cafe in Lyon", "Cafe in Paris", "Cafe on the edge"]
+    );
+    let pacific = BoundingBox {
+        min_lat: -20.0,
+        max_lat: -10.0,
+        min_lon: 170.0,
+        max_lon: -170.0,
+    };
+    assert_eq!(titles(pacific)?, ["Cafe in Fiji", "Cafe in Samoa"]);
+    let upside_down = BoundingBox {
+        min_lat: 51.0,
+        max_lat: 42.0,
+        ..france
+    };
+    assert!(titles(upside_down).is_err());
+    Ok(())
+}
+
+#[test]
+fn unstored_fields_are_highlighted_in_any_document() -> tantivy::Result<()> {
+    let index = sample_index()?;
+    let body = index.schema().get_field("body")?;
+    let searcher = index.reader()?.searcher();
+    let query = QueryParser::for_index(&index, vec![body]).parse_query("north")?;
+    let highlighter = Highlighter::new(&searcher, query.as_ref(), body, "<b>", "</b>")?;
+
+    // Most terms of the body of the first book are in no other document,
+    // and the postings of the others start after it.
+    let old_man = DocAddress::new(0, 0);
+    let doc = searcher.doc(old_man)?;
+    assert!(highlighter
+        .highlight(&searcher, old_man, &doc)?
+        .starts_with("he was an old man"));
+    let frankenstein = DocAddress::new(0, 2);
+    let doc = searcher.doc(frankenstein)?;
+    assert!(highlighter
+        .highlight(&searcher, frankenstein, &doc)?
+        .contains("far <b>north</b> of london"));
+    Ok(())
+}
+
+#[test]
+fn match_counts_cover_every_occurrence() -> tantivy::Result<()> {
+    let index = sample_index()?;
+    let schema = index.schema();
+    let title = schema.get_field("title")?;
+    let body = schema.get_field("body")?;
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(
+        title => "Sea, sea and SEA",
+        body => "The sea was calm. The sea was grey. Then the sea was gone, and a fish.",
+    ))?;
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+    let query = QueryParser::for_index(&index, vec![title, body]).parse_query("sea fish")?;
+    let (_score, doc_address) = searcher.search(&query, &TopDocs::with_limit(1))?[0];
+    let doc = searcher.doc(doc_address)?;
+
+    // The title is stored and re-tokenized, the body is rebuilt from the index.
+    let in_title = Highlighter::new(&searcher, query.as_ref(), title, "<b>", "</b>")?;
+    assert_eq!(in_title.match_count(&searcher, doc_address, &doc)?, 3);
+    let in_body = Highlighter::new(&searcher, query.as_ref(), body, "<b>", "</b>")?;
+    assert_eq!(in_body.match_count(&searcher, doc_address, &doc)?, 4);
+
+    let old_man = DocAddress::new(0, 0);
+    let doc = searcher.doc(old_man)?;
+    assert_eq!(in_title.match_count(&searcher, old_man, &doc)?, 1);
+    assert_eq!(in_body.match_count(&searcher, old_man, &doc)?, 1);
+    Ok(())
+}
+
+#[test]
+fn rerankers_reorder_the_collected_hits() -> tantivy::Result<()> {
+    let mut schema_builder = Schema::builder();
+    let title = schema_builder.add_text_field("title", TEXT | STORED);
+    let index = Index::create_in_ram(schema_builder.build());
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(title => "The Old Man and the Sea"))?;
+    index_writer.add_document(doc!(title => "Man, Old"))?;
+    index_writer.add_document(doc!(title => "An old sailor"))?;
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+    let query = QueryParser::for_index(&index, vec![title]).parse_query("old man")?;
+
+    let titles = |hits: &[SearchHit]| -> Vec<String> {
+        hits.iter()
+            .map(|hit| {
+                hit.fields["title"][0]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string()
+            })
+            .collect()
+    };
+    let plain = search_reranked(&searcher, &query, 10, None)?;
+    assert_eq!(
+        titles(&plain),
+        ["Man, Old", "The Old Man and the Sea", "An old sailor"]
+    );
+
+    let verbatim = VerbatimBoost {
+        field: "title".to_string(),
+        text: "OLD MAN".to_string(),
+        boost: 10.0,
+    };
+    let boosted = search_reranked(&searcher, &query, 10, Some(&verbatim))?;
+    assert_eq!(
+        titles(&boosted),
+        ["The Old Man and the Sea", "Man, Old", "An old sailor"]
+    );
+    assert!((boosted[0].score - 10.0 * plain[1].score).abs() < 1e-4);
+
+    struct Shortest;
+    impl Reranker for Shortest {
+        fn rerank(&self, mut hits: Vec<SearchHit>) -> Vec<SearchHit> {
+            hits.sort_by_key(|hit| hit.fields["title"][0].as_str().map_or(0, str::len));
+            hits
+        }
+    }
+    let shortest = search_reranked(&searcher, &query, 10, Some(&Shortest))?;
+    assert_eq!(
+        titles(&shortest),
+        ["Man, Old", "An old sailor", "The Old Man and the Sea"]
+    );
+    Ok(())
+}
+
+#[test]
+fn cursor_pages_have_no_overlap_and_no_gap() -> tantivy::Result<()> {
+    let mut schema_builder = Schema::builder();
+    let title = schema_builder.add_text_field("title", TEXT | STORED);
+    let index = Index::create_in_ram(schema_builder.build());
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.set_merge_policy(Box::new(NoMergePolicy));
+    for i in 0..30 {
+        // Four distinct scores, so most hits tie with others, across segments.
+        let repeats = "sea ".repeat(i % 4 + 1);
+        index_writer.add_document(doc!(title => format!("{repeats}tale")))?;
+        if i % 10 == 9 {
+            index_writer.commit()?;
+        }
+    }
+    index_writer.add_document(doc!(title => "desert tale"))?;
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+    assert_eq!(searcher.segment_readers().len(), 4);
+    let query = QueryParser::for_index(&index, vec![title]).parse_query("sea")?;
+
+    let all = searcher.search(&query, &TopDocs::with_limit(100))?;
+    assert_eq!(all.len(), 30);
+    let mut paged = searcher.search(&query, &TopDocs::with_limit(7))?;
+    loop {
+        let (last_score, last_doc) = paged[paged.len() - 1];
+        let page = search_after(&searcher, &query, last_score, last_doc, 7)?;
+        if page.is_empty() {
+            break;
+        }
+        assert!(page.len() == 7 || paged.len() + page.len() == 30);
+        paged.extend(page);
+    }
+    assert_eq!(paged, all);
+    Ok(())
+}
+
+#[test]
+fn ids_exist_only_while_their_document_is_live() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let title = schema.get_field("title")?;
+    let id = schema.get_field("id")?;
+    let tenant = schema.get_field("tenant")?;
+    let index = Index::create_in_ram(schema);
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(title => "Kept", id => 7u64, tenant => "acme"))?;
+    index_writer.add_document(doc!(title => "Deleted", id => 8u64))?;
+    index_writer.commit()?;
+    index_writer.delete_term(Term::from_field_u64(id, 8));
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+
+    assert!(exists(&searcher, id, "7")?);
+    assert!(!exists(&searcher, id, "9")?);
+    // Deletes only mark the document, its id is still in the postings.
+    assert!(!exists(&searcher, id, "8")?);
+    assert!(exists(&searcher, tenant, "acme")?);
+    assert!(!exists(&searcher, tenant, "Acme")?);
+    // Nothing can match a value the field can't hold.
+    assert!(!exists(&searcher, id, "seven")?);
+    Ok(())
+}
+
+#[test]
+fn facet_drill_downs_keep_only_that_facet() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let title = schema.get_field("title")?;
+    let category = schema.get_field("category")?;
+    let index = Index::create_in_ram(schema);
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    for (name, path) in [
+        ("Sea novel", "/fiction/classics"),
+        ("Sea thriller", "/fiction/thrillers"),
+        ("Sea classic", "/fiction/classics"),
+        ("Sea atlas", "/reference/maps"),
+        ("Desert novel", "/fiction/classics"),
+    ] {
+        index_writer.add_document(doc!(title => name, category => Facet::from(path)))?;
+    }
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+    let query_parser = QueryParser::for_index(&index, vec![title]);
+
+    let summary = search_with_facet(&searcher, &query_parser, "sea", category, "/fiction", 10)?;
+    assert_eq!(summary.count, 3);
+    let mut titles = Vec::new();
+    for (_score, doc_address) in &summary.top_docs {
+        let doc = searcher.doc(*doc_address)?;
+        let value = doc.get_first(title).and_then(|value| value.as_text());
+        titles.push(value.unwrap_or_default().to_string());
+    }
+    titles.sort();
+    assert_eq!(titles, ["Sea classic", "Sea novel", "Sea thriller"]);
+    let sub_facets: Vec<(String, u64)> = summary
+        .facets
+        .get("/fiction")
+        .map(|(facet, count)| (facet.to_string(), count))
+        .collect();
+    assert_eq!(
+        sub_facets,
+        [
+            ("/fiction/classics".to_string(), 2),
+            ("/fiction/thrillers".to_string(), 1),
+        ]
+    );
+    assert!(search_with_facet(&searcher, &query_parser, "sea", category, "fiction", 10).is_err());
+    Ok(())
+}
+
+#[test]
+fn boosted_words_rank_under_the_phrase_matches() -> tantivy::Result<()> {
+    let mut schema_builder = Schema::builder();
+    let title = schema_builder.add_text_field("title", TEXT | STORED);
+    let index = Index::create_in_ram(schema_builder.build());
+    let mut index_writer = index.writer(15_000_000)?;
+    for name in [
+        "a man by the sea",
+        "old man by the sea",
+        "old tales of the sea",
+        "old man and man sea",
+    ] {
+        index_writer.add_document(doc!(title => name))?;
+    }
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+
+    let titles = |term_boosts: &[(&str, Score)]| -> tantivy::Result<Vec<String>> {
+        let Some(query) = phrase_with_term_boosts(&index, title, "old man", term_boosts)? else {
+            return Ok(Vec::new());
+        };
+        let mut titles = Vec::new();
+        for (_score, doc_address) in searcher.search(&query, &TopDocs::with_limit(10))? {
+            let doc = searcher.doc(doc_address)?;
+            let value = doc.get_first(title).and_then(|value| value.as_text());
+            titles.push(value.unwrap_or_default().to_string());
+        }
+        Ok(titles)
+    };
+    // The phrase alone ties, the lower doc address first.
+    assert_eq!(titles(&[])?, ["old man by the sea", "old man and man sea"]);
+    // The phrase matches first, the one repeating `man` on top,
+    // then the document with `man` alone. `old` alone is not boosted.
+    assert_eq!(
+        titles(&[("man", 2.0)])?,
+        [
+            "old man and man sea",
+            "old man by the sea",
+            "a man by the sea"
+        ]
+    );
+    assert_eq!(titles(&[("Old", 1.0)])?.len(), 3);
+    assert!(phrase_with_term_boosts(&index, title, "", &[("", 2.0)])?.is_none());
+    Ok(())
+}
+
+#[test]
+fn command_line_flags_override_the_config_file() -> tantivy::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let config = dir.path().join("config.toml");
+    std::fs::write(
+        &config,
+        "path = \"/var/lib/books\"\nheap_size = 100_000_000\nsearch_fields = [\"title\", \"body\"]\n",
+    )?;
+    let config = config.to_string_lossy().into_owned();
+
+    let mut args =
+        Args::new(["search", "--config", &config, "--path", "/tmp/books"].map(String::from));
+    args.load_config()?;
+    assert_eq!(args.command(), Some("search"));
+    assert_eq!(args.value("--path"), Some("/tmp/books"));
+    assert_eq!(args.parsed::<usize>("--heap-size")?, Some(100_000_000));
+    assert_eq!(args.value("--fields"), Some("title,body"));
+    assert_eq!(args.value("--threads"), None);
+
+    assert!(CliConfig::from_toml("paht = \"/var/lib/books\"").is_err());
+    Ok(())
+}
+
+#[test]
+fn capped_counts_stop_at_the_cap() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let title = schema.get_field("title")?;
+    let index = Index::create_in_ram(schema);
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    for _ in 0..5 {
+        index_writer.add_document(doc!(title => "The Old Man and the Sea"))?;
+    }
+    index_writer.add_document(doc!(title => "Frankenstein"))?;
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+    let query = QueryParser::for_index(&index, vec![title]).parse_query("sea")?;
+
+    let capped = searcher.search(&query, &CappedCountCollector::new(3))?;
+    assert_eq!(
+        capped,
+        HitCount {
+            count: 3,
+            exact: false
+        }
+    );
+    let uncapped = searcher.search(&query, &CappedCountCollector::new(5))?;
+    assert_eq!(
+        uncapped,
+        HitCount {
+            count: 5,
+            exact: true
+        }
+    );
+    Ok(())
+}
+
+#[test]
+fn exact_matches_must_meet_every_constraint() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let title = schema.get_field("title")?;
+    let tenant = schema.get_field("tenant")?;
+    let year = schema.get_field("year")?;
+    let index = Index::create_in_ram(schema);
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer
+        .add_document(doc!(title => "The Old Man and the Sea", tenant => "a", year => 1952u64))?;
+    index_writer.add_document(doc!(title => "Of Mice and Men", tenant => "a", year => 1937u64))?;
+    index_writer.add_document(doc!(title => "East of Eden", tenant => "b", year => 1952u64))?;
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+
+    let titles = |constraints: &[(Field, &str)]| -> tantivy::Result<Vec<String>> {
+        let mut titles = Vec::new();
+        for doc_address in exact_match_all(&searcher, constraints)? {
+            let doc = searcher.doc(doc_address)?;
+            let value = doc.get_first(title).and_then(|value| value.as_text());
+            titles.push(value.unwrap_or_default().to_string());
+        }
+        Ok(titles)
+    };
+    assert_eq!(titles(&[(tenant, "a")])?.len(), 2);
+    assert_eq!(titles(&[(year, "1952")])?.len(), 2);
+    assert_eq!(
+        titles(&[(tenant, "a"), (year, "1952")])?,
+        ["The Old Man and the Sea"]
+    );
+    assert!(titles(&[(tenant, "b"), (year, "1937")])?.is_empty());
+    assert!(titles(&[(tenant, "a"), (year, "not a year")])?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn updating_a_field_keeps_the_others() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let id = schema.get_field("id")?;
+    let title = schema.get_field("title")?;
+    let tenant = schema.get_field("tenant")?;
+    let year = schema.get_field("year")?;
+    let index = Index::create_in_ram(schema);
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(
+        id => 7u64,
+        title => "The Old Man and the Sea",
+        tenant => "a",
+        year => 1952u64,
+    ))?;
+    index_writer.commit()?;
+    let reader = index.reader()?;
+
+    let new_title = Value::Str("The Old Man and the Sea, Illustrated".to_string());
+    let updated = update_field(&index_writer, &reader.searcher(), id, "7", title, new_title)?;
+    assert!(updated.is_some());
+    let missing = Value::Str("Frankenstein".to_string());
+    assert_eq!(
+        update_field(&index_writer, &reader.searcher(), id, "8", title, missing)?,
+        None
+    );
+    index_writer.commit()?;
+    reader.reload()?;
+    let searcher = reader.searcher();
+
+    assert_eq!(searcher.num_docs(), 1);
+    let query = TermQuery::new(Term::from_field_u64(id, 7), IndexRecordOption::Basic);
+    let (_, doc_address) = searcher.search(&query, &TopDocs::with_limit(1))?[0];
+    let doc = searcher.doc(doc_address)?;
+    let text = |field: Field| doc.get_first(field).and_then(|value| value.as_text());
+    assert_eq!(text(title), Some("The Old Man and the Sea, Illustrated"));
+    assert_eq!(text(tenant), Some("a"));
+    assert_eq!(
+        doc.get_first(year).and_then(|value| value.as_u64()),
+        Some(1952)
+    );
+    assert_eq!(doc.get_all(title).count(), 1);
+    Ok(())
+}
+
+#[test]
+fn dates_render_in_iso_8601() -> tantivy::Result<()> {
+    let mut schema_builder = Schema::builder();
+    let title = schema_builder.add_text_field("title", TEXT | STORED);
+    let year = schema_builder.add_u64_field("year", STORED);
+    let created = schema_builder.add_date_field("created", STORED);
+    let schema = schema_builder.build();
+
+    let doc = doc!(
+        title => "The Old Man and the Sea",
+        year => 1952u64,
+        created => DateTime::from_timestamp_secs(1_682_942_400),
+    );
+    assert_eq!(
+        template::render("{title} ({year}), added {created}", &schema, 1.0, &doc),
+        "The Old Man and the Sea (1952), added 2023-05-01T12:00:00Z"
+    );
+    Ok(())
+}
+
+#[test]
+fn plans_show_the_boolean_structure_of_the_query() -> tantivy::Result<()> {
+    let index = sample_index()?;
+    let schema = index.schema();
+    let title = schema.get_field("title")?;
+    let body = schema.get_field("body")?;
+    let query_parser = QueryParser::for_index(&index, vec![title, body]);
+    let query = query_parser.parse_query("sea AND year:[1950 TO 1960]")?;
+    let plan = render_plan(&schema, query.as_ref());
+    let lines: Vec<&str> = plan.lines().collect();
+    assert_eq!(
+        lines[..4],
+        [
+            "Boolean",
+            "  +Boolean",
+            "    Term(title:\"sea\")",
+            "    Term(body:\"sea\")",
+        ]
+    );
+    // Ranges have no pretty-printer, only their `Debug` representation.
+    assert!(
+        lines[4].starts_with("  +RangeQuery { field: \"year\""),
+        "{plan}"
+    );
+    assert_eq!(lines.len(), 5);
+    Ok(())
+}
+
+#[test]
+fn low_scores_fall_back_to_fuzzy_matches() -> tantivy::Result<()> {
+    let index = sample_index()?;
+    let schema = index.schema();
+    let title = schema.get_field("title")?;
+    let body = schema.get_field("body")?;
+    let searcher = index.reader()?.searcher();
+    let query_parser = QueryParser::for_index(&index, vec![title, body]);
+
+    let titles = |text: &str, floor: Score| -> tantivy::Result<Vec<String>> {
+        let query = query_parser.parse_query(text)?;
+        let top_docs = search_with_fuzzy_fallback(
+            &searcher,
+            query.as_ref(),
+            &[title, body],
+            text,
+            10,
+            floor,
+            FuzzyDistance::Auto,
+        )?;
+        let mut titles = Vec::new();
+        for (_score, doc_address) in top_docs {
+            let doc = searcher.doc(doc_address)?;
+            let value = doc.get_first(title).and_then(|value| value.as_text());
+            titles.push(value.unwrap_or_default().to_string());
+        }
+        Ok(titles)
+    };
+    // The typo leaves only "man" to match, which scores over 1 but under 5.
+    assert_eq!(
+        titles("man frankenstien", 1.0)?,
+        ["The Old Man and the Sea"]
+    );
+    let recovered = titles("man frankenstien", 5.0)?;
+    assert_eq!(recovered[..2], ["The Old Man and the Sea", "Frankenstein"]);
+    // Without any exact hit, the fuzzy ones are all there is.
+    assert_eq!(titles("frankenstien", 1.0)?, ["Frankenstein"]);
+    Ok(())
+}
+
+#[test]
+fn ingested_tag_arrays_match_on_any_element() -> tantivy::Result<()> {
+    let index = Index::create_in_ram(sample_schema());
+    register_sample_tokenizers(&index);
+    let schema = index.schema();
+    let title = schema.get_field("title")?;
+    let tags = schema.get_field("tags")?;
+    let input = concat!(
+        r#"{"title": "The Old Man and the Sea", "tags": ["Sea", "Fishing", "Classic"]}"#,
+        "\n",
+        r#"{"title": "Frankenstein", "tags": "Horror"}"#,
+        "\n",
+    );
+    let mut index_writer = index.writer(15_000_000)?;
+    let report = ingest_ndjson(&mut index_writer, &schema, input.as_bytes())?;
+    assert_eq!(report.indexed, 2);
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+
+    let titles = |tag: &str| -> tantivy::Result<Vec<String>> {
+        let query = TermQuery::new(Term::from_field_text(tags, tag), IndexRecordOption::Basic);
+        let mut titles = Vec::new();
+        for (_score, doc_address) in searcher.search(&query, &TopDocs::with_limit(10))? {
+            let doc = searcher.doc(doc_address)?;
+            let value = doc.get_first(title).and_then(|value| value.as_text());
+            titles.push(value.unwrap_or_default().to_string());
+        }
+        Ok(titles)
+    };
+    for tag in ["sea", "fishing", "classic"] {
+        assert_eq!(titles(tag)?, ["The Old Man and the Sea"], "{tag}");
+    }
+    assert_eq!(titles("horror")?, ["Frankenstein"]);
+    Ok(())
+}
+
+#[test]
+fn raising_a_field_boost_reorders_the_results() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let title = schema.get_field("title")?;
+    let body = schema.get_field("body")?;
+    let index = Index::create_in_ram(schema);
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(
+        title => "The Sea Around Us",
+        body => "A history of the oceans, from their birth to the tides.",
+    ))?;
+    index_writer.add_document(doc!(
+        title => "The Old Man",
+        body => "The sea, the sea, always the sea, as far as the old man could see.",
+    ))?;
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+
+    let titles = |boosts: &str| -> tantivy::Result<Vec<String>> {
+        let boosts: FieldBoosts = boosts.parse()?;
+        let query = boosts
+            .query_parser(&index, vec![title, body])?
+            .parse_query("sea")?;
+        let mut titles = Vec::new();
+        for (_score, doc_address) in searcher.search(&query, &TopDocs::with_limit(10))? {
+            let doc = searcher.doc(doc_address)?;
+            let value = doc.get_first(title).and_then(|value| value.as_text());
+            titles.push(value.unwrap_or_default().to_string());
+        }
+        Ok(titles)
+    };
+    assert_eq!(
+        titles("title:1,body:1")?,
+        ["The Old Man", "The Sea Around Us"]
+    );
+    assert_eq!(
+        titles("title:5,body:1")?,
+        ["The Sea Around Us", "The Old Man"]
+    );
+    Ok(())
+}
+
+#[test]
+fn snapshots_open_with_the_same_documents() -> tantivy::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("index");
+    let index = open_or_create(&path, sample_schema())?;
+    register_sample_tokenizers(&index);
+    let title = index.schema().get_field("title")?;
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(title => "The Old Man and the Sea"))?;
+    index_writer.commit()?;
+    index_writer.add_document(doc!(title => "Of Mice and Men"))?;
+    index_writer.add_document(doc!(title => "Frankenstein"))?;
+    index_writer.commit()?;
+    drop(index_writer);
+
+    let to = dir.path().join("snapshot");
+    let report = snapshot(&index, &path, &to)?;
+    assert_eq!(
+        report.linked + report.copied,
+        std::fs::read_dir(&to)?.count()
+    );
+
+    // Later changes to the index don't reach the snapshot.
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(title => "East of Eden"))?;
+    index_writer.commit()?;
+    drop(index_writer);
+
+    let copy = Index::open_in_dir(&to)?;
+    register_sample_tokenizers(&copy);
+    let searcher = copy.reader()?.searcher();
+    assert_eq!(searcher.num_docs(), 3);
+    assert_eq!(
+        search_titles(&copy, &searcher, "mice")?,
+        ["Of Mice and Men"]
+    );
+    assert!(matches!(
+        snapshot(&index, &path, &to),
+        Err(TantivyError::InvalidArgument(_))
+    ));
+    Ok(())
+}
+
+#[test]
+fn query_time_stop_words_are_ignored() -> tantivy::Result<()> {
+    let index = sample_index()?;
+    let searcher = index.reader()?.searcher();
+
+    // Every body has a "the", so alone it matches everything.
+    assert_eq!(search_titles(&index, &searcher, "the sea")?.len(), 3);
+    let without_the = remove_stop_words("the sea", &["the"]);
+    assert_eq!(without_the, "sea");
+    assert_eq!(
+        search_titles(&index, &searcher, &without_the)?,
+        ["The Old Man and the Sea"]
+    );
+
+    assert_eq!(remove_stop_words("The sea", &["the"]), "sea");
+    assert_eq!(remove_stop_words("sea AND (the)", &["the"]), "sea");
+    assert_eq!(remove_stop_words("\"the sea\"", &["the"]), "\"the sea\"");
+    assert_eq!(remove_stop_words("the", &["the"]), "the");
+    Ok(())
+}
+
+#[test]
+fn term_features_cover_every_query_term() -> tantivy::Result<()> {
+    let index = sample_index()?;
+    let title = index.schema().get_field("title")?;
+    let searcher = index.reader()?.searcher();
+    let query = QueryParser::for_index(&index, vec![title]).parse_query("old sea frankenstein")?;
+    let old_man = DocAddress::new(0, 0);
+
+    let features = term_features(&searcher, query.as_ref(), old_man)?;
+    for term in ["title:old", "title:sea"] {
+        let feature = |name: &str| features[&format!("{term}.{name}")];
+        assert_eq!(feature("tf"), 1.0, "{term}");
+        assert_eq!(feature("dl"), 6.0, "{term}");
+        // BM25 with the default k1 of 1.2.
+        let score = feature("idf") * feature("tf_norm") * 2.2;
+        assert!((feature("score") - score).abs() < 1e-4, "{term}");
+    }
+    assert_eq!(features["title:frankenstein.score"], 0.0);
+    assert_eq!(features["title:frankenstein.tf"], 0.0);
+    assert_eq!(features.len(), 2 * 5 + 2);
+    Ok(())
+}
+
+#[test]
+fn fields_without_norms_ignore_their_length() -> tantivy::Result<()> {
+    // The scores of "sea" in a short and a long title.
+    let scores = |spec: SchemaSpec| -> tantivy::Result<(Score, Score)> {
+        let index = Index::create_in_ram(spec.build()?);
+        let title = index.schema().get_field("title")?;
+        let mut index_writer = index.writer(15_000_000)?;
+        index_writer.add_document(doc!(title => "The Sea"))?;
+        index_writer.add_document(doc!(title => "The Sea Around Us and the Islands Within It"))?;
+        index_writer.add_document(doc!(title => "Frankenstein"))?;
+        index_writer.commit()?;
+        let searcher = index.reader()?.searcher();
+        let query = QueryParser::for_index(&index, vec![title]).parse_query("sea")?;
+        let top_docs = searcher.search(&query, &TopDocs::with_limit(10))?;
+        let score_of = |doc_id| {
+            let hit = top_docs
+                .iter()
+                .find(|(_, doc_address)| doc_address.doc_id == doc_id);
+            hit.map(|&(score, _)| score).unwrap_or_default()
+        };
+        Ok((score_of(0), score_of(1)))
+    };
+
+    let (short, long) = scores(SchemaSpec::new().text_field("title").indexed())?;
+    assert!(short > long, "{short} <= {long}");
+    let spec = SchemaSpec::new()
+        .text_field("title")
+        .indexed()
+        .without_fieldnorms();
+    let (short, long) = scores(spec)?;
+    assert_eq!(short, long);
+    Ok(())
+}
+
+#[test]
+fn set_operations_combine_overlapping_queries() -> tantivy::Result<()> {
+    let index = sample_index()?;
+    let title = index.schema().get_field("title")?;
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(title => "Old Times"))?;
+    index_writer.add_document(doc!(title => "Sea of Stories"))?;
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+    let query_parser = QueryParser::for_index(&index, vec![title]);
+    let old = query_parser.parse_query("old")?;
+    let sea = query_parser.parse_query("sea")?;
+
+    let titles = |set: ResultSet| -> tantivy::Result<Vec<String>> {
+        let mut titles = Vec::new();
+        for doc_address in set.doc_addresses() {
+            let doc = searcher.doc(doc_address)?;
+            let value = doc.get_first(title).and_then(|value| value.as_text());
+            titles.push(value.unwrap_or_default().to_string());
+        }
+        Ok(titles)
+    };
+    assert_eq!(
+        titles(query_intersection(&searcher, old.as_ref(), sea.as_ref())?)?,
+        ["The Old Man and the Sea"]
+    );
+    assert_eq!(
+        titles(query_union(&searcher, old.as_ref(), sea.as_ref())?)?,
+        ["The Old Man and the Sea", "Old Times", "Sea of Stories"]
+    );
+    assert_eq!(
+        titles(query_difference(&searcher, old.as_ref(), sea.as_ref())?)?,
+        ["Old Times"]
+    );
+    assert_eq!(
+        titles(query_difference(&searcher, sea.as_ref(), old.as_ref())?)?,
+        ["Sea of Stories"]
+    );
+    Ok(())
+}
+
+#[test]
+fn merging_refreshes_stale_doc_frequencies() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let id = schema.get_field("id")?;
+    let title = schema.get_field("title")?;
+    let index = Index::create_in_ram(schema);
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    for i in 0..20u64 {
+        index_writer.add_document(doc!(id => i, title => "The Old Man and the Sea"))?;
+    }
+    index_writer.commit()?;
+    for i in 0..15u64 {
+        index_writer.delete_term(Term::from_field_u64(id, i));
+    }
+    index_writer.commit()?;
+    drop(index_writer);
+
+    let sea = Term::from_field_text(title, "sea");
+    let searcher = index.reader()?.searcher();
+    assert_eq!(searcher.num_docs(), 5);
+    assert_eq!(searcher.doc_freq(&sea)?, 20);
+    drop(searcher);
+
+    let report = refresh_statistics(&index, &sea)?;
+    assert_eq!(report.doc_freq_before, 20);
+    assert_eq!(report.doc_freq_after, 5);
+    assert_eq!(report.compaction.segments_after, 1);
+    assert_eq!(index.reader()?.searcher().doc_freq(&sea)?, 5);
+    Ok(())
+}
+
+#[test]
+fn sorting_places_missing_values_first_or_last() -> tantivy::Result<()> {
+    let schema = sample_schema();
+    let title = schema.get_field("title")?;
+    let year = schema.get_field("year")?;
+    let index = Index::create_in_ram(schema);
+    register_sample_tokenizers(&index);
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(title => "The Old Man and the Sea", year => 1952u64))?;
+    index_writer.add_document(doc!(title => "An Undated Pamphlet"))?;
+    index_writer.add_document(doc!(title => "Of Mice and Men", year => 1937u64))?;
+    index_writer.add_document(doc!(title => "Another Undated Pamphlet"))?;
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+
+    let sorted = |order: Order, missing: Missing| -> tantivy::Result<Vec<(Option<u64>, u32)>> {
+        let top = search_sorted(&searcher, &AllQuery, year, order, missing, 10)?;
+        Ok(top
+            .into_iter()
+            .map(|(value, doc_address)| (value, doc_address.doc_id))
+            .collect())
+    };
+    assert_eq!(
+        sorted(Order::Asc, Missing::Last)?,
+        [(Some(1937), 2), (Some(1952), 0), (None, 1), (None, 3)]
+    );
+    assert_eq!(
+        sorted(Order::Asc, Missing::First)?,
+        [(None, 1), (None, 3), (Some(1937), 2), (Some(1952), 0)]
+    );
+    assert_eq!(
+        sorted(Order::Desc, Missing::First)?,
+        [(None, 1), (None, 3), (Some(1952), 0), (Some(1937), 2)]
+    );
+    assert_eq!(
+        sorted(Order::Desc, Missing::Last)?,
+        [(Some(1952), 0), (Some(1937), 2), (None, 1), (None, 3)]
+    );
+    Ok(())
+}
+
+#[test]
+fn in_memory_searches_return_the_matching_structs() -> tantivy::Result<()> {
+    #[derive(Debug, PartialEq, Serialize)]
+    struct Book {
+        title: String,
+        author: Option<String>,
+        year: u64,
+        in_print: bool,
+    }
+
+    let book = |title: &str, author: Option<&str>, year: u64, in_print: bool| Book {
+        title: title.to_string(),
+        author: author.map(str::to_string),
+        year,
+        in_print,
+    };
+    let books = vec![
+        book(
+            "The Old Man and the Sea",
+            Some("Ernest Hemingway"),
+            1952,
+            true,
+        ),
+        book("Of Mice and Men", Some("John Steinbeck"), 1937, true),
+        book("The Sea Around Us", None, 1951, false),
+    ];
+    let search = InMemorySearch::new(&books)?;
+
+    let found = search.search("old sea")?;
+    assert!(std::ptr::eq(found[0], &books[0]));
+    assert_eq!(found, [&books[0], &books[2]]);
+    assert_eq!(search.search("steinbeck")?, [&books[1]]);
+    assert_eq!(search.search("in_print:false")?, [&books[2]]);
+    assert_eq!(search.search("year:1937")?, [&books[1]]);
+    assert!(search.search("frankenstein")?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn time_decay_lets_fresh_documents_outrank_old_ones() -> tantivy::Result<()> {
+    let mut schema_builder = Schema::builder();
+    let title = schema_builder.add_text_field("title", TEXT | STORED);
+    let created = schema_builder.add_date_field("created", FAST);
+    let index = Index::create_in_ram(schema_builder.build());
+    let now = DateTime::from_timestamp_secs(1_700_000_000);
+    let days_ago = |days: i64| DateTime::from_timestamp_secs(1_700_000_000 - days * 86_400);
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(title => "Sea storm, sea swell", created => days_ago(30)))?;
+    index_writer.add_document(doc!(
+        title => "Storm warning for the sea and the harbor",
+        created => days_ago(1),
+    ))?;
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+    let query = QueryParser::for_index(&index, vec![title]).parse_query("sea storm")?;
+
+    let titles = |top_docs: Vec<(Score, DocAddress)>| -> tantivy::Result<Vec<String>> {
+        let mut titles = Vec::new();
+        for (_score, doc_address) in top_docs {
+            let doc = searcher.doc(doc_address)?;
+            let value = doc.get_first(title).and_then(|value| value.as_text());
+            titles.push(value.unwrap_or_default().to_string());
+        }
+        Ok(titles)
+    };
+    let decay = |lambda: f32| TimeDecay {
+        created_field: "created".to_string(),
+        now,
+        lambda,
+    };
+    let more_relevant = searcher.search(&query, &TopDocs::with_limit(10))?;
+    assert_eq!(titles(more_relevant)?[0], "Sea storm, sea swell");
+    let undecayed = search_with_time_decay(&searcher, &query, 10, &decay(0.0))?;
+    assert_eq!(titles(undecayed)?[0], "Sea storm, sea swell");
+    let decayed = search_with_time_decay(&searcher, &query, 10, &decay(0.1))?;
+    assert_eq!(
+        titles(decayed)?,
+        [
+            "Storm warning for the sea and the harbor",
+            "Sea storm, sea swell"
+        ]
+    );
+    Ok(())
+}
+
+/// A RAM directory whose writes can be made to fail, to simulate a flaky disk.
+#[derive(Clone, Debug, Default)]
+struct FlakyDirectory {
+    inner: RamDirectory,
+    /// How many more writes of `meta.json` fail.
+    failing_meta_writes: Arc<AtomicUsize>,
+    /// Whether new segment files fail to open.
+    failing_segment_writes: Arc<AtomicBool>,
+}
+
+impl Directory for FlakyDirectory {
+    fn get_file_handle(&self, path: &Path) -> Result<Arc<dyn FileHandle>, OpenReadError> {
+        self.inner.get_file_handle(path)
+    }
+
+    fn delete(&self, path: &Path) -> Result<(), DeleteError> {
+        self.inner.delete(path)
+    }
+
+    fn exists(&self, path: &Path) -> Result<bool, OpenReadError> {
+        self.inner.exists(path)
+    }
+
+    fn open_write(&self, path: &Path) -> Result<WritePtr, OpenWriteError> {
+        let is_lock = path
+            .extension()
+            .is_some_and(|extension| extension == "lock");
+        if !is_lock && self.failing_segment_writes.load(AtomicOrdering::SeqCst) {
+            let io_error = io::Error::other("injected write failure");
+            return Err(OpenWriteError::wrap_io_error(io_error, path.to_path_buf()));
+        }
+        self.inner.open_write(path)
+    }
+
+    fn atomic_read(&self, path: &Path) -> Result<Vec<u8>, OpenReadError> {
+        self.inner.atomic_read(path)
+    }
+
+    fn atomic_write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        let failing = &self.failing_meta_writes;
+        if path == Path::new("meta.json")
+            && failing
+                .fetch_update(AtomicOrdering::SeqCst, AtomicOrdering::SeqCst, |left| {
+                    left.checked_sub(1)
+                })
+                .is_ok()
+        {
+            return Err(io::Error::other("injected meta.json failure"));
+        }
+        self.inner.atomic_write(path, data)
+    }
+
+    fn sync_directory(&self) -> io::Result<()> {
+        self.inner.sync_directory()
+    }
+
+    fn watch(&self, watch_callback: WatchCallback) -> tantivy::Result<WatchHandle> {
+        self.inner.watch(watch_callback)
+    }
+}
+
+#[test]
+fn commits_are_retried_on_transient_io_errors_only() -> tantivy::Result<()> {
+    let directory = FlakyDirectory::default();
+    let index = Index::create(directory.clone(), sample_schema(), IndexSettings::default())?;
+    register_sample_tokenizers(&index);
+    let title = index.schema().get_field("title")?;
+    let mut index_writer = index.writer_with_num_threads(1, 15_000_000)?;
+    let reader = index.reader()?;
+
+    // Two failed writes of the metas, then the third attempt goes through.
+    index_writer.add_document(doc!(title => "The Old Man and the Sea"))?;
+    index_writer.add_document(doc!(title => "Of Mice and Men"))?;
+    directory
+        .failing_meta_writes
+        .store(2, AtomicOrdering::SeqCst);
+    commit_with_retry(&mut index_writer, 3, Duration::from_millis(1))?;
+    reader.reload()?;
+    assert_eq!(reader.searcher().num_docs(), 2);
+
+    // Out of retries, the last error is returned.
+    index_writer.add_document(doc!(title => "Frankenstein"))?;
+    directory
+        .failing_meta_writes
+        .store(2, AtomicOrdering::SeqCst);
+    let result = commit_with_retry(&mut index_writer, 1, Duration::from_millis(1));
+    assert!(
+        matches!(result, Err(TantivyError::IoError(_))),
+        "{result:?}"
+    );
+    directory
+        .failing_meta_writes
+        .store(0, AtomicOrdering::SeqCst);
+    index_writer.rollback()?;
+
+    // The indexing thread can't write its segment: the document is lost,
+    // so the commit fails rather than retrying without it.
+    directory
+        .failing_segment_writes
+        .store(true, AtomicOrdering::SeqCst);
+    index_writer.add_document(doc!(title => "East of Eden"))?;
+    let result = commit_with_retry(&mut index_writer, 3, Duration::from_millis(1));
+    assert!(result.is_err());
+    directory
+        .failing_segment_writes
+        .store(false, AtomicOrdering::SeqCst);
+
+    // The writer was rolled back and still works.
+    index_writer.add_document(doc!(title => "Cannery Row"))?;
+    commit_with_retry(&mut index_writer, 3, Duration::from_millis(1))?;
+    reader.reload()?;
+    let searcher = reader.searcher();
+    assert_eq!(searcher.num_docs(), 3);
+    assert!(search_titles(&index, &searcher, "eden")?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn corrections_keep_operators_and_unknown_words() -> tantivy::Result<()> {
+    let index = sample_index()?;
+    let schema = index.schema();
+    let fields = [schema.get_field("title")?, schema.get_field("body")?];
+    let searcher = index.reader()?.searcher();
+    let correct = |query: &str| correct_query(&index, &searcher, &fields, query);
+
+    assert_eq!(
+        correct("frankenstien AND NOT sea")?.as_deref(),
+        Some("frankenstein AND NOT sea")
+    );
+    assert_eq!(correct("sea OR mics")?.as_deref(), Some("sea OR mice"));
+    assert_eq!(
+        correct("Zyzzyva frankenstien")?.as_deref(),
+        Some("Zyzzyva frankenstein")
+    );
+    assert_eq!(correct("Zyzzyva AND sea")?, None);
+    assert_eq!(correct("title:frankenstien")?, None);
+    Ok(())
+}
+
+#[test]
+fn migrations_recover_from_an_interrupted_swap() -> tantivy::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("books");
+    let mut schema_builder = Schema::builder();
+    let title = schema_builder.add_text_field("title", TEXT | STORED);
+    let old_schema = schema_builder.build();
+    let mut schema_builder = Schema::builder();
+    schema_builder.add_text_field("title", TEXT | STORED);
+    let year = schema_builder.add_u64_field("year", INDEXED | STORED);
+    let new_schema = schema_builder.build();
+
+    std::fs::create_dir_all(&path)?;
+    let index = Index::create_in_dir(&path, old_schema)?;
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(title => "The Old Man and the Sea"))?;
+    index_writer.add_document(doc!(title => "Of Mice and Men"))?;
+    index_writer.commit()?;
+    drop(index_writer);
+    drop(index);
+
+    // A migration died between its two renames: the index is only at `.old`.
+    let old_path = dir.path().join("books.old");
+    std::fs::rename(&path, &old_path)?;
+    let report = migrate_add_field(&path, &new_schema, |_| Ok(()), false)?;
+    assert_eq!(report.documents, 2);
+    assert_eq!(report.added_fields, ["year"]);
+    assert!(!old_path.exists());
+    let index = Index::open_in_dir(&path)?;
+    assert_eq!(index.schema(), new_schema);
+    let mut index_writer = index.writer(15_000_000)?;
+    index_writer.add_document(doc!(title => "East of Eden", year => 1952u64))?;
+    index_writer.commit()?;
+    assert_eq!(index.reader()?.searcher().num_docs(), 3);
+
+    // With both directories there, the leftover one must be dealt with first.
+    std::fs::create_dir_all(&old_path)?;
+    let result = migrate_add_field(&path, &new_schema, |_| Ok(()), false);
+    assert!(
+        matches!(result, Err(TantivyError::InvalidArgument(_))),
+        "{result:?}"
+    );
+    assert!(old_path.exists());
+    Ok(())
+}
+
+#[test]
+fn documents_are_truncated_to_fit_the_whole_output() -> tantivy::Result<()> {
+    let mut schema_builder = Schema::builder();
+    let title = schema_builder.add_text_field("title", TEXT | STORED);
+    let body = schema_builder.add_text_field("body", STORED);
+    let schema = schema_builder.build();
+    let doc = doc!(title => "The Old Man and the Sea", body => "sea ".repeat(1000));
+
+    // The score and the segment of a hit take room too.
+    let hit = |doc: &Document| {
+        format!(
+            r#"{{"score":0.5,"_segment":0,"doc":{}}}"#,
+            schema.to_json(doc)
+        )
+    };
+    let truncated = truncate_to_fit(&doc, 200, |doc| hit(doc).len());
+    assert!(hit(&truncated).len() <= 200, "{}", hit(&truncated));
+    assert!(hit(&truncated).len() > 190, "{}", hit(&truncated));
+    let text = |field| truncated.get_first(field).and_then(|value| value.as_text());
+    assert_eq!(text(title), Some("The Old Man and the Sea"));
+    assert!(text(body).is_some_and(|text| text.ends_with(ELLIPSIS)));
+
+    let rendered = |doc: &Document| template::render("{title}: {body}", &schema, 1.0, doc);
+    let truncated = truncate_to_fit(&doc, 100, |doc| rendered(doc).len());
+    assert!(rendered(&truncated).len() <= 100);
+    assert!(rendered(&truncated).starts_with("The Old Man and the Sea: sea sea"));
+    Ok(())
+}